@@ -0,0 +1,426 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use crate::capture::Record;
+use crate::decoder::Decoded;
+use crate::{DataFrame, DeskToPanelMessage, Direction, PanelToDeskMessage};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Observation {
+    pub timestamp: Duration,
+    pub direction: Direction,
+    pub frame: DataFrame,
+}
+
+impl Observation {
+    /// Invalid frames and stray bytes have nothing to analyze, so they give `None`.
+    pub fn from_record(record: &Record) -> Option<Observation> {
+        match record.data {
+            Decoded::Frame(frame) => Some(Observation {
+                timestamp: record.timestamp,
+                direction: record.direction,
+                frame,
+            }),
+            Decoded::Invalid(_) | Decoded::Stray(_) => None,
+        }
+    }
+}
+
+/// Checksum schemes worth trying against frames we cannot decode yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChecksumAlgorithm {
+    /// What every known frame uses: the sum of bytes 1 to 4, modulo 256.
+    Sum,
+    SumWithStartByte,
+    SumOfPayload,
+    TwosComplement,
+    Xor,
+    Crc8,
+    Crc8Maxim,
+}
+
+impl ChecksumAlgorithm {
+    pub const ALL: [ChecksumAlgorithm; 7] = [
+        ChecksumAlgorithm::Sum,
+        ChecksumAlgorithm::SumWithStartByte,
+        ChecksumAlgorithm::SumOfPayload,
+        ChecksumAlgorithm::TwosComplement,
+        ChecksumAlgorithm::Xor,
+        ChecksumAlgorithm::Crc8,
+        ChecksumAlgorithm::Crc8Maxim,
+    ];
+
+    pub fn compute(&self, frame: &DataFrame) -> u8 {
+        let body = &frame[1..5];
+        match *self {
+            ChecksumAlgorithm::Sum => sum(body),
+            ChecksumAlgorithm::SumWithStartByte => sum(&frame[0..5]),
+            ChecksumAlgorithm::SumOfPayload => sum(&frame[2..5]),
+            ChecksumAlgorithm::TwosComplement => sum(body).wrapping_neg(),
+            ChecksumAlgorithm::Xor => body.iter().fold(0u8, |acc, b| acc ^ b),
+            ChecksumAlgorithm::Crc8 => crc8(body, 0x07),
+            ChecksumAlgorithm::Crc8Maxim => crc8_reflected(body, 0x8c),
+        }
+    }
+
+    pub fn matches(&self, frame: &DataFrame) -> bool {
+        self.compute(frame) == frame[5]
+    }
+}
+
+impl fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            ChecksumAlgorithm::Sum => "sum(1..=4)",
+            ChecksumAlgorithm::SumWithStartByte => "sum(0..=4)",
+            ChecksumAlgorithm::SumOfPayload => "sum(2..=4)",
+            ChecksumAlgorithm::TwosComplement => "-sum(1..=4)",
+            ChecksumAlgorithm::Xor => "xor(1..=4)",
+            ChecksumAlgorithm::Crc8 => "crc8",
+            ChecksumAlgorithm::Crc8Maxim => "crc8/maxim",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Everything we learned about the Unknown frames sharing a direction and command byte.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Cluster {
+    pub count: usize,
+    pub first_seen: Duration,
+    pub last_seen: Duration,
+    /// Value distributions of bytes 1 to 5 (everything between the start and end bytes).
+    pub byte_values: [BTreeMap<u8, usize>; 5],
+    /// The last panel action seen within the correlation window before each frame.
+    pub preceding_actions: BTreeMap<&'static str, usize>,
+    pub while_moving: usize,
+    pub while_stationary: usize,
+    pub checksum_matches: BTreeMap<ChecksumAlgorithm, usize>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    pub total_frames: usize,
+    pub unknown_frames: usize,
+    pub clusters: BTreeMap<(Direction, u8), Cluster>,
+}
+
+/// Clusters the Unknown frames in a capture and correlates them with what the panel and desk were
+/// doing within `window` of each frame.
+pub fn analyze(observations: &[Observation], window: Duration) -> Report {
+    let mut report = Report {
+        total_frames: observations.len(),
+        ..Report::default()
+    };
+
+    // Merged captures can step back in time; the sort is stable, so ties keep capture order.
+    let mut observations = observations.to_vec();
+    observations.sort_by_key(|obs| obs.timestamp);
+    let heights: Vec<(Duration, f32)> = observations
+        .iter()
+        .filter(|obs| obs.direction == Direction::DeskToPanel)
+        .filter_map(|obs| match DeskToPanelMessage::from_frame(&obs.frame) {
+            DeskToPanelMessage::Height(h) => Some((obs.timestamp, h)),
+            DeskToPanelMessage::Unknown(..) => None,
+        })
+        .collect();
+
+    for (i, obs) in observations.iter().enumerate() {
+        if !is_unknown(obs) {
+            continue;
+        }
+        report.unknown_frames += 1;
+
+        let cluster = report
+            .clusters
+            .entry((obs.direction, obs.frame[2]))
+            .or_insert_with(|| Cluster {
+                first_seen: obs.timestamp,
+                ..Cluster::default()
+            });
+
+        cluster.count += 1;
+        cluster.last_seen = obs.timestamp;
+
+        for (values, b) in cluster.byte_values.iter_mut().zip(&obs.frame[1..6]) {
+            *values.entry(*b).or_insert(0) += 1;
+        }
+
+        if let Some(action) = preceding_action(&observations, i, window) {
+            *cluster.preceding_actions.entry(action).or_insert(0) += 1;
+        }
+
+        if is_moving(&heights, obs.timestamp, window) {
+            cluster.while_moving += 1;
+        } else {
+            cluster.while_stationary += 1;
+        }
+
+        for algorithm in ChecksumAlgorithm::ALL.iter() {
+            if algorithm.matches(&obs.frame) {
+                *cluster.checksum_matches.entry(*algorithm).or_insert(0) += 1;
+            }
+        }
+    }
+
+    report
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} frames, {} unknown, {} clusters",
+            self.total_frames,
+            self.unknown_frames,
+            self.clusters.len()
+        )?;
+
+        for ((direction, command), cluster) in &self.clusters {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "{} command {}: {} frames between {:.3}s and {:.3}s",
+                direction,
+                command,
+                cluster.count,
+                cluster.first_seen.as_secs_f64(),
+                cluster.last_seen.as_secs_f64()
+            )?;
+
+            for (i, values) in cluster.byte_values.iter().enumerate() {
+                write!(f, "  byte {}:", i + 1)?;
+                for (value, count) in values {
+                    write!(f, " {:02x}x{}", value, count)?;
+                }
+                writeln!(f)?;
+            }
+
+            write!(f, "  after:")?;
+            if cluster.preceding_actions.is_empty() {
+                write!(f, " nothing")?;
+            }
+            for (action, count) in &cluster.preceding_actions {
+                write!(f, " {}x{}", action, count)?;
+            }
+            writeln!(f)?;
+
+            writeln!(
+                f,
+                "  desk moving: {}, stationary: {}",
+                cluster.while_moving, cluster.while_stationary
+            )?;
+
+            write!(f, "  checksums:")?;
+            if cluster.checksum_matches.is_empty() {
+                write!(f, " none match")?;
+            }
+            for (algorithm, count) in &cluster.checksum_matches {
+                write!(f, " {} {}/{}", algorithm, count, cluster.count)?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn is_unknown(obs: &Observation) -> bool {
+    match obs.direction {
+        Direction::PanelToDesk => matches!(
+            PanelToDeskMessage::from_frame(&obs.frame),
+            PanelToDeskMessage::Unknown(..)
+        ),
+        Direction::DeskToPanel => matches!(
+            DeskToPanelMessage::from_frame(&obs.frame),
+            DeskToPanelMessage::Unknown(..)
+        ),
+    }
+}
+
+fn action_label(msg: PanelToDeskMessage) -> Option<&'static str> {
+    match msg {
+        PanelToDeskMessage::Up => Some("up"),
+        PanelToDeskMessage::Down => Some("down"),
+        PanelToDeskMessage::DeskReset => Some("desk-reset"),
+        PanelToDeskMessage::One(_) => Some("preset-1"),
+        PanelToDeskMessage::Two(_) => Some("preset-2"),
+        PanelToDeskMessage::Three(_) => Some("preset-3"),
+        PanelToDeskMessage::ResetOne => Some("reset-1"),
+        PanelToDeskMessage::ResetTwo => Some("reset-2"),
+        PanelToDeskMessage::ResetThree => Some("reset-3"),
+        PanelToDeskMessage::NoKey | PanelToDeskMessage::Unknown(..) => None,
+    }
+}
+
+/// The last action within `window` before observation `i`, which have to be sorted by time.
+fn preceding_action(
+    observations: &[Observation],
+    i: usize,
+    window: Duration,
+) -> Option<&'static str> {
+    let at = observations[i].timestamp;
+    observations[..i]
+        .iter()
+        .rev()
+        .take_while(|obs| at - obs.timestamp <= window)
+        .filter(|obs| obs.direction == Direction::PanelToDesk)
+        .find_map(|obs| action_label(PanelToDeskMessage::from_frame(&obs.frame)))
+}
+
+/// Whether the height changed within `window` either side of `at`, given the reported heights
+/// sorted by time.
+fn is_moving(heights: &[(Duration, f32)], at: Duration, window: Duration) -> bool {
+    let start = heights.partition_point(|(t, _)| *t < at.saturating_sub(window));
+    let end = heights.partition_point(|(t, _)| *t <= at.saturating_add(window));
+    let mut heights = heights[start..end.max(start)].iter().map(|(_, h)| h);
+
+    match heights.next() {
+        Some(first) => heights.any(|h| h != first),
+        None => false,
+    }
+}
+
+fn sum(b: &[u8]) -> u8 {
+    b.iter().fold(0u8, |acc, x| acc.wrapping_add(*x))
+}
+
+fn crc8(b: &[u8], poly: u8) -> u8 {
+    let mut crc = 0u8;
+    for byte in b {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ poly
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn crc8_reflected(b: &[u8], poly: u8) -> u8 {
+    let mut crc = 0u8;
+    for byte in b {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x01 != 0 {
+                (crc >> 1) ^ poly
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obs(ms: u64, direction: Direction, frame: DataFrame) -> Observation {
+        Observation {
+            timestamp: Duration::from_millis(ms),
+            direction,
+            frame,
+        }
+    }
+
+    #[test]
+    fn test_checksum_algorithms() {
+        let frame = PanelToDeskMessage::One(100.0).as_frame();
+        assert!(ChecksumAlgorithm::Sum.matches(&frame));
+        assert!(!ChecksumAlgorithm::Xor.matches(&frame));
+
+        // Check values for "123456789" from the CRC catalogue.
+        assert_eq!(crc8(b"123456789", 0x07), 0xf4);
+        assert_eq!(crc8_reflected(b"123456789", 0x8c), 0xa1);
+    }
+
+    #[test]
+    fn test_analyze() {
+        let observations = [
+            obs(
+                0,
+                Direction::DeskToPanel,
+                DeskToPanelMessage::Height(100.0).as_frame(),
+            ),
+            obs(
+                10,
+                Direction::PanelToDesk,
+                PanelToDeskMessage::Up.as_frame(),
+            ),
+            obs(
+                20,
+                Direction::DeskToPanel,
+                DeskToPanelMessage::Height(100.5).as_frame(),
+            ),
+            obs(30, Direction::DeskToPanel, [104, 1, 9, 1, 2, 13, 22]),
+            obs(
+                40,
+                Direction::PanelToDesk,
+                PanelToDeskMessage::NoKey.as_frame(),
+            ),
+            obs(
+                5000,
+                Direction::DeskToPanel,
+                DeskToPanelMessage::Height(100.5).as_frame(),
+            ),
+            obs(5010, Direction::DeskToPanel, [104, 1, 9, 1, 3, 99, 22]),
+            obs(5020, Direction::PanelToDesk, [104, 1, 5, 0, 0, 6, 22]),
+        ];
+
+        let report = analyze(&observations, Duration::from_millis(100));
+        assert_eq!(report.total_frames, 8);
+        assert_eq!(report.unknown_frames, 3);
+        assert_eq!(report.clusters.len(), 2);
+
+        let cluster = &report.clusters[&(Direction::DeskToPanel, 9)];
+        assert_eq!(cluster.count, 2);
+        assert_eq!(cluster.first_seen, Duration::from_millis(30));
+        assert_eq!(cluster.last_seen, Duration::from_millis(5010));
+        assert_eq!(cluster.byte_values[2][&1], 2);
+        assert_eq!(cluster.byte_values[3].len(), 2);
+        assert_eq!(cluster.preceding_actions["up"], 1);
+        assert_eq!(cluster.while_moving, 1);
+        assert_eq!(cluster.while_stationary, 1);
+        assert_eq!(cluster.checksum_matches[&ChecksumAlgorithm::Sum], 1);
+
+        let cluster = &report.clusters[&(Direction::PanelToDesk, 5)];
+        assert_eq!(cluster.count, 1);
+        assert!(cluster.preceding_actions.is_empty());
+        assert_eq!(cluster.checksum_matches[&ChecksumAlgorithm::Sum], 1);
+    }
+
+    #[test]
+    fn test_analyze_out_of_order_timestamps() {
+        // Merged captures can step back in time: an Up recorded later comes after the frame.
+        let observations = [
+            obs(
+                500,
+                Direction::PanelToDesk,
+                PanelToDeskMessage::Up.as_frame(),
+            ),
+            obs(10, Direction::DeskToPanel, [104, 1, 9, 1, 2, 13, 22]),
+        ];
+
+        let report = analyze(&observations, Duration::from_millis(100));
+        let cluster = &report.clusters[&(Direction::DeskToPanel, 9)];
+        assert!(cluster.preceding_actions.is_empty());
+
+        // Listed out of order, but the Up does come first.
+        let observations = [
+            obs(550, Direction::DeskToPanel, [104, 1, 9, 1, 2, 13, 22]),
+            obs(
+                500,
+                Direction::PanelToDesk,
+                PanelToDeskMessage::Up.as_frame(),
+            ),
+        ];
+        let report = analyze(&observations, Duration::from_millis(100));
+        let cluster = &report.clusters[&(Direction::DeskToPanel, 9)];
+        assert_eq!(cluster.preceding_actions["up"], 1);
+    }
+}
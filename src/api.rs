@@ -0,0 +1,421 @@
+//! The daemon's HTTP/JSON API.
+//!
+//! | method | path                  | body                 |                                    |
+//! |--------|-----------------------|----------------------|------------------------------------|
+//! | GET    | `/state`              |                      | everything the daemon knows        |
+//! | GET    | `/height`             |                      | the current height                 |
+//! | POST   | `/move`               | `{"height_cm": 110}` | move to a height                   |
+//! | POST   | `/stop`               |                      | stop a move                        |
+//! | GET    | `/presets`            |                      | the three preset heights           |
+//! | POST   | `/presets/<n>`        | `{"height_cm": 110}` | program a preset                   |
+//! | POST   | `/presets/<n>/recall` |                      | move to a preset                   |
+//! | GET    | `/profiles`           |                      | every user profile, and the active one |
+//! | GET    | `/profiles/<name>`    |                      | export a profile                   |
+//! | PUT    | `/profiles/<name>`    | a profile            | import or update a profile         |
+//! | DELETE | `/profiles/<name>`    |                      | remove a profile                   |
+//! | POST   | `/profiles/<name>/activate` |                | switch to a profile                |
+//! | POST   | `/recommend`          | body measurements, see below | recommend sitting and standing heights |
+//! | GET    | `/events`             |                      | server-sent events, see [`Event`]  |
+//! | GET    | `/metrics`            |                      | Prometheus metrics                 |
+//!
+//! `/recommend` takes `{"body_height_cm": 175}`, optionally with `elbow_cm`, `chair_cm` and
+//! `tray_cm` (see [`Body`]). With `"program": true` the heights become presets 1 and 2, and with
+//! `"profile": "<name>"` they are saved to that user profile.
+//!
+//! [`Event`]: crate::daemon::Event
+//! [`Body`]: crate::ergonomics::Body
+
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+
+use crate::daemon::Handle;
+use crate::ergonomics::{self, Body};
+use crate::http::{self, EventStream, Request, Response};
+use crate::json::Value;
+use crate::profile::Profile;
+
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Serves the API for `handle` forever.
+pub fn serve(listener: TcpListener, handle: Handle) -> io::Result<()> {
+    http::serve(listener, move |request, stream| {
+        if request.method == "GET" && request.path == "/events" {
+            return stream_events(&handle, stream);
+        }
+        respond(&handle, request).write_to(stream)
+    })
+}
+
+/// Answers any request apart from the event stream.
+pub fn respond(handle: &Handle, request: &Request) -> Response {
+    let Some(segments) = request.segments() else {
+        return Response::error(400, "badly encoded path");
+    };
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let method = request.method.as_str();
+
+    match (method, segments.as_slice()) {
+        ("GET", ["state"]) => Response::json(200, &handle.state().to_json()),
+        ("GET", ["height"]) => Response::json(
+            200,
+            &Value::object([("height_cm", handle.state().height_cm.into())]),
+        ),
+        ("POST", ["move"]) => match height_from(request) {
+            Ok(height) => match handle.move_to(height) {
+                Ok(target) => Response::json(202, &Value::object([("target_cm", target.into())])),
+                Err(e) => Response::error(400, &e),
+            },
+            Err(response) => response,
+        },
+        ("POST", ["stop"]) => {
+            handle.stop();
+            Response::json(202, &Value::object::<&str, _>([]))
+        }
+        ("GET", ["metrics"]) => Response::text(
+            200,
+            "text/plain; version=0.0.4",
+            handle.metrics().to_string(),
+        ),
+        ("GET", ["presets"]) => Response::json(
+            200,
+            &Value::object([("presets", handle.state().presets.to_vec().into())]),
+        ),
+        ("POST", ["presets", n]) => {
+            let height = match height_from(request) {
+                Ok(height) => height,
+                Err(response) => return response,
+            };
+            let programmed = preset_number(n)
+                .and_then(|n| handle.program_preset(n, height).map(|height| (n, height)));
+            match programmed {
+                Ok((n, height)) => Response::json(
+                    200,
+                    &Value::object([("preset", n.into()), ("height_cm", height.into())]),
+                ),
+                Err(e) => Response::error(404, &e),
+            }
+        }
+        ("POST", ["presets", n, "recall"]) => {
+            match preset_number(n).and_then(|n| handle.recall_preset(n)) {
+                Ok(target) => Response::json(202, &Value::object([("target_cm", target.into())])),
+                Err(e) => Response::error(409, &e),
+            }
+        }
+        ("GET", ["profiles"]) => {
+            let profiles: Vec<Value> = handle.profiles().iter().map(Profile::to_json).collect();
+            let active = handle.active_profile().map(|p| p.name);
+            Response::json(
+                200,
+                &Value::object([("profiles", profiles.into()), ("active", active.into())]),
+            )
+        }
+        ("GET", ["profiles", name]) => match handle.profile(name) {
+            Some(profile) => Response::json(200, &profile.to_json()),
+            None => Response::error(404, &format!("there is no profile {:?}", name)),
+        },
+        ("PUT", ["profiles", name]) => {
+            let profile = match request.json() {
+                // The name in the path wins over any in the body, so that profiles exported
+                // from one name can be imported under another.
+                Ok(Value::Object(mut fields)) => {
+                    fields.retain(|(k, _)| k != "name");
+                    fields.push(("name".to_string(), (*name).into()));
+                    Profile::from_json(&Value::Object(fields))
+                }
+                Ok(_) => Err("expected a profile object".to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            match profile.map(|p| handle.save_profile(p)) {
+                Ok(Ok(profile)) => Response::json(200, &profile.to_json()),
+                Ok(Err(e)) => Response::error(500, &format!("saving profiles: {}", e)),
+                Err(e) => Response::error(400, &e),
+            }
+        }
+        ("DELETE", ["profiles", name]) => match handle.delete_profile(name) {
+            Ok(true) => Response::json(200, &Value::object::<&str, _>([])),
+            Ok(false) => Response::error(404, &format!("there is no profile {:?}", name)),
+            Err(e) => Response::error(500, &format!("saving profiles: {}", e)),
+        },
+        ("POST", ["profiles", name, "activate"]) => match handle.activate_profile(name) {
+            Ok(profile) => Response::json(200, &profile.to_json()),
+            Err(e) => Response::error(404, &e),
+        },
+        ("POST", ["recommend"]) => match recommendation(handle, request) {
+            Ok(value) => Response::json(200, &value),
+            Err(response) => response,
+        },
+        (
+            _,
+            ["state" | "height" | "move" | "stop" | "presets" | "events" | "metrics" | "recommend"],
+        )
+        | (_, ["presets", _] | ["presets", _, "recall"])
+        | (_, ["profiles"] | ["profiles", _] | ["profiles", _, "activate"]) => {
+            Response::error(405, "method not allowed")
+        }
+        _ => Response::error(404, "not found"),
+    }
+}
+
+fn height_from(request: &Request) -> Result<f32, Response> {
+    let body = request
+        .json()
+        .map_err(|e| Response::error(400, &e.to_string()))?;
+    body.get("height_cm")
+        .and_then(Value::as_f32)
+        .filter(|h| h.is_finite())
+        .ok_or_else(|| Response::error(400, "expected {\"height_cm\": <number>}"))
+}
+
+fn recommendation(handle: &Handle, request: &Request) -> Result<Value, Response> {
+    let body = request
+        .json()
+        .map_err(|e| Response::error(400, &e.to_string()))?;
+    let measurement = |key: &str| match body.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => value
+            .as_f32()
+            .filter(|h| h.is_finite() && *h >= 0.0)
+            .map(Some)
+            .ok_or_else(|| Response::error(400, &format!("expected a number for {}", key))),
+    };
+    let stature = measurement("body_height_cm")?
+        .ok_or_else(|| Response::error(400, "expected {\"body_height_cm\": <number>}"))?;
+    let measurements = Body {
+        elbow_cm: measurement("elbow_cm")?,
+        chair_cm: measurement("chair_cm")?,
+        tray_cm: measurement("tray_cm")?.unwrap_or(0.0),
+        ..Body::new(stature)
+    };
+    let recommended = ergonomics::recommend(&measurements, &handle.state().profile);
+
+    if let Some(name) = body.get("profile").filter(|v| !v.is_null()) {
+        let name = name
+            .as_str()
+            .ok_or_else(|| Response::error(400, "expected a string for profile"))?;
+        crate::profile::check_name(name).map_err(|e| Response::error(400, &e))?;
+        let mut profile = handle
+            .profile(name)
+            .unwrap_or_else(|| Profile::new(name, 0.0, 0.0));
+        profile.set_heights(recommended.sitting_cm, recommended.standing_cm);
+        handle
+            .save_profile(profile)
+            .map_err(|e| Response::error(500, &format!("saving profiles: {}", e)))?;
+    }
+    if body.get("program").and_then(Value::as_bool) == Some(true) {
+        // Both heights are already within the desk's range.
+        let _ = handle.program_preset(1, recommended.sitting_cm);
+        let _ = handle.program_preset(2, recommended.standing_cm);
+    }
+
+    Ok(Value::object([
+        ("sitting_cm", recommended.sitting_cm.into()),
+        ("standing_cm", recommended.standing_cm.into()),
+        ("clamped", recommended.clamped.into()),
+    ]))
+}
+
+fn preset_number(n: &str) -> Result<usize, String> {
+    n.parse().map_err(|_| format!("there is no preset {}", n))
+}
+
+fn stream_events(handle: &Handle, stream: &mut TcpStream) -> io::Result<()> {
+    let events = handle.subscribe();
+    let mut out = EventStream::start(stream)?;
+    out.send("state", &handle.state().to_json().to_string())?;
+    loop {
+        match events.recv_timeout(KEEP_ALIVE_INTERVAL) {
+            Ok(event) => out.send(event.name(), &event.to_json().to_string())?,
+            Err(RecvTimeoutError::Timeout) => out.keep_alive()?,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::{Daemon, DaemonOptions};
+    use crate::link::{ManualClock, SimulatedDesk};
+    use crate::sim::DeskSimulator;
+    use crate::DeskProfile;
+
+    fn request(method: &str, path: &str, body: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: None,
+            headers: Vec::new(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    fn daemon() -> (Daemon<ManualClock>, Handle) {
+        let clock = ManualClock::new();
+        let desk = SimulatedDesk::new(
+            DeskSimulator::new(DeskProfile::default(), 90.0),
+            clock.clone(),
+        );
+        let mut daemon = Daemon::new(
+            Box::new(desk),
+            None,
+            clock.clone(),
+            DaemonOptions::default(),
+        );
+        daemon.step().unwrap();
+        let handle = daemon.handle();
+        (daemon, handle)
+    }
+
+    fn call(handle: &Handle, method: &str, path: &str, body: &str) -> (u16, String) {
+        let response = respond(handle, &request(method, path, body));
+        (response.status, String::from_utf8(response.body).unwrap())
+    }
+
+    #[test]
+    fn test_height() {
+        let (_daemon, handle) = daemon();
+        assert_eq!(
+            call(&handle, "GET", "/height", ""),
+            (200, r#"{"height_cm":90}"#.into())
+        );
+    }
+
+    #[test]
+    fn test_move() {
+        let (mut daemon, handle) = daemon();
+        assert_eq!(
+            call(&handle, "POST", "/move", r#"{"height_cm": 100.5}"#),
+            (202, r#"{"target_cm":100.5}"#.into())
+        );
+        assert_eq!(call(&handle, "POST", "/move", "{}").0, 400);
+        assert_eq!(call(&handle, "POST", "/move", "{").0, 400);
+        daemon.step().unwrap();
+        let state = Value::parse(&call(&handle, "GET", "/state", "").1).unwrap();
+        assert_eq!(state.get("target_cm"), Some(&Value::Number(100.5)));
+        assert_eq!(
+            state.get("mode").and_then(Value::as_str),
+            Some("panel-replacement")
+        );
+
+        assert_eq!(call(&handle, "POST", "/stop", ""), (202, "{}".into()));
+        daemon.step().unwrap();
+        let state = Value::parse(&call(&handle, "GET", "/state", "").1).unwrap();
+        assert_eq!(state.get("target_cm"), Some(&Value::Null));
+    }
+
+    #[test]
+    fn test_presets() {
+        let (mut daemon, handle) = daemon();
+        assert_eq!(call(&handle, "POST", "/presets/1/recall", "").0, 409);
+        assert_eq!(
+            call(&handle, "POST", "/presets/1", r#"{"height_cm": 72}"#),
+            (200, r#"{"preset":1,"height_cm":72}"#.into())
+        );
+        assert_eq!(
+            call(&handle, "POST", "/presets/9", r#"{"height_cm": 72}"#).0,
+            404
+        );
+        daemon.step().unwrap();
+        assert_eq!(
+            call(&handle, "GET", "/presets", ""),
+            (200, r#"{"presets":[72,null,null]}"#.into())
+        );
+        assert_eq!(
+            call(&handle, "POST", "/presets/1/recall", ""),
+            (202, r#"{"target_cm":72}"#.into())
+        );
+    }
+
+    #[test]
+    fn test_metrics() {
+        let (_daemon, handle) = daemon();
+        let (status, metrics) = call(&handle, "GET", "/metrics", "");
+        assert_eq!(status, 200);
+        assert!(metrics.contains("\nvaridesk_height_cm 90\n"));
+        assert!(metrics.contains("\nvaridesk_frames_total{direction=\"desk_to_panel\"} 1\n"));
+    }
+
+    #[test]
+    fn test_profiles() {
+        let (mut daemon, handle) = daemon();
+        let alex = r#"{"name":"alex","sitting_cm":70,"standing_cm":140,"presets":[70,null,null],"schedule":null}"#;
+        assert_eq!(
+            call(&handle, "PUT", "/profiles/alex", alex),
+            (
+                200,
+                alex.replace("\"standing_cm\":140", "\"standing_cm\":129.5")
+            )
+        );
+        assert_eq!(call(&handle, "PUT", "/profiles/a b", alex).0, 400);
+        assert_eq!(call(&handle, "PUT", "/profiles/a%20b", alex).0, 400);
+        assert_eq!(call(&handle, "GET", "/profiles/%61lex", "").0, 200);
+        assert_eq!(call(&handle, "GET", "/profiles/%zz", "").0, 400);
+        assert_eq!(call(&handle, "POST", "/profiles/sam/activate", "").0, 404);
+        assert_eq!(call(&handle, "POST", "/profiles/alex/activate", "").0, 200);
+        daemon.step().unwrap();
+        assert_eq!(
+            call(&handle, "GET", "/profiles", "").1,
+            format!(
+                r#"{{"profiles":[{}],"active":"alex"}}"#,
+                call(&handle, "GET", "/profiles/alex", "").1
+            )
+        );
+        let state = Value::parse(&call(&handle, "GET", "/state", "").1).unwrap();
+        assert_eq!(state.get("user").and_then(Value::as_str), Some("alex"));
+        assert_eq!(
+            call(&handle, "GET", "/presets", ""),
+            (200, r#"{"presets":[70,null,null]}"#.into())
+        );
+        assert_eq!(call(&handle, "DELETE", "/profiles/alex", "").0, 200);
+        assert_eq!(call(&handle, "GET", "/profiles/alex", "").0, 404);
+    }
+
+    #[test]
+    fn test_recommend() {
+        let (mut daemon, handle) = daemon();
+        assert_eq!(
+            call(
+                &handle,
+                "POST",
+                "/recommend",
+                r#"{"body_height_cm": 175, "program": true}"#
+            ),
+            (
+                200,
+                r#"{"sitting_cm":70,"standing_cm":108.5,"clamped":false}"#.into()
+            )
+        );
+        assert_eq!(
+            call(&handle, "POST", "/recommend", r#"{"elbow_cm": 110}"#).0,
+            400
+        );
+        daemon.step().unwrap();
+        assert_eq!(
+            call(&handle, "GET", "/presets", ""),
+            (200, r#"{"presets":[70,108.5,null]}"#.into())
+        );
+        assert_eq!(
+            call(
+                &handle,
+                "POST",
+                "/recommend",
+                r#"{"body_height_cm": 150, "profile": "kim"}"#
+            )
+            .1,
+            r#"{"sitting_cm":65,"standing_cm":93,"clamped":true}"#
+        );
+        assert_eq!(
+            handle.profile("kim").map(|p| p.presets),
+            Some([Some(65.0), Some(93.0), None])
+        );
+    }
+
+    #[test]
+    fn test_unknown_routes() {
+        let (_daemon, handle) = daemon();
+        assert_eq!(call(&handle, "DELETE", "/state", "").0, 405);
+        assert_eq!(call(&handle, "GET", "/nothing", "").0, 404);
+    }
+}
@@ -0,0 +1,122 @@
+use std::error::Error;
+use std::str::FromStr;
+
+/// Command-line arguments split into positionals and `--name value` / `--name=value` options.
+#[derive(Debug, Default)]
+pub struct Args {
+    // Each argument keeps its original position, so that a positional swallowed by a flag can be
+    // put back in the right place.
+    positional: Vec<(usize, String)>,
+    options: Vec<(String, Option<(usize, String)>)>,
+}
+
+impl Args {
+    pub fn new<I: Iterator<Item = String>>(args: I) -> Args {
+        let mut parsed = Args::default();
+        let mut args = args.enumerate().peekable();
+
+        while let Some((i, arg)) = args.next() {
+            let name = match arg.strip_prefix("--") {
+                Some(name) => name,
+                None => {
+                    parsed.positional.push((i, arg));
+                    continue;
+                }
+            };
+
+            match name.split_once('=') {
+                Some((name, value)) => parsed
+                    .options
+                    .push((name.to_string(), Some((i, value.to_string())))),
+                None => {
+                    let value = match args.peek() {
+                        Some((_, next)) if !next.starts_with("--") => args.next(),
+                        _ => None,
+                    };
+                    parsed.options.push((name.to_string(), value));
+                }
+            }
+        }
+
+        parsed
+    }
+
+    pub fn next_positional(&mut self) -> Option<String> {
+        if self.positional.is_empty() {
+            return None;
+        }
+        Some(self.positional.remove(0).1)
+    }
+
+    pub fn rest(&mut self) -> Vec<String> {
+        self.positional.drain(..).map(|(_, arg)| arg).collect()
+    }
+
+    /// Whether a valueless `--name` was given. A flag that swallowed a following positional is
+    /// given the positional back.
+    pub fn flag(&mut self, name: &str) -> bool {
+        match self.options.iter().position(|(n, _)| n == name) {
+            Some(i) => {
+                if let (_, Some(value)) = self.options.remove(i) {
+                    let at = self.positional.partition_point(|(j, _)| *j < value.0);
+                    self.positional.insert(at, value);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn option(&mut self, name: &str) -> Option<String> {
+        let i = self.options.iter().position(|(n, _)| n == name)?;
+        self.options.remove(i).1.map(|(_, value)| value)
+    }
+
+    pub fn parsed_option<T>(&mut self, name: &str) -> Result<Option<T>, Box<dyn Error>>
+    where
+        T: FromStr,
+        T::Err: Error + 'static,
+    {
+        match self.option(name) {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|e| format!("--{} {:?}: {}", name, value, e).into()),
+            None => Ok(None),
+        }
+    }
+
+    /// Fails if any options were given that nothing asked for.
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self.options.first() {
+            Some((name, _)) => Err(format!("unexpected option --{}", name).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Args {
+        Args::new(s.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn test_args() {
+        let mut args = parse("decode --dir panel 68 --baud=9600 01 --inverted");
+        assert_eq!(args.next_positional().as_deref(), Some("decode"));
+        assert_eq!(args.option("dir").as_deref(), Some("panel"));
+        assert_eq!(args.parsed_option::<u32>("baud").unwrap(), Some(9600));
+        assert!(args.flag("inverted"));
+        assert!(!args.flag("missing"));
+        assert_eq!(args.rest(), ["68", "01"]);
+        assert!(args.finish().is_ok());
+
+        let mut args = parse("a --verbose b c --what");
+        assert!(args.flag("verbose"));
+        assert_eq!(args.rest(), ["a", "b", "c"]);
+        assert!(args.finish().is_err());
+    }
+}
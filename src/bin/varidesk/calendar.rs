@@ -0,0 +1,83 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{Local, NaiveDate};
+use vari_desk_2020::calendar::{Calendar, CalendarMover, CalendarOptions, Rules, Step};
+
+use crate::args::Args;
+use crate::daemon::Heights;
+use crate::CommandResult;
+
+pub fn calendar(mut args: Args) -> CommandResult {
+    let path = args
+        .next_positional()
+        .ok_or("expected an .ics file or a directory of them")?;
+    let rules = args.option("rules").ok_or("expected --rules <file>")?;
+    let date: Option<NaiveDate> = args.parsed_option("date")?;
+    let heights = Heights {
+        sitting_cm: args.parsed_option("sit-height")?,
+        standing_cm: args.parsed_option("stand-height")?,
+    };
+    args.finish()?;
+
+    let mover = CalendarMover::new(
+        read(Path::new(&path))?,
+        read_rules(&rules)?,
+        options(heights),
+    );
+    let date = date.unwrap_or_else(|| Local::now().date_naive());
+    let plan = mover.plan_day(&Local, date);
+    if plan.is_empty() {
+        println!("no moves planned for {}", date);
+    }
+    for planned in plan {
+        let action = match planned.step {
+            Step::Move { height_cm } => format!("move to {:.1}cm", height_cm),
+            Step::Return => "move back".to_string(),
+        };
+        let when = match planned.step {
+            Step::Move { .. } => "start of",
+            Step::Return => "end of",
+        };
+        println!(
+            "{}  {:<16}  {} {}",
+            planned.at.format("%Y-%m-%d %H:%M"),
+            action,
+            when,
+            planned.summary
+        );
+    }
+    Ok(())
+}
+
+fn read(path: &Path) -> Result<Calendar, Box<dyn Error>> {
+    Ok(Calendar::read(path).map_err(|e| format!("reading {}: {}", path.display(), e))?)
+}
+
+fn read_rules(path: &str) -> Result<Rules, Box<dyn Error>> {
+    let text = fs::read_to_string(path).map_err(|e| format!("reading {}: {}", path, e))?;
+    Ok(Rules::parse(&text).map_err(|e| format!("{}: {}", path, e))?)
+}
+
+fn options(heights: Heights) -> CalendarOptions {
+    let defaults = CalendarOptions::default();
+    CalendarOptions {
+        sitting_cm: heights.sitting_cm.unwrap_or(defaults.sitting_cm),
+        standing_cm: heights.standing_cm.unwrap_or(defaults.standing_cm),
+    }
+}
+
+/// Reads the daemon's `--calendar` options.
+pub fn mover(
+    args: &mut Args,
+    heights: Heights,
+) -> Result<Option<(PathBuf, CalendarMover)>, Box<dyn Error>> {
+    let (path, rules) = match (args.option("calendar"), args.option("calendar-rules")) {
+        (Some(path), Some(rules)) => (PathBuf::from(path), rules),
+        (None, None) => return Ok(None),
+        _ => return Err("--calendar and --calendar-rules go together".into()),
+    };
+    let mover = CalendarMover::new(read(&path)?, read_rules(&rules)?, options(heights));
+    Ok(Some((path, mover)))
+}
@@ -0,0 +1,438 @@
+use std::error::Error;
+
+use vari_desk_2020::decoder::{Decoded, FrameDecoder};
+use vari_desk_2020::{
+    validate_checksum, DataFrame, DeskToPanelMessage, Direction, PanelToDeskMessage,
+    DATA_FRAME_END_BYTE, DATA_FRAME_SIZE, DATA_FRAME_START_BYTE,
+};
+
+use crate::args::Args;
+use crate::input;
+use crate::CommandResult;
+
+pub fn decode(mut args: Args) -> CommandResult {
+    if let Some(path) = args.option("file") {
+        let records = input::read_records(&path, &mut args)?;
+        args.finish()?;
+        for record in &records {
+            println!("{}", input::format_record(record));
+        }
+        return Ok(());
+    }
+
+    let direction = match args.option("dir") {
+        Some(dir) => Some(parse_direction(&dir)?),
+        None => None,
+    };
+    let bytes = parse_hex(&args.rest().join(" "))?;
+    args.finish()?;
+
+    let mut decoder = FrameDecoder::new();
+    for b in bytes {
+        match decoder.push(b) {
+            Some(Decoded::Frame(frame)) => {
+                let checksum = if validate_checksum(&frame) {
+                    ""
+                } else {
+                    " (bad checksum)"
+                };
+                match direction {
+                    Some(direction) => {
+                        println!(
+                            "{}: {}{}",
+                            hex(&frame),
+                            describe(direction, &frame),
+                            checksum
+                        )
+                    }
+                    None => println!(
+                        "{}: as panel->desk: {}; as desk->panel: {}{}",
+                        hex(&frame),
+                        PanelToDeskMessage::from_frame(&frame),
+                        DeskToPanelMessage::from_frame(&frame),
+                        checksum
+                    ),
+                }
+            }
+            Some(Decoded::Invalid(frame)) => {
+                println!("{}: invalid frame (try `varidesk validate`)", hex(&frame))
+            }
+            Some(Decoded::Stray(b)) => println!("{:02x}: stray byte", b),
+            None => {}
+        }
+    }
+
+    if decoder.is_mid_frame() {
+        return Err("input ends part way through a frame".into());
+    }
+    Ok(())
+}
+
+pub fn encode(mut args: Args) -> CommandResult {
+    let words = args.rest();
+    args.finish()?;
+
+    let frame = match words.iter().map(|w| w.as_str()).collect::<Vec<_>>()[..] {
+        ["panel", message] => panel_message(message, None)?.as_frame(),
+        ["panel", message, height] => {
+            let height = encodable(parse_height(height)?, 0.0)?;
+            panel_message(message, Some(height))?.as_frame()
+        }
+        ["desk", "height", height] => {
+            let height = encodable(parse_height(height)?, DESK_HEIGHT_OFFSET_CM)?;
+            DeskToPanelMessage::Height(height).as_frame()
+        }
+        _ => {
+            return Err(
+                "expected `encode panel <message> [height]` or `encode desk height <height>`"
+                    .into(),
+            )
+        }
+    };
+
+    println!("{}", hex(&frame));
+    Ok(())
+}
+
+pub fn validate(mut args: Args) -> CommandResult {
+    let direction = match args.option("dir") {
+        Some(dir) => Some(parse_direction(&dir)?),
+        None => None,
+    };
+    let frames = args.rest();
+    args.finish()?;
+    if frames.is_empty() {
+        return Err("expected one or more hex frames".into());
+    }
+
+    let mut failed = 0;
+    for frame in &frames {
+        let bytes = parse_hex(frame)?;
+        let problems = problems(&bytes, direction);
+        if problems.is_empty() {
+            println!("{}: ok", hex(&bytes));
+        } else {
+            failed += 1;
+            println!("{}:", hex(&bytes));
+            for problem in problems {
+                println!("  {}", problem);
+            }
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        n => Err(format!("{} of {} frames failed validation", n, frames.len()).into()),
+    }
+}
+
+pub fn tables(args: Args) -> CommandResult {
+    args.finish()?;
+
+    println!("frame layout ({} bytes):", DATA_FRAME_SIZE);
+    println!("  0    start byte, always {:#04x}", DATA_FRAME_START_BYTE);
+    println!("  1    always 0x01 in known frames");
+    println!("  2    command");
+    println!("  3-4  payload");
+    println!("  5    checksum: sum of bytes 1 to 4, modulo 256");
+    println!("  6    end byte, always {:#04x}", DATA_FRAME_END_BYTE);
+    println!();
+
+    println!("panel -> desk:");
+    println!("  cmd  message      payload                          example");
+    let panel = [
+        ("up", "-", PanelToDeskMessage::Up),
+        ("down", "-", PanelToDeskMessage::Down),
+        ("no-key", "-", PanelToDeskMessage::NoKey),
+        ("desk-reset", "-", PanelToDeskMessage::DeskReset),
+        (
+            "one",
+            "target, little-endian mm",
+            PanelToDeskMessage::One(100.0),
+        ),
+        (
+            "two",
+            "target, little-endian mm",
+            PanelToDeskMessage::Two(100.0),
+        ),
+        (
+            "three",
+            "target, little-endian mm",
+            PanelToDeskMessage::Three(100.0),
+        ),
+        ("reset-one", "-", PanelToDeskMessage::ResetOne),
+        ("reset-two", "-", PanelToDeskMessage::ResetTwo),
+        ("reset-three", "-", PanelToDeskMessage::ResetThree),
+    ];
+    for (name, payload, message) in panel {
+        let frame = message.as_frame();
+        println!(
+            "  {:<4} {:<12} {:<32} {}",
+            frame[2],
+            name,
+            payload,
+            hex(&frame)
+        );
+    }
+    println!();
+
+    println!("desk -> panel:");
+    println!("  cmd  message      payload                          example");
+    let frame = DeskToPanelMessage::Height(100.0).as_frame();
+    println!(
+        "  {:<4} {:<12} {:<32} {}",
+        frame[2],
+        "height",
+        "big-endian mm above 650mm",
+        hex(&frame)
+    );
+
+    Ok(())
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+pub fn describe(direction: Direction, frame: &DataFrame) -> String {
+    match direction {
+        Direction::PanelToDesk => PanelToDeskMessage::from_frame(frame).to_string(),
+        Direction::DeskToPanel => DeskToPanelMessage::from_frame(frame).to_string(),
+    }
+}
+
+/// Accepts bytes in hex with or without separators, such as `68 01 01`, `68:01:01`, `0x68,0x01`
+/// or `680101`.
+pub fn parse_hex(s: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let digits: String = s
+        .split(|c: char| c.is_whitespace() || c == ',' || c == ':')
+        .map(|part| part.trim_start_matches("0x").trim_start_matches("0X"))
+        .flat_map(|part| {
+            // A lone digit between separators is a byte with its leading zero left off.
+            if part.len() == 1 {
+                format!("0{}", part).chars().collect::<Vec<char>>()
+            } else {
+                part.chars().collect()
+            }
+        })
+        .collect();
+
+    if !digits.is_ascii() {
+        return Err(format!("{:?} is not hex", s).into());
+    }
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("odd number of hex digits in {:?}", s).into());
+    }
+
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| format!("{:?} is not a hex byte", &digits[i..i + 2]).into())
+        })
+        .collect()
+}
+
+/// Accepts heights such as `100`, `100cm`, `1000mm` or `39.5in`, and returns centimetres.
+pub fn parse_height(s: &str) -> Result<f32, Box<dyn Error>> {
+    let (number, scale) = if let Some(n) = s.strip_suffix("cm") {
+        (n, 1.0)
+    } else if let Some(n) = s.strip_suffix("mm") {
+        (n, 0.1)
+    } else if let Some(n) = s.strip_suffix("in") {
+        (n, 2.54)
+    } else {
+        (s, 1.0)
+    };
+
+    let height: f32 = number
+        .trim()
+        .parse()
+        .ok()
+        .filter(|h: &f32| h.is_finite())
+        .ok_or_else(|| format!("{:?} is not a height", s))?;
+    Ok(height * scale)
+}
+
+/// Desk-to-panel frames carry heights as tenths above this.
+const DESK_HEIGHT_OFFSET_CM: f32 = 65.0;
+
+/// Checks a height fits in a frame, which carries it as 16 bits of tenths above `offset_cm`.
+fn encodable(height_cm: f32, offset_cm: f32) -> Result<f32, Box<dyn Error>> {
+    let max_cm = offset_cm + u16::MAX as f32 / 10.0;
+    if !(offset_cm..=max_cm).contains(&height_cm) {
+        return Err(format!(
+            "{}cm can't be encoded, frames carry {:.1}cm to {:.1}cm",
+            height_cm, offset_cm, max_cm
+        )
+        .into());
+    }
+    Ok(height_cm)
+}
+
+pub fn parse_direction(s: &str) -> Result<Direction, Box<dyn Error>> {
+    match s {
+        "panel" | "panel->desk" => Ok(Direction::PanelToDesk),
+        "desk" | "desk->panel" => Ok(Direction::DeskToPanel),
+        _ => Err(format!("{:?} is not a direction; expected panel or desk", s).into()),
+    }
+}
+
+fn panel_message(name: &str, height: Option<f32>) -> Result<PanelToDeskMessage, Box<dyn Error>> {
+    let message = match (name, height) {
+        ("up", None) => PanelToDeskMessage::Up,
+        ("down", None) => PanelToDeskMessage::Down,
+        ("no-key", None) => PanelToDeskMessage::NoKey,
+        ("desk-reset", None) => PanelToDeskMessage::DeskReset,
+        ("one", Some(h)) => PanelToDeskMessage::One(h),
+        ("two", Some(h)) => PanelToDeskMessage::Two(h),
+        ("three", Some(h)) => PanelToDeskMessage::Three(h),
+        ("reset-one", None) => PanelToDeskMessage::ResetOne,
+        ("reset-two", None) => PanelToDeskMessage::ResetTwo,
+        ("reset-three", None) => PanelToDeskMessage::ResetThree,
+        ("one", None) | ("two", None) | ("three", None) => {
+            return Err(format!("preset {} needs a target height", name).into())
+        }
+        (_, Some(_)) => return Err(format!("{} does not take a height", name).into()),
+        (_, None) => return Err(format!("unknown panel message {:?}", name).into()),
+    };
+    Ok(message)
+}
+
+fn problems(bytes: &[u8], direction: Option<Direction>) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let frame: DataFrame = match bytes.try_into() {
+        Ok(frame) => frame,
+        Err(_) => {
+            problems.push(format!(
+                "frames are {} bytes long, not {}",
+                DATA_FRAME_SIZE,
+                bytes.len()
+            ));
+            return problems;
+        }
+    };
+
+    if frame[0] != DATA_FRAME_START_BYTE {
+        problems.push(format!(
+            "starts with {:#04x} instead of the start byte {:#04x}",
+            frame[0], DATA_FRAME_START_BYTE
+        ));
+    }
+    if frame[DATA_FRAME_SIZE - 1] != DATA_FRAME_END_BYTE {
+        problems.push(format!(
+            "ends with {:#04x} instead of the end byte {:#04x}",
+            frame[DATA_FRAME_SIZE - 1],
+            DATA_FRAME_END_BYTE
+        ));
+    }
+    if !validate_checksum(&frame) {
+        let expected = frame[1..5].iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+        problems.push(format!(
+            "checksum is {:#04x} but bytes 1 to 4 add up to {:#04x}",
+            frame[5], expected
+        ));
+    }
+    if frame[1] != 1u8 {
+        problems.push(format!(
+            "byte 1 is {:#04x}; every known frame has 0x01",
+            frame[1]
+        ));
+    }
+
+    let unknown_to_panel = matches!(
+        PanelToDeskMessage::from_frame(&frame),
+        PanelToDeskMessage::Unknown(..)
+    );
+    let unknown_to_desk = matches!(
+        DeskToPanelMessage::from_frame(&frame),
+        DeskToPanelMessage::Unknown(..)
+    );
+    let unknown = match direction {
+        Some(Direction::PanelToDesk) => unknown_to_panel,
+        Some(Direction::DeskToPanel) => unknown_to_desk,
+        None => unknown_to_panel && unknown_to_desk,
+    };
+    if unknown {
+        problems.push(format!("command {} is not a known message", frame[2]));
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hex() {
+        let up = PanelToDeskMessage::Up.as_frame().to_vec();
+        assert_eq!(parse_hex("68 01 01 00 00 02 16").unwrap(), up);
+        assert_eq!(parse_hex("68010100000216").unwrap(), up);
+        assert_eq!(parse_hex("0x68,0x1,0x1,0,0,2,0x16").unwrap(), up);
+        assert_eq!(parse_hex("68:01:01:00:00:02:16").unwrap(), up);
+        assert!(parse_hex("680").is_err());
+        assert!(parse_hex("6g").is_err());
+    }
+
+    #[test]
+    fn test_parse_height() {
+        assert_eq!(parse_height("100").unwrap(), 100.0);
+        assert_eq!(parse_height("100cm").unwrap(), 100.0);
+        assert_eq!(parse_height("1000mm").unwrap(), 100.0);
+        assert_eq!(parse_height("10in").unwrap(), 25.4);
+        assert!(parse_height("tall").is_err());
+        assert!(parse_height("inf").is_err());
+        assert!(parse_height("NaNcm").is_err());
+    }
+
+    #[test]
+    fn test_encodable() {
+        assert_eq!(encodable(65.0, DESK_HEIGHT_OFFSET_CM).unwrap(), 65.0);
+        assert_eq!(encodable(6618.5, DESK_HEIGHT_OFFSET_CM).unwrap(), 6618.5);
+        assert!(encodable(64.9, DESK_HEIGHT_OFFSET_CM).is_err());
+        assert!(encodable(6618.6, DESK_HEIGHT_OFFSET_CM).is_err());
+        assert_eq!(encodable(0.0, 0.0).unwrap(), 0.0);
+        assert!(encodable(-1.0, 0.0).is_err());
+    }
+
+    #[test]
+    fn test_panel_message() {
+        assert_eq!(
+            panel_message("one", Some(100.0)).unwrap(),
+            PanelToDeskMessage::One(100.0)
+        );
+        assert_eq!(
+            panel_message("no-key", None).unwrap(),
+            PanelToDeskMessage::NoKey
+        );
+        assert!(panel_message("one", None).is_err());
+        assert!(panel_message("up", Some(100.0)).is_err());
+        assert!(panel_message("sideways", None).is_err());
+    }
+
+    #[test]
+    fn test_problems() {
+        assert!(problems(&PanelToDeskMessage::Up.as_frame(), None).is_empty());
+        assert_eq!(
+            problems(&[0x68, 1, 1, 0, 0, 3, 0x16], None),
+            ["checksum is 0x03 but bytes 1 to 4 add up to 0x02"]
+        );
+        assert_eq!(
+            problems(&[0x68, 1, 0, 0], None),
+            ["frames are 7 bytes long, not 4"]
+        );
+        assert_eq!(
+            problems(
+                &DeskToPanelMessage::Height(100.0).as_frame(),
+                Some(Direction::PanelToDesk)
+            ),
+            ["command 0 is not a known message"]
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use std::error::Error;
+use std::net::TcpListener;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::Duration;
+
+use vari_desk_2020::api;
+use vari_desk_2020::daemon::{Daemon, DaemonOptions, Event};
+use vari_desk_2020::duty::DutyCycleOptions;
+use vari_desk_2020::fleet::{self, FleetAgent};
+use vari_desk_2020::health::HealthOptions;
+use vari_desk_2020::link::{Link, SimulatedDesk, SystemClock};
+use vari_desk_2020::mqtt::{self, MqttOptions};
+use vari_desk_2020::profile::ProfileStore;
+use vari_desk_2020::sim::DeskSimulator;
+use vari_desk_2020::uart::UartConfig;
+use vari_desk_2020::DeskProfile;
+
+use crate::args::Args;
+use crate::CommandResult;
+
+/// The `--sit-height` and `--stand-height` shared by schedules and calendars.
+#[cfg(any(feature = "schedule", feature = "calendar"))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Heights {
+    pub sitting_cm: Option<f32>,
+    pub standing_cm: Option<f32>,
+}
+
+/// Reads `--min-height` and `--max-height`, defaulting to the desk's full travel.
+pub fn desk_profile(args: &mut Args) -> Result<DeskProfile, Box<dyn Error>> {
+    let defaults = DeskProfile::default();
+    let profile = DeskProfile::new(
+        args.parsed_option("min-height")?
+            .unwrap_or(defaults.min_height_cm),
+        args.parsed_option("max-height")?
+            .unwrap_or(defaults.max_height_cm),
+    )?;
+    Ok(profile)
+}
+
+pub fn daemon(mut args: Args) -> CommandResult {
+    let desk = args.option("desk");
+    let panel = args.option("panel");
+    let simulate = args.flag("simulate");
+    let listen = args
+        .option("listen")
+        .unwrap_or_else(|| "127.0.0.1:8080".to_string());
+    let baud = args
+        .parsed_option("baud")?
+        .unwrap_or(UartConfig::default().baud);
+    let profile = desk_profile(&mut args)?;
+    let standing_threshold = args.parsed_option("standing-threshold")?;
+    let duty_cycle = args
+        .parsed_option::<DutyCycleOptions>("duty-cycle")?
+        .map(|d| DutyCycleOptions {
+            defer: args.flag("duty-defer"),
+            panel_overrides: args.flag("duty-panel-override"),
+            ..d
+        });
+    let mqtt = match args.option("mqtt") {
+        Some(broker) => {
+            let id = args.option("mqtt-id").unwrap_or_else(|| "desk".to_string());
+            let mut options = MqttOptions::new(&broker, &id);
+            options.username = args.option("mqtt-user");
+            options.password = args.option("mqtt-password");
+            if let Some(name) = args.option("mqtt-name") {
+                options.device_name = name;
+            }
+            if let Some(prefix) = args.option("discovery-prefix") {
+                options.discovery_prefix = Some(prefix);
+            }
+            if args.flag("no-discovery") {
+                options.discovery_prefix = None;
+            }
+            Some(options)
+        }
+        None => None,
+    };
+    let default_health = HealthOptions::default();
+    let millis = |ms: Option<u64>, default| ms.map_or(default, Duration::from_millis);
+    let health = HealthOptions {
+        panel_timeout: millis(
+            args.parsed_option("panel-timeout")?,
+            default_health.panel_timeout,
+        ),
+        desk_timeout: millis(
+            args.parsed_option("desk-timeout")?,
+            default_health.desk_timeout,
+        ),
+        noisy_errors: args
+            .parsed_option("noise-threshold")?
+            .unwrap_or(default_health.noisy_errors),
+        ..default_health
+    };
+    let history = args.option("history");
+    let profiles = args.option("profiles");
+    let fleet = match args.option("fleet") {
+        Some(server) => Some(FleetAgent::new(
+            &server,
+            crate::fleet::registration(&mut args)?,
+        )),
+        None => None,
+    };
+    #[cfg(any(feature = "schedule", feature = "calendar"))]
+    let heights = Heights {
+        sitting_cm: args.parsed_option("sit-height")?,
+        standing_cm: args.parsed_option("stand-height")?,
+    };
+    #[cfg(feature = "schedule")]
+    let scheduler = crate::schedule::scheduler(
+        &mut args,
+        heights,
+        standing_threshold,
+        profiles.is_some() || fleet.is_some(),
+    )?;
+    #[cfg(not(feature = "schedule"))]
+    if args.option("schedule").is_some() {
+        return Err("varidesk was built without the `schedule` feature".into());
+    }
+    #[cfg(feature = "calendar")]
+    let calendar = crate::calendar::mover(&mut args, heights)?;
+    #[cfg(not(feature = "calendar"))]
+    if args.option("calendar").is_some() {
+        return Err("varidesk was built without the `calendar` feature".into());
+    }
+    args.finish()?;
+
+    let clock = SystemClock::new();
+    let desk: Box<dyn Link> = match (desk, simulate) {
+        (Some(path), false) => open_link(&path, baud)?,
+        (None, true) => Box::new(SimulatedDesk::new(DeskSimulator::new(profile, 90.0), clock)),
+        _ => return Err("expected either --desk <port> or --simulate".into()),
+    };
+    let panel = match panel {
+        Some(path) => Some(open_link(&path, baud)?),
+        None => None,
+    };
+
+    let defaults = DaemonOptions::default();
+    let options = DaemonOptions {
+        profile,
+        standing_threshold_cm: standing_threshold.unwrap_or(defaults.standing_threshold_cm),
+        duty_cycle,
+        health,
+        ..defaults
+    };
+    #[cfg(feature = "history")]
+    let history = match history {
+        Some(path) => Some(crate::history::open(&path)?),
+        None => None,
+    };
+    #[cfg(not(feature = "history"))]
+    if history.is_some() {
+        return Err("varidesk was built without the `history` feature".into());
+    }
+
+    let mut daemon = Daemon::new(desk, panel, clock, options);
+    if let Some(path) = profiles {
+        let store = ProfileStore::open(&path).map_err(|e| format!("opening {}: {}", path, e))?;
+        daemon = daemon.with_profiles(store);
+    }
+    let listener = TcpListener::bind(&listen)?;
+    eprintln!(
+        "{} mode, listening on http://{}",
+        daemon.handle().state().mode.name(),
+        listener.local_addr()?
+    );
+
+    let events = daemon.handle().subscribe();
+    thread::spawn(move || print_notices(events));
+    let handle = daemon.handle();
+    thread::spawn(move || api::serve(listener, handle));
+    if let Some(options) = mqtt {
+        let handle = daemon.handle();
+        thread::spawn(move || mqtt::run_bridge(handle, options));
+    }
+    if let Some(agent) = fleet {
+        let handle = daemon.handle();
+        thread::spawn(move || fleet::run_agent(handle, agent));
+    }
+    #[cfg(feature = "history")]
+    if let Some(store) = history {
+        let events = daemon.handle().subscribe();
+        thread::spawn(move || crate::history::record(events, store));
+    }
+    #[cfg(feature = "schedule")]
+    if let Some(scheduler) = scheduler {
+        let handle = daemon.handle();
+        thread::spawn(move || vari_desk_2020::schedule::run_scheduler(handle, scheduler));
+    }
+    #[cfg(feature = "calendar")]
+    if let Some((path, mover)) = calendar {
+        let handle = daemon.handle();
+        thread::spawn(move || vari_desk_2020::calendar::run_calendar(handle, path, mover));
+    }
+    daemon.run()?;
+    Ok(())
+}
+
+/// Prints what the daemon and its helpers have to say, until the daemon goes away.
+pub fn print_notices(events: Receiver<Event>) {
+    for event in events {
+        if let Event::Notice { source, message } = event {
+            eprintln!("{}: {}", source, message);
+        }
+    }
+}
+
+#[cfg(feature = "serial")]
+pub fn open_link(path: &str, baud: u32) -> Result<Box<dyn Link>, Box<dyn Error>> {
+    let link = vari_desk_2020::link::open_serial(path, baud)
+        .map_err(|e| format!("opening {}: {}", path, e))?;
+    Ok(Box::new(link))
+}
+
+#[cfg(not(feature = "serial"))]
+pub fn open_link(path: &str, _baud: u32) -> Result<Box<dyn Link>, Box<dyn Error>> {
+    Err(format!(
+        "cannot open {}: varidesk was built without the `serial` feature",
+        path
+    )
+    .into())
+}
@@ -0,0 +1,73 @@
+use std::io::{self, BufRead};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+use vari_desk_2020::decoder::Decoded;
+use vari_desk_2020::desk::{DeskEmulator, KeyEvent};
+use vari_desk_2020::link::{Clock, SystemClock};
+use vari_desk_2020::uart::UartConfig;
+
+use crate::args::Args;
+use crate::daemon::open_link;
+use crate::CommandResult;
+
+/// Stands in for the desk, printing the panel's key presses on stdout and showing each number
+/// given on stdin.
+pub fn desk(mut args: Args) -> CommandResult {
+    let panel = args.option("panel").ok_or("expected --panel <port>")?;
+    let baud = args
+        .parsed_option("baud")?
+        .unwrap_or(UartConfig::default().baud);
+    let value = args.parsed_option("show")?.unwrap_or(0.0);
+    args.finish()?;
+
+    let clock = SystemClock::new();
+    let mut link = open_link(&panel, baud)?;
+    let (tx, lines) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            if line.map(|line| tx.send(line)).is_err() {
+                return;
+            }
+        }
+    });
+
+    let mut desk = DeskEmulator::new(value)?;
+    let print = |event: KeyEvent| match event {
+        KeyEvent::Pressed(key) => println!("pressed {}", key),
+        KeyEvent::Released { key, held } => {
+            println!("released {} after {:.1}s", key, held.as_secs_f32())
+        }
+    };
+    loop {
+        let now = clock.now();
+        match lines.try_recv() {
+            Ok(line) => match line.trim().parse() {
+                Ok(value) => {
+                    if let Err(e) = desk.show(value) {
+                        eprintln!("error: {}", e);
+                    }
+                }
+                Err(_) => eprintln!("error: {:?} is not a number", line.trim()),
+            },
+            Err(TryRecvError::Disconnected) => return Ok(()),
+            Err(TryRecvError::Empty) => {}
+        }
+
+        while let Some(data) = link.recv()? {
+            if let Decoded::Frame(frame) = data {
+                desk.on_panel_frame(&frame, now)
+                    .into_iter()
+                    .flatten()
+                    .for_each(print);
+            }
+        }
+        let (report, released) = desk.poll(now);
+        released.into_iter().for_each(print);
+        if let Some(frame) = report {
+            link.send(&frame)?;
+        }
+        clock.sleep(Duration::from_millis(5));
+    }
+}
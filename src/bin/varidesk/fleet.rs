@@ -0,0 +1,94 @@
+use std::error::Error;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use vari_desk_2020::daemon::{Daemon, DaemonOptions};
+use vari_desk_2020::fleet::{self, check_id, Fleet, FleetAgent, Registration};
+use vari_desk_2020::link::{SimulatedDesk, SystemClock};
+use vari_desk_2020::sim::DeskSimulator;
+
+use crate::args::Args;
+use crate::daemon::print_notices;
+use crate::CommandResult;
+
+/// Runs a fleet server, with any number of simulated desks registered with it for trying it out.
+pub fn fleet(mut args: Args) -> CommandResult {
+    let listen = args
+        .option("listen")
+        .unwrap_or_else(|| "127.0.0.1:8090".to_string());
+    let simulate: usize = args.parsed_option("simulate")?.unwrap_or(0);
+    args.finish()?;
+
+    let listener = TcpListener::bind(&listen)?;
+    let addr = listener.local_addr()?;
+    eprintln!("fleet server listening on http://{}", addr);
+    for n in 1..=simulate {
+        let registration = Registration {
+            id: format!("sim-{}", n),
+            // Spread over two floors, to have something to pick by location.
+            location: format!("simulated/{}", 1 + n % 2),
+            groups: vec!["simulated".to_string()],
+            quirks: Vec::new(),
+        };
+        let agent = FleetAgent::new(&addr.to_string(), registration);
+        thread::spawn(move || simulated_desk(agent));
+    }
+    fleet::serve(
+        listener,
+        Arc::new(Mutex::new(Fleet::new())),
+        SystemClock::new(),
+    )?;
+    Ok(())
+}
+
+/// Runs a daemon in panel-replacement mode on a simulated desk, reporting to the fleet.
+fn simulated_desk(agent: FleetAgent) {
+    let clock = SystemClock::new();
+    let options = DaemonOptions::default();
+    let sim = DeskSimulator::new(options.profile, 90.0);
+    let mut daemon = Daemon::new(
+        Box::new(SimulatedDesk::new(sim, clock)),
+        None,
+        clock,
+        options,
+    );
+    let events = daemon.handle().subscribe();
+    thread::spawn(move || print_notices(events));
+    let handle = daemon.handle();
+    thread::spawn(move || fleet::run_agent(handle, agent));
+    #[cfg(feature = "schedule")]
+    {
+        use vari_desk_2020::schedule::{Schedule, Scheduler, SchedulerOptions};
+
+        let empty = Schedule::parse("").expect("an empty schedule parses");
+        let base = Scheduler::new(empty, SchedulerOptions::default());
+        let handle = daemon.handle();
+        thread::spawn(move || vari_desk_2020::schedule::run_scheduler(handle, base));
+    }
+    // A simulated desk never fails.
+    let _ = daemon.run();
+}
+
+/// Reads how the daemon describes itself to a `--fleet` server.
+pub fn registration(args: &mut Args) -> Result<Registration, Box<dyn Error>> {
+    let id = args
+        .option("desk-id")
+        .ok_or("--fleet needs a --desk-id for the desk")?;
+    check_id(&id)?;
+    let mut all = |name: &str| {
+        let mut values = Vec::new();
+        while let Some(value) = args.option(name) {
+            values.push(value);
+        }
+        values
+    };
+    let groups = all("group");
+    let quirks = all("quirk");
+    Ok(Registration {
+        id,
+        location: args.option("location").unwrap_or_default(),
+        groups,
+        quirks,
+    })
+}
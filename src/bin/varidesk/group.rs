@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use vari_desk_2020::group::{DeskGroup, GroupOptions, GroupOutcome};
+use vari_desk_2020::link::{Clock, Link, SimulatedDesk, SystemClock};
+use vari_desk_2020::sim::DeskSimulator;
+use vari_desk_2020::uart::UartConfig;
+
+use crate::args::Args;
+use crate::codec::parse_height;
+use crate::daemon::{desk_profile, open_link};
+use crate::CommandResult;
+
+const PRINT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Moves every desk given to one height together, printing their heights on the way.
+pub fn group(mut args: Args) -> CommandResult {
+    let target = args
+        .next_positional()
+        .ok_or("expected a height to move to, such as 110cm")?;
+    let target = parse_height(&target)?;
+    let mut ports = Vec::new();
+    while let Some(port) = args.option("desk") {
+        ports.push(port);
+    }
+    let simulate: Option<usize> = args.parsed_option("simulate")?;
+    let baud = args
+        .parsed_option("baud")?
+        .unwrap_or(UartConfig::default().baud);
+    let defaults = GroupOptions::default();
+    let profile = desk_profile(&mut args)?;
+    let options = GroupOptions {
+        profile,
+        tolerance_cm: args
+            .parsed_option("tolerance")?
+            .unwrap_or(defaults.tolerance_cm),
+        max_spread_cm: args
+            .parsed_option("max-spread")?
+            .unwrap_or(defaults.max_spread_cm),
+        ..defaults
+    };
+    args.finish()?;
+
+    let clock = SystemClock::new();
+    let links: Vec<Box<dyn Link>> = match (ports.len(), simulate) {
+        (0, Some(n)) if n > 0 => (0..n)
+            .map(|i| {
+                // Desks never run quite alike, so give each a different speed.
+                let sim = DeskSimulator::new(profile, 90.0).with_speed(3.8 - 0.3 * i as f32);
+                Box::new(SimulatedDesk::new(sim, clock)) as Box<dyn Link>
+            })
+            .collect(),
+        (n, None) if n > 0 => ports
+            .iter()
+            .map(|port| open_link(port, baud))
+            .collect::<Result<_, _>>()?,
+        _ => return Err("expected one or more --desk <port>, or --simulate <n>".into()),
+    };
+
+    let mut group = DeskGroup::new(links, clock, options);
+    let target = group.move_to(target);
+    println!("moving {} desks to {:.1}cm", group.heights().len(), target);
+    let mut last_print = clock.now();
+    let outcome = loop {
+        if let Some(outcome) = group.step() {
+            break outcome;
+        }
+        let now = clock.now();
+        if now >= last_print + PRINT_INTERVAL {
+            last_print = now;
+            print_heights(&group);
+        }
+        clock.sleep(Duration::from_millis(5));
+    };
+    print_heights(&group);
+
+    match outcome {
+        GroupOutcome::Reached => Ok(()),
+        GroupOutcome::Stopped => Err("stopped".into()),
+        GroupOutcome::Stalled(desk) => {
+            Err(format!("desk {} stalled, so all desks were stopped", desk + 1).into())
+        }
+        GroupOutcome::LinkLost { desk, reason } => Err(format!(
+            "lost desk {} ({}), so all desks were stopped",
+            desk + 1,
+            reason
+        )
+        .into()),
+    }
+}
+
+/// Prints one line of heights, marking desks waiting for the others with `*`.
+fn print_heights<C: Clock>(group: &DeskGroup<C>) {
+    let line: Vec<String> = group
+        .heights()
+        .into_iter()
+        .zip(group.paused())
+        .map(|(height, paused)| {
+            let mark = if paused { "*" } else { " " };
+            match height {
+                Some(h) => format!("{:6.1}{}", h, mark),
+                None => format!("{:>6}{}", "-", mark),
+            }
+        })
+        .collect();
+    println!("{}", line.join(" "));
+}
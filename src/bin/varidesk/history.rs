@@ -0,0 +1,147 @@
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, SystemTime};
+
+use chrono::{Days, Local, TimeZone};
+use vari_desk_2020::daemon::Event;
+use vari_desk_2020::history::{
+    self, daily_stats, format_duration, streaks, unix_millis, weekly_stats, History, Recorder,
+    StatsOptions,
+};
+use vari_desk_2020::json::Value;
+
+use crate::args::Args;
+use crate::CommandResult;
+
+pub fn history(mut args: Args) -> CommandResult {
+    let path = args
+        .next_positional()
+        .ok_or("expected the path of a history database")?;
+    let days: u64 = args.parsed_option("days")?.unwrap_or(7);
+    let weeks = args.flag("weeks");
+    let movements = args.flag("movements");
+    let format = args.option("format").unwrap_or_else(|| "table".to_string());
+    let defaults = StatsOptions::default();
+    let options = StatsOptions {
+        standing_threshold_cm: args
+            .parsed_option("standing-threshold")?
+            .unwrap_or(defaults.standing_threshold_cm),
+        standing_goal: args
+            .parsed_option::<u64>("standing-goal")?
+            .map_or(defaults.standing_goal, |m| Duration::from_secs(m * 60)),
+        ..defaults
+    };
+    args.finish()?;
+    if !["table", "csv", "json"].contains(&format.as_str()) {
+        return Err(format!("unknown format {:?}, expected table, csv or json", format).into());
+    }
+
+    let store = open(&path)?;
+    let until = unix_millis(SystemTime::now());
+    let today = Local.timestamp_millis_opt(until).unwrap().date_naive();
+    let first = today - Days::new(days.max(1) - 1);
+    let since = Local
+        .from_local_datetime(&first.and_hms_opt(0, 0, 0).unwrap())
+        .earliest()
+        .map_or(until, |t| t.timestamp_millis());
+    let records = store.movements(since, until)?;
+
+    if movements {
+        match format.as_str() {
+            "csv" => print!("{}", history::movements_csv(&records)),
+            "json" => {
+                let values: Vec<Value> = records.iter().map(|m| m.to_json()).collect();
+                println!("{}", Value::from(values));
+            }
+            _ => {
+                for m in &records {
+                    let start = Local.timestamp_millis_opt(m.started_at).unwrap();
+                    println!(
+                        "{}  {:5.1}cm -> {:5.1}cm  {:.1}s",
+                        start.format("%Y-%m-%d %H:%M:%S"),
+                        m.from_cm,
+                        m.to_cm,
+                        (m.ended_at - m.started_at) as f64 / 1000.0
+                    );
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    let before = store.last_movement_before(since)?;
+    let day_stats = daily_stats(&Local, before.as_ref(), &records, since, until, &options);
+    let streaks = streaks(&day_stats, &options);
+    if weeks {
+        let week_stats = weekly_stats(&day_stats, &options);
+        match format.as_str() {
+            "csv" => print!("{}", history::weeks_csv(&week_stats)),
+            "json" => {
+                let values: Vec<Value> = week_stats.iter().map(|w| w.to_json()).collect();
+                println!("{}", Value::from(values));
+            }
+            _ => {
+                println!("week      sitting  standing  transitions  goal days");
+                for w in &week_stats {
+                    println!(
+                        "{}  {:>7}  {:>8}  {:>11}  {:>9}",
+                        w.label(),
+                        format_duration(w.sitting),
+                        format_duration(w.standing),
+                        w.transitions,
+                        w.goal_days
+                    );
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    match format.as_str() {
+        "csv" => print!("{}", history::days_csv(&day_stats)),
+        "json" => {
+            let values: Vec<Value> = day_stats.iter().map(|d| d.to_json()).collect();
+            let report = Value::object([
+                ("days", Value::from(values)),
+                ("current_streak_days", (streaks.current_days as u64).into()),
+                ("longest_streak_days", (streaks.longest_days as u64).into()),
+            ]);
+            println!("{}", report);
+        }
+        _ => {
+            println!("date        sitting  standing  transitions  longest stand");
+            for d in &day_stats {
+                println!(
+                    "{}  {:>7}  {:>8}  {:>11}  {:>13}",
+                    d.date,
+                    format_duration(d.sitting),
+                    format_duration(d.standing),
+                    d.transitions,
+                    format_duration(d.longest_standing)
+                );
+            }
+            println!(
+                "\nstreak: {} days (longest {}), goal {} standing a day",
+                streaks.current_days,
+                streaks.longest_days,
+                format_duration(options.standing_goal)
+            );
+        }
+    }
+    Ok(())
+}
+
+pub fn open(path: &str) -> Result<History, Box<dyn std::error::Error>> {
+    Ok(History::open(path).map_err(|e| format!("opening {}: {}", path, e))?)
+}
+
+/// Records the movements in a daemon's `events` until the daemon stops.
+pub fn record(events: Receiver<Event>, store: History) {
+    let mut recorder = Recorder::new();
+    for event in events {
+        if let Some(movement) = recorder.on_event(&event, unix_millis(SystemTime::now())) {
+            if let Err(e) = store.record(&movement) {
+                eprintln!("error: recording a movement: {}", e);
+            }
+        }
+    }
+}
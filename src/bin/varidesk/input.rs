@@ -0,0 +1,143 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::time::Duration;
+
+use vari_desk_2020::analysis::{self, Observation};
+use vari_desk_2020::capture::{self, CaptureReader, CaptureWriter, Header, Record};
+use vari_desk_2020::decoder::Decoded;
+use vari_desk_2020::pcapng::{PcapngReader, PcapngWriter};
+use vari_desk_2020::uart::{self, LogicCapture, Parity, UartConfig};
+use vari_desk_2020::{validate_checksum, DeskProfile};
+
+use crate::args::Args;
+use crate::codec::{describe, hex};
+use crate::CommandResult;
+
+const PCAPNG_MAGIC: [u8; 4] = [0x0a, 0x0d, 0x0d, 0x0a];
+
+/// Reads a recording in any format we understand: our own captures, pcapng, or a logic-analyzer
+/// CSV export, which takes the UART options from `args`.
+pub fn read_records(path: &str, args: &mut Args) -> Result<Vec<Record>, Box<dyn Error>> {
+    let mut magic = [0u8; 4];
+    let n = File::open(path)?.read(&mut magic)?;
+
+    if n == 4 && magic == capture::CAPTURE_MAGIC {
+        let reader = CaptureReader::new(BufReader::new(File::open(path)?))?;
+        return Ok(reader.collect::<Result<Vec<Record>, _>>()?);
+    }
+    if n == 4 && magic == PCAPNG_MAGIC {
+        let reader = PcapngReader::new(BufReader::new(File::open(path)?))?;
+        return Ok(reader.collect::<Result<Vec<Record>, _>>()?);
+    }
+
+    let csv = fs::read_to_string(path)
+        .map_err(|e| format!("{} is not a capture, pcapng or CSV file: {}", path, e))?;
+    read_logic_csv(&csv, args)
+}
+
+fn read_logic_csv(csv: &str, args: &mut Args) -> Result<Vec<Record>, Box<dyn Error>> {
+    let capture = LogicCapture::parse_csv(csv)?;
+
+    let panel = args
+        .option("panel-channel")
+        .ok_or("--panel-channel is needed to decode a CSV file")?;
+    let desk = args
+        .option("desk-channel")
+        .ok_or("--desk-channel is needed to decode a CSV file")?;
+
+    let defaults = UartConfig::default();
+    let data_bits = args
+        .parsed_option("data-bits")?
+        .unwrap_or(defaults.data_bits);
+    let config = UartConfig {
+        baud: args.parsed_option("baud")?.unwrap_or(defaults.baud),
+        data_bits,
+        parity: match args.option("parity").as_deref() {
+            None | Some("none") => Parity::None,
+            Some("even") => Parity::Even,
+            Some("odd") => Parity::Odd,
+            Some(other) => return Err(format!("unknown parity {:?}", other).into()),
+        },
+        stop_bits: args
+            .parsed_option("stop-bits")?
+            .unwrap_or(defaults.stop_bits),
+        inverted: args.flag("inverted"),
+    };
+
+    let panel = uart::decode_uart(&capture.channel(capture.channel_index(&panel)?), &config)?;
+    let desk = uart::decode_uart(&capture.channel(capture.channel_index(&desk)?), &config)?;
+    Ok(uart::transcript(&panel, &desk))
+}
+
+pub fn format_record(record: &Record) -> String {
+    let (bytes, description) = match &record.data {
+        Decoded::Frame(frame) => {
+            let mut description = describe(record.direction, frame);
+            if !validate_checksum(frame) {
+                description.push_str(" (bad checksum)");
+            }
+            (hex(frame), description)
+        }
+        Decoded::Invalid(frame) => (hex(frame), "invalid frame".to_string()),
+        Decoded::Stray(b) => (hex(&[*b]), "stray byte".to_string()),
+    };
+
+    format!(
+        "{:>14.6}s {:<11} {:<20} {}",
+        record.timestamp.as_secs_f64(),
+        record.direction.to_string(),
+        bytes,
+        description
+    )
+}
+
+pub fn analyze(mut args: Args) -> CommandResult {
+    let window = Duration::from_millis(args.parsed_option("window-ms")?.unwrap_or(500));
+    let path = args
+        .next_positional()
+        .ok_or("expected a recording to analyze")?;
+    let records = read_records(&path, &mut args)?;
+    args.finish()?;
+
+    let observations: Vec<Observation> = records
+        .iter()
+        .filter_map(Observation::from_record)
+        .collect();
+    print!("{}", analysis::analyze(&observations, window));
+    Ok(())
+}
+
+pub fn convert(mut args: Args) -> CommandResult {
+    let input = args.next_positional().ok_or("expected an input file")?;
+    let output = args.next_positional().ok_or("expected an output file")?;
+    let records = read_records(&input, &mut args)?;
+    args.finish()?;
+
+    let mut out = BufWriter::new(File::create(&output)?);
+    if output.ends_with(".pcapng") {
+        let mut writer = PcapngWriter::new(out)?;
+        for record in &records {
+            writer.write(record)?;
+        }
+        out = writer.into_inner();
+    } else if output.ends_with(".jsonl") {
+        for record in &records {
+            writeln!(out, "{}", capture::record_json(record))?;
+        }
+    } else {
+        let source = format!("converted from {}", input);
+        let header = Header {
+            profile: DeskProfile::default(),
+            metadata: &[("source", &source)],
+        };
+        let mut writer = CaptureWriter::new(out, &header).map_err(|e| e.to_string())?;
+        for record in &records {
+            writer.write(record)?;
+        }
+        out = writer.into_inner();
+    }
+
+    out.flush()?;
+    Ok(())
+}
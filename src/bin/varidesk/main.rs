@@ -0,0 +1,163 @@
+use std::env;
+use std::error::Error;
+use std::process;
+
+mod args;
+#[cfg(feature = "calendar")]
+mod calendar;
+mod codec;
+mod daemon;
+mod desk;
+mod fleet;
+mod group;
+#[cfg(feature = "history")]
+mod history;
+mod input;
+#[cfg(feature = "serial")]
+mod monitor;
+mod panel;
+mod profile;
+mod recommend;
+#[cfg(feature = "schedule")]
+mod schedule;
+#[cfg(feature = "tui")]
+mod tui;
+
+use args::Args;
+
+const USAGE: &str = "\
+usage: varidesk <command> [options]
+
+commands:
+  decode <hex frame>...        decode hex frames (--dir panel|desk to pick a direction)
+  decode --file <path>         decode a capture, pcapng or logic-analyzer CSV file
+  encode panel <message>       encode a panel-to-desk message, e.g. `encode panel one 100cm`
+  encode desk height <height>  encode a desk-to-panel height report
+  validate <hex frame>...      check frames and explain what is wrong with them
+  tables                       print the protocol tables
+  analyze <path>               cluster and report on Unknown frames in a recording
+  convert <in> <out>           convert a recording to .vdcp, .pcapng or .jsonl
+  monitor                      watch live bus traffic on serial ports (needs the `serial` feature)
+  daemon                       own the bus and serve an HTTP/JSON API
+  panel                        drive a desk in place of its panel with commands on stdin
+  desk --panel <port>          use the panel as an input device, showing numbers from stdin
+  group <height>               move several linked desks to a height together
+  fleet                        run a server that manages the desks of a whole office
+  tui                          dashboard that drives a desk in place of its panel (needs `tui`)
+  history <db>                 sit/stand statistics recorded by `daemon --history` (needs `history`)
+  schedule <file>              list the next changes a sit/stand schedule makes (needs `schedule`)
+  calendar <ics> --rules <f>   print the moves planned for a day's events (needs `calendar`)
+  profile <store> <action>     list, show, import, export or remove user profiles
+  recommend <body height>      recommend sitting and standing heights for someone
+
+analyze options:
+  --window-ms <n> (how close an action has to be to count as preceding a frame, 500 by default)
+
+monitor options:
+  --panel <port>  --desk <port>  --baud <n>  --capture <path.vdcp>  --no-color
+
+daemon options:
+  --desk <port> or --simulate  --panel <port> (pass-through mode)  --listen <addr:port>
+  --baud <n>  --min-height <cm>  --max-height <cm>  --standing-threshold <cm>
+  --mqtt <host:port>  --mqtt-id <id>  --mqtt-name <name>  --mqtt-user <user>
+  --mqtt-password <password>  --discovery-prefix <topic>  --no-discovery
+  --history <db> (record movements to SQLite, needs the `history` feature)
+  --schedule <file> (sit/stand routine, needs the `schedule` feature)  --sit-height <cm>
+  --stand-height <cm>  --schedule-warning <duration>  --schedule-hold <duration>
+  --calendar <file or directory> --calendar-rules <file> (needs the `calendar` feature)
+  --profiles <file> (user profiles, switched with Up Down Up Down on the panel)
+  --duty-cycle <on>/<window> (such as 2m/18m)  --duty-defer  --duty-panel-override
+  --panel-timeout <ms>  --desk-timeout <ms>  --noise-threshold <errors per 10s>
+  --fleet <addr:port> (register with a fleet server)  --desk-id <id>  --location <path>
+  --group <name> (repeatable)  --quirk <text> (repeatable)
+
+history options:
+  --days <n>  --weeks  --movements  --format table|csv|json
+  --standing-threshold <cm>  --standing-goal <minutes>
+
+schedule options:
+  --count <n>
+
+calendar options:
+  --rules <file>  --date <yyyy-mm-dd>  --sit-height <cm>  --stand-height <cm>
+
+profile actions:
+  list  show <name>  export <name> [file]  import <file>...  remove <name>
+
+recommend options:
+  --elbow-height <cm>  --chair-height <cm>  --tray-drop <cm>  --min-height <cm>
+  --max-height <cm>  --frames (print preset frames)  --profiles <store> --name <name>
+
+panel options:
+  --desk <port>  --simulate  --baud <n>
+  commands: up, down, stop, move <cm>, preset <n> <cm>
+
+desk options:
+  --panel <port>  --baud <n>  --show <number>
+
+group options:
+  --desk <port> (once per desk) or --simulate <n>  --baud <n>  --min-height <cm>
+  --max-height <cm>  --tolerance <cm>  --max-spread <cm> (how far a desk may get ahead)
+
+fleet options:
+  --listen <addr:port>  --simulate <n> (run n simulated desks registered with the server)
+
+tui options:
+  --desk <port>  --simulate  --baud <n>  --presets <cm>,<cm>,<cm>
+
+logic-analyzer CSV options:
+  --panel-channel <name>  --desk-channel <name>  --baud <n>
+  --data-bits 5-8         --parity none|even|odd  --stop-bits <n>  --inverted
+";
+
+fn main() {
+    let mut args = Args::new(env::args().skip(1));
+
+    let result = match args.next_positional().as_deref() {
+        Some("decode") => codec::decode(args),
+        Some("encode") => codec::encode(args),
+        Some("validate") => codec::validate(args),
+        Some("tables") => codec::tables(args),
+        Some("analyze") => input::analyze(args),
+        Some("convert") => input::convert(args),
+        #[cfg(feature = "serial")]
+        Some("monitor") => monitor::monitor(args),
+        #[cfg(not(feature = "serial"))]
+        Some("monitor") => Err("varidesk was built without the `serial` feature".into()),
+        Some("daemon") => daemon::daemon(args),
+        Some("panel") => panel::panel(args),
+        Some("desk") => desk::desk(args),
+        Some("group") => group::group(args),
+        Some("fleet") => fleet::fleet(args),
+        Some("profile") => profile::profile(args),
+        Some("recommend") => recommend::recommend(args),
+        #[cfg(feature = "history")]
+        Some("history") => history::history(args),
+        #[cfg(not(feature = "history"))]
+        Some("history") => Err("varidesk was built without the `history` feature".into()),
+        #[cfg(feature = "calendar")]
+        Some("calendar") => calendar::calendar(args),
+        #[cfg(not(feature = "calendar"))]
+        Some("calendar") => Err("varidesk was built without the `calendar` feature".into()),
+        #[cfg(feature = "schedule")]
+        Some("schedule") => schedule::schedule(args),
+        #[cfg(not(feature = "schedule"))]
+        Some("schedule") => Err("varidesk was built without the `schedule` feature".into()),
+        #[cfg(feature = "tui")]
+        Some("tui") => tui::tui(args),
+        #[cfg(not(feature = "tui"))]
+        Some("tui") => Err("varidesk was built without the `tui` feature".into()),
+        Some("help") | None => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        Some(other) => Err(format!("unknown command {:?}\n\n{}", other, USAGE).into()),
+    };
+
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+pub type CommandResult = Result<(), Box<dyn Error>>;
@@ -0,0 +1,332 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use vari_desk_2020::capture::{CaptureWriter, Header, Record};
+use vari_desk_2020::decoder::Decoded;
+use vari_desk_2020::link::{self, Clock, Link, SystemClock};
+use vari_desk_2020::uart::UartConfig;
+use vari_desk_2020::{
+    validate_checksum, DataFrame, DeskProfile, DeskToPanelMessage, Direction, PanelToDeskMessage,
+};
+
+use crate::args::Args;
+use crate::input::format_record;
+use crate::CommandResult;
+
+const RESET: &str = "\x1b[0m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const CYAN: &str = "\x1b[36m";
+
+/// How often the capture file is flushed, so little is lost if the monitor is killed.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+enum Event {
+    Received(Record),
+    Failed(Direction, io::Error),
+}
+
+pub fn monitor(mut args: Args) -> CommandResult {
+    let panel = args.option("panel");
+    let desk = args.option("desk");
+    let baud = args
+        .parsed_option("baud")?
+        .unwrap_or(UartConfig::default().baud);
+    let capture = args.option("capture");
+    let color = !args.flag("no-color");
+    args.finish()?;
+
+    if panel.is_none() && desk.is_none() {
+        return Err("expected --panel and/or --desk serial ports to listen on".into());
+    }
+
+    let clock = SystemClock::new();
+    let (tx, rx) = mpsc::channel();
+    for (direction, path) in [
+        (Direction::PanelToDesk, panel),
+        (Direction::DeskToPanel, desk),
+    ] {
+        let Some(path) = path else { continue };
+        let mut link =
+            link::open_serial(&path, baud).map_err(|e| format!("opening {}: {}", path, e))?;
+        let tx = tx.clone();
+        thread::spawn(move || loop {
+            let event = match link.recv() {
+                Ok(Some(data)) => Event::Received(Record {
+                    direction,
+                    timestamp: clock.now(),
+                    data,
+                }),
+                Ok(None) => continue,
+                Err(e) => Event::Failed(direction, e),
+            };
+            let failed = matches!(event, Event::Failed(..));
+            if tx.send(event).is_err() || failed {
+                return;
+            }
+        });
+    }
+    drop(tx);
+
+    let mut writer = match &capture {
+        Some(path) => {
+            let header = Header {
+                profile: DeskProfile::default(),
+                metadata: &[("source", "varidesk monitor")],
+            };
+            let out = BufWriter::new(File::create(path)?);
+            Some(CaptureWriter::new(out, &header).map_err(|e| e.to_string())?)
+        }
+        None => None,
+    };
+
+    let mut log = Log::new();
+    let print = |lines: Vec<Line>| -> io::Result<()> {
+        let mut out = io::stdout().lock();
+        for line in lines {
+            if color {
+                writeln!(out, "{}", paint(&line))?;
+            } else {
+                writeln!(out, "{}", line.text)?;
+            }
+        }
+        Ok(())
+    };
+    let mut flushed_at = clock.now();
+    loop {
+        match rx.recv_timeout(FLUSH_INTERVAL) {
+            Ok(Event::Received(record)) => {
+                if let Some(writer) = &mut writer {
+                    writer.write(&record)?;
+                }
+                print(log.push(&record))?;
+            }
+            Ok(Event::Failed(direction, e)) => {
+                eprintln!("error: {} link failed: {}", direction, e);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        let now = clock.now();
+        if now.saturating_sub(flushed_at) >= FLUSH_INTERVAL {
+            if let Some(writer) = &mut writer {
+                writer.get_mut().flush()?;
+            }
+            flushed_at = now;
+        }
+    }
+
+    print(log.finish())?;
+    if let Some(writer) = writer {
+        writer.into_inner().flush()?;
+    }
+    Ok(())
+}
+
+/// How a log line should stand out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Highlight {
+    Panel,
+    Desk,
+    Unknown,
+    Error,
+}
+
+pub struct Line {
+    pub highlight: Highlight,
+    pub text: String,
+}
+
+/// Formats received records as log lines, folding runs of identical keep-alives and height
+/// reports into a single count so that the interesting frames stand out.
+#[derive(Default)]
+pub struct Log {
+    // The last repeatable frame seen in each direction, and how many times it has repeated since
+    // it was printed.
+    repeating: [Option<(DataFrame, usize)>; 2],
+}
+
+impl Log {
+    pub fn new() -> Log {
+        Log::default()
+    }
+
+    pub fn push(&mut self, record: &Record) -> Vec<Line> {
+        let slot = &mut self.repeating[record.direction as usize];
+        let repeatable = match &record.data {
+            Decoded::Frame(frame) if is_repeatable(record.direction, frame) => Some(*frame),
+            _ => None,
+        };
+
+        if let (Some(frame), Some((last, count))) = (repeatable, slot.as_mut()) {
+            if frame == *last {
+                *count += 1;
+                return Vec::new();
+            }
+        }
+
+        let mut lines: Vec<Line> = repeated(record.direction, slot.take())
+            .into_iter()
+            .collect();
+        *slot = repeatable.map(|frame| (frame, 0));
+
+        let highlight = match &record.data {
+            Decoded::Frame(frame) if !validate_checksum(frame) => Highlight::Error,
+            Decoded::Invalid(_) | Decoded::Stray(_) => Highlight::Error,
+            Decoded::Frame(frame) if is_unknown(record.direction, frame) => Highlight::Unknown,
+            Decoded::Frame(_) => direction_highlight(record.direction),
+        };
+        lines.push(Line {
+            highlight,
+            text: format_record(record),
+        });
+        lines
+    }
+
+    /// The repeats not yet counted out, for when no more records are coming.
+    pub fn finish(&mut self) -> Vec<Line> {
+        [Direction::PanelToDesk, Direction::DeskToPanel]
+            .into_iter()
+            .filter_map(|direction| repeated(direction, self.repeating[direction as usize].take()))
+            .collect()
+    }
+}
+
+/// The line counting out the repeats of a folded frame, if it repeated at all.
+fn repeated(direction: Direction, slot: Option<(DataFrame, usize)>) -> Option<Line> {
+    let (_, count) = slot.filter(|(_, count)| *count > 0)?;
+    Some(Line {
+        highlight: direction_highlight(direction),
+        text: format!(
+            "{:>15} {:<11} (repeated {} more times)",
+            "",
+            direction.to_string(),
+            count
+        ),
+    })
+}
+
+fn paint(line: &Line) -> String {
+    let color = match line.highlight {
+        Highlight::Panel => CYAN,
+        Highlight::Desk => GREEN,
+        Highlight::Unknown => YELLOW,
+        Highlight::Error => RED,
+    };
+    format!("{}{}{}", color, line.text, RESET)
+}
+
+fn direction_highlight(direction: Direction) -> Highlight {
+    match direction {
+        Direction::PanelToDesk => Highlight::Panel,
+        Direction::DeskToPanel => Highlight::Desk,
+    }
+}
+
+fn is_repeatable(direction: Direction, frame: &DataFrame) -> bool {
+    match direction {
+        Direction::PanelToDesk => {
+            matches!(
+                PanelToDeskMessage::from_frame(frame),
+                PanelToDeskMessage::NoKey
+            )
+        }
+        Direction::DeskToPanel => {
+            matches!(
+                DeskToPanelMessage::from_frame(frame),
+                DeskToPanelMessage::Height(_)
+            )
+        }
+    }
+}
+
+fn is_unknown(direction: Direction, frame: &DataFrame) -> bool {
+    match direction {
+        Direction::PanelToDesk => {
+            matches!(
+                PanelToDeskMessage::from_frame(frame),
+                PanelToDeskMessage::Unknown(..)
+            )
+        }
+        Direction::DeskToPanel => {
+            matches!(
+                DeskToPanelMessage::from_frame(frame),
+                DeskToPanelMessage::Unknown(..)
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(direction: Direction, frame: DataFrame) -> Record {
+        Record {
+            direction,
+            timestamp: Duration::ZERO,
+            data: Decoded::Frame(frame),
+        }
+    }
+
+    #[test]
+    fn test_log_folds_repeats() {
+        let mut log = Log::new();
+        let no_key = record(Direction::PanelToDesk, PanelToDeskMessage::NoKey.as_frame());
+        let up = record(Direction::PanelToDesk, PanelToDeskMessage::Up.as_frame());
+        let height = record(
+            Direction::DeskToPanel,
+            DeskToPanelMessage::Height(100.0).as_frame(),
+        );
+
+        assert_eq!(log.push(&no_key).len(), 1);
+        assert!(log.push(&no_key).is_empty());
+        assert!(log.push(&no_key).is_empty());
+
+        // The other direction is folded separately.
+        assert_eq!(log.push(&height).len(), 1);
+        assert!(log.push(&height).is_empty());
+
+        let lines = log.push(&up);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].text.ends_with("(repeated 2 more times)"));
+        assert!(lines[1].text.ends_with("up key held"));
+
+        // Key presses are never folded.
+        assert_eq!(log.push(&up).len(), 1);
+        assert_eq!(log.push(&no_key).len(), 1);
+
+        // Repeats still being folded are counted out at the end.
+        assert!(log.push(&no_key).is_empty());
+        let lines = log.finish();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].text.contains("panel"));
+        assert!(lines[0].text.ends_with("(repeated 1 more times)"));
+        assert!(lines[1].text.ends_with("(repeated 1 more times)"));
+        assert!(log.finish().is_empty());
+    }
+
+    #[test]
+    fn test_log_highlights() {
+        let mut log = Log::new();
+        let mut bad = PanelToDeskMessage::Up.as_frame();
+        bad[5] ^= 1;
+
+        let lines = log.push(&record(Direction::PanelToDesk, bad));
+        assert_eq!(lines[0].highlight, Highlight::Error);
+        let lines = log.push(&record(
+            Direction::DeskToPanel,
+            [0x68, 1, 9, 0, 0, 10, 0x16],
+        ));
+        assert_eq!(lines[0].highlight, Highlight::Unknown);
+        let lines = log.push(&record(
+            Direction::DeskToPanel,
+            DeskToPanelMessage::Height(100.0).as_frame(),
+        ));
+        assert_eq!(lines[0].highlight, Highlight::Desk);
+    }
+}
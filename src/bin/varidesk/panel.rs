@@ -0,0 +1,86 @@
+use std::io::{self, BufRead};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+use vari_desk_2020::decoder::Decoded;
+use vari_desk_2020::link::{Clock, Link, SimulatedDesk, SystemClock};
+use vari_desk_2020::panel::{PanelCommand, PanelEmulator};
+use vari_desk_2020::sim::DeskSimulator;
+use vari_desk_2020::uart::UartConfig;
+use vari_desk_2020::DeskProfile;
+
+use crate::args::Args;
+use crate::daemon::open_link;
+use crate::CommandResult;
+
+/// How long the height has to stay put before the desk counts as stopped.
+const SETTLE_TIME: Duration = Duration::from_secs(1);
+
+/// Stands in for the panel, taking commands a line at a time on stdin and printing each new
+/// height on stdout.
+pub fn panel(mut args: Args) -> CommandResult {
+    let desk = args.option("desk");
+    let simulate = args.flag("simulate");
+    let baud = args
+        .parsed_option("baud")?
+        .unwrap_or(UartConfig::default().baud);
+    args.finish()?;
+
+    let clock = SystemClock::new();
+    let mut link: Box<dyn Link> = match (desk, simulate) {
+        (Some(path), false) => open_link(&path, baud)?,
+        (None, true) => Box::new(SimulatedDesk::new(
+            DeskSimulator::new(DeskProfile::default(), 90.0),
+            clock,
+        )),
+        _ => return Err("expected either --desk <port> or --simulate".into()),
+    };
+
+    let (tx, lines) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            if line.map(|line| tx.send(line)).is_err() {
+                return;
+            }
+        }
+    });
+
+    let mut panel = PanelEmulator::new();
+    let mut last_height = None;
+    let mut last_change = clock.now();
+    loop {
+        let now = clock.now();
+        match lines.try_recv() {
+            Ok(line) if line.trim().is_empty() => {}
+            Ok(line) => match PanelCommand::parse(&line) {
+                Ok(command) => panel.apply(command, now),
+                Err(e) => eprintln!("error: {}", e),
+            },
+            // Stay connected until the desk has been let go of and has come to a stop.
+            Err(TryRecvError::Disconnected)
+                if !panel.is_busy() && now >= last_change + SETTLE_TIME =>
+            {
+                return Ok(())
+            }
+            Err(_) => {}
+        }
+
+        if let Some(frame) = panel.poll(now) {
+            link.send(&frame)?;
+        }
+        while let Some(data) = link.recv()? {
+            if let Decoded::Frame(frame) = data {
+                panel.on_desk_frame(&frame);
+            }
+        }
+        if panel.height_cm() != last_height {
+            last_height = panel.height_cm();
+            last_change = now;
+            if let Some(h) = last_height {
+                println!("{:.1}", h);
+            }
+        }
+        clock.sleep(Duration::from_millis(5));
+    }
+}
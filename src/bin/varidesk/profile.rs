@@ -0,0 +1,78 @@
+use std::fs;
+
+use vari_desk_2020::json::Value;
+use vari_desk_2020::profile::{Profile, ProfileStore};
+
+use crate::args::Args;
+use crate::CommandResult;
+
+pub fn profile(mut args: Args) -> CommandResult {
+    let path = args
+        .next_positional()
+        .ok_or("expected the path of a profile store")?;
+    let action = args.next_positional().unwrap_or_else(|| "list".to_string());
+    let rest = args.rest();
+    args.finish()?;
+
+    let mut store = ProfileStore::open(&path).map_err(|e| format!("opening {}: {}", path, e))?;
+    let find = |store: &ProfileStore, name: &str| {
+        store
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("there is no profile {:?} in {}", name, path))
+    };
+    match (action.as_str(), rest.as_slice()) {
+        ("list", []) => {
+            let active = store.active().map(|p| p.name.clone());
+            for p in store.profiles() {
+                let presets: Vec<String> = p
+                    .presets
+                    .iter()
+                    .map(|h| h.map_or("-".to_string(), |h| format!("{:.1}", h)))
+                    .collect();
+                println!(
+                    "{} {:<16} sit {:5.1}cm  stand {:5.1}cm  presets {}{}",
+                    if active.as_ref() == Some(&p.name) {
+                        '*'
+                    } else {
+                        ' '
+                    },
+                    p.name,
+                    p.sitting_cm,
+                    p.standing_cm,
+                    presets.join(","),
+                    if p.schedule.is_some() {
+                        "  own schedule"
+                    } else {
+                        ""
+                    }
+                );
+            }
+        }
+        ("show", [name]) | ("export", [name]) => println!("{}", find(&store, name)?.to_json()),
+        ("export", [name, file]) => fs::write(file, format!("{}\n", find(&store, name)?.to_json()))
+            .map_err(|e| format!("writing {}: {}", file, e))?,
+        ("import", files) if !files.is_empty() => {
+            for file in files {
+                let text =
+                    fs::read_to_string(file).map_err(|e| format!("reading {}: {}", file, e))?;
+                let profile = Value::parse(&text)
+                    .map_err(|e| e.to_string())
+                    .and_then(|v| Profile::from_json(&v))
+                    .map_err(|e| format!("{}: {}", file, e))?;
+                eprintln!("imported {}", profile.name);
+                store.insert(profile)?;
+            }
+        }
+        ("remove", [name]) => {
+            if !store.remove(name)? {
+                return Err(format!("there is no profile {:?} in {}", name, path).into());
+            }
+        }
+        _ => return Err(
+            "expected list, show <name>, export <name> [file], import <file>... or remove <name>"
+                .into(),
+        ),
+    }
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use vari_desk_2020::ergonomics::{recommend as recommend_heights, Body};
+use vari_desk_2020::profile::{check_name, Profile, ProfileStore};
+
+use crate::args::Args;
+use crate::codec::{hex, parse_height};
+use crate::daemon::desk_profile;
+use crate::CommandResult;
+
+pub fn recommend(mut args: Args) -> CommandResult {
+    let stature = args
+        .next_positional()
+        .ok_or("expected a body height, such as 175cm")?;
+    let height = |s: Option<String>| s.as_deref().map(parse_height).transpose();
+    let body = Body {
+        elbow_cm: height(args.option("elbow-height"))?,
+        chair_cm: height(args.option("chair-height"))?,
+        tray_cm: height(args.option("tray-drop"))?.unwrap_or(0.0),
+        ..Body::new(parse_height(&stature)?)
+    };
+    let desk = desk_profile(&mut args)?;
+    let frames = args.flag("frames");
+    let store = args.option("profiles");
+    let name = args.option("name");
+    args.finish()?;
+    if let Some(name) = &name {
+        check_name(name)?;
+    }
+
+    let recommendation = recommend_heights(&body, &desk);
+    println!("sitting  {:5.1}cm", recommendation.sitting_cm);
+    println!("standing {:5.1}cm", recommendation.standing_cm);
+    if recommendation.clamped {
+        eprintln!(
+            "warning: outside the desk's {:.1}-{:.1}cm range, so clamped",
+            desk.min_height_cm, desk.max_height_cm
+        );
+    }
+    if frames {
+        for message in recommendation.preset_messages() {
+            println!("{}  {}", hex(&message.as_frame()), message);
+        }
+    }
+
+    match (store, name) {
+        (Some(path), Some(name)) => {
+            let mut store =
+                ProfileStore::open(&path).map_err(|e| format!("opening {}: {}", path, e))?;
+            // Anything else already in the profile is kept.
+            let mut profile = store
+                .get(&name)
+                .cloned()
+                .unwrap_or_else(|| Profile::new(&name, 0.0, 0.0));
+            profile.set_heights(recommendation.sitting_cm, recommendation.standing_cm);
+            store.insert(profile)?;
+            eprintln!("saved profile {} to {}", name, path);
+        }
+        (None, None) => {}
+        _ => return Err("--profiles and --name go together".into()),
+    }
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use std::fs;
+
+use chrono::Local;
+use vari_desk_2020::schedule::{parse_duration, Schedule, Scheduler, SchedulerOptions};
+
+use crate::args::Args;
+use crate::daemon::Heights;
+use crate::CommandResult;
+
+pub fn schedule(mut args: Args) -> CommandResult {
+    let path = args
+        .next_positional()
+        .ok_or("expected the path of a schedule file")?;
+    let count: usize = args.parsed_option("count")?.unwrap_or(10);
+    args.finish()?;
+
+    let schedule = read(&path)?;
+    let mut after = Local::now().naive_local();
+    for _ in 0..count {
+        let Some((at, posture)) = schedule.next_change(after) else {
+            break;
+        };
+        println!("{}  {}", at.format("%a %Y-%m-%d %H:%M:%S"), posture.name());
+        after = at;
+    }
+    Ok(())
+}
+
+pub fn read(path: &str) -> Result<Schedule, Box<dyn std::error::Error>> {
+    let text = fs::read_to_string(path).map_err(|e| format!("reading {}: {}", path, e))?;
+    Ok(Schedule::parse(&text).map_err(|e| format!("{}: {}", path, e))?)
+}
+
+/// Reads the daemon's `--schedule` options. With user profiles or a fleet server there is always
+/// a scheduler, as either can bring a schedule of its own.
+pub fn scheduler(
+    args: &mut Args,
+    heights: Heights,
+    standing_threshold_cm: Option<f32>,
+    always: bool,
+) -> Result<Option<Scheduler>, Box<dyn std::error::Error>> {
+    let path = args.option("schedule");
+    let defaults = SchedulerOptions::default();
+    let options = SchedulerOptions {
+        sitting_cm: heights.sitting_cm.unwrap_or(defaults.sitting_cm),
+        standing_cm: heights.standing_cm.unwrap_or(defaults.standing_cm),
+        standing_threshold_cm: standing_threshold_cm.unwrap_or(defaults.standing_threshold_cm),
+        warning: match args.option("schedule-warning") {
+            Some(d) => parse_duration(&d)?,
+            None => defaults.warning,
+        },
+        back_off: match args.option("schedule-hold") {
+            Some(d) => parse_duration(&d)?,
+            None => defaults.back_off,
+        },
+    };
+    match path {
+        Some(path) => Ok(Some(Scheduler::new(read(&path)?, options))),
+        None if always => Ok(Some(Scheduler::new(Schedule::parse("")?, options))),
+        None => Ok(None),
+    }
+}
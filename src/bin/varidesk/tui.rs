@@ -0,0 +1,342 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style};
+use ratatui::text::{Line as TextLine, Span};
+use ratatui::widgets::{Block, Paragraph, Sparkline};
+use ratatui::{DefaultTerminal, Frame};
+
+use vari_desk_2020::capture::Record;
+use vari_desk_2020::decoder::Decoded;
+use vari_desk_2020::link::{Clock, Link, SimulatedDesk, SystemClock};
+use vari_desk_2020::panel::PanelEmulator;
+use vari_desk_2020::sim::DeskSimulator;
+use vari_desk_2020::tracker::{BusTracker, Movement};
+use vari_desk_2020::uart::UartConfig;
+use vari_desk_2020::{DeskProfile, Direction, PanelToDeskMessage};
+
+use crate::args::Args;
+use crate::daemon::open_link;
+use crate::monitor::{Highlight, Log};
+use crate::CommandResult;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+const HISTORY_LEN: usize = 600;
+const LOG_LEN: usize = 500;
+
+pub fn tui(mut args: Args) -> CommandResult {
+    let desk = args.option("desk");
+    let simulate = args.flag("simulate");
+    let baud = args
+        .parsed_option("baud")?
+        .unwrap_or(UartConfig::default().baud);
+    let presets = match args.option("presets") {
+        Some(list) => parse_presets(&list)?,
+        None => [None; 3],
+    };
+    args.finish()?;
+
+    let clock = SystemClock::new();
+    let link: Box<dyn Link> = match (desk, simulate) {
+        (Some(path), false) => open_link(&path, baud)?,
+        (None, true) => Box::new(SimulatedDesk::new(
+            DeskSimulator::new(DeskProfile::default(), 90.0),
+            clock,
+        )),
+        _ => return Err("expected either --desk <port> or --simulate".into()),
+    };
+
+    let mut dashboard = Dashboard::new(link, clock, presets);
+    let mut terminal = ratatui::init();
+    let result = dashboard.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+fn parse_presets(list: &str) -> Result<[Option<f32>; 3], String> {
+    let mut presets = [None; 3];
+    let heights: Vec<&str> = list.split(',').collect();
+    if heights.len() > 3 {
+        return Err(format!(
+            "--presets takes up to three heights, not {:?}",
+            list
+        ));
+    }
+    for (preset, height) in presets.iter_mut().zip(heights) {
+        *preset = Some(
+            height
+                .trim()
+                .trim_end_matches("cm")
+                .parse()
+                .map_err(|e| format!("--presets {:?}: {}", list, e))?,
+        );
+    }
+    Ok(presets)
+}
+
+/// Stands in for the panel: sends whatever key is held, or keep-alives, and shows what comes back.
+struct Dashboard {
+    link: Box<dyn Link>,
+    clock: SystemClock,
+    tracker: BusTracker,
+    presets: [Option<f32>; 3],
+    panel: PanelEmulator,
+    history: VecDeque<f32>,
+    last_sample: Option<Duration>,
+    log: Log,
+    lines: VecDeque<(Highlight, String)>,
+    status: String,
+}
+
+impl Dashboard {
+    fn new(link: Box<dyn Link>, clock: SystemClock, presets: [Option<f32>; 3]) -> Dashboard {
+        Dashboard {
+            link,
+            clock,
+            tracker: BusTracker::new(),
+            presets,
+            panel: PanelEmulator::new(),
+            history: VecDeque::new(),
+            last_sample: None,
+            log: Log::new(),
+            lines: VecDeque::new(),
+            status: String::new(),
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> CommandResult {
+        loop {
+            self.step()?;
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if !event::poll(Duration::from_millis(20))? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind == KeyEventKind::Release {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Up | KeyCode::Char('k') => self.hold(PanelToDeskMessage::Up),
+                KeyCode::Down | KeyCode::Char('j') => self.hold(PanelToDeskMessage::Down),
+                KeyCode::Char(' ') => self.panel.release(),
+                KeyCode::Char(c @ '1'..='3') => self.recall(c as usize - '1' as usize),
+                _ => {}
+            }
+        }
+    }
+
+    /// Presses a key on the panel. Terminals repeat held keys well within the length of a press.
+    fn hold(&mut self, message: PanelToDeskMessage) {
+        self.panel.press(message, self.clock.now());
+        self.status.clear();
+    }
+
+    fn recall(&mut self, preset: usize) {
+        let Some(height) = self.presets[preset].or(self.tracker.presets()[preset]) else {
+            self.status = format!("preset {} is not known; pass --presets", preset + 1);
+            return;
+        };
+        let message = match preset {
+            0 => PanelToDeskMessage::One(height),
+            1 => PanelToDeskMessage::Two(height),
+            _ => PanelToDeskMessage::Three(height),
+        };
+        self.hold(message);
+    }
+
+    fn step(&mut self) -> CommandResult {
+        let now = self.clock.now();
+
+        if let Some(frame) = self.panel.poll(now) {
+            self.link.send(&frame)?;
+            self.record(Direction::PanelToDesk, Decoded::Frame(frame), now);
+        }
+
+        while let Some(data) = self.link.recv()? {
+            if let Decoded::Frame(frame) = data {
+                self.panel.on_desk_frame(&frame);
+            }
+            self.record(Direction::DeskToPanel, data, self.clock.now());
+        }
+
+        if self
+            .last_sample
+            .is_none_or(|last| now >= last + SAMPLE_INTERVAL)
+        {
+            if let Some(height) = self.tracker.height_cm() {
+                self.history.push_back(height);
+                if self.history.len() > HISTORY_LEN {
+                    self.history.pop_front();
+                }
+                self.last_sample = Some(now);
+            }
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, direction: Direction, data: Decoded, now: Duration) {
+        self.tracker.observe(direction, &data, now);
+        let record = Record {
+            direction,
+            timestamp: now,
+            data,
+        };
+        for line in self.log.push(&record) {
+            self.lines.push_back((line.highlight, line.text));
+            if self.lines.len() > LOG_LEN {
+                self.lines.pop_front();
+            }
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [status, history, log, help] = Layout::vertical([
+            Constraint::Length(6),
+            Constraint::Length(8),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let now = self.clock.now();
+
+        frame.render_widget(
+            Paragraph::new(self.status_lines(now)).block(Block::bordered().title(" desk ")),
+            status,
+        );
+
+        // Scale to the range actually seen so that small movements are still visible.
+        let width = history.width.saturating_sub(2) as usize;
+        let shown: Vec<f32> = self
+            .history
+            .iter()
+            .rev()
+            .take(width)
+            .rev()
+            .copied()
+            .collect();
+        let low = shown.iter().copied().fold(f32::INFINITY, f32::min);
+        let high = shown.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let data: Vec<u64> = shown
+            .iter()
+            .map(|h| ((h - low) * 10.0).round() as u64 + 1)
+            .collect();
+        let title = if shown.is_empty() {
+            " height ".to_string()
+        } else {
+            format!(" height, last {}s: {:.1}-{:.1}cm ", shown.len(), low, high)
+        };
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(title))
+                .data(&data)
+                .max(((high - low) * 10.0).round() as u64 + 1),
+            history,
+        );
+
+        let rows = log.height.saturating_sub(2) as usize;
+        let lines: Vec<TextLine> = self
+            .lines
+            .iter()
+            .rev()
+            .take(rows)
+            .rev()
+            .map(|(highlight, text)| {
+                TextLine::styled(text.as_str(), Style::default().fg(color(*highlight)))
+            })
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" frames ")),
+            log,
+        );
+
+        frame.render_widget(
+            Paragraph::new(" up/down or k/j: move  1-3: recall preset  space: release  q: quit"),
+            help,
+        );
+    }
+
+    fn status_lines(&self, now: Duration) -> Vec<TextLine<'_>> {
+        let height = match self.tracker.height_cm() {
+            Some(h) => format!("{:.1}cm", h),
+            None => "unknown".to_string(),
+        };
+        let movement = match self.tracker.movement(now) {
+            Movement::Stopped => "stopped".to_string(),
+            Movement::Rising => format!("rising at {:.1}cm/s", self.tracker.speed_cm_per_s(now)),
+            Movement::Falling => format!("falling at {:.1}cm/s", self.tracker.speed_cm_per_s(now)),
+        };
+        let presets: Vec<String> = (0..3)
+            .map(|i| match self.presets[i].or(self.tracker.presets()[i]) {
+                Some(h) => format!("{}: {:.1}cm", i + 1, h),
+                None => format!("{}: -", i + 1),
+            })
+            .collect();
+
+        let stats = self.tracker.stats(Direction::DeskToPanel);
+        let (health, health_color) = match stats.last_seen {
+            None => ("no reports".to_string(), Color::Red),
+            Some(at) => {
+                let ago = now.saturating_sub(at);
+                let color = if ago < Duration::from_secs(1) && stats.checksum_failures == 0 {
+                    Color::Green
+                } else {
+                    Color::Yellow
+                };
+                (
+                    format!(
+                        "last report {:.1}s ago, {} frames, {} bad checksums, {} resyncs",
+                        ago.as_secs_f32(),
+                        stats.frames,
+                        stats.checksum_failures,
+                        stats.resyncs
+                    ),
+                    color,
+                )
+            }
+        };
+
+        vec![
+            TextLine::from(vec![
+                Span::raw("height   "),
+                Span::styled(height, Style::default().fg(Color::White)),
+                Span::raw(format!(", {}", movement)),
+            ]),
+            TextLine::from(format!("presets  {}", presets.join("  "))),
+            TextLine::from(vec![
+                Span::raw("link     "),
+                Span::styled(health, Style::default().fg(health_color)),
+            ]),
+            TextLine::styled(self.status.as_str(), Style::default().fg(Color::Yellow)),
+        ]
+    }
+}
+
+fn color(highlight: Highlight) -> Color {
+    match highlight {
+        Highlight::Panel => Color::Cyan,
+        Highlight::Desk => Color::Green,
+        Highlight::Unknown => Color::Yellow,
+        Highlight::Error => Color::Red,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_presets() {
+        assert_eq!(
+            parse_presets("72, 110.5cm").unwrap(),
+            [Some(72.0), Some(110.5), None]
+        );
+        assert!(parse_presets("1,2,3,4").is_err());
+        assert!(parse_presets("tall").is_err());
+    }
+}
@@ -0,0 +1,1202 @@
+//! Moves the desk for events in iCalendar files, say standing up for video calls.
+//!
+//! Only as much of RFC 5545 as calendar exports need is read: timed events with `DTEND` or
+//! `DURATION`, `TZID`s from the IANA database, and daily, weekly, monthly and yearly `RRULE`s with
+//! `INTERVAL`, `BYDAY`, `BYMONTHDAY`, `COUNT` and `UNTIL`, along with `EXDATE`s and rescheduled
+//! instances. All-day and cancelled events are ignored, and times in zones the database doesn't
+//! know are taken as local time.
+//!
+//! Rules say which events to move for, one per line:
+//!
+//! | Rule                      | Matches                                      |
+//! |---------------------------|----------------------------------------------|
+//! | `title standup stand`     | titles containing "standup", in any case     |
+//! | `category "Video call" 115cm` | events with the category "Video call"    |
+//!
+//! The height is `sit`, `stand` or a height in centimetres. The first rule that matches an event
+//! wins. When the last of a run of overlapping events ends, the desk goes back to where it was,
+//! unless someone has moved it in the meantime.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::TryRecvError;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{
+    DateTime, Datelike, Days, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone, Utc,
+    Weekday,
+};
+use chrono_tz::Tz;
+
+use crate::daemon::{Event, Handle};
+use crate::metrics::Posture;
+use crate::profile::Profile;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Moves more than this late, say after the machine was suspended, are dropped rather than made.
+const MAX_LATENESS: TimeDelta = TimeDelta::minutes(1);
+
+/// How far from where it was moved to the desk can be at the end of an event and still count as
+/// not having been touched.
+const MOVED_TOLERANCE_CM: f32 = 1.0;
+
+/// A limit on how many periods of a recurrence are looked at, for rules that never end.
+const MAX_PERIODS: u32 = 100_000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RulesError {
+    /// Numbered from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for RulesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for RulesError {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Zone {
+    Utc,
+    /// Local time wherever the calendar is read.
+    Floating,
+    Named(Tz),
+}
+
+/// A time as written in the calendar, in its own zone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Time {
+    local: NaiveDateTime,
+    zone: Zone,
+    all_day: bool,
+}
+
+impl Time {
+    /// The same instant as local time in `zone`.
+    fn in_zone(&self, zone: Zone) -> NaiveDateTime {
+        let utc = match self.zone {
+            Zone::Utc => Some(self.local.and_utc()),
+            Zone::Named(tz) => tz
+                .from_local_datetime(&self.local)
+                .earliest()
+                .map(|t| t.with_timezone(&Utc)),
+            Zone::Floating => None,
+        };
+        match (utc, zone) {
+            (Some(utc), Zone::Utc) => utc.naive_utc(),
+            (Some(utc), Zone::Named(tz)) => utc.with_timezone(&tz).naive_local(),
+            _ => self.local,
+        }
+    }
+}
+
+/// `local` in `zone` as local time in `tz`.
+fn local_time<Z: TimeZone>(local: NaiveDateTime, zone: Zone, tz: &Z) -> Option<NaiveDateTime> {
+    let utc: DateTime<Utc> = match zone {
+        Zone::Utc => local.and_utc(),
+        Zone::Named(z) => z
+            .from_local_datetime(&local)
+            .earliest()?
+            .with_timezone(&Utc),
+        Zone::Floating => return Some(local),
+    };
+    Some(utc.with_timezone(tz).naive_local())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Recurrence {
+    frequency: Frequency,
+    interval: u32,
+    /// Weekdays with an optional ordinal within the month, such as `2TU` or `-1FR`.
+    by_day: Vec<(Option<i32>, Weekday)>,
+    by_month_day: Vec<i32>,
+    count: Option<u32>,
+    until: Option<Time>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct CalendarEvent {
+    uid: String,
+    summary: String,
+    categories: Vec<String>,
+    start: Time,
+    duration: TimeDelta,
+    recurrence: Option<Recurrence>,
+    exdates: Vec<Time>,
+    recurrence_id: Option<Time>,
+}
+
+/// One instance of an event, in local time wherever the calendar is read.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Occurrence {
+    pub summary: String,
+    pub categories: Vec<String>,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Calendar {
+    events: Vec<CalendarEvent>,
+}
+
+impl Calendar {
+    /// Parses an iCalendar file, skipping events it can't make sense of.
+    pub fn parse(text: &str) -> Result<Calendar, String> {
+        let lines = unfold(text);
+        if !lines
+            .iter()
+            .any(|l| l.eq_ignore_ascii_case("BEGIN:VCALENDAR"))
+        {
+            return Err("not an iCalendar file".to_string());
+        }
+
+        let mut events = Vec::new();
+        let mut properties: Option<Vec<Property>> = None;
+        // Components nested in an event, such as alarms, whose properties aren't the event's.
+        let mut nested = 0;
+        for line in &lines {
+            let Some(property) = Property::parse(line) else {
+                continue;
+            };
+            match (
+                property.name.as_str(),
+                property.value.to_ascii_uppercase().as_str(),
+            ) {
+                ("BEGIN", "VEVENT") => properties = Some(Vec::new()),
+                ("END", "VEVENT") => {
+                    if let Some(event) = properties.take().and_then(|p| event(&p)) {
+                        events.push(event);
+                    }
+                    nested = 0;
+                }
+                ("BEGIN", _) if properties.is_some() => nested += 1,
+                ("END", _) if properties.is_some() => nested -= 1,
+                _ => {
+                    if let (Some(properties), 0) = (&mut properties, nested) {
+                        properties.push(property);
+                    }
+                }
+            }
+        }
+        Ok(Calendar { events })
+    }
+
+    /// Reads an `.ics` file, or every `.ics` file in a directory and the directories within it.
+    pub fn read(path: &Path) -> io::Result<Calendar> {
+        let mut calendar = Calendar::default();
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = fs::read_dir(path)?
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<io::Result<_>>()?;
+            entries.sort();
+            for entry in entries {
+                if entry.is_dir() || entry.extension().is_some_and(|e| e == "ics") {
+                    calendar.events.extend(Calendar::read(&entry)?.events);
+                }
+            }
+        } else {
+            let text = fs::read_to_string(path)?;
+            let parsed = Calendar::parse(&text).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), e),
+                )
+            })?;
+            calendar.events = parsed.events;
+        }
+        Ok(calendar)
+    }
+
+    /// Every instance of every event that overlaps `from..to`, in local time in `tz`, in order
+    /// of their start.
+    pub fn occurrences<Z: TimeZone>(
+        &self,
+        tz: &Z,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Vec<Occurrence> {
+        let mut occurrences = Vec::new();
+        for event in &self.events {
+            // Rescheduled instances stand in for the instance they replace.
+            let replaced: Vec<NaiveDateTime> = self
+                .events
+                .iter()
+                .filter(|e| e.uid == event.uid && event.recurrence_id.is_none())
+                .filter_map(|e| e.recurrence_id)
+                .map(|t| t.in_zone(event.start.zone))
+                .collect();
+            // Expanding a day either side in the event's own zone covers any difference in
+            // offsets.
+            let bound = to + TimeDelta::days(2);
+            for start in event.starts(bound) {
+                if replaced.contains(&start) {
+                    continue;
+                }
+                let Some(start_here) = local_time(start, event.start.zone, tz) else {
+                    continue;
+                };
+                let end_here = start_here + event.duration;
+                if start_here < to && end_here > from {
+                    occurrences.push(Occurrence {
+                        summary: event.summary.clone(),
+                        categories: event.categories.clone(),
+                        start: start_here,
+                        end: end_here,
+                    });
+                }
+            }
+        }
+        occurrences.sort_by_key(|o| o.start);
+        occurrences
+    }
+}
+
+impl CalendarEvent {
+    /// Start times of every instance up to `bound`, in the event's own zone.
+    fn starts(&self, bound: NaiveDateTime) -> Vec<NaiveDateTime> {
+        let start = self.start.local;
+        let Some(rule) = &self.recurrence else {
+            return vec![start];
+        };
+        let until = rule.until.map(|t| t.in_zone(self.start.zone));
+        let excluded: Vec<NaiveDateTime> = self
+            .exdates
+            .iter()
+            .map(|t| t.in_zone(self.start.zone))
+            .collect();
+
+        let mut starts = Vec::new();
+        let mut produced = 0;
+        for period in 0..MAX_PERIODS {
+            let n = period * rule.interval;
+            let dates = match rule.frequency {
+                Frequency::Daily => vec![start.date() + Days::new(n as u64)],
+                Frequency::Weekly => {
+                    let monday = start.date().week(Weekday::Mon).first_day();
+                    let week = monday + Days::new(7 * n as u64);
+                    let mut days: Vec<Weekday> = rule.by_day.iter().map(|(_, d)| *d).collect();
+                    if days.is_empty() {
+                        days.push(start.weekday());
+                    }
+                    days.sort_by_key(|d| d.num_days_from_monday());
+                    days.iter()
+                        .map(|d| week + Days::new(d.num_days_from_monday() as u64))
+                        .collect()
+                }
+                Frequency::Monthly => {
+                    let months = start.month0() + n;
+                    let (year, month) = (start.year() + (months / 12) as i32, months % 12 + 1);
+                    month_days(rule, year, month, start.day())
+                }
+                Frequency::Yearly => {
+                    NaiveDate::from_ymd_opt(start.year() + n as i32, start.month(), start.day())
+                        .into_iter()
+                        .collect()
+                }
+            };
+            if dates
+                .first()
+                .is_some_and(|d| d.and_time(start.time()) > bound)
+            {
+                break;
+            }
+
+            for date in dates {
+                let at = date.and_time(start.time());
+                if at < start {
+                    continue;
+                }
+                if at > bound
+                    || until.is_some_and(|until| at > until)
+                    || rule.count.is_some_and(|count| produced >= count)
+                {
+                    return starts;
+                }
+                produced += 1;
+                if !excluded.contains(&at) {
+                    starts.push(at);
+                }
+            }
+        }
+        starts
+    }
+}
+
+/// Days in a month a monthly rule falls on.
+fn month_days(rule: &Recurrence, year: i32, month: u32, start_day: u32) -> Vec<NaiveDate> {
+    let first = match NaiveDate::from_ymd_opt(year, month, 1) {
+        Some(first) => first,
+        None => return Vec::new(),
+    };
+    let length = first
+        .checked_add_months(chrono::Months::new(1))
+        .map_or(31, |next| (next - first).num_days() as i32);
+
+    let mut days: Vec<NaiveDate> = Vec::new();
+    for &(ordinal, weekday) in &rule.by_day {
+        let matching: Vec<NaiveDate> = (0..length)
+            .map(|i| first + Days::new(i as u64))
+            .filter(|d| d.weekday() == weekday)
+            .collect();
+        match ordinal {
+            None => days.extend(&matching),
+            Some(n) if n > 0 => days.extend(matching.get(n as usize - 1)),
+            Some(n) => days.extend(
+                matching
+                    .len()
+                    .checked_sub(n.unsigned_abs() as usize)
+                    .and_then(|i| matching.get(i)),
+            ),
+        }
+    }
+    for &day in &rule.by_month_day {
+        let day = if day < 0 { length + day + 1 } else { day };
+        days.extend(NaiveDate::from_ymd_opt(year, month, day as u32));
+    }
+    if rule.by_day.is_empty() && rule.by_month_day.is_empty() {
+        days.extend(NaiveDate::from_ymd_opt(year, month, start_day));
+    }
+    days.sort();
+    days.dedup();
+    days
+}
+
+/// Joins folded lines back together.
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Property {
+    /// Upper case.
+    name: String,
+    /// Names in upper case, values without quotes.
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn parse(line: &str) -> Option<Property> {
+        // The value starts at the first colon outside a quoted parameter value.
+        let mut quoted = false;
+        let colon = line.char_indices().find_map(|(i, c)| match c {
+            '"' => {
+                quoted = !quoted;
+                None
+            }
+            ':' if !quoted => Some(i),
+            _ => None,
+        })?;
+        let mut parts = line[..colon].split(';');
+        let name = parts.next()?.trim().to_ascii_uppercase();
+        let params = parts
+            .filter_map(|p| p.split_once('='))
+            .map(|(k, v)| (k.to_ascii_uppercase(), v.trim_matches('"').to_string()))
+            .collect();
+        Some(Property {
+            name,
+            params,
+            value: line[colon + 1..].to_string(),
+        })
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn time(&self) -> Option<Time> {
+        parse_time(&self.value, self.param("TZID"))
+    }
+}
+
+fn event(properties: &[Property]) -> Option<CalendarEvent> {
+    let find = |name: &str| properties.iter().find(|p| p.name == name);
+    if find("STATUS").is_some_and(|p| p.value.eq_ignore_ascii_case("CANCELLED")) {
+        return None;
+    }
+    let start = find("DTSTART")?.time()?;
+    if start.all_day {
+        return None;
+    }
+    let duration = match (find("DTEND"), find("DURATION")) {
+        (Some(end), _) => end.time()?.in_zone(start.zone) - start.local,
+        (None, Some(duration)) => parse_duration(&duration.value)?,
+        (None, None) => return None,
+    };
+    if duration <= TimeDelta::zero() {
+        return None;
+    }
+
+    let mut categories = Vec::new();
+    let mut exdates = Vec::new();
+    for property in properties {
+        match property.name.as_str() {
+            "CATEGORIES" => categories.extend(split_list(&property.value)),
+            "EXDATE" => exdates.extend(
+                property
+                    .value
+                    .split(',')
+                    .filter_map(|v| parse_time(v, property.param("TZID"))),
+            ),
+            _ => {}
+        }
+    }
+    Some(CalendarEvent {
+        uid: find("UID").map(|p| p.value.clone()).unwrap_or_default(),
+        summary: find("SUMMARY")
+            .map(|p| unescape(&p.value))
+            .unwrap_or_default(),
+        categories,
+        start,
+        duration,
+        recurrence: find("RRULE").and_then(|p| parse_recurrence(&p.value)),
+        exdates,
+        recurrence_id: find("RECURRENCE-ID").and_then(Property::time),
+    })
+}
+
+fn parse_time(value: &str, tzid: Option<&str>) -> Option<Time> {
+    let value = value.trim();
+    if value.len() == 8 {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d").ok()?;
+        return Some(Time {
+            local: date.and_time(NaiveTime::MIN),
+            zone: Zone::Floating,
+            all_day: true,
+        });
+    }
+    let (value, utc) = match value.strip_suffix(['Z', 'z']) {
+        Some(value) => (value, true),
+        None => (value, false),
+    };
+    let local = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+    let zone = match (utc, tzid.and_then(|id| id.parse::<Tz>().ok())) {
+        (true, _) => Zone::Utc,
+        (false, Some(tz)) => Zone::Named(tz),
+        (false, None) => Zone::Floating,
+    };
+    Some(Time {
+        local,
+        zone,
+        all_day: false,
+    })
+}
+
+/// Parses durations such as `PT1H30M` and `P1D`.
+fn parse_duration(value: &str) -> Option<TimeDelta> {
+    let (sign, value) = match value.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let mut seconds = 0i64;
+    let mut number = String::new();
+    for c in value.strip_prefix('P')?.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' => {}
+            'W' | 'D' | 'H' | 'M' | 'S' => {
+                let n: i64 = number.parse().ok()?;
+                number.clear();
+                seconds += n * match c {
+                    'W' => 7 * 24 * 60 * 60,
+                    'D' => 24 * 60 * 60,
+                    'H' => 60 * 60,
+                    'M' => 60,
+                    _ => 1,
+                };
+            }
+            _ => return None,
+        }
+    }
+    Some(TimeDelta::seconds(sign * seconds))
+}
+
+fn parse_recurrence(value: &str) -> Option<Recurrence> {
+    let mut rule = Recurrence {
+        frequency: Frequency::Daily,
+        interval: 1,
+        by_day: Vec::new(),
+        by_month_day: Vec::new(),
+        count: None,
+        until: None,
+    };
+    let mut frequency = None;
+    for part in value.split(';') {
+        let (key, value) = part.split_once('=')?;
+        match key.to_ascii_uppercase().as_str() {
+            "FREQ" => {
+                frequency = Some(match value.to_ascii_uppercase().as_str() {
+                    "DAILY" => Frequency::Daily,
+                    "WEEKLY" => Frequency::Weekly,
+                    "MONTHLY" => Frequency::Monthly,
+                    "YEARLY" => Frequency::Yearly,
+                    // Hourly and finer aren't something anyone sets for a meeting.
+                    _ => return None,
+                })
+            }
+            "INTERVAL" => rule.interval = value.parse().ok().filter(|&n| n > 0)?,
+            "COUNT" => rule.count = Some(value.parse().ok()?),
+            "UNTIL" => rule.until = Some(parse_time(value, None)?),
+            "BYDAY" => {
+                for day in value.split(',') {
+                    let split = day.len().checked_sub(2)?;
+                    let ordinal = match &day[..split] {
+                        "" => None,
+                        n => Some(n.trim_start_matches('+').parse().ok()?),
+                    };
+                    rule.by_day.push((ordinal, parse_weekday(&day[split..])?));
+                }
+            }
+            "BYMONTHDAY" => {
+                for day in value.split(',') {
+                    rule.by_month_day.push(day.parse().ok()?);
+                }
+            }
+            // Anything else narrows the rule in ways we don't follow, so the event is better
+            // ignored than moved for at the wrong times.
+            "WKST" => {}
+            _ => return None,
+        }
+    }
+    rule.frequency = frequency?;
+    Some(rule)
+}
+
+fn parse_weekday(day: &str) -> Option<Weekday> {
+    Some(match day.to_ascii_uppercase().as_str() {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return None,
+    })
+}
+
+/// Splits a list on commas that aren't escaped, unescaping each item.
+fn split_list(value: &str) -> Vec<String> {
+    let mut items = vec![String::new()];
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let item = items.last_mut().unwrap();
+                item.push('\\');
+                item.extend(chars.next());
+            }
+            ',' => items.push(String::new()),
+            _ => items.last_mut().unwrap().push(c),
+        }
+    }
+    items
+        .iter()
+        .map(|item| unescape(item.trim()))
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn unescape(value: &str) -> String {
+    let mut out = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => out.push('\n'),
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Match {
+    /// Lower case.
+    Title(String),
+    Category(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    Posture(Posture),
+    Height(f32),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rules {
+    rules: Vec<(Match, Target)>,
+}
+
+impl Rules {
+    pub fn parse(text: &str) -> Result<Rules, RulesError> {
+        let mut rules = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let error = |message: String| RulesError {
+                line: i + 1,
+                message,
+            };
+            let words = split_words(line).map_err(|m| error(m.to_string()))?;
+            if words.is_empty() {
+                continue;
+            }
+            let [kind, pattern, target] = &words[..] else {
+                return Err(error(
+                    "expected `title|category <pattern> <height>`".to_string(),
+                ));
+            };
+            let matches = match kind.as_str() {
+                "title" => Match::Title(pattern.to_lowercase()),
+                "category" => Match::Category(pattern.clone()),
+                _ => return Err(error(format!("expected title or category, not {:?}", kind))),
+            };
+            rules.push((matches, parse_target(target).map_err(error)?));
+        }
+        Ok(Rules { rules })
+    }
+
+    /// Where to move for an event, if anywhere.
+    pub fn target(&self, occurrence: &Occurrence) -> Option<Target> {
+        let title = occurrence.summary.to_lowercase();
+        self.rules
+            .iter()
+            .find(|(matches, _)| match matches {
+                Match::Title(pattern) => title.contains(pattern.as_str()),
+                Match::Category(category) => occurrence
+                    .categories
+                    .iter()
+                    .any(|c| c.eq_ignore_ascii_case(category)),
+            })
+            .map(|(_, target)| *target)
+    }
+}
+
+/// Splits a rule into words, keeping double-quoted words whole and dropping `#` comments.
+fn split_words(line: &str) -> Result<Vec<String>, &'static str> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c == '#' {
+            break;
+        } else if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let word: String = chars.by_ref().take_while(|&c| c != '"').collect();
+            words.push(word);
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek().filter(|c| !c.is_whitespace()) {
+                word.push(c);
+                chars.next();
+            }
+            words.push(word);
+        }
+    }
+    if !line.matches('"').count().is_multiple_of(2) {
+        return Err("unterminated quote");
+    }
+    Ok(words)
+}
+
+fn parse_target(word: &str) -> Result<Target, String> {
+    match word {
+        "sit" | "sitting" => Ok(Target::Posture(Posture::Sitting)),
+        "stand" | "standing" => Ok(Target::Posture(Posture::Standing)),
+        _ => word
+            .strip_suffix("cm")
+            .unwrap_or(word)
+            .parse()
+            .map(Target::Height)
+            .map_err(|_| format!("expected sit, stand or a height in cm, not {:?}", word)),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CalendarOptions {
+    pub sitting_cm: f32,
+    pub standing_cm: f32,
+}
+
+impl Default for CalendarOptions {
+    fn default() -> Self {
+        CalendarOptions {
+            sitting_cm: 72.0,
+            standing_cm: 110.0,
+        }
+    }
+}
+
+impl CalendarOptions {
+    fn height(&self, target: Target) -> f32 {
+        match target {
+            Target::Posture(Posture::Sitting) => self.sitting_cm,
+            Target::Posture(Posture::Standing) => self.standing_cm,
+            Target::Height(h) => h,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Step {
+    /// Move for the start of an event.
+    Move { height_cm: f32 },
+    /// Go back to where the desk was before the run of events.
+    Return,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlannedMove {
+    pub at: NaiveDateTime,
+    pub step: Step,
+    /// The event that starts or ends.
+    pub summary: String,
+}
+
+/// Works out the moves for `occurrences`, which must be in order of their start.
+pub fn plan(
+    occurrences: &[Occurrence],
+    rules: &Rules,
+    options: &CalendarOptions,
+) -> Vec<PlannedMove> {
+    let mut moves = Vec::new();
+    // The end, height and title of the run of overlapping events we're in.
+    let mut run: Option<(NaiveDateTime, f32, String)> = None;
+    for occurrence in occurrences {
+        let Some(target) = rules.target(occurrence) else {
+            continue;
+        };
+        let height_cm = options.height(target);
+        let summary = occurrence.summary.clone();
+        match &mut run {
+            Some((end, height, last)) if occurrence.start <= *end => {
+                if height_cm != *height {
+                    moves.push(PlannedMove {
+                        at: occurrence.start,
+                        step: Step::Move { height_cm },
+                        summary: summary.clone(),
+                    });
+                    *height = height_cm;
+                }
+                if occurrence.end >= *end {
+                    (*end, *last) = (occurrence.end, summary);
+                }
+            }
+            _ => {
+                if let Some((end, _, last)) = run.take() {
+                    moves.push(PlannedMove {
+                        at: end,
+                        step: Step::Return,
+                        summary: last,
+                    });
+                }
+                moves.push(PlannedMove {
+                    at: occurrence.start,
+                    step: Step::Move { height_cm },
+                    summary: summary.clone(),
+                });
+                run = Some((occurrence.end, height_cm, summary));
+            }
+        }
+    }
+    if let Some((end, _, last)) = run {
+        moves.push(PlannedMove {
+            at: end,
+            step: Step::Return,
+            summary: last,
+        });
+    }
+    moves
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    MoveTo {
+        height_cm: f32,
+        reason: String,
+    },
+    /// The desk was moved during an event, so it is left where it is at the end.
+    LeftAlone {
+        reason: String,
+    },
+}
+
+/// Follows a calendar, working out when to move the desk.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CalendarMover {
+    calendar: Calendar,
+    rules: Rules,
+    options: CalendarOptions,
+    last_poll: Option<NaiveDateTime>,
+    // Where the desk was before the current run of events, and where we moved it to.
+    return_to: Option<f32>,
+    moved_to: Option<f32>,
+}
+
+impl CalendarMover {
+    pub fn new(calendar: Calendar, rules: Rules, options: CalendarOptions) -> CalendarMover {
+        CalendarMover {
+            calendar,
+            rules,
+            options,
+            last_poll: None,
+            return_to: None,
+            moved_to: None,
+        }
+    }
+
+    pub fn set_calendar(&mut self, calendar: Calendar) {
+        self.calendar = calendar;
+    }
+
+    pub fn set_options(&mut self, options: CalendarOptions) {
+        self.options = options;
+    }
+
+    /// The moves planned for the day containing `now`, in `tz`.
+    pub fn plan_day<Z: TimeZone>(&self, tz: &Z, day: NaiveDate) -> Vec<PlannedMove> {
+        let midnight = day.and_time(NaiveTime::MIN);
+        let occurrences = self
+            .calendar
+            .occurrences(tz, midnight, midnight + TimeDelta::days(1));
+        plan(&occurrences, &self.rules, &self.options)
+    }
+
+    /// Returns what to do now, given the desk's height. Moves are only made from the first poll
+    /// after they are due, so starting up in the middle of an event doesn't move the desk.
+    pub fn poll<Z: TimeZone>(
+        &mut self,
+        tz: &Z,
+        now: NaiveDateTime,
+        height_cm: Option<f32>,
+    ) -> Option<Action> {
+        let last_poll = self.last_poll.replace(now)?;
+        let occurrences =
+            self.calendar
+                .occurrences(tz, now - TimeDelta::days(1), now + TimeDelta::minutes(1));
+        let due = plan(&occurrences, &self.rules, &self.options)
+            .into_iter()
+            .rfind(|m| m.at > last_poll && m.at <= now && now - m.at <= MAX_LATENESS)?;
+
+        match due.step {
+            Step::Move { height_cm: target } => {
+                if self.return_to.is_none() {
+                    self.return_to = height_cm;
+                }
+                self.moved_to = Some(target);
+                Some(Action::MoveTo {
+                    height_cm: target,
+                    reason: format!("start of {}", due.summary),
+                })
+            }
+            Step::Return => {
+                let (back, moved_to) = (self.return_to.take()?, self.moved_to.take()?);
+                if height_cm.is_some_and(|h| (h - moved_to).abs() <= MOVED_TOLERANCE_CM) {
+                    Some(Action::MoveTo {
+                        height_cm: back,
+                        reason: format!("end of {}", due.summary),
+                    })
+                } else {
+                    Some(Action::LeftAlone {
+                        reason: format!("the desk was moved during {}", due.summary),
+                    })
+                }
+            }
+        }
+    }
+}
+
+/// Moves the desk for events in the calendar at `path`, read again every minute so that changes
+/// synced in by other tools are picked up, until the daemon goes away.
+pub fn run_calendar(handle: Handle, path: PathBuf, mut mover: CalendarMover) {
+    let events = handle.subscribe();
+    let base = mover.options;
+    // Sitting and standing mean the active user's heights.
+    let options = |profile: Option<Profile>| match profile {
+        Some(profile) => CalendarOptions {
+            sitting_cm: profile.sitting_cm,
+            standing_cm: profile.standing_cm,
+        },
+        None => base,
+    };
+    mover.set_options(options(handle.active_profile()));
+    let mut loaded = Instant::now();
+    loop {
+        loop {
+            match events.try_recv() {
+                Ok(Event::ProfileActivated { .. }) => {
+                    mover.set_options(options(handle.active_profile()))
+                }
+                Ok(_) => {}
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+
+        if loaded.elapsed() >= RELOAD_INTERVAL {
+            match Calendar::read(&path) {
+                Ok(calendar) => mover.set_calendar(calendar),
+                Err(e) => handle.notify("calendar", format!("{}: {}", path.display(), e)),
+            }
+            loaded = Instant::now();
+        }
+
+        let now = Local::now().naive_local();
+        match mover.poll(&Local, now, handle.state().height_cm) {
+            Some(Action::MoveTo { height_cm, reason }) => {
+                handle.notify(
+                    "calendar",
+                    format!("moving to {}cm for the {}", height_cm, reason),
+                );
+                if let Err(e) = handle.move_to(height_cm) {
+                    handle.notify("calendar", e);
+                }
+            }
+            Some(Action::LeftAlone { reason }) => {
+                handle.notify("calendar", format!("not moving back, {}", reason))
+            }
+            None => {}
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+
+    const ICS: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+UID:standup\r
+SUMMARY:Team standup\r
+DTSTART;TZID=Europe/London:20240304T093000\r
+DTEND;TZID=Europe/London:20240304T094500\r
+RRULE:FREQ=WEEKLY;BYDAY=MO,WE,FR;COUNT=6\r
+EXDATE;TZID=Europe/London:20240306T093000\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:standup\r
+RECURRENCE-ID;TZID=Europe/London:20240308T093000\r
+SUMMARY:Team standup (moved)\r
+DTSTART;TZID=Europe/London:20240308T110000\r
+DURATION:PT15M\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:review\r
+SUMMARY:Design review\\, part 2\r
+CATEGORIES:Meeting,Video call\r
+DTSTART:20240304T094000Z\r
+DTEND:20240304T103000Z\r
+BEGIN:VALARM\r
+TRIGGER:-PT5M\r
+DURATION:PT1M\r
+END:VALARM\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:holiday\r
+SUMMARY:Standup-free day\r
+DTSTART;VALUE=DATE:20240305\r
+DTEND;VALUE=DATE:20240306\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:cancelled\r
+SUMMARY:Standup\r
+STATUS:CANCELLED\r
+DTSTART:20240304T150000Z\r
+DTEND:20240304T160000Z\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:lunch\r
+SUMMARY:Lunch and a very long title that has been folded onto a\r
+  second line\r
+DTSTART:20240304T120000\r
+DTEND:20240304T130000\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    /// Central European time in winter, an hour ahead of London and UTC.
+    fn cet() -> FixedOffset {
+        FixedOffset::east_opt(60 * 60).unwrap()
+    }
+
+    fn rules() -> Rules {
+        Rules::parse("# standing for calls\ntitle standup stand\ncategory \"video call\" 115cm\n")
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let calendar = Calendar::parse(ICS).unwrap();
+        assert_eq!(calendar.events.len(), 4);
+        assert_eq!(calendar.events[2].summary, "Design review, part 2");
+        assert_eq!(calendar.events[2].categories, ["Meeting", "Video call"]);
+        assert_eq!(calendar.events[2].duration, TimeDelta::minutes(50));
+        assert_eq!(
+            calendar.events[3].summary,
+            "Lunch and a very long title that has been folded onto a second line"
+        );
+        assert!(Calendar::parse("BEGIN:VCARD\nEND:VCARD\n").is_err());
+
+        assert_eq!(parse_duration("PT1H30M"), Some(TimeDelta::minutes(90)));
+        assert_eq!(parse_duration("P1W"), Some(TimeDelta::days(7)));
+        assert_eq!(split_list("a\\,b,c"), ["a,b", "c"]);
+    }
+
+    #[test]
+    fn test_occurrences() {
+        let calendar = Calendar::parse(ICS).unwrap();
+        let occurrences =
+            calendar.occurrences(&cet(), at("2024-03-04 00:00"), at("2024-03-16 00:00"));
+        let summary: Vec<(String, &str)> = occurrences
+            .iter()
+            .map(|o| {
+                (
+                    o.start.format("%a %d %H:%M").to_string(),
+                    o.summary.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("Mon 04 10:30".to_string(), "Team standup"),
+                ("Mon 04 10:40".to_string(), "Design review, part 2"),
+                // Floating times are local wherever the calendar is read.
+                (
+                    "Mon 04 12:00".to_string(),
+                    "Lunch and a very long title that has been folded onto a second line"
+                ),
+                ("Fri 08 12:00".to_string(), "Team standup (moved)"),
+                ("Mon 11 10:30".to_string(), "Team standup"),
+                ("Wed 13 10:30".to_string(), "Team standup"),
+                ("Fri 15 10:30".to_string(), "Team standup"),
+            ]
+        );
+        // The count of six includes the excluded and the rescheduled instances.
+        assert_eq!(occurrences[4].end, at("2024-03-11 10:45"));
+    }
+
+    #[test]
+    fn test_monthly_recurrence() {
+        let rule = parse_recurrence("FREQ=MONTHLY;BYDAY=-1FR").unwrap();
+        let days = month_days(&rule, 2024, 3, 1);
+        assert_eq!(days, [NaiveDate::from_ymd_opt(2024, 3, 29).unwrap()]);
+        let rule = parse_recurrence("FREQ=MONTHLY;BYDAY=2TU;INTERVAL=2").unwrap();
+        assert_eq!(rule.interval, 2);
+        assert_eq!(
+            month_days(&rule, 2024, 3, 1),
+            [NaiveDate::from_ymd_opt(2024, 3, 12).unwrap()]
+        );
+        assert_eq!(parse_recurrence("FREQ=MONTHLY;BYSETPOS=1"), None);
+    }
+
+    #[test]
+    fn test_plan() {
+        let calendar = Calendar::parse(ICS).unwrap();
+        let mover = CalendarMover::new(calendar, rules(), CalendarOptions::default());
+        let plan = mover.plan_day(&cet(), NaiveDate::from_ymd_opt(2024, 3, 4).unwrap());
+        assert_eq!(
+            plan,
+            [
+                PlannedMove {
+                    at: at("2024-03-04 10:30"),
+                    step: Step::Move { height_cm: 110.0 },
+                    summary: "Team standup".to_string(),
+                },
+                PlannedMove {
+                    at: at("2024-03-04 10:40"),
+                    step: Step::Move { height_cm: 115.0 },
+                    summary: "Design review, part 2".to_string(),
+                },
+                PlannedMove {
+                    at: at("2024-03-04 11:30"),
+                    step: Step::Return,
+                    summary: "Design review, part 2".to_string(),
+                },
+            ]
+        );
+        assert!(Rules::parse("title \"open").is_err());
+        assert_eq!(Rules::parse("\ntime x stand").unwrap_err().line, 2);
+    }
+
+    #[test]
+    fn test_mover() {
+        let calendar = Calendar::parse(ICS).unwrap();
+        let mut mover = CalendarMover::new(calendar, rules(), CalendarOptions::default());
+        let tz = cet();
+        assert_eq!(mover.poll(&tz, at("2024-03-04 10:29"), Some(72.0)), None);
+        assert_eq!(
+            mover.poll(&tz, at("2024-03-04 10:30"), Some(72.0)),
+            Some(Action::MoveTo {
+                height_cm: 110.0,
+                reason: "start of Team standup".to_string()
+            })
+        );
+        assert_eq!(
+            mover.poll(&tz, at("2024-03-04 10:40"), Some(110.0)),
+            Some(Action::MoveTo {
+                height_cm: 115.0,
+                reason: "start of Design review, part 2".to_string()
+            })
+        );
+        assert_eq!(
+            mover.poll(&tz, at("2024-03-04 11:30"), Some(115.0)),
+            Some(Action::MoveTo {
+                height_cm: 72.0,
+                reason: "end of Design review, part 2".to_string()
+            })
+        );
+
+        // Someone sat down during the meeting, so the desk stays where they put it.
+        assert!(mover
+            .poll(&tz, at("2024-03-11 10:30"), Some(72.0))
+            .is_some());
+        assert_eq!(
+            mover.poll(&tz, at("2024-03-11 10:45"), Some(80.0)),
+            Some(Action::LeftAlone {
+                reason: "the desk was moved during Team standup".to_string()
+            })
+        );
+
+        // Moves missed while asleep aren't caught up on.
+        assert_eq!(mover.poll(&tz, at("2024-03-13 10:40"), Some(72.0)), None);
+    }
+}
@@ -0,0 +1,492 @@
+//! A versioned file format for bus recordings.
+//!
+//! A capture starts with a header:
+//!
+//! | bytes | contents                                                  |
+//! |-------|-----------------------------------------------------------|
+//! | 4     | magic `VDCP`                                              |
+//! | 2     | format version, little endian                             |
+//! | 4     | desk profile minimum height in cm, `f32` little endian    |
+//! | 4     | desk profile maximum height in cm, `f32` little endian    |
+//! | 2     | number of metadata entries                                |
+//! | ...   | per entry: key length (2), key, value length (2), value   |
+//!
+//! followed by records until the end of the file. Each record is a kind byte (0 frame,
+//! 1 invalid frame, 2 stray byte), a direction byte (0 panel to desk, 1 desk to panel), the
+//! monotonic timestamp in nanoseconds as a little endian `u64`, and then either the seven frame
+//! bytes or the single stray byte.
+
+use core::fmt;
+use core::time::Duration;
+
+use crate::decoder::Decoded;
+use crate::{DeskProfile, Direction};
+
+pub const CAPTURE_MAGIC: [u8; 4] = *b"VDCP";
+pub const CAPTURE_VERSION: u16 = 1;
+
+const KIND_FRAME: u8 = 0u8;
+const KIND_INVALID: u8 = 1u8;
+const KIND_STRAY: u8 = 2u8;
+
+const DIRECTION_PANEL_TO_DESK: u8 = 0u8;
+const DIRECTION_DESK_TO_PANEL: u8 = 1u8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Record {
+    pub direction: Direction,
+    pub timestamp: Duration,
+    pub data: Decoded,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Header<'a> {
+    pub profile: DeskProfile,
+    pub metadata: &'a [(&'a str, &'a str)],
+}
+
+/// Somewhere to put capture bytes. Anything implementing `std::io::Write` is a sink when the
+/// `std` feature is enabled; on a microcontroller, implement it for a UART or flash writer.
+pub trait Sink {
+    type Error;
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> Sink for W {
+    type Error = std::io::Error;
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        std::io::Write::write_all(self, bytes)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum WriteError<E> {
+    Sink(E),
+    MetadataTooLong,
+}
+
+impl<E: fmt::Display> fmt::Display for WriteError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::Sink(e) => write!(f, "failed to write capture: {}", e),
+            WriteError::MetadataTooLong => write!(f, "capture metadata is too long"),
+        }
+    }
+}
+
+impl<E> From<E> for WriteError<E> {
+    fn from(e: E) -> Self {
+        WriteError::Sink(e)
+    }
+}
+
+pub struct CaptureWriter<S: Sink> {
+    sink: S,
+}
+
+impl<S: Sink> CaptureWriter<S> {
+    pub fn new(mut sink: S, header: &Header) -> Result<CaptureWriter<S>, WriteError<S::Error>> {
+        let count =
+            u16::try_from(header.metadata.len()).map_err(|_| WriteError::MetadataTooLong)?;
+
+        sink.write_all(&CAPTURE_MAGIC)?;
+        sink.write_all(&CAPTURE_VERSION.to_le_bytes())?;
+        sink.write_all(&header.profile.min_height_cm.to_le_bytes())?;
+        sink.write_all(&header.profile.max_height_cm.to_le_bytes())?;
+        sink.write_all(&count.to_le_bytes())?;
+
+        for (key, value) in header.metadata {
+            for s in [key, value] {
+                let len = u16::try_from(s.len()).map_err(|_| WriteError::MetadataTooLong)?;
+                sink.write_all(&len.to_le_bytes())?;
+                sink.write_all(s.as_bytes())?;
+            }
+        }
+
+        Ok(CaptureWriter { sink })
+    }
+
+    pub fn write(&mut self, record: &Record) -> Result<(), S::Error> {
+        let (kind, bytes): (u8, &[u8]) = match &record.data {
+            Decoded::Frame(frame) => (KIND_FRAME, frame),
+            Decoded::Invalid(frame) => (KIND_INVALID, frame),
+            Decoded::Stray(b) => (KIND_STRAY, core::slice::from_ref(b)),
+        };
+        let direction = match record.direction {
+            Direction::PanelToDesk => DIRECTION_PANEL_TO_DESK,
+            Direction::DeskToPanel => DIRECTION_DESK_TO_PANEL,
+        };
+        let nanos = record.timestamp.as_nanos() as u64;
+
+        self.sink.write_all(&[kind, direction])?;
+        self.sink.write_all(&nanos.to_le_bytes())?;
+        self.sink.write_all(bytes)
+    }
+
+    /// The sink, for flushing it between records.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+}
+
+#[cfg(feature = "std")]
+pub use self::std_io::*;
+
+#[cfg(feature = "std")]
+mod std_io {
+    use std::io::{self, Read, Write};
+
+    use super::*;
+    use crate::{DataFrame, DeskProfileError, DATA_FRAME_SIZE};
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct CaptureInfo {
+        pub version: u16,
+        pub profile: DeskProfile,
+        pub metadata: Vec<(String, String)>,
+    }
+
+    #[derive(Debug)]
+    pub enum ReadError {
+        Io(io::Error),
+        BadMagic,
+        UnsupportedVersion(u16),
+        BadRecord { kind: u8, direction: u8 },
+        Truncated,
+        BadProfile(DeskProfileError),
+    }
+
+    impl fmt::Display for ReadError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                ReadError::Io(e) => write!(f, "failed to read capture: {}", e),
+                ReadError::BadMagic => write!(f, "not a capture file"),
+                ReadError::UnsupportedVersion(v) => {
+                    write!(f, "unsupported capture version {}", v)
+                }
+                ReadError::BadRecord { kind, direction } => write!(
+                    f,
+                    "bad record with kind {} and direction {}",
+                    kind, direction
+                ),
+                ReadError::Truncated => write!(f, "capture ends part way through a record"),
+                ReadError::BadProfile(e) => write!(f, "bad desk profile: {}", e),
+            }
+        }
+    }
+
+    impl std::error::Error for ReadError {}
+
+    impl From<io::Error> for ReadError {
+        fn from(e: io::Error) -> Self {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                ReadError::Truncated
+            } else {
+                ReadError::Io(e)
+            }
+        }
+    }
+
+    pub struct CaptureReader<R: Read> {
+        reader: R,
+        info: CaptureInfo,
+    }
+
+    impl<R: Read> CaptureReader<R> {
+        pub fn new(mut reader: R) -> Result<CaptureReader<R>, ReadError> {
+            let mut magic = [0u8; 4];
+            reader.read_exact(&mut magic)?;
+            if magic != CAPTURE_MAGIC {
+                return Err(ReadError::BadMagic);
+            }
+
+            let version = read_u16(&mut reader)?;
+            if version != CAPTURE_VERSION {
+                return Err(ReadError::UnsupportedVersion(version));
+            }
+
+            let profile = DeskProfile::new(read_f32(&mut reader)?, read_f32(&mut reader)?)
+                .map_err(ReadError::BadProfile)?;
+
+            let count = read_u16(&mut reader)?;
+            let mut metadata = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                let key = read_string(&mut reader)?;
+                let value = read_string(&mut reader)?;
+                metadata.push((key, value));
+            }
+
+            Ok(CaptureReader {
+                reader,
+                info: CaptureInfo {
+                    version,
+                    profile,
+                    metadata,
+                },
+            })
+        }
+
+        pub fn info(&self) -> &CaptureInfo {
+            &self.info
+        }
+
+        fn read_record(&mut self) -> Result<Option<Record>, ReadError> {
+            let mut tag = [0u8; 2];
+            match self.reader.read(&mut tag[..1])? {
+                0 => return Ok(None),
+                _ => self.reader.read_exact(&mut tag[1..])?,
+            }
+
+            let direction = match tag[1] {
+                DIRECTION_PANEL_TO_DESK => Direction::PanelToDesk,
+                DIRECTION_DESK_TO_PANEL => Direction::DeskToPanel,
+                _ => {
+                    return Err(ReadError::BadRecord {
+                        kind: tag[0],
+                        direction: tag[1],
+                    })
+                }
+            };
+
+            let mut nanos = [0u8; 8];
+            self.reader.read_exact(&mut nanos)?;
+            let timestamp = Duration::from_nanos(u64::from_le_bytes(nanos));
+
+            let data = match tag[0] {
+                KIND_FRAME => Decoded::Frame(read_frame(&mut self.reader)?),
+                KIND_INVALID => Decoded::Invalid(read_frame(&mut self.reader)?),
+                KIND_STRAY => {
+                    let mut b = [0u8; 1];
+                    self.reader.read_exact(&mut b)?;
+                    Decoded::Stray(b[0])
+                }
+                _ => {
+                    return Err(ReadError::BadRecord {
+                        kind: tag[0],
+                        direction: tag[1],
+                    })
+                }
+            };
+
+            Ok(Some(Record {
+                direction,
+                timestamp,
+                data,
+            }))
+        }
+    }
+
+    impl<R: Read> Iterator for CaptureReader<R> {
+        type Item = Result<Record, ReadError>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            self.read_record().transpose()
+        }
+    }
+
+    /// Writes the header and then every record as one JSON object per line.
+    pub fn export_json_lines<R: Read, W: Write>(
+        reader: CaptureReader<R>,
+        mut out: W,
+    ) -> Result<(), ReadError> {
+        let info = reader.info().clone();
+        write!(
+            out,
+            "{{\"version\":{},\"min_height_cm\":{},\"max_height_cm\":{},\"metadata\":{{",
+            info.version, info.profile.min_height_cm, info.profile.max_height_cm
+        )?;
+        for (i, (key, value)) in info.metadata.iter().enumerate() {
+            if i > 0 {
+                write!(out, ",")?;
+            }
+            write!(out, "{}:{}", json_string(key), json_string(value))?;
+        }
+        writeln!(out, "}}}}")?;
+
+        for record in reader {
+            writeln!(out, "{}", record_json(&record?))?;
+        }
+
+        Ok(())
+    }
+
+    /// Renders a record as a single-line JSON object.
+    pub fn record_json(record: &Record) -> String {
+        let (kind, bytes) = match &record.data {
+            Decoded::Frame(frame) => ("frame", &frame[..]),
+            Decoded::Invalid(frame) => ("invalid", &frame[..]),
+            Decoded::Stray(b) => ("stray", core::slice::from_ref(b)),
+        };
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+        let mut json = format!(
+            "{{\"timestamp_ns\":{},\"direction\":{},\"kind\":\"{}\",\"bytes\":\"{}\"",
+            record.timestamp.as_nanos(),
+            json_string(&record.direction.to_string()),
+            kind,
+            hex.join(" ")
+        );
+        if let Decoded::Frame(frame) = &record.data {
+            let message = match record.direction {
+                Direction::PanelToDesk => crate::PanelToDeskMessage::from_frame(frame).to_string(),
+                Direction::DeskToPanel => crate::DeskToPanelMessage::from_frame(frame).to_string(),
+            };
+            json.push_str(&format!(",\"message\":{}", json_string(&message)));
+        }
+        json.push('}');
+        json
+    }
+
+    pub(crate) fn json_string(s: &str) -> String {
+        let mut out = String::with_capacity(s.len() + 2);
+        out.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+        out
+    }
+
+    fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+        let mut b = [0u8; 2];
+        reader.read_exact(&mut b)?;
+        Ok(u16::from_le_bytes(b))
+    }
+
+    fn read_f32<R: Read>(reader: &mut R) -> io::Result<f32> {
+        let mut b = [0u8; 4];
+        reader.read_exact(&mut b)?;
+        Ok(f32::from_le_bytes(b))
+    }
+
+    fn read_string<R: Read>(reader: &mut R) -> Result<String, ReadError> {
+        let len = read_u16(reader)?;
+        let mut b = vec![0u8; len as usize];
+        reader.read_exact(&mut b)?;
+        String::from_utf8(b)
+            .map_err(|e| ReadError::Io(io::Error::new(io::ErrorKind::InvalidData, e)))
+    }
+
+    fn read_frame<R: Read>(reader: &mut R) -> io::Result<DataFrame> {
+        let mut frame = [0u8; DATA_FRAME_SIZE];
+        reader.read_exact(&mut frame)?;
+        Ok(frame)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{DeskToPanelMessage, PanelToDeskMessage};
+
+    fn records() -> [Record; 3] {
+        [
+            Record {
+                direction: Direction::PanelToDesk,
+                timestamp: Duration::from_nanos(1),
+                data: Decoded::Frame(PanelToDeskMessage::Up.as_frame()),
+            },
+            Record {
+                direction: Direction::DeskToPanel,
+                timestamp: Duration::from_millis(20),
+                data: Decoded::Invalid([104u8, 1, 0, 0, 0, 1, 0]),
+            },
+            Record {
+                direction: Direction::DeskToPanel,
+                timestamp: Duration::from_secs(3600),
+                data: Decoded::Stray(42u8),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_capture_round_trip() {
+        let header = Header {
+            profile: DeskProfile::default(),
+            metadata: &[("desk", "3rd floor"), ("note", "stuck at 100\"")],
+        };
+        let mut writer = CaptureWriter::new(Vec::new(), &header).unwrap();
+        for record in records().iter() {
+            writer.write(record).unwrap();
+        }
+        let bytes = writer.into_inner();
+
+        let reader = CaptureReader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.info().version, CAPTURE_VERSION);
+        assert_eq!(reader.info().profile, DeskProfile::default());
+        assert_eq!(reader.info().metadata[1].1, "stuck at 100\"");
+
+        let read: Vec<Record> = reader.map(|r| r.unwrap()).collect();
+        assert_eq!(read, records());
+    }
+
+    #[test]
+    fn test_capture_reader_errors() {
+        assert!(matches!(
+            CaptureReader::new(&b"PCAP"[..]),
+            Err(ReadError::BadMagic)
+        ));
+        assert!(matches!(
+            CaptureReader::new(&b"VDCP\x02\x00"[..]),
+            Err(ReadError::UnsupportedVersion(2))
+        ));
+
+        let header = Header {
+            profile: DeskProfile::default(),
+            metadata: &[],
+        };
+        let mut writer = CaptureWriter::new(Vec::new(), &header).unwrap();
+        writer.write(&records()[0]).unwrap();
+        let bytes = writer.into_inner();
+
+        let mut reader = CaptureReader::new(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(matches!(reader.next(), Some(Err(ReadError::Truncated))));
+    }
+
+    #[test]
+    fn test_export_json_lines() {
+        let header = Header {
+            profile: DeskProfile::default(),
+            metadata: &[("desk", "a\"b")],
+        };
+        let mut writer = CaptureWriter::new(Vec::new(), &header).unwrap();
+        writer
+            .write(&Record {
+                direction: Direction::DeskToPanel,
+                timestamp: Duration::from_millis(5),
+                data: Decoded::Frame(DeskToPanelMessage::Height(100.0).as_frame()),
+            })
+            .unwrap();
+        writer.write(&records()[2]).unwrap();
+        let bytes = writer.into_inner();
+
+        let mut out = Vec::new();
+        export_json_lines(CaptureReader::new(&bytes[..]).unwrap(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+
+        assert_eq!(
+            lines,
+            [
+                "{\"version\":1,\"min_height_cm\":65,\"max_height_cm\":129.5,\"metadata\":{\"desk\":\"a\\\"b\"}}",
+                "{\"timestamp_ns\":5000000,\"direction\":\"desk->panel\",\"kind\":\"frame\",\"bytes\":\"68 01 00 01 5e 60 16\",\"message\":\"height 100.0cm\"}",
+                "{\"timestamp_ns\":3600000000000,\"direction\":\"desk->panel\",\"kind\":\"stray\",\"bytes\":\"2a\"}",
+            ]
+        );
+    }
+}
@@ -0,0 +1,1391 @@
+//! A long-running owner of the bus, shared with any number of clients through a [`Handle`].
+//!
+//! In pass-through mode the daemon sits between the panel and the desk on two serial ports and
+//! forwards everything, taking over from the panel only while it moves the desk itself. In
+//! panel-replacement mode the panel is unplugged and the daemon keeps the desk alive on its own.
+//!
+//! Moves are made by holding Up or Down until the reported height reaches the target, so they
+//! work the same whether or not the desk honours the height carried in preset frames.
+//!
+//! Presets programmed through the daemon, or by activating a [`Profile`], take the place of the
+//! panel's own: in pass-through mode the height in the panel's preset frames is rewritten on its
+//! way to the desk.
+//!
+//! With a duty cycle set, moves the daemon is asked to make are refused, or deferred, when they
+//! would run the motor for longer than is left of its budget, and ones under way are stopped when
+//! the budget runs out. Until the desk has reported its height, moves are refused outright.
+//!
+//! The daemon also keeps an eye on the health of the bus, and stops a move of its own the moment
+//! the desk stops reporting its height.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::decoder::Decoded;
+use crate::display::{DisplayOverride, Undisplayable, DISPLAY_RANGE};
+use crate::duty::{DutyCycle, DutyCycleOptions};
+use crate::health::{HealthMonitor, HealthOptions, LinkHealth};
+use crate::json::Value;
+use crate::link::{Clock, Link};
+use crate::metrics::BusMetrics;
+use crate::profile::{Gesture, Profile, ProfileStore};
+use crate::tracker::{BusTracker, LinkStats, Movement};
+use crate::{validate_checksum, DeskProfile, Direction, PanelToDeskMessage};
+
+/// How long the panel shows the number of a profile switched to with the panel gesture.
+const PROFILE_DISPLAY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A slower speed than desks really manage, used to estimate how long the motor will run for a
+/// move without underestimating it.
+const ESTIMATED_SPEED_CM_PER_S: f32 = 3.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    PassThrough,
+    PanelReplacement,
+}
+
+impl Mode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mode::PassThrough => "pass-through",
+            Mode::PanelReplacement => "panel-replacement",
+        }
+    }
+}
+
+pub fn movement_name(movement: Movement) -> &'static str {
+    match movement {
+        Movement::Stopped => "stopped",
+        Movement::Rising => "rising",
+        Movement::Falling => "falling",
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DaemonOptions {
+    pub profile: DeskProfile,
+    /// How close to the target a move has to get to count as done.
+    pub tolerance_cm: f32,
+    /// How often the daemon sends a frame while it is driving the desk.
+    pub send_interval: Duration,
+    /// How long a move may go without the height changing before it is given up.
+    pub stall_timeout: Duration,
+    /// Heights from here up count as standing.
+    pub standing_threshold_cm: f32,
+    pub duty_cycle: Option<DutyCycleOptions>,
+    pub health: HealthOptions,
+}
+
+impl Default for DaemonOptions {
+    fn default() -> Self {
+        DaemonOptions {
+            profile: DeskProfile::default(),
+            tolerance_cm: 0.5,
+            send_interval: Duration::from_millis(50),
+            stall_timeout: Duration::from_secs(2),
+            standing_threshold_cm: 90.0,
+            duty_cycle: None,
+            health: HealthOptions::default(),
+        }
+    }
+}
+
+/// Something that happened, as told to subscribers.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    Height(f32),
+    Movement(Movement),
+    /// A key pressed on the panel itself.
+    PanelKey(PanelToDeskMessage),
+    MoveStarted {
+        target_cm: f32,
+    },
+    MoveFinished {
+        height_cm: Option<f32>,
+    },
+    MoveCancelled {
+        reason: String,
+    },
+    /// A move put off until the motor's duty cycle allows it, `seconds` from now.
+    MoveDeferred {
+        target_cm: f32,
+        seconds: f32,
+    },
+    PresetProgrammed {
+        preset: usize,
+        height_cm: f32,
+    },
+    ProfileActivated {
+        name: String,
+    },
+    /// Schedule rules pushed to the daemon, or `None` to go back to its own.
+    ScheduleChanged {
+        schedule: Option<String>,
+    },
+    /// A change in the health of one direction of the bus.
+    LinkHealth {
+        direction: Direction,
+        health: LinkHealth,
+    },
+    /// Something the daemon or one of the helpers running alongside it has to tell its user,
+    /// such as a lost broker connection. `source` names the helper, like `"mqtt"`.
+    Notice {
+        source: &'static str,
+        message: String,
+    },
+}
+
+impl Event {
+    /// The event type, as used for the event stream.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Height(_) => "height",
+            Event::Movement(_) => "movement",
+            Event::PanelKey(_) => "key",
+            Event::MoveStarted { .. }
+            | Event::MoveFinished { .. }
+            | Event::MoveCancelled { .. }
+            | Event::MoveDeferred { .. } => "move",
+            Event::PresetProgrammed { .. } => "preset",
+            Event::ProfileActivated { .. } => "profile",
+            Event::ScheduleChanged { .. } => "schedule",
+            Event::LinkHealth { .. } => "health",
+            Event::Notice { .. } => "notice",
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            Event::Height(h) => Value::object([("height_cm", (*h).into())]),
+            Event::Movement(m) => Value::object([("movement", movement_name(*m).into())]),
+            Event::PanelKey(key) => Value::object([("key", key.to_string().into())]),
+            Event::MoveStarted { target_cm } => Value::object([
+                ("status", "started".into()),
+                ("target_cm", (*target_cm).into()),
+            ]),
+            Event::MoveFinished { height_cm } => Value::object([
+                ("status", "finished".into()),
+                ("height_cm", (*height_cm).into()),
+            ]),
+            Event::MoveCancelled { reason } => Value::object([
+                ("status", "cancelled".into()),
+                ("reason", reason.as_str().into()),
+            ]),
+            Event::PresetProgrammed { preset, height_cm } => Value::object([
+                ("preset", (preset + 1).into()),
+                ("height_cm", (*height_cm).into()),
+            ]),
+            Event::MoveDeferred { target_cm, seconds } => Value::object([
+                ("status", "deferred".into()),
+                ("target_cm", (*target_cm).into()),
+                ("seconds", (*seconds).into()),
+            ]),
+            Event::ProfileActivated { name } => Value::object([("profile", name.as_str().into())]),
+            Event::ScheduleChanged { schedule } => {
+                Value::object([("schedule", schedule.clone().into())])
+            }
+            Event::LinkHealth { direction, health } => Value::object([
+                ("link", link_name(*direction).into()),
+                ("health", health.name().into()),
+            ]),
+            Event::Notice { source, message } => Value::object([
+                ("source", (*source).into()),
+                ("message", message.as_str().into()),
+            ]),
+        }
+    }
+}
+
+fn link_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::PanelToDesk => "panel",
+        Direction::DeskToPanel => "desk",
+    }
+}
+
+/// A snapshot of everything the daemon knows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct State {
+    pub mode: Mode,
+    pub profile: DeskProfile,
+    /// Time since the daemon started.
+    pub uptime: Duration,
+    pub height_cm: Option<f32>,
+    pub movement: Movement,
+    pub speed_cm_per_s: f32,
+    /// Where the daemon is moving the desk to, if it is.
+    pub target_cm: Option<f32>,
+    pub presets: [Option<f32>; 3],
+    /// The name of the active user profile.
+    pub user: Option<String>,
+    /// Schedule rules pushed to the daemon in place of the ones it was started with.
+    pub schedule: Option<String>,
+    /// How much longer the motor may run under its duty cycle, if it has one.
+    pub motor_budget: Option<Duration>,
+    pub panel: LinkStats,
+    pub desk: LinkStats,
+    /// The health of what the panel sends, in pass-through mode.
+    pub panel_health: Option<LinkHealth>,
+    pub desk_health: LinkHealth,
+}
+
+impl State {
+    pub fn to_json(&self) -> Value {
+        let link = |stats: &LinkStats, health: Option<LinkHealth>| {
+            Value::object([
+                ("health", health.map(|h| h.name()).into()),
+                ("frames", stats.frames.into()),
+                ("checksum_failures", stats.checksum_failures.into()),
+                ("resyncs", stats.resyncs.into()),
+                (
+                    "seconds_since_last_frame",
+                    stats
+                        .last_seen
+                        .map(|at| self.uptime.saturating_sub(at).as_secs_f64())
+                        .into(),
+                ),
+            ])
+        };
+        Value::object([
+            ("mode", self.mode.name().into()),
+            ("height_cm", self.height_cm.into()),
+            ("movement", movement_name(self.movement).into()),
+            ("speed_cm_per_s", self.speed_cm_per_s.into()),
+            ("target_cm", self.target_cm.into()),
+            ("presets", self.presets.to_vec().into()),
+            ("user", self.user.clone().into()),
+            ("schedule", self.schedule.clone().into()),
+            (
+                "motor_budget_s",
+                self.motor_budget.map(|b| b.as_secs_f64()).into(),
+            ),
+            ("min_height_cm", self.profile.min_height_cm.into()),
+            ("max_height_cm", self.profile.max_height_cm.into()),
+            ("panel", link(&self.panel, self.panel_health)),
+            ("desk", link(&self.desk, Some(self.desk_health))),
+        ])
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Command {
+    MoveTo(f32),
+    Stop,
+    ProgramPreset(usize, f32),
+    ShowOnPanel(f32, Duration),
+    SetLimits(DeskProfile),
+    ActivateProfile(Profile),
+    SetSchedule(Option<String>),
+}
+
+struct Shared {
+    state: State,
+    metrics: BusMetrics,
+    profiles: ProfileStore,
+    commands: VecDeque<Command>,
+    subscribers: Vec<Sender<Event>>,
+}
+
+/// The way in to a running daemon, for API servers and other integrations. Clones share the
+/// same daemon and can be sent to other threads.
+#[derive(Clone)]
+pub struct Handle {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Handle {
+    fn lock(&self) -> MutexGuard<'_, Shared> {
+        // Nothing holding the lock can leave the state half-updated, so a panic elsewhere doesn't
+        // make it unusable.
+        self.shared.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn state(&self) -> State {
+        self.lock().state.clone()
+    }
+
+    pub fn metrics(&self) -> BusMetrics {
+        self.lock().metrics.clone()
+    }
+
+    /// Starts moving the desk, and returns the target after clamping it to the desk's range.
+    /// Moves to a height, clamped to the desk's limits, and returns the height it will move to.
+    pub fn move_to(&self, height_cm: f32) -> Result<f32, String> {
+        let height_cm = finite(height_cm)?;
+        let mut shared = self.lock();
+        let target = shared.state.profile.clamp(height_cm);
+        shared.commands.push_back(Command::MoveTo(target));
+        Ok(target)
+    }
+
+    pub fn stop(&self) {
+        self.lock().commands.push_back(Command::Stop);
+    }
+
+    /// Moves to a preset, numbered from 1, and returns its height.
+    pub fn recall_preset(&self, preset: usize) -> Result<f32, String> {
+        let height = self
+            .state()
+            .presets
+            .get(preset.wrapping_sub(1))
+            .copied()
+            .ok_or_else(|| format!("there is no preset {}", preset))?
+            .ok_or_else(|| format!("preset {} has not been set", preset))?;
+        self.move_to(height)
+    }
+
+    /// Sets a preset, numbered from 1, and returns the height it was set to after clamping.
+    pub fn program_preset(&self, preset: usize, height_cm: f32) -> Result<f32, String> {
+        if !(1..=3).contains(&preset) {
+            return Err(format!("there is no preset {}", preset));
+        }
+        let height_cm = finite(height_cm)?;
+        let mut shared = self.lock();
+        let height = shared.state.profile.clamp(height_cm);
+        shared
+            .commands
+            .push_back(Command::ProgramPreset(preset - 1, height));
+        Ok(height)
+    }
+
+    /// Changes the range moves and presets are clamped to, for a desk whose travel has been
+    /// limited, say to clear a windowsill. Programmed presets and a move under way are clamped
+    /// to the new range too.
+    pub fn set_limits(&self, limits: DeskProfile) -> Result<(), String> {
+        let limits = limits.validated().map_err(|e| e.to_string())?;
+        let mut shared = self.lock();
+        shared.state.profile = limits;
+        shared.commands.push_back(Command::SetLimits(limits));
+        Ok(())
+    }
+
+    /// Replaces the schedule the daemon was started with by `rules`, or goes back to it with
+    /// `None`. Only has an effect if a scheduler is running.
+    pub fn set_schedule(&self, rules: Option<String>) {
+        self.lock().commands.push_back(Command::SetSchedule(rules));
+    }
+
+    /// Shows `value` on the panel in place of the height for up to `timeout`, or until the desk
+    /// moves. Does nothing in panel-replacement mode.
+    pub fn show_on_panel(&self, value: f32, timeout: Duration) -> Result<(), Undisplayable> {
+        if !DISPLAY_RANGE.contains(&value) {
+            return Err(Undisplayable(value));
+        }
+        self.lock()
+            .commands
+            .push_back(Command::ShowOnPanel(value, timeout));
+        Ok(())
+    }
+
+    pub fn profiles(&self) -> Vec<Profile> {
+        self.lock().profiles.profiles().to_vec()
+    }
+
+    pub fn profile(&self, name: &str) -> Option<Profile> {
+        self.lock().profiles.get(name).cloned()
+    }
+
+    pub fn active_profile(&self) -> Option<Profile> {
+        self.lock().profiles.active().cloned()
+    }
+
+    /// Adds or replaces a profile, and returns it after clamping its heights to the desk's
+    /// range. Replacing the active profile applies the changes straight away.
+    pub fn save_profile(&self, profile: Profile) -> io::Result<Profile> {
+        let mut shared = self.lock();
+        let profile = profile.clamped(&shared.state.profile);
+        shared.profiles.insert(profile.clone())?;
+        if shared.state.user.as_ref() == Some(&profile.name) {
+            shared
+                .commands
+                .push_back(Command::ActivateProfile(profile.clone()));
+        }
+        Ok(profile)
+    }
+
+    /// Removes a profile, and returns whether there was one. Its presets stay programmed if it
+    /// was active.
+    pub fn delete_profile(&self, name: &str) -> io::Result<bool> {
+        self.lock().profiles.remove(name)
+    }
+
+    pub fn activate_profile(&self, name: &str) -> Result<Profile, String> {
+        let mut shared = self.lock();
+        let profile = shared
+            .profiles
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("there is no profile {:?}", name))?;
+        shared
+            .profiles
+            .set_active(Some(name))
+            .map_err(|e| format!("saving profiles: {}", e))?;
+        shared
+            .commands
+            .push_back(Command::ActivateProfile(profile.clone()));
+        Ok(profile)
+    }
+
+    /// Returns a channel that receives every event from now on.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (tx, rx) = mpsc::channel();
+        self.lock().subscribers.push(tx);
+        rx
+    }
+
+    /// Tells subscribers about something, as an [`Event::Notice`] from `source`.
+    pub fn notify(&self, source: &'static str, message: String) {
+        let event = Event::Notice { source, message };
+        self.lock()
+            .subscribers
+            .retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
+
+/// Clamping passes NaN through, so heights from clients are checked before they are.
+fn finite(height_cm: f32) -> Result<f32, String> {
+    if !height_cm.is_finite() {
+        return Err(format!("{} is not a height", height_cm));
+    }
+    Ok(height_cm)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Move {
+    target_cm: f32,
+    key: Option<PanelToDeskMessage>,
+    /// When the height last changed, and what it was.
+    progress: (Duration, Option<f32>),
+}
+
+pub struct Daemon<C: Clock> {
+    desk: Box<dyn Link>,
+    panel: Option<Box<dyn Link>>,
+    clock: C,
+    options: DaemonOptions,
+    handle: Handle,
+    tracker: BusTracker,
+    display: DisplayOverride,
+    gesture: Gesture,
+    programmed: [Option<f32>; 3],
+    moving: Option<Move>,
+    duty: Option<DutyCycle>,
+    // A move waiting for the duty cycle, and when it can be made.
+    deferred: Option<(f32, Duration)>,
+    health: HealthMonitor,
+    link_health: [LinkHealth; 2],
+    last_send: Option<Duration>,
+    // What subscribers were last told, so that only changes are published.
+    last_height: Option<f32>,
+    last_movement: Movement,
+    last_key: Option<PanelToDeskMessage>,
+    events: Vec<Event>,
+    // Frames seen and sent since the last publish, for the shared metrics.
+    observed: Vec<(Direction, Decoded, Duration)>,
+    sent: u64,
+}
+
+impl<C: Clock> Daemon<C> {
+    /// Runs in pass-through mode if there is a link to the panel, and in panel-replacement mode
+    /// otherwise.
+    pub fn new(
+        desk: Box<dyn Link>,
+        panel: Option<Box<dyn Link>>,
+        clock: C,
+        options: DaemonOptions,
+    ) -> Daemon<C> {
+        let mode = match panel {
+            Some(_) => Mode::PassThrough,
+            None => Mode::PanelReplacement,
+        };
+        let started = clock.now();
+        let state = State {
+            mode,
+            profile: options.profile,
+            uptime: started,
+            height_cm: None,
+            movement: Movement::Stopped,
+            speed_cm_per_s: 0.0,
+            target_cm: None,
+            presets: [None; 3],
+            user: None,
+            schedule: None,
+            motor_budget: options.duty_cycle.map(|d| d.max_on),
+            panel: LinkStats::default(),
+            desk: LinkStats::default(),
+            panel_health: panel.as_ref().map(|_| LinkHealth::Ok),
+            desk_health: LinkHealth::Ok,
+        };
+        Daemon {
+            desk,
+            panel,
+            clock,
+            options,
+            handle: Handle {
+                shared: Arc::new(Mutex::new(Shared {
+                    state,
+                    metrics: BusMetrics::new(options.standing_threshold_cm),
+                    profiles: ProfileStore::new(),
+                    commands: VecDeque::new(),
+                    subscribers: Vec::new(),
+                })),
+            },
+            tracker: BusTracker::new(),
+            display: DisplayOverride::new(),
+            gesture: Gesture::new(),
+            programmed: [None; 3],
+            moving: None,
+            duty: options
+                .duty_cycle
+                .map(|d| DutyCycle::new(d.max_on, d.window)),
+            deferred: None,
+            health: HealthMonitor::new(options.health, started),
+            link_health: [LinkHealth::Ok; 2],
+            last_send: None,
+            last_height: None,
+            last_movement: Movement::Stopped,
+            last_key: None,
+            events: Vec::new(),
+            observed: Vec::new(),
+            sent: 0,
+        }
+    }
+
+    /// Takes its user profiles from `store`, activating the one that was active when it was
+    /// last saved.
+    pub fn with_profiles(self, store: ProfileStore) -> Daemon<C> {
+        {
+            let mut shared = self.handle.lock();
+            if let Some(active) = store.active() {
+                let profile = active.clone().clamped(&shared.state.profile);
+                shared.commands.push_back(Command::ActivateProfile(profile));
+            }
+            shared.profiles = store;
+        }
+        self
+    }
+
+    pub fn handle(&self) -> Handle {
+        self.handle.clone()
+    }
+
+    pub fn tracker(&self) -> &BusTracker {
+        &self.tracker
+    }
+
+    /// Runs until a link fails.
+    pub fn run(&mut self) -> io::Result<()> {
+        loop {
+            if !self.step()? {
+                self.clock.sleep(Duration::from_millis(2));
+            }
+        }
+    }
+
+    /// Does whatever is due now, and returns whether anything arrived on either link.
+    pub fn step(&mut self) -> io::Result<bool> {
+        let now = self.clock.now();
+        let commands: Vec<Command> = self.handle.lock().commands.drain(..).collect();
+        for command in commands {
+            self.apply(command, now)?;
+        }
+
+        let mut busy = false;
+        while let Some(data) = self.desk.recv()? {
+            busy = true;
+            self.observe(Direction::DeskToPanel, &data, now);
+            if let (Some(panel), Decoded::Frame(frame) | Decoded::Invalid(frame)) =
+                (&mut self.panel, data)
+            {
+                panel.send(&self.display.desk_to_panel(&frame, now))?;
+            }
+        }
+
+        let mut from_panel = Vec::new();
+        if let Some(panel) = &mut self.panel {
+            while let Some(data) = panel.recv()? {
+                from_panel.push(data);
+            }
+        }
+        for data in from_panel {
+            busy = true;
+            self.on_panel(data, now)?;
+        }
+
+        self.supervise(now)?;
+        self.check_duty_cycle(now)?;
+        self.drive(now)?;
+        self.publish(now);
+        Ok(busy)
+    }
+
+    fn apply(&mut self, command: Command, now: Duration) -> io::Result<()> {
+        match command {
+            Command::MoveTo(target_cm) => {
+                self.deferred = None;
+                if self.duty.is_some() && self.tracker.height_cm().is_none() {
+                    // Without a height there is no telling how long the motor would run.
+                    self.events.push(Event::MoveCancelled {
+                        reason: "height unknown, so the motor duty cycle can't be checked"
+                            .to_string(),
+                    });
+                    return Ok(());
+                }
+                match self.motor_available_at(target_cm, now) {
+                    Some(at) if at <= now => self.start_move(target_cm, now),
+                    Some(at) if self.options.duty_cycle.is_some_and(|d| d.defer) => {
+                        self.deferred = Some((target_cm, at));
+                        self.events.push(Event::MoveDeferred {
+                            target_cm,
+                            seconds: (at - now).as_secs_f32(),
+                        });
+                    }
+                    _ => self.events.push(Event::MoveCancelled {
+                        reason: "motor duty cycle exceeded".to_string(),
+                    }),
+                }
+            }
+            Command::Stop => {
+                self.deferred = None;
+                if self.moving.is_some() {
+                    self.cancel("stopped", now)?;
+                }
+            }
+            Command::ProgramPreset(preset, height_cm) => {
+                self.programmed[preset] = Some(height_cm);
+                self.events
+                    .push(Event::PresetProgrammed { preset, height_cm });
+            }
+            Command::ShowOnPanel(value, timeout) => {
+                if let Err(e) = self.display.show(value, now, timeout) {
+                    self.events.push(Event::Notice {
+                        source: "display",
+                        message: e.to_string(),
+                    });
+                }
+            }
+            Command::SetLimits(limits) => {
+                for (preset, programmed) in self.programmed.iter_mut().enumerate() {
+                    let Some(height_cm) = *programmed else {
+                        continue;
+                    };
+                    let clamped = limits.clamp(height_cm);
+                    if clamped != height_cm {
+                        *programmed = Some(clamped);
+                        self.events.push(Event::PresetProgrammed {
+                            preset,
+                            height_cm: clamped,
+                        });
+                    }
+                }
+                if let Some(moving) = &mut self.moving {
+                    moving.target_cm = limits.clamp(moving.target_cm);
+                }
+                if let Some((target_cm, _)) = &mut self.deferred {
+                    *target_cm = limits.clamp(*target_cm);
+                }
+            }
+            Command::ActivateProfile(profile) => {
+                self.programmed = profile.presets;
+                self.handle.lock().state.user = Some(profile.name.clone());
+                self.events
+                    .push(Event::ProfileActivated { name: profile.name });
+            }
+            Command::SetSchedule(schedule) => {
+                self.handle.lock().state.schedule = schedule.clone();
+                self.events.push(Event::ScheduleChanged { schedule });
+            }
+        }
+        Ok(())
+    }
+
+    fn start_move(&mut self, target_cm: f32, now: Duration) {
+        self.moving = Some(Move {
+            target_cm,
+            key: None,
+            progress: (now, self.tracker.height_cm()),
+        });
+        self.events.push(Event::MoveStarted { target_cm });
+    }
+
+    /// When the duty cycle would next allow the motor to run long enough to reach `target_cm`,
+    /// or `None` if it never would or the height isn't known.
+    fn motor_available_at(&self, target_cm: f32, now: Duration) -> Option<Duration> {
+        let Some(duty) = &self.duty else {
+            return Some(now);
+        };
+        let distance = (target_cm - self.tracker.height_cm()?).abs();
+        let needed = Duration::from_secs_f32(distance / ESTIMATED_SPEED_CM_PER_S);
+        duty.available_at(needed, now)
+    }
+
+    /// Keeps track of the motor's running time, and stops it when the duty cycle is used up.
+    fn check_duty_cycle(&mut self, now: Duration) -> io::Result<()> {
+        let running = self.tracker.movement(now) != Movement::Stopped
+            || self.moving.is_some_and(|m| m.key.is_some());
+        let Some(duty) = &mut self.duty else {
+            return Ok(());
+        };
+        duty.set_running(running, now);
+        if self.moving.is_some() && duty.remaining(now).is_zero() {
+            self.cancel("motor duty cycle used up", now)?;
+        }
+        if let Some((target_cm, at)) = self.deferred {
+            if now >= at {
+                self.deferred = None;
+                self.start_move(target_cm, now);
+            }
+        }
+        Ok(())
+    }
+
+    /// Tells subscribers about changes in the health of the bus, and stops a move as soon as the
+    /// desk's height can no longer be seen.
+    fn supervise(&mut self, now: Duration) -> io::Result<()> {
+        let health = self.health.check(&self.tracker, now);
+        for direction in [Direction::PanelToDesk, Direction::DeskToPanel] {
+            let i = direction as usize;
+            let watched = direction == Direction::DeskToPanel || self.panel.is_some();
+            if watched && health[i] != self.link_health[i] {
+                self.events.push(Event::LinkHealth {
+                    direction,
+                    health: health[i],
+                });
+            }
+        }
+        self.link_health = health;
+
+        let desk = health[Direction::DeskToPanel as usize];
+        if self.moving.is_some() && desk == LinkHealth::Silent {
+            self.cancel("desk stopped reporting its height", now)?;
+        }
+        Ok(())
+    }
+
+    fn on_panel(&mut self, data: Decoded, now: Duration) -> io::Result<()> {
+        self.observe(Direction::PanelToDesk, &data, now);
+        let frame = match data {
+            // Corrupt frames are recorded above but never forwarded to the desk.
+            Decoded::Frame(frame) if validate_checksum(&frame) => frame,
+            _ => return Ok(()),
+        };
+
+        let message = PanelToDeskMessage::from_frame(&frame);
+        let moves_desk = matches!(
+            message,
+            PanelToDeskMessage::Up
+                | PanelToDeskMessage::Down
+                | PanelToDeskMessage::One(_)
+                | PanelToDeskMessage::Two(_)
+                | PanelToDeskMessage::Three(_)
+        );
+        if self.moving.is_some() {
+            if !moves_desk {
+                // We are sending our own key in place of the panel's keep-alives.
+                return Ok(());
+            }
+            self.cancel("panel key pressed", now)?;
+        }
+        if self.gesture.on_key(message, now) {
+            self.next_profile(now);
+        }
+
+        let exhausted = self
+            .duty
+            .as_ref()
+            .is_some_and(|d| d.remaining(now).is_zero())
+            && !self.options.duty_cycle.is_some_and(|d| d.panel_overrides);
+        if exhausted && moves_desk {
+            return self.desk.send(&PanelToDeskMessage::NoKey.as_frame());
+        }
+
+        let frame = match message {
+            PanelToDeskMessage::One(_) => self.programmed[0].map(PanelToDeskMessage::One),
+            PanelToDeskMessage::Two(_) => self.programmed[1].map(PanelToDeskMessage::Two),
+            PanelToDeskMessage::Three(_) => self.programmed[2].map(PanelToDeskMessage::Three),
+            _ => None,
+        }
+        .map_or(frame, |m| m.as_frame());
+        self.desk.send(&frame)
+    }
+
+    /// Switches to the next user profile, showing its number on the panel.
+    fn next_profile(&mut self, now: Duration) {
+        let next = {
+            let mut shared = self.handle.lock();
+            let desk = shared.state.profile;
+            let Some(next) = shared.profiles.next().cloned() else {
+                return;
+            };
+            if let Err(e) = shared.profiles.set_active(Some(&next.name)) {
+                self.events.push(Event::Notice {
+                    source: "profiles",
+                    message: format!("saving: {}", e),
+                });
+            }
+            let number = shared
+                .profiles
+                .profiles()
+                .iter()
+                .position(|p| p.name == next.name);
+            (next.clamped(&desk), number.unwrap_or(0) + 1)
+        };
+        if let Err(e) = self
+            .display
+            .show(next.1 as f32, now, PROFILE_DISPLAY_TIMEOUT)
+        {
+            self.events.push(Event::Notice {
+                source: "profiles",
+                message: e.to_string(),
+            });
+        }
+        if let Err(e) = self.apply(Command::ActivateProfile(next.0), now) {
+            self.events.push(Event::Notice {
+                source: "profiles",
+                message: format!("activating: {}", e),
+            });
+        }
+    }
+
+    fn observe(&mut self, direction: Direction, data: &Decoded, now: Duration) {
+        self.tracker.observe(direction, data, now);
+        self.observed.push((direction, *data, now));
+    }
+
+    fn cancel(&mut self, reason: &str, now: Duration) -> io::Result<()> {
+        self.moving = None;
+        self.events.push(Event::MoveCancelled {
+            reason: reason.to_string(),
+        });
+        self.send(PanelToDeskMessage::NoKey, now)
+    }
+
+    fn drive(&mut self, now: Duration) -> io::Result<()> {
+        let Some(mut moving) = self.moving else {
+            if self.panel.is_none() && self.is_due(now) {
+                self.send(PanelToDeskMessage::NoKey, now)?;
+            }
+            return Ok(());
+        };
+
+        let height = self.tracker.height_cm();
+        if height != moving.progress.1 {
+            moving.progress = (now, height);
+        } else if now.saturating_sub(moving.progress.0) >= self.options.stall_timeout {
+            let reason = match height {
+                Some(_) => "desk stopped moving",
+                None => "desk is not reporting its height",
+            };
+            return self.cancel(reason, now);
+        }
+
+        let key = match height {
+            None => None,
+            Some(h) => {
+                let remaining = moving.target_cm - h;
+                let overshot = match moving.key {
+                    Some(PanelToDeskMessage::Up) => remaining < 0.0,
+                    Some(PanelToDeskMessage::Down) => remaining > 0.0,
+                    _ => false,
+                };
+                if overshot || remaining.abs() <= self.options.tolerance_cm {
+                    self.moving = None;
+                    self.events.push(Event::MoveFinished { height_cm: height });
+                    return self.send(PanelToDeskMessage::NoKey, now);
+                }
+                Some(if remaining > 0.0 {
+                    PanelToDeskMessage::Up
+                } else {
+                    PanelToDeskMessage::Down
+                })
+            }
+        };
+
+        let changed = key != moving.key;
+        moving.key = key;
+        self.moving = Some(moving);
+        if changed || self.is_due(now) {
+            self.send(key.unwrap_or(PanelToDeskMessage::NoKey), now)?;
+        }
+        Ok(())
+    }
+
+    fn is_due(&self, now: Duration) -> bool {
+        self.last_send
+            .is_none_or(|last| now >= last + self.options.send_interval)
+    }
+
+    fn send(&mut self, message: PanelToDeskMessage, now: Duration) -> io::Result<()> {
+        self.last_send = Some(now);
+        self.sent += 1;
+        self.desk.send(&message.as_frame())
+    }
+
+    fn publish(&mut self, now: Duration) {
+        let height = self.tracker.height_cm();
+        if let Some(h) = height.filter(|_| height != self.last_height) {
+            self.events.push(Event::Height(h));
+        }
+        self.last_height = height;
+
+        let movement = self.tracker.movement(now);
+        if movement != self.last_movement {
+            self.events.push(Event::Movement(movement));
+            self.last_movement = movement;
+        }
+
+        let key = self.tracker.last_key();
+        if key != self.last_key {
+            if let Some(key) = key.filter(|k| *k != PanelToDeskMessage::NoKey) {
+                self.events.push(Event::PanelKey(key));
+            }
+            self.last_key = key;
+        }
+
+        let observed = self.tracker.presets();
+        let mut shared = self.handle.lock();
+        for (direction, data, at) in self.observed.drain(..) {
+            shared.metrics.observe(direction, &data, at);
+        }
+        for _ in 0..self.sent {
+            shared.metrics.on_sent();
+        }
+        self.sent = 0;
+        shared.state = State {
+            uptime: now,
+            height_cm: height,
+            movement,
+            speed_cm_per_s: self.tracker.speed_cm_per_s(now),
+            target_cm: self.moving.map(|m| m.target_cm),
+            presets: [0, 1, 2].map(|i| self.programmed[i].or(observed[i])),
+            user: shared.state.user.take(),
+            schedule: shared.state.schedule.take(),
+            motor_budget: self.duty.as_ref().map(|d| d.remaining(now)),
+            panel: *self.tracker.stats(Direction::PanelToDesk),
+            desk: *self.tracker.stats(Direction::DeskToPanel),
+            panel_health: self
+                .panel
+                .as_ref()
+                .map(|_| self.link_health[Direction::PanelToDesk as usize]),
+            desk_health: self.link_health[Direction::DeskToPanel as usize],
+            ..shared.state
+        };
+        for event in self.events.drain(..) {
+            shared
+                .subscribers
+                .retain(|tx| tx.send(event.clone()).is_ok());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::displayed_value;
+    use crate::link::{ManualClock, SimulatedDesk};
+    use crate::sim::DeskSimulator;
+    use crate::DataFrame;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// The panel end of a pass-through daemon: frames queued in `incoming` are received, and
+    /// frames the daemon sends to the panel are kept in `sent`.
+    #[derive(Clone, Default)]
+    struct FakePanel {
+        incoming: Rc<RefCell<VecDeque<DataFrame>>>,
+        sent: Rc<RefCell<Vec<DataFrame>>>,
+    }
+
+    impl Link for FakePanel {
+        fn send(&mut self, frame: &DataFrame) -> io::Result<()> {
+            self.sent.borrow_mut().push(*frame);
+            Ok(())
+        }
+
+        fn recv(&mut self) -> io::Result<Option<Decoded>> {
+            Ok(self.incoming.borrow_mut().pop_front().map(Decoded::Frame))
+        }
+    }
+
+    fn daemon(panel: Option<FakePanel>) -> (Daemon<ManualClock>, ManualClock) {
+        let clock = ManualClock::new();
+        let desk = SimulatedDesk::new(
+            DeskSimulator::new(DeskProfile::default(), 90.0),
+            clock.clone(),
+        );
+        let panel = panel.map(|p| Box::new(p) as Box<dyn Link>);
+        let daemon = Daemon::new(
+            Box::new(desk),
+            panel,
+            clock.clone(),
+            DaemonOptions::default(),
+        );
+        (daemon, clock)
+    }
+
+    fn run_for(daemon: &mut Daemon<ManualClock>, clock: &ManualClock, duration: Duration) {
+        let end = clock.now() + duration;
+        while clock.now() < end {
+            daemon.step().unwrap();
+            clock.advance(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_move_to() {
+        let (mut daemon, clock) = daemon(None);
+        let handle = daemon.handle();
+        let events = handle.subscribe();
+
+        run_for(&mut daemon, &clock, Duration::from_millis(100));
+        assert_eq!(handle.state().height_cm, Some(90.0));
+        assert_eq!(handle.state().mode, Mode::PanelReplacement);
+
+        assert_eq!(handle.move_to(200.0), Ok(129.5));
+        assert!(handle.move_to(f32::NAN).is_err());
+        assert!(handle.move_to(f32::INFINITY).is_err());
+        assert_eq!(handle.move_to(100.0), Ok(100.0));
+        run_for(&mut daemon, &clock, Duration::from_secs(5));
+
+        let state = handle.state();
+        assert!((state.height_cm.unwrap() - 100.0).abs() <= 0.5);
+        assert_eq!(state.target_cm, None);
+        assert_eq!(state.movement, Movement::Stopped);
+
+        let events: Vec<Event> = events.try_iter().collect();
+        assert_eq!(events[0], Event::Height(90.0));
+        assert!(events.contains(&Event::MoveStarted { target_cm: 100.0 }));
+        assert!(events.contains(&Event::Movement(Movement::Rising)));
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, Event::MoveFinished { height_cm: Some(_) })));
+        assert_eq!(events.last(), Some(&Event::Movement(Movement::Stopped)));
+    }
+
+    #[test]
+    fn test_presets() {
+        let (mut daemon, clock) = daemon(None);
+        let handle = daemon.handle();
+
+        assert!(handle.recall_preset(1).is_err());
+        assert!(handle.program_preset(4, 100.0).is_err());
+        assert!(handle.program_preset(1, f32::NAN).is_err());
+        assert_eq!(handle.program_preset(2, 85.0), Ok(85.0));
+        run_for(&mut daemon, &clock, Duration::from_millis(50));
+        assert_eq!(handle.state().presets, [None, Some(85.0), None]);
+
+        assert_eq!(handle.recall_preset(2), Ok(85.0));
+        run_for(&mut daemon, &clock, Duration::from_secs(3));
+        assert!((handle.state().height_cm.unwrap() - 85.0).abs() <= 0.5);
+    }
+
+    #[test]
+    fn test_set_limits() {
+        let (mut daemon, clock) = daemon(None);
+        let handle = daemon.handle();
+
+        run_for(&mut daemon, &clock, Duration::from_millis(100));
+        handle.program_preset(3, 120.0).unwrap();
+        handle.move_to(125.0).unwrap();
+        run_for(&mut daemon, &clock, Duration::from_millis(100));
+
+        let limits = DeskProfile {
+            min_height_cm: 70.0,
+            max_height_cm: 100.0,
+        };
+        handle.set_limits(limits).unwrap();
+        run_for(&mut daemon, &clock, Duration::from_millis(50));
+        assert_eq!(handle.state().presets[2], Some(100.0));
+        assert_eq!(handle.state().target_cm, Some(100.0));
+
+        run_for(&mut daemon, &clock, Duration::from_secs(5));
+        assert!((handle.state().height_cm.unwrap() - 100.0).abs() <= 0.5);
+    }
+
+    #[test]
+    fn test_stop() {
+        let (mut daemon, clock) = daemon(None);
+        let handle = daemon.handle();
+        let events = handle.subscribe();
+
+        handle.move_to(120.0).unwrap();
+        run_for(&mut daemon, &clock, Duration::from_secs(1));
+        handle.stop();
+        run_for(&mut daemon, &clock, Duration::from_secs(1));
+
+        let stopped_at = handle.state().height_cm.unwrap();
+        assert!(stopped_at > 92.0 && stopped_at < 100.0);
+        assert!(events.try_iter().any(|e| e
+            == Event::MoveCancelled {
+                reason: "stopped".to_string()
+            }));
+    }
+
+    #[test]
+    fn test_pass_through() {
+        let panel = FakePanel::default();
+        let (mut daemon, clock) = daemon(Some(panel.clone()));
+        let handle = daemon.handle();
+        let events = handle.subscribe();
+
+        // Heights from the desk reach the panel.
+        run_for(&mut daemon, &clock, Duration::from_millis(100));
+        assert!(!panel.sent.borrow().is_empty());
+        assert_eq!(handle.state().mode, Mode::PassThrough);
+
+        // A key pressed on the panel takes over from a move in progress.
+        handle.move_to(120.0).unwrap();
+        run_for(&mut daemon, &clock, Duration::from_millis(500));
+        panel
+            .incoming
+            .borrow_mut()
+            .push_back(PanelToDeskMessage::NoKey.as_frame());
+        run_for(&mut daemon, &clock, Duration::from_millis(50));
+        assert_eq!(handle.state().target_cm, Some(120.0));
+
+        panel
+            .incoming
+            .borrow_mut()
+            .push_back(PanelToDeskMessage::Down.as_frame());
+        run_for(&mut daemon, &clock, Duration::from_millis(50));
+        assert_eq!(handle.state().target_cm, None);
+
+        let events: Vec<Event> = events.try_iter().collect();
+        assert!(events.contains(&Event::PanelKey(PanelToDeskMessage::Down)));
+        assert!(events.contains(&Event::MoveCancelled {
+            reason: "panel key pressed".to_string()
+        }));
+    }
+
+    #[test]
+    fn test_pass_through_ignores_noise() {
+        let panel = FakePanel::default();
+        let (mut daemon, clock) = daemon(Some(panel.clone()));
+        let handle = daemon.handle();
+
+        handle.move_to(120.0).unwrap();
+        run_for(&mut daemon, &clock, Duration::from_millis(500));
+        let mut corrupt = PanelToDeskMessage::Down.as_frame();
+        corrupt[5] ^= 0xff;
+        let unknown = PanelToDeskMessage::Unknown(1, 9, 0, 0, 10).as_frame();
+        for frame in [corrupt, unknown] {
+            panel.incoming.borrow_mut().push_back(frame);
+            run_for(&mut daemon, &clock, Duration::from_millis(50));
+            assert_eq!(handle.state().target_cm, Some(120.0));
+        }
+    }
+
+    #[test]
+    fn test_notify() {
+        let (daemon, _) = daemon(None);
+        let handle = daemon.handle();
+        let events = handle.subscribe();
+
+        handle.notify("mqtt", "connection refused".to_string());
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.name(), "notice");
+        assert_eq!(
+            event.to_json().to_string(),
+            r#"{"source":"mqtt","message":"connection refused"}"#
+        );
+    }
+
+    #[test]
+    fn test_show_on_panel() {
+        let panel = FakePanel::default();
+        let (mut daemon, clock) = daemon(Some(panel.clone()));
+        let handle = daemon.handle();
+        let last_shown = || displayed_value(panel.sent.borrow().last().unwrap());
+
+        run_for(&mut daemon, &clock, Duration::from_millis(100));
+        assert_eq!(last_shown(), 90.0);
+        handle.show_on_panel(30.0, Duration::from_secs(1)).unwrap();
+        assert!(handle.show_on_panel(1000.0, Duration::MAX).is_err());
+        run_for(&mut daemon, &clock, Duration::from_millis(500));
+        assert_eq!(last_shown(), 30.0);
+        run_for(&mut daemon, &clock, Duration::from_millis(600));
+        assert_eq!(last_shown(), 90.0);
+    }
+
+    #[test]
+    fn test_profiles() {
+        let panel = FakePanel::default();
+        let (daemon, clock) = daemon(Some(panel.clone()));
+        let mut store = ProfileStore::new();
+        let mut alex = Profile::new("alex", 72.0, 110.0);
+        alex.presets = [Some(75.0), None, None];
+        store.insert(alex).unwrap();
+        store.insert(Profile::new("sam", 68.0, 104.0)).unwrap();
+        store.set_active(Some("alex")).unwrap();
+        let mut daemon = daemon.with_profiles(store);
+        let handle = daemon.handle();
+        let events = handle.subscribe();
+        let press = |key: PanelToDeskMessage| {
+            panel.incoming.borrow_mut().push_back(key.as_frame());
+            panel
+                .incoming
+                .borrow_mut()
+                .push_back(PanelToDeskMessage::NoKey.as_frame());
+        };
+
+        run_for(&mut daemon, &clock, Duration::from_millis(100));
+        assert_eq!(handle.state().user.as_deref(), Some("alex"));
+
+        // The panel's own preset 1 is 100cm, but the desk goes to the profile's.
+        press(PanelToDeskMessage::One(100.0));
+        run_for(&mut daemon, &clock, Duration::from_secs(6));
+        assert_eq!(handle.state().height_cm, Some(75.0));
+
+        for key in [
+            PanelToDeskMessage::Up,
+            PanelToDeskMessage::Down,
+            PanelToDeskMessage::Up,
+            PanelToDeskMessage::Down,
+        ] {
+            press(key);
+            run_for(&mut daemon, &clock, Duration::from_millis(200));
+        }
+        assert_eq!(handle.state().user.as_deref(), Some("sam"));
+        assert_eq!(
+            handle.active_profile().map(|p| p.name),
+            Some("sam".to_string())
+        );
+        assert_eq!(displayed_value(panel.sent.borrow().last().unwrap()), 2.0);
+        assert!(events.try_iter().any(|e| e
+            == Event::ProfileActivated {
+                name: "sam".to_string()
+            }));
+    }
+
+    #[test]
+    fn test_duty_cycle() {
+        let clock = ManualClock::new();
+        let desk = SimulatedDesk::new(
+            DeskSimulator::new(DeskProfile::default(), 90.0),
+            clock.clone(),
+        );
+        let options = DaemonOptions {
+            duty_cycle: Some(DutyCycleOptions {
+                max_on: Duration::from_secs(10),
+                window: Duration::from_secs(60),
+                defer: false,
+                panel_overrides: false,
+            }),
+            ..DaemonOptions::default()
+        };
+        let mut daemon = Daemon::new(Box::new(desk), None, clock.clone(), options);
+        let handle = daemon.handle();
+        let events = handle.subscribe();
+
+        // Until the desk reports its height there's no knowing how long a move would take.
+        handle.move_to(110.0).unwrap();
+        daemon.step().unwrap();
+        assert_eq!(handle.state().target_cm, None);
+        assert!(events.try_iter().any(|e| e
+            == Event::MoveCancelled {
+                reason: "height unknown, so the motor duty cycle can't be checked".to_string()
+            }));
+
+        run_for(&mut daemon, &clock, Duration::from_millis(100));
+        handle.move_to(110.0).unwrap();
+        run_for(&mut daemon, &clock, Duration::from_secs(8));
+        assert!((handle.state().height_cm.unwrap() - 110.0).abs() <= 0.5);
+        let budget = handle.state().motor_budget.unwrap();
+        assert!(budget < Duration::from_secs(5), "{:?}", budget);
+
+        // Too long a move for what is left.
+        handle.move_to(72.0).unwrap();
+        run_for(&mut daemon, &clock, Duration::from_secs(1));
+        assert!((handle.state().height_cm.unwrap() - 110.0).abs() <= 0.5);
+        assert!(events.try_iter().any(|e| e
+            == Event::MoveCancelled {
+                reason: "motor duty cycle exceeded".to_string()
+            }));
+
+        // Deferred, it is made once enough running time has left the window.
+        daemon.options.duty_cycle.as_mut().unwrap().defer = true;
+        handle.move_to(90.0).unwrap();
+        run_for(&mut daemon, &clock, Duration::from_secs(1));
+        let seconds = events
+            .try_iter()
+            .find_map(|e| match e {
+                Event::MoveDeferred { seconds, .. } => Some(seconds),
+                _ => None,
+            })
+            .unwrap();
+        assert!(seconds > 40.0 && seconds < 60.0, "{}", seconds);
+        run_for(&mut daemon, &clock, Duration::from_secs(40));
+        assert!((handle.state().height_cm.unwrap() - 110.0).abs() <= 0.5);
+        run_for(&mut daemon, &clock, Duration::from_secs(30));
+        assert!((handle.state().height_cm.unwrap() - 90.0).abs() <= 0.5);
+    }
+
+    /// A desk whose reports can be cut off, as if its cable were pulled, and which keeps what it
+    /// is sent.
+    struct UnpluggableDesk {
+        desk: SimulatedDesk<ManualClock>,
+        unplugged: Rc<RefCell<bool>>,
+        sent: Rc<RefCell<Vec<DataFrame>>>,
+    }
+
+    impl Link for UnpluggableDesk {
+        fn send(&mut self, frame: &DataFrame) -> io::Result<()> {
+            self.sent.borrow_mut().push(*frame);
+            self.desk.send(frame)
+        }
+
+        fn recv(&mut self) -> io::Result<Option<Decoded>> {
+            let data = self.desk.recv()?;
+            Ok(data.filter(|_| !*self.unplugged.borrow()))
+        }
+    }
+
+    #[test]
+    fn test_lost_height() {
+        let clock = ManualClock::new();
+        let unplugged = Rc::new(RefCell::new(false));
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let desk = UnpluggableDesk {
+            desk: SimulatedDesk::new(
+                DeskSimulator::new(DeskProfile::default(), 90.0),
+                clock.clone(),
+            ),
+            unplugged: unplugged.clone(),
+            sent: sent.clone(),
+        };
+        let mut daemon = Daemon::new(
+            Box::new(desk),
+            Some(Box::new(FakePanel::default())),
+            clock.clone(),
+            DaemonOptions::default(),
+        );
+        let handle = daemon.handle();
+        let events = handle.subscribe();
+
+        handle.move_to(120.0).unwrap();
+        run_for(&mut daemon, &clock, Duration::from_millis(1500));
+        assert_eq!(handle.state().desk_health, LinkHealth::Ok);
+        // Nothing has come from the panel.
+        assert_eq!(handle.state().panel_health, Some(LinkHealth::Silent));
+
+        *unplugged.borrow_mut() = true;
+        run_for(&mut daemon, &clock, Duration::from_millis(600));
+        let events: Vec<Event> = events.try_iter().collect();
+        assert!(events.contains(&Event::LinkHealth {
+            direction: Direction::DeskToPanel,
+            health: LinkHealth::Silent,
+        }));
+        assert!(events.contains(&Event::MoveCancelled {
+            reason: "desk stopped reporting its height".to_string()
+        }));
+        assert_eq!(handle.state().target_cm, None);
+        assert_eq!(
+            sent.borrow().last().copied(),
+            Some(PanelToDeskMessage::NoKey.as_frame())
+        );
+    }
+}
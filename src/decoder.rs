@@ -0,0 +1,75 @@
+use crate::{is_start_byte, validate_frame, DataFrame, DATA_FRAME_SIZE};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Decoded {
+    Frame(DataFrame),
+    /// Seven bytes that started with a start byte but did not end with an end byte.
+    Invalid(DataFrame),
+    /// A byte seen between frames that could not have started one.
+    Stray(u8),
+}
+
+/// Splits a raw byte stream from either side of the bus into frames.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameDecoder {
+    buf: DataFrame,
+    len: usize,
+}
+
+impl FrameDecoder {
+    pub fn new() -> FrameDecoder {
+        FrameDecoder::default()
+    }
+
+    /// Whether some bytes of a frame have been seen but not all of them.
+    pub fn is_mid_frame(&self) -> bool {
+        self.len > 0
+    }
+
+    pub fn push(&mut self, b: u8) -> Option<Decoded> {
+        if self.len == 0 && !is_start_byte(b) {
+            return Some(Decoded::Stray(b));
+        }
+
+        self.buf[self.len] = b;
+        self.len += 1;
+
+        if self.len < DATA_FRAME_SIZE {
+            return None;
+        }
+
+        self.len = 0;
+        if validate_frame(&self.buf) {
+            Some(Decoded::Frame(self.buf))
+        } else {
+            Some(Decoded::Invalid(self.buf))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PanelToDeskMessage;
+
+    #[test]
+    fn test_frame_decoder() {
+        let mut decoder = FrameDecoder::new();
+        let frame = PanelToDeskMessage::Up.as_frame();
+
+        assert_eq!(decoder.push(0u8), Some(Decoded::Stray(0u8)));
+        for b in &frame[..DATA_FRAME_SIZE - 1] {
+            assert_eq!(decoder.push(*b), None);
+        }
+        assert_eq!(
+            decoder.push(frame[DATA_FRAME_SIZE - 1]),
+            Some(Decoded::Frame(frame))
+        );
+
+        let mut decoded = None;
+        for b in [104u8, 1, 2, 3, 4, 5, 6] {
+            decoded = decoder.push(b);
+        }
+        assert_eq!(decoded, Some(Decoded::Invalid([104u8, 1, 2, 3, 4, 5, 6])));
+    }
+}
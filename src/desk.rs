@@ -0,0 +1,207 @@
+//! An emulation of the desk, for using the panel on its own as an input device.
+//!
+//! [`DeskEmulator`] answers the panel with height reports at the desk's cadence, so the panel
+//! stays awake and shows whatever number the application chooses, and turns the stream of key
+//! frames from the panel into key presses and releases. Like [`PanelEmulator`], it owns no link.
+//!
+//! [`PanelEmulator`]: crate::panel::PanelEmulator
+
+use core::time::Duration;
+
+use crate::display::{display_frame, Undisplayable};
+use crate::{validate_frame, DataFrame, PanelToDeskMessage};
+
+const REPORT_INTERVAL: Duration = Duration::from_millis(50);
+/// How long a held key lasts after its last frame, as on the desk.
+const KEY_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// A key on the panel going down or coming back up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyEvent {
+    Pressed(PanelToDeskMessage),
+    Released {
+        key: PanelToDeskMessage,
+        held: Duration,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DeskEmulator {
+    value: f32,
+    frame: DataFrame,
+    report_interval: Duration,
+    // The key held, when it went down, and when its last frame came.
+    held: Option<(PanelToDeskMessage, Duration, Duration)>,
+    last_report: Option<Duration>,
+}
+
+impl DeskEmulator {
+    /// Shows `value` on the panel until told otherwise, or refuses a value the panel can't show.
+    pub fn new(value: f32) -> Result<DeskEmulator, Undisplayable> {
+        Ok(DeskEmulator {
+            value,
+            frame: display_frame(value)?,
+            report_interval: REPORT_INTERVAL,
+            held: None,
+            last_report: None,
+        })
+    }
+
+    pub fn with_report_interval(mut self, report_interval: Duration) -> DeskEmulator {
+        self.report_interval = report_interval;
+        self
+    }
+
+    /// Changes the number the panel shows, from the next report on. A value the panel can't show
+    /// leaves the old one in place.
+    pub fn show(&mut self, value: f32) -> Result<(), Undisplayable> {
+        self.frame = display_frame(value)?;
+        self.value = value;
+        Ok(())
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    /// The key held on the panel, if any.
+    pub fn held(&self) -> Option<PanelToDeskMessage> {
+        self.held.map(|(key, _, _)| key)
+    }
+
+    /// Takes a frame from the panel, and returns the key events it makes. A different key
+    /// replacing a held one releases it and presses the new one.
+    pub fn on_panel_frame(&mut self, frame: &DataFrame, now: Duration) -> [Option<KeyEvent>; 2] {
+        if !validate_frame(frame) {
+            return [None, None];
+        }
+        let message = PanelToDeskMessage::from_frame(frame);
+        match self.held {
+            Some((key, since, _)) if key == message => {
+                self.held = Some((key, since, now));
+                [None, None]
+            }
+            _ => {
+                let released = self.release(now);
+                let pressed = match message {
+                    PanelToDeskMessage::NoKey => None,
+                    key => {
+                        self.held = Some((key, now, now));
+                        Some(KeyEvent::Pressed(key))
+                    }
+                };
+                [released, pressed]
+            }
+        }
+    }
+
+    /// Returns the height frame to send at `now`, if one is due, and a release if the panel
+    /// has gone quiet with a key held.
+    pub fn poll(&mut self, now: Duration) -> (Option<DataFrame>, Option<KeyEvent>) {
+        let released = match self.held {
+            Some((_, _, last)) if now >= last + KEY_TIMEOUT => self.release(last),
+            _ => None,
+        };
+        let report = match self.last_report {
+            Some(last) if now < last + self.report_interval => None,
+            _ => {
+                self.last_report = Some(now);
+                Some(self.frame)
+            }
+        };
+        (report, released)
+    }
+
+    fn release(&mut self, now: Duration) -> Option<KeyEvent> {
+        let (key, since, _) = self.held.take()?;
+        Some(KeyEvent::Released {
+            key,
+            held: now.saturating_sub(since),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::displayed_value;
+    use crate::panel::PanelEmulator;
+
+    /// Key events seen so far, without needing an allocator.
+    #[derive(Default)]
+    struct Events {
+        seen: [Option<KeyEvent>; 8],
+        count: usize,
+    }
+
+    impl Extend<KeyEvent> for Events {
+        fn extend<I: IntoIterator<Item = KeyEvent>>(&mut self, events: I) {
+            for event in events {
+                self.seen[self.count] = Some(event);
+                self.count += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn test_desk_emulator() {
+        let ms = Duration::from_millis;
+        let mut panel = PanelEmulator::new();
+        let mut desk = DeskEmulator::new(100.0).unwrap();
+        let mut events = Events::default();
+        let mut run = |panel: &mut PanelEmulator, desk: &mut DeskEmulator, from, to| {
+            for at in (from..to).step_by(10) {
+                if let Some(frame) = panel.poll(ms(at)) {
+                    events.extend(desk.on_panel_frame(&frame, ms(at)).into_iter().flatten());
+                }
+                let (report, released) = desk.poll(ms(at));
+                events.extend(released);
+                if let Some(frame) = report {
+                    panel.on_desk_frame(&frame);
+                }
+            }
+        };
+
+        run(&mut panel, &mut desk, 0, 100);
+        assert_eq!(panel.height_cm(), Some(100.0));
+
+        panel.hold(PanelToDeskMessage::Up);
+        run(&mut panel, &mut desk, 100, 600);
+        assert_eq!(desk.held(), Some(PanelToDeskMessage::Up));
+        panel.press(PanelToDeskMessage::Two(100.0), ms(600));
+        run(&mut panel, &mut desk, 600, 1000);
+        desk.show(7.5).unwrap();
+        assert!(desk.show(1000.0).is_err());
+        run(&mut panel, &mut desk, 1000, 1100);
+        assert_eq!(displayed_value(&desk.poll(ms(2000)).0.unwrap()), 7.5);
+
+        assert_eq!(
+            events.seen[..events.count],
+            [
+                KeyEvent::Pressed(PanelToDeskMessage::Up),
+                KeyEvent::Released {
+                    key: PanelToDeskMessage::Up,
+                    held: ms(500),
+                },
+                KeyEvent::Pressed(PanelToDeskMessage::Two(100.0)),
+                KeyEvent::Released {
+                    key: PanelToDeskMessage::Two(100.0),
+                    held: ms(300),
+                },
+            ]
+            .map(Some)
+        );
+
+        // A panel unplugged with a key down lets go of it.
+        let mut desk = DeskEmulator::new(0.0).unwrap();
+        desk.on_panel_frame(&PanelToDeskMessage::Down.as_frame(), ms(0));
+        assert_eq!(desk.poll(ms(100)).1, None);
+        assert_eq!(
+            desk.poll(ms(300)).1,
+            Some(KeyEvent::Released {
+                key: PanelToDeskMessage::Down,
+                held: ms(0),
+            })
+        );
+    }
+}
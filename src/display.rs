@@ -0,0 +1,371 @@
+use core::fmt;
+use core::ops::RangeInclusive;
+use core::time::Duration;
+
+use crate::{
+    build_frame, validate_checksum, validate_frame, DataFrame, DESK_TO_PANEL_HEIGHT_BYTE,
+    PANEL_TO_DESK_ONE_BYTE, PANEL_TO_DESK_THREE_BYTE,
+};
+
+const CM_PER_INCH: f32 = 2.54;
+
+/// The numbers the panel's three digits have room for.
+pub const DISPLAY_RANGE: RangeInclusive<f32> = 0.0..=999.0;
+
+/// A number that doesn't fit in [`DISPLAY_RANGE`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Undisplayable(pub f32);
+
+impl fmt::Display for Undisplayable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} can't be shown on the panel, it only has room for {} to {}",
+            self.0,
+            DISPLAY_RANGE.start(),
+            DISPLAY_RANGE.end()
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Undisplayable {}
+
+// The panel adds this offset (in tenths of a unit) to the raw height value before displaying it.
+const DISPLAY_OFFSET_TENTHS: i32 = 650;
+
+/// Builds a desk-to-panel height frame that makes the panel show `value`, or refuses a value
+/// outside [`DISPLAY_RANGE`].
+///
+/// The panel only ever sees tenths above a 65.0 offset, so values below 65.0 are sent as a
+/// negative raw value in two's complement. That assumes the panel does its offset arithmetic in
+/// 16 bits; no capture confirms it, since the desk itself never reports a height below 65.0.
+pub fn display_frame(value: f32) -> Result<DataFrame, Undisplayable> {
+    if !DISPLAY_RANGE.contains(&value) {
+        return Err(Undisplayable(value));
+    }
+    let raw = i16::try_from(round_to_i32(value * 10.0) - DISPLAY_OFFSET_TENTHS)
+        .map_err(|_| Undisplayable(value))?;
+    let [msb, lsb] = raw.to_be_bytes();
+    Ok(build_frame(DESK_TO_PANEL_HEIGHT_BYTE, msb, lsb))
+}
+
+/// Returns the number the panel shows for a desk-to-panel height frame.
+pub fn displayed_value(frame: &DataFrame) -> f32 {
+    let raw = u16::from_be_bytes([frame[3], frame[4]]) as i16 as i32;
+    (raw + DISPLAY_OFFSET_TENTHS) as f32 / 10.0
+}
+
+/// Rewrites traffic between panel and desk so the panel reads out inches.
+///
+/// Heights reported by the desk are converted to inches before they reach the panel, or passed
+/// through in centimetres if the panel has no room for them in inches. Because the
+/// panel stores preset targets as whatever it was displaying, preset recalls coming from the
+/// panel are converted back to centimetres before they reach the desk. Frames with a bad
+/// checksum are passed through unchanged rather than rebuilt with a good one.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ImperialDisplay;
+
+impl ImperialDisplay {
+    pub fn desk_to_panel(&self, frame: &DataFrame) -> DataFrame {
+        if !is_intact(frame) || frame[2] != DESK_TO_PANEL_HEIGHT_BYTE {
+            return *frame;
+        }
+
+        display_frame(displayed_value(frame) / CM_PER_INCH).unwrap_or(*frame)
+    }
+
+    pub fn panel_to_desk(&self, frame: &DataFrame) -> DataFrame {
+        if !is_intact(frame)
+            || frame[2] < PANEL_TO_DESK_ONE_BYTE
+            || frame[2] > PANEL_TO_DESK_THREE_BYTE
+        {
+            return *frame;
+        }
+
+        let inches_tenths = u16::from_le_bytes([frame[3], frame[4]]);
+        let cm_tenths = round_to_i32(inches_tenths as f32 * CM_PER_INCH) as u16;
+        let [lsb, msb] = cm_tenths.to_le_bytes();
+        build_frame(frame[2], lsb, msb)
+    }
+}
+
+/// Temporarily replaces the height shown on the panel with an arbitrary number.
+///
+/// The override ends when it times out or as soon as the desk reports a different height, so the
+/// panel never hides a moving desk. Frames with a bad checksum are passed through unchanged.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DisplayOverride {
+    shown: Option<DataFrame>,
+    expires_at: Duration,
+    last_height: Option<[u8; 2]>,
+    baseline: Option<[u8; 2]>,
+}
+
+impl DisplayOverride {
+    pub fn new() -> DisplayOverride {
+        DisplayOverride::default()
+    }
+
+    /// Shows `value` until `timeout` has passed, or refuses it if it doesn't fit on the panel.
+    pub fn show(
+        &mut self,
+        value: f32,
+        now: Duration,
+        timeout: Duration,
+    ) -> Result<(), Undisplayable> {
+        let shown = display_frame(value)?;
+        if self.shown.is_none() {
+            self.baseline = self.last_height;
+        }
+        self.shown = Some(shown);
+        self.expires_at = now.saturating_add(timeout);
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.shown = None;
+        self.baseline = None;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.shown.is_some()
+    }
+
+    pub fn desk_to_panel(&mut self, frame: &DataFrame, now: Duration) -> DataFrame {
+        if !is_intact(frame) || frame[2] != DESK_TO_PANEL_HEIGHT_BYTE {
+            return *frame;
+        }
+
+        let height = [frame[3], frame[4]];
+        self.last_height = Some(height);
+
+        let shown = match self.shown {
+            Some(shown) => shown,
+            None => return *frame,
+        };
+
+        if now >= self.expires_at {
+            self.clear();
+            return *frame;
+        }
+
+        match self.baseline {
+            Some(baseline) if baseline != height => {
+                self.clear();
+                *frame
+            }
+            Some(_) => shown,
+            None => {
+                self.baseline = Some(height);
+                shown
+            }
+        }
+    }
+}
+
+fn is_intact(frame: &DataFrame) -> bool {
+    validate_frame(frame) && validate_checksum(frame)
+}
+
+pub(crate) fn round_to_i32(x: f32) -> i32 {
+    if x < 0.0 {
+        (x - 0.5) as i32
+    } else {
+        (x + 0.5) as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DeskToPanelMessage, PanelToDeskMessage};
+
+    #[test]
+    fn test_display_frame() {
+        assert_eq!(
+            display_frame(65.0),
+            Ok(DeskToPanelMessage::Height(65.0).as_frame())
+        );
+        assert_eq!(
+            display_frame(100.0),
+            Ok(DeskToPanelMessage::Height(100.0).as_frame())
+        );
+        assert_eq!(
+            display_frame(129.5),
+            Ok(DeskToPanelMessage::Height(129.5).as_frame())
+        );
+
+        assert_eq!(displayed_value(&display_frame(25.6).unwrap()), 25.6);
+        assert_eq!(displayed_value(&display_frame(0.0).unwrap()), 0.0);
+        assert_eq!(displayed_value(&display_frame(999.0).unwrap()), 999.0);
+
+        assert_eq!(display_frame(-1.5), Err(Undisplayable(-1.5)));
+        assert_eq!(display_frame(999.5), Err(Undisplayable(999.5)));
+        assert!(display_frame(f32::NAN).is_err());
+    }
+
+    #[test]
+    fn test_display_override() {
+        let mut display = DisplayOverride::new();
+        let height = DeskToPanelMessage::Height(100.0).as_frame();
+
+        assert_eq!(
+            display.desk_to_panel(&height, Duration::from_secs(0)),
+            height
+        );
+
+        display
+            .show(12.0, Duration::from_secs(1), Duration::from_secs(10))
+            .unwrap();
+        assert!(display.is_active());
+        assert_eq!(
+            displayed_value(&display.desk_to_panel(&height, Duration::from_secs(2))),
+            12.0
+        );
+
+        display
+            .show(11.0, Duration::from_secs(3), Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(
+            displayed_value(&display.desk_to_panel(&height, Duration::from_secs(4))),
+            11.0
+        );
+
+        let unknown = DeskToPanelMessage::Unknown(1u8, 9u8, 0u8, 0u8, 10u8).as_frame();
+        assert_eq!(
+            display.desk_to_panel(&unknown, Duration::from_secs(5)),
+            unknown
+        );
+
+        assert_eq!(
+            display.desk_to_panel(&height, Duration::from_secs(13)),
+            height
+        );
+        assert!(!display.is_active());
+    }
+
+    #[test]
+    fn test_display_override_restores_on_movement() {
+        let mut display = DisplayOverride::new();
+        let height = DeskToPanelMessage::Height(100.0).as_frame();
+        let moved = DeskToPanelMessage::Height(100.5).as_frame();
+
+        display
+            .show(7.0, Duration::from_secs(0), Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(
+            displayed_value(&display.desk_to_panel(&height, Duration::from_secs(1))),
+            7.0
+        );
+        assert_eq!(display.desk_to_panel(&moved, Duration::from_secs(2)), moved);
+        assert!(!display.is_active());
+
+        display
+            .show(7.0, Duration::from_secs(3), Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(
+            display.desk_to_panel(&height, Duration::from_secs(4)),
+            height
+        );
+        assert!(!display.is_active());
+    }
+
+    #[test]
+    fn test_display_override_rejects_what_does_not_fit() {
+        let mut display = DisplayOverride::new();
+        let height = DeskToPanelMessage::Height(100.0).as_frame();
+
+        for value in [1000.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert!(display
+                .show(value, Duration::ZERO, Duration::from_secs(60))
+                .is_err());
+        }
+        assert!(!display.is_active());
+
+        display
+            .show(999.0, Duration::from_secs(1), Duration::MAX)
+            .unwrap();
+        assert_eq!(
+            displayed_value(&display.desk_to_panel(&height, Duration::from_secs(2))),
+            999.0
+        );
+    }
+
+    #[test]
+    fn test_display_override_passes_bad_checksums_through() {
+        let mut display = DisplayOverride::new();
+        let height = DeskToPanelMessage::Height(100.0).as_frame();
+        let mut corrupt = DeskToPanelMessage::Height(100.5).as_frame();
+        corrupt[5] ^= 0xff;
+
+        display
+            .show(7.0, Duration::from_secs(0), Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(
+            displayed_value(&display.desk_to_panel(&height, Duration::from_secs(1))),
+            7.0
+        );
+        assert_eq!(
+            display.desk_to_panel(&corrupt, Duration::from_secs(2)),
+            corrupt
+        );
+        // A corrupt frame is no sign the desk moved, so the override stays.
+        assert!(display.is_active());
+        assert_eq!(
+            displayed_value(&display.desk_to_panel(&height, Duration::from_secs(3))),
+            7.0
+        );
+    }
+
+    #[test]
+    fn test_imperial_display_desk_to_panel() {
+        let display = ImperialDisplay;
+
+        let frame = display.desk_to_panel(&DeskToPanelMessage::Height(65.0).as_frame());
+        assert_eq!(displayed_value(&frame), 25.6);
+
+        let frame = display.desk_to_panel(&DeskToPanelMessage::Height(101.5).as_frame());
+        assert_eq!(displayed_value(&frame), 40.0);
+
+        let frame = display.desk_to_panel(&DeskToPanelMessage::Height(129.5).as_frame());
+        assert_eq!(displayed_value(&frame), 51.0);
+
+        let unknown = DeskToPanelMessage::Unknown(1u8, 9u8, 0u8, 0u8, 10u8).as_frame();
+        assert_eq!(display.desk_to_panel(&unknown), unknown);
+    }
+
+    #[test]
+    fn test_imperial_display_panel_to_desk() {
+        let display = ImperialDisplay;
+
+        assert_eq!(
+            display.panel_to_desk(&PanelToDeskMessage::One(40.0).as_frame()),
+            PanelToDeskMessage::One(101.6).as_frame(),
+        );
+        assert_eq!(
+            display.panel_to_desk(&PanelToDeskMessage::Two(25.6).as_frame()),
+            PanelToDeskMessage::Two(65.0).as_frame(),
+        );
+        assert_eq!(
+            display.panel_to_desk(&PanelToDeskMessage::Three(51.0).as_frame()),
+            PanelToDeskMessage::Three(129.5).as_frame(),
+        );
+
+        let up = PanelToDeskMessage::Up.as_frame();
+        assert_eq!(display.panel_to_desk(&up), up);
+    }
+
+    #[test]
+    fn test_imperial_display_passes_bad_checksums_through() {
+        let display = ImperialDisplay;
+
+        let mut height = DeskToPanelMessage::Height(101.5).as_frame();
+        height[5] ^= 0xff;
+        assert_eq!(display.desk_to_panel(&height), height);
+
+        let mut preset = PanelToDeskMessage::One(40.0).as_frame();
+        preset[5] ^= 0xff;
+        assert_eq!(display.panel_to_desk(&preset), preset);
+    }
+}
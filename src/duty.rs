@@ -0,0 +1,229 @@
+//! Keeping the motor within the duty cycle the desk controller is rated for.
+//!
+//! Controllers are typically rated for something like 2 minutes of running in any 18. A
+//! [`DutyCycle`] remembers when the motor ran over the last window and says how much running
+//! time is left, and when a move of a given length could next be made.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How long the motor may run in any window of time, and what happens when it has run enough.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DutyCycleOptions {
+    pub max_on: Duration,
+    pub window: Duration,
+    /// Whether automated moves that don't fit are made later rather than refused.
+    pub defer: bool,
+    /// Whether keys pressed on the panel still move the desk once the budget is used up.
+    pub panel_overrides: bool,
+}
+
+impl Default for DutyCycleOptions {
+    fn default() -> Self {
+        DutyCycleOptions {
+            max_on: Duration::from_secs(2 * 60),
+            window: Duration::from_secs(18 * 60),
+            defer: false,
+            panel_overrides: false,
+        }
+    }
+}
+
+/// Parses a limit such as `2m/18m`: at most two minutes of running in any eighteen.
+impl FromStr for DutyCycleOptions {
+    type Err = DutyCycleParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || DutyCycleParseError(s.to_string());
+        let (on, window) = s.split_once('/').ok_or_else(error)?;
+        let max_on = parse_duration(on).ok_or_else(error)?;
+        let window = parse_duration(window).ok_or_else(error)?;
+        if max_on.is_zero() || max_on > window {
+            return Err(error());
+        }
+        Ok(DutyCycleOptions {
+            max_on,
+            window,
+            ..DutyCycleOptions::default()
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DutyCycleParseError(String);
+
+impl fmt::Display for DutyCycleParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid duty cycle {:?}, expected running time per window such as 2m/18m",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for DutyCycleParseError {}
+
+/// Parses durations such as `45m`, `1h30m` and `90s`, also used for schedules. Anything else,
+/// including a number too big for a `Duration`, is `None`.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    if s.is_empty() {
+        return None;
+    }
+    let mut seconds = 0u64;
+    let mut number: Option<u64> = None;
+    for c in s.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            number = Some(
+                number
+                    .unwrap_or(0)
+                    .checked_mul(10)?
+                    .checked_add(digit.into())?,
+            );
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            _ => return None,
+        };
+        seconds = seconds.checked_add(number.take()?.checked_mul(unit)?)?;
+    }
+    // A number without a unit.
+    if number.is_some() {
+        return None;
+    }
+    Some(Duration::from_secs(seconds))
+}
+
+/// The motor's running time over the last window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DutyCycle {
+    max_on: Duration,
+    window: Duration,
+    // Finished runs, oldest first, and the start of the one in progress.
+    runs: VecDeque<(Duration, Duration)>,
+    running_since: Option<Duration>,
+}
+
+impl DutyCycle {
+    pub fn new(max_on: Duration, window: Duration) -> DutyCycle {
+        DutyCycle {
+            max_on,
+            window,
+            runs: VecDeque::new(),
+            running_since: None,
+        }
+    }
+
+    /// Notes whether the motor is running at `now`.
+    pub fn set_running(&mut self, running: bool, now: Duration) {
+        match (running, self.running_since) {
+            (true, None) => self.running_since = Some(now),
+            (false, Some(since)) => {
+                self.runs.push_back((since, now));
+                self.running_since = None;
+            }
+            _ => {}
+        }
+        let start = now.saturating_sub(self.window);
+        while self.runs.front().is_some_and(|&(_, end)| end <= start) {
+            self.runs.pop_front();
+        }
+    }
+
+    /// How long the motor has run within the window ending at `now`.
+    pub fn used(&self, now: Duration) -> Duration {
+        let start = now.saturating_sub(self.window);
+        self.runs_until(now)
+            .map(|(from, to)| to.saturating_sub(from.max(start)))
+            .sum()
+    }
+
+    /// How much longer the motor may run at `now`.
+    pub fn remaining(&self, now: Duration) -> Duration {
+        self.max_on.saturating_sub(self.used(now))
+    }
+
+    /// The earliest time from `now` at which the motor could run for `needed`, assuming it
+    /// doesn't run in the meantime, or `None` if `needed` is more than the cycle ever allows.
+    pub fn available_at(&self, needed: Duration, now: Duration) -> Option<Duration> {
+        if needed > self.max_on {
+            return None;
+        }
+        // Enough running time has to drop out of the window to make room.
+        let mut excess = (self.used(now) + needed).saturating_sub(self.max_on);
+        if excess.is_zero() {
+            return Some(now);
+        }
+        let start = now.saturating_sub(self.window);
+        for (from, to) in self.runs_until(now) {
+            let from = from.max(start);
+            let length = to.saturating_sub(from);
+            if length >= excess {
+                return Some(from + excess + self.window);
+            }
+            excess -= length;
+        }
+        Some(now)
+    }
+
+    fn runs_until(&self, now: Duration) -> impl Iterator<Item = (Duration, Duration)> + '_ {
+        self.runs
+            .iter()
+            .copied()
+            .chain(self.running_since.map(|since| (since, now)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let options: DutyCycleOptions = "2m/18m".parse().unwrap();
+        assert_eq!(options.max_on, Duration::from_secs(120));
+        assert_eq!(options.window, Duration::from_secs(18 * 60));
+        assert!("90s/1h".parse::<DutyCycleOptions>().is_ok());
+        assert!("2m".parse::<DutyCycleOptions>().is_err());
+        assert!("20m/18m".parse::<DutyCycleOptions>().is_err());
+        assert!("2x/18m".parse::<DutyCycleOptions>().is_err());
+        assert!("2é/18m".parse::<DutyCycleOptions>().is_err());
+        assert!("2m/18446744073709551h".parse::<DutyCycleOptions>().is_err());
+        assert_eq!(parse_duration("1h30m"), Some(Duration::from_secs(90 * 60)));
+        assert_eq!(parse_duration("30"), None);
+        assert_eq!(parse_duration("m"), None);
+    }
+
+    #[test]
+    fn test_duty_cycle() {
+        let s = Duration::from_secs;
+        let mut duty = DutyCycle::new(s(60), s(600));
+        assert_eq!(duty.remaining(s(0)), s(60));
+        assert_eq!(duty.available_at(s(30), s(0)), Some(s(0)));
+
+        duty.set_running(true, s(10));
+        assert_eq!(duty.remaining(s(30)), s(40));
+        duty.set_running(false, s(50));
+        duty.set_running(true, s(100));
+        duty.set_running(false, s(115));
+        assert_eq!(duty.used(s(200)), s(55));
+        assert_eq!(duty.remaining(s(200)), s(5));
+
+        assert_eq!(duty.available_at(s(5), s(200)), Some(s(200)));
+        // 10s of the first run has to drop out of the window.
+        assert_eq!(duty.available_at(s(15), s(200)), Some(s(620)));
+        assert_eq!(duty.available_at(s(60), s(200)), Some(s(715)));
+        assert_eq!(duty.available_at(s(61), s(200)), None);
+
+        // Runs age out of the window.
+        assert_eq!(duty.used(s(630)), s(35));
+        duty.set_running(false, s(800));
+        assert_eq!(duty.remaining(s(800)), s(60));
+    }
+}
@@ -0,0 +1,135 @@
+//! Desk heights recommended from someone's body measurements.
+//!
+//! The desk should put the keyboard at or just below elbow height, with the upper arms hanging
+//! relaxed and the forearms level. Measurements that aren't known are estimated from stature
+//! using average anthropometric proportions:
+//!
+//! | measurement                          | estimate           |
+//! |--------------------------------------|--------------------|
+//! | standing elbow height, in shoes      | 0.635 × stature    |
+//! | seat height, popliteal height + shoe | 0.25 × stature + 2.5cm |
+//! | elbow height above the seat          | 0.15 × stature     |
+
+use crate::display::round_to_i32;
+use crate::{DeskProfile, PanelToDeskMessage};
+
+const STANDING_ELBOW_RATIO: f32 = 0.635;
+const POPLITEAL_RATIO: f32 = 0.25;
+const SHOE_CM: f32 = 2.5;
+const SITTING_ELBOW_RATIO: f32 = 0.15;
+
+/// How far below the elbow the desk surface goes, to leave room for the keyboard.
+const KEYBOARD_CM: f32 = 2.5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Body {
+    pub height_cm: f32,
+    /// Elbow height from the floor while standing in shoes, if measured.
+    pub elbow_cm: Option<f32>,
+    /// Seat height of the chair, if known.
+    pub chair_cm: Option<f32>,
+    /// How far below the desk surface a keyboard tray sits.
+    pub tray_cm: f32,
+}
+
+impl Body {
+    pub fn new(height_cm: f32) -> Body {
+        Body {
+            height_cm,
+            elbow_cm: None,
+            chair_cm: None,
+            tray_cm: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Recommendation {
+    pub sitting_cm: f32,
+    pub standing_cm: f32,
+    /// Whether either height was outside the desk's range and had to be clamped.
+    pub clamped: bool,
+}
+
+impl Recommendation {
+    /// The preset frames for the sitting and standing heights, as presets 1 and 2.
+    pub fn preset_messages(&self) -> [PanelToDeskMessage; 2] {
+        [
+            PanelToDeskMessage::One(self.sitting_cm),
+            PanelToDeskMessage::Two(self.standing_cm),
+        ]
+    }
+}
+
+/// Recommends sitting and standing heights for `body` within `desk`'s range, rounded to half a
+/// centimetre.
+pub fn recommend(body: &Body, desk: &DeskProfile) -> Recommendation {
+    let stature = body.height_cm;
+    let standing_elbow = body.elbow_cm.unwrap_or(stature * STANDING_ELBOW_RATIO);
+    let seat = body.chair_cm.unwrap_or(stature * POPLITEAL_RATIO + SHOE_CM);
+    let sitting_elbow = seat + stature * SITTING_ELBOW_RATIO;
+
+    let surface = |elbow: f32| round_to_half(elbow - KEYBOARD_CM + body.tray_cm);
+    let sitting = surface(sitting_elbow);
+    let standing = surface(standing_elbow);
+    let sitting_cm = desk.clamp(sitting);
+    let standing_cm = desk.clamp(standing);
+    Recommendation {
+        sitting_cm,
+        standing_cm,
+        clamped: sitting_cm != sitting || standing_cm != standing,
+    }
+}
+
+fn round_to_half(height_cm: f32) -> f32 {
+    round_to_i32(height_cm * 2.0) as f32 / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recommend() {
+        let desk = DeskProfile::default();
+        assert_eq!(
+            recommend(&Body::new(175.0), &desk),
+            Recommendation {
+                sitting_cm: 70.0,
+                standing_cm: 108.5,
+                clamped: false,
+            }
+        );
+
+        // Measurements win over estimates, and a tray raises the desk by its drop.
+        let body = Body {
+            elbow_cm: Some(115.0),
+            chair_cm: Some(50.0),
+            tray_cm: 4.0,
+            ..Body::new(180.0)
+        };
+        assert_eq!(
+            recommend(&body, &desk),
+            Recommendation {
+                sitting_cm: 78.5,
+                standing_cm: 116.5,
+                clamped: false,
+            }
+        );
+
+        let short = recommend(&Body::new(150.0), &desk);
+        assert_eq!(short.sitting_cm, 65.0);
+        assert!(short.clamped);
+        let tall = recommend(&Body::new(210.0), &desk);
+        assert_eq!(tall.standing_cm, 129.5);
+        assert!(tall.clamped);
+
+        assert_eq!(
+            recommend(&Body::new(175.0), &desk).preset_messages(),
+            [
+                PanelToDeskMessage::One(70.0),
+                PanelToDeskMessage::Two(108.5)
+            ]
+        );
+    }
+}
@@ -0,0 +1,808 @@
+//! Managing an office's desks from one place.
+//!
+//! Daemons started with `--fleet <addr>` run a [`FleetAgent`], which registers the desk with a
+//! fleet server and reports in every few seconds with its state and usage. The server keeps the
+//! inventory, works out the health of the fleet as a whole, and answers each report with the
+//! settings pushed to that desk, which the agent applies through the daemon's [`Handle`]. Desks
+//! only ever connect out, so the server doesn't need to be able to reach them.
+//!
+//! | method | path          | body                     |                                      |
+//! |--------|---------------|--------------------------|--------------------------------------|
+//! | GET    | `/desks`      |                          | the inventory, with each desk's health and usage |
+//! | GET    | `/desks/<id>` |                          | one desk                             |
+//! | PUT    | `/desks/<id>` | a report                 | register a desk or report in, used by agents |
+//! | DELETE | `/desks/<id>` |                          | forget a desk                        |
+//! | GET    | `/summary`    |                          | health and usage across the fleet    |
+//! | POST   | `/push`       | a selection and settings | push settings to a group of desks    |
+//!
+//! A push picks desks with any of `"desks": [<id>, ...]`, `"group": <name>` and `"location":
+//! <prefix>`, all of which a desk has to match, and sets any of `"presets": [<cm>, <cm>, <cm>]`,
+//! `"limits": {"min_height_cm": 65, "max_height_cm": 110}` and `"schedule": <rules>`. A schedule
+//! of `null` sends a desk back to its own. Settings are kept for each desk, and sent again
+//! whenever its daemon restarts.
+
+use std::io;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::daemon::Handle;
+use crate::http::{self, Request, Response};
+use crate::json::Value;
+use crate::link::Clock;
+use crate::metrics::{BusMetrics, Posture};
+use crate::DeskProfile;
+
+/// How often agents report in.
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long to give a daemon to apply settings before reporting on them.
+const APPLY_DELAY: Duration = Duration::from_millis(100);
+
+/// How long a desk may go without reporting in before it counts as offline.
+const OFFLINE_AFTER: Duration = Duration::from_secs(15);
+
+/// What a desk tells the server about itself when it registers.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Registration {
+    pub id: String,
+    /// Where the desk is, such as `london/3/east`. Pushes can pick desks by prefix.
+    pub location: String,
+    pub groups: Vec<String>,
+    /// Ways this desk's firmware differs from others, such as `ignores-preset-height`.
+    pub quirks: Vec<String>,
+}
+
+/// Desk ids end up in URLs, so they are kept to letters, digits, `-`, `_` and `.`.
+pub fn check_id(id: &str) -> Result<(), String> {
+    let valid = !id.is_empty()
+        && id.len() <= 64
+        && !id.starts_with('.')
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid desk id {:?}: use letters, digits, -, _ and .",
+            id
+        ))
+    }
+}
+
+/// How much a desk has been used since its daemon started.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Usage {
+    pub travel_cm: f64,
+    pub motor_on: Duration,
+    pub stand_ups: u64,
+    pub sit_downs: u64,
+}
+
+impl Usage {
+    pub fn from_metrics(metrics: &BusMetrics) -> Usage {
+        Usage {
+            travel_cm: metrics.travel_cm(),
+            motor_on: metrics.motor_on(),
+            stand_ups: metrics.transitions(Posture::Standing),
+            sit_downs: metrics.transitions(Posture::Sitting),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        Value::object([
+            ("travel_cm", self.travel_cm.into()),
+            ("motor_on_s", self.motor_on.as_secs_f64().into()),
+            ("stand_ups", self.stand_ups.into()),
+            ("sit_downs", self.sit_downs.into()),
+        ])
+    }
+
+    /// Reads usage back, treating anything missing or malformed as none.
+    pub fn from_json(value: &Value) -> Usage {
+        let number = |key: &str| {
+            value
+                .get(key)
+                .and_then(Value::as_f64)
+                .filter(|n| n.is_finite() && *n >= 0.0)
+                .unwrap_or(0.0)
+        };
+        Usage {
+            travel_cm: number("travel_cm"),
+            motor_on: Duration::try_from_secs_f64(number("motor_on_s")).unwrap_or_default(),
+            stand_ups: number("stand_ups") as u64,
+            sit_downs: number("sit_downs") as u64,
+        }
+    }
+
+    fn add(&mut self, other: &Usage) {
+        self.travel_cm += other.travel_cm;
+        self.motor_on = self.motor_on.saturating_add(other.motor_on);
+        self.stand_ups = self.stand_ups.saturating_add(other.stand_ups);
+        self.sit_downs = self.sit_downs.saturating_add(other.sit_downs);
+    }
+}
+
+/// Settings pushed to a desk. Anything left as `None` is left alone.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeskConfig {
+    pub presets: Option<[Option<f32>; 3]>,
+    pub limits: Option<DeskProfile>,
+    /// Schedule rules, or `Some(None)` to go back to the desk's own schedule.
+    pub schedule: Option<Option<String>>,
+}
+
+impl DeskConfig {
+    pub fn to_json(&self) -> Value {
+        let mut fields = Vec::new();
+        if let Some(presets) = self.presets {
+            fields.push(("presets", presets.to_vec().into()));
+        }
+        if let Some(limits) = self.limits {
+            let limits = Value::object([
+                ("min_height_cm", limits.min_height_cm.into()),
+                ("max_height_cm", limits.max_height_cm.into()),
+            ]);
+            fields.push(("limits", limits));
+        }
+        if let Some(schedule) = &self.schedule {
+            fields.push(("schedule", schedule.clone().into()));
+        }
+        Value::object(fields)
+    }
+
+    pub fn from_json(value: &Value) -> Result<DeskConfig, String> {
+        let presets = match value.get("presets") {
+            None => None,
+            Some(values) => {
+                let values = values
+                    .as_array()
+                    .filter(|v| v.len() <= 3)
+                    .ok_or("expected up to three presets")?;
+                let mut presets = [None; 3];
+                for (preset, value) in presets.iter_mut().zip(values) {
+                    *preset = match value {
+                        Value::Null => None,
+                        _ => Some(
+                            value
+                                .as_f32()
+                                .filter(|h| h.is_finite())
+                                .ok_or("expected a number or null for each preset")?,
+                        ),
+                    };
+                }
+                Some(presets)
+            }
+        };
+
+        let limits = match value.get("limits") {
+            None => None,
+            Some(limits) => {
+                let height = |key: &str| {
+                    limits
+                        .get(key)
+                        .and_then(Value::as_f32)
+                        .filter(|h| h.is_finite())
+                        .ok_or_else(|| format!("expected a number for limits.{}", key))
+                };
+                let limits = DeskProfile::new(height("min_height_cm")?, height("max_height_cm")?)
+                    .map_err(|e| e.to_string())?;
+                Some(limits)
+            }
+        };
+
+        let schedule = match value.get("schedule") {
+            None => None,
+            Some(Value::Null) => Some(None),
+            Some(Value::String(s)) => Some(Some(s.clone())),
+            Some(_) => return Err("expected a string or null for schedule".to_string()),
+        };
+        #[cfg(feature = "schedule")]
+        if let Some(Some(rules)) = &schedule {
+            crate::schedule::Schedule::parse(rules).map_err(|e| format!("schedule: {}", e))?;
+        }
+
+        Ok(DeskConfig {
+            presets,
+            limits,
+            schedule,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == DeskConfig::default()
+    }
+
+    /// Applies the settings to a daemon, limits first so that presets are clamped to them.
+    pub fn apply(&self, handle: &Handle) -> Result<(), String> {
+        if let Some(limits) = self.limits {
+            handle.set_limits(limits)?;
+        }
+        for (n, height) in self.presets.iter().flatten().enumerate() {
+            if let Some(height) = height {
+                handle.program_preset(n + 1, *height)?;
+            }
+        }
+        if let Some(schedule) = &self.schedule {
+            handle.set_schedule(schedule.clone());
+        }
+        Ok(())
+    }
+
+    /// Takes on whatever `other` sets.
+    fn merge(&mut self, other: &DeskConfig) {
+        self.presets = other.presets.or(self.presets);
+        self.limits = other.limits.or(self.limits);
+        if other.schedule.is_some() {
+            self.schedule = other.schedule.clone();
+        }
+    }
+}
+
+/// Which desks a push goes to. A desk has to match everything given.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Selection {
+    pub desks: Option<Vec<String>>,
+    pub group: Option<String>,
+    /// A location prefix, matched a whole `/`-separated part at a time.
+    pub location: Option<String>,
+}
+
+impl Selection {
+    pub fn from_json(value: &Value) -> Result<Selection, String> {
+        let string = |key: &str| match value.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s.clone())),
+            Some(_) => Err(format!("expected a string for {}", key)),
+        };
+        let desks = match value.get("desks") {
+            None | Some(Value::Null) => None,
+            Some(ids) => Some(
+                ids.as_array()
+                    .and_then(|ids| {
+                        ids.iter()
+                            .map(|id| id.as_str().map(str::to_string))
+                            .collect()
+                    })
+                    .ok_or("expected an array of desk ids for desks")?,
+            ),
+        };
+        let selection = Selection {
+            desks,
+            group: string("group")?,
+            location: string("location")?,
+        };
+        if selection == Selection::default() {
+            return Err("expected desks, group or location to pick desks by".to_string());
+        }
+        Ok(selection)
+    }
+
+    pub fn matches(&self, desk: &Registration) -> bool {
+        self.desks.as_ref().is_none_or(|ids| ids.contains(&desk.id))
+            && self.group.as_ref().is_none_or(|g| desk.groups.contains(g))
+            && self.location.as_ref().is_none_or(|prefix| {
+                let prefix = prefix.trim_end_matches('/');
+                prefix.is_empty()
+                    || desk
+                        .location
+                        .strip_prefix(prefix)
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Desk {
+    registration: Registration,
+    /// The daemon's state as last reported.
+    state: Value,
+    usage: Usage,
+    last_report: Duration,
+    config: DeskConfig,
+    /// Bumped by every push to the desk.
+    revision: u64,
+    /// The revision the desk last said it had applied.
+    applied: u64,
+}
+
+impl Desk {
+    /// `offline`, or the worse of the health of the daemon's links.
+    fn status(&self, now: Duration) -> &'static str {
+        if now.saturating_sub(self.last_report) > OFFLINE_AFTER {
+            return "offline";
+        }
+        let health = |link: &str| {
+            self.state
+                .get(link)
+                .and_then(|l| l.get("health"))
+                .and_then(Value::as_str)
+        };
+        let links = [health("desk"), health("panel")];
+        ["silent", "noisy"]
+            .into_iter()
+            .find(|bad| links.contains(&Some(*bad)))
+            .unwrap_or("ok")
+    }
+
+    fn to_json(&self, now: Duration) -> Value {
+        let state = |key: &str| self.state.get(key).cloned().unwrap_or(Value::Null);
+        let registration = &self.registration;
+        Value::object([
+            ("id", registration.id.as_str().into()),
+            ("location", registration.location.as_str().into()),
+            ("groups", registration.groups.clone().into()),
+            ("quirks", registration.quirks.clone().into()),
+            ("profile", state("user")),
+            ("status", self.status(now).into()),
+            (
+                "seconds_since_report",
+                now.saturating_sub(self.last_report).as_secs_f64().into(),
+            ),
+            ("height_cm", state("height_cm")),
+            ("usage", self.usage.to_json()),
+            ("config", self.config.to_json()),
+            ("pending", (self.applied != self.revision).into()),
+            ("state", self.state.clone()),
+        ])
+    }
+}
+
+/// The server's view of the fleet. Times are monotonic, from the server's [`Clock`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Fleet {
+    desks: Vec<Desk>,
+}
+
+impl Fleet {
+    pub fn new() -> Fleet {
+        Fleet::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.desks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.desks.is_empty()
+    }
+
+    /// Registers a desk or takes a report from it, and returns the settings it should have.
+    pub fn report(&mut self, id: &str, report: &Value, now: Duration) -> Result<Value, String> {
+        check_id(id)?;
+        let strings = |key: &str| -> Result<Vec<String>, String> {
+            match report.get(key) {
+                None | Some(Value::Null) => Ok(Vec::new()),
+                Some(values) => values
+                    .as_array()
+                    .and_then(|v| v.iter().map(|s| s.as_str().map(str::to_string)).collect())
+                    .ok_or_else(|| format!("expected an array of strings for {}", key)),
+            }
+        };
+        let registration = Registration {
+            id: id.to_string(),
+            location: report
+                .get("location")
+                .and_then(Value::as_str)
+                .unwrap_or("")
+                .to_string(),
+            groups: strings("groups")?,
+            quirks: strings("quirks")?,
+        };
+        let applied = report
+            .get("revision")
+            .and_then(Value::as_f64)
+            .map_or(0, |r| r as u64);
+        let state = report.get("state").cloned().unwrap_or(Value::Null);
+        let usage = report
+            .get("usage")
+            .map(Usage::from_json)
+            .unwrap_or_default();
+
+        let desk = match self.desks.iter().position(|d| d.registration.id == id) {
+            Some(i) => &mut self.desks[i],
+            None => {
+                self.desks.push(Desk {
+                    registration: registration.clone(),
+                    state: Value::Null,
+                    usage: Usage::default(),
+                    last_report: now,
+                    config: DeskConfig::default(),
+                    revision: 0,
+                    applied: 0,
+                });
+                self.desks.last_mut().unwrap()
+            }
+        };
+        desk.registration = registration;
+        desk.state = state;
+        desk.usage = usage;
+        desk.last_report = now;
+        desk.applied = applied;
+        Ok(Value::object([
+            ("revision", desk.revision.into()),
+            ("config", desk.config.to_json()),
+        ]))
+    }
+
+    pub fn remove(&mut self, id: &str) -> bool {
+        let before = self.desks.len();
+        self.desks.retain(|d| d.registration.id != id);
+        self.desks.len() != before
+    }
+
+    /// Pushes settings to every desk `selection` matches, and returns their ids.
+    pub fn push(&mut self, selection: &Selection, config: &DeskConfig) -> Vec<String> {
+        let mut pushed = Vec::new();
+        for desk in &mut self.desks {
+            if selection.matches(&desk.registration) {
+                desk.config.merge(config);
+                desk.revision += 1;
+                pushed.push(desk.registration.id.clone());
+            }
+        }
+        pushed
+    }
+
+    pub fn desk(&self, id: &str, now: Duration) -> Option<Value> {
+        self.desks
+            .iter()
+            .find(|d| d.registration.id == id)
+            .map(|d| d.to_json(now))
+    }
+
+    pub fn inventory(&self, now: Duration) -> Value {
+        let desks: Vec<Value> = self.desks.iter().map(|d| d.to_json(now)).collect();
+        Value::object([("desks", desks.into())])
+    }
+
+    /// How many desks are in each state of health, how many have settings still to apply, and
+    /// their usage added up.
+    pub fn summary(&self, now: Duration) -> Value {
+        let mut usage = Usage::default();
+        let mut pending = 0;
+        let statuses = ["ok", "noisy", "silent", "offline"];
+        let mut counts = [0usize; 4];
+        for desk in &self.desks {
+            usage.add(&desk.usage);
+            pending += (desk.applied != desk.revision) as usize;
+            let status = desk.status(now);
+            counts[statuses.iter().position(|s| *s == status).unwrap_or(0)] += 1;
+        }
+        Value::object([
+            ("desks", self.desks.len().into()),
+            (
+                "health",
+                Value::object(statuses.into_iter().zip(counts.map(Value::from))),
+            ),
+            ("pending", pending.into()),
+            ("usage", usage.to_json()),
+        ])
+    }
+}
+
+/// Serves the fleet API forever.
+pub fn serve<C>(listener: TcpListener, fleet: Arc<Mutex<Fleet>>, clock: C) -> io::Result<()>
+where
+    C: Clock + Send + Sync + 'static,
+{
+    http::serve(listener, move |request, stream| {
+        respond(&fleet, request, clock.now()).write_to(stream)
+    })
+}
+
+pub fn respond(fleet: &Mutex<Fleet>, request: &Request, now: Duration) -> Response {
+    let Some(segments) = request.segments() else {
+        return Response::error(400, "badly encoded path");
+    };
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    // Nothing holding the lock can leave the fleet half-updated.
+    let mut fleet = fleet.lock().unwrap_or_else(|e| e.into_inner());
+
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["desks"]) => Response::json(200, &fleet.inventory(now)),
+        ("GET", ["desks", id]) => match fleet.desk(id, now) {
+            Some(desk) => Response::json(200, &desk),
+            None => Response::error(404, &format!("there is no desk {:?}", id)),
+        },
+        ("PUT", ["desks", id]) => {
+            let result = request
+                .json()
+                .map_err(|e| e.to_string())
+                .and_then(|report| fleet.report(id, &report, now));
+            match result {
+                Ok(response) => Response::json(200, &response),
+                Err(e) => Response::error(400, &e),
+            }
+        }
+        ("DELETE", ["desks", id]) => match fleet.remove(id) {
+            true => Response::json(200, &Value::object::<&str, _>([])),
+            false => Response::error(404, &format!("there is no desk {:?}", id)),
+        },
+        ("GET", ["summary"]) => Response::json(200, &fleet.summary(now)),
+        ("POST", ["push"]) => {
+            let push = request.json().map_err(|e| e.to_string()).and_then(|body| {
+                let config = DeskConfig::from_json(&body)?;
+                if config.is_empty() {
+                    return Err("expected presets, limits or schedule to push".to_string());
+                }
+                Ok((Selection::from_json(&body)?, config))
+            });
+            match push {
+                Ok((selection, config)) => {
+                    let pushed = fleet.push(&selection, &config);
+                    Response::json(200, &Value::object([("desks", pushed.into())]))
+                }
+                Err(e) => Response::error(400, &e),
+            }
+        }
+        (_, ["desks"] | ["desks", _] | ["summary"] | ["push"]) => {
+            Response::error(405, "method not allowed")
+        }
+        _ => Response::error(404, "not found"),
+    }
+}
+
+/// Reports a daemon in to a fleet server, and applies the settings pushed to it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FleetAgent {
+    server: String,
+    registration: Registration,
+    revision: u64,
+}
+
+impl FleetAgent {
+    pub fn new(server: &str, registration: Registration) -> FleetAgent {
+        FleetAgent {
+            server: server.to_string(),
+            registration,
+            revision: 0,
+        }
+    }
+
+    /// Reports in once, and applies the settings if they have changed since the last report.
+    /// Returns whether they had.
+    pub fn report(&mut self, handle: &Handle) -> io::Result<bool> {
+        let registration = &self.registration;
+        let report = Value::object([
+            ("location", registration.location.as_str().into()),
+            ("groups", registration.groups.clone().into()),
+            ("quirks", registration.quirks.clone().into()),
+            ("revision", self.revision.into()),
+            ("state", handle.state().to_json()),
+            ("usage", Usage::from_metrics(&handle.metrics()).to_json()),
+        ]);
+        let path = format!("/desks/{}", registration.id);
+        let response = http::request(
+            self.server.as_str(),
+            "PUT",
+            &path,
+            Some(&report.to_string()),
+        )?;
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let body = Value::parse(&response.body).map_err(|e| invalid(e.to_string()))?;
+        if response.status != 200 {
+            let error = body.get("error").and_then(Value::as_str).unwrap_or("");
+            return Err(invalid(format!("status {}: {}", response.status, error)));
+        }
+
+        let revision =
+            body.get("revision")
+                .and_then(Value::as_f64)
+                .ok_or_else(|| invalid("expected a revision".to_string()))? as u64;
+        if revision == self.revision {
+            return Ok(false);
+        }
+        let config = body
+            .get("config")
+            .ok_or_else(|| invalid("expected a config".to_string()))?;
+        DeskConfig::from_json(config)
+            .and_then(|config| config.apply(handle))
+            .map_err(invalid)?;
+        self.revision = revision;
+        Ok(true)
+    }
+}
+
+/// Reports in every [`REPORT_INTERVAL`] forever, carrying on through any errors.
+pub fn run_agent(handle: Handle, mut agent: FleetAgent) {
+    loop {
+        let wait = match agent.report(&handle) {
+            // Report again once the daemon has taken the settings on, so that the server
+            // sees them in place without waiting a whole interval.
+            Ok(true) => APPLY_DELAY,
+            Ok(false) => REPORT_INTERVAL,
+            Err(e) => {
+                handle.notify("fleet", format!("{}: {}", agent.server, e));
+                REPORT_INTERVAL
+            }
+        };
+        thread::sleep(wait);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::{Daemon, DaemonOptions};
+    use crate::link::{ManualClock, SimulatedDesk, SystemClock};
+    use crate::sim::DeskSimulator;
+
+    fn s(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn report(location: &str, groups: &[&str]) -> Value {
+        let groups: Vec<Value> = groups.iter().map(|&g| g.into()).collect();
+        Value::object([
+            ("location", location.into()),
+            ("groups", groups.into()),
+            (
+                "state",
+                Value::object([("desk", Value::object([("health", "ok".into())]))]),
+            ),
+            (
+                "usage",
+                Value::object([("travel_cm", 10.0f64.into()), ("stand_ups", 2u64.into())]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_fleet() {
+        let mut fleet = Fleet::new();
+        fleet
+            .report("a", &report("london/3/east", &["team"]), s(0))
+            .unwrap();
+        fleet
+            .report("b", &report("london/30", &["team"]), s(0))
+            .unwrap();
+        fleet.report("c", &report("paris/1", &[]), s(0)).unwrap();
+        assert!(fleet.report("../x", &report("", &[]), s(0)).is_err());
+
+        let config = DeskConfig {
+            presets: Some([Some(72.0), Some(110.0), None]),
+            ..DeskConfig::default()
+        };
+        let london = Selection {
+            location: Some("london/3".to_string()),
+            ..Selection::default()
+        };
+        assert_eq!(fleet.push(&london, &config), ["a"]);
+        let team = Selection {
+            group: Some("team".to_string()),
+            ..Selection::default()
+        };
+        let schedule = DeskConfig {
+            schedule: Some(None),
+            ..DeskConfig::default()
+        };
+        assert_eq!(fleet.push(&team, &schedule), ["a", "b"]);
+
+        // Pushes add up, and are sent until the desk says it has them.
+        let response = fleet.report("a", &report("london/3/east", &["team"]), s(5));
+        assert_eq!(
+            response.unwrap().to_string(),
+            r#"{"revision":2,"config":{"presets":[72,110,null],"schedule":null}}"#
+        );
+        assert_eq!(
+            fleet.summary(s(5)).get("pending"),
+            Some(&Value::from(2usize))
+        );
+
+        let mut up_to_date = report("london/3/east", &["team"]);
+        if let Value::Object(fields) = &mut up_to_date {
+            fields.push(("revision".to_string(), 2u64.into()));
+        }
+        fleet.report("a", &up_to_date, s(10)).unwrap();
+        assert_eq!(
+            fleet.summary(s(20)).to_string(),
+            concat!(
+                r#"{"desks":3,"health":{"ok":1,"noisy":0,"silent":0,"offline":2},"pending":1,"#,
+                r#""usage":{"travel_cm":30,"motor_on_s":0,"stand_ups":6,"sit_downs":0}}"#
+            )
+        );
+        assert!(fleet.remove("c"));
+        assert_eq!(fleet.len(), 2);
+    }
+
+    #[test]
+    fn test_usage_does_not_overflow() {
+        let huge = Value::parse(r#"{"motor_on_s":1e300,"stand_ups":1e300}"#).unwrap();
+        let usage = Usage::from_json(&huge);
+        assert_eq!(usage.motor_on, Duration::ZERO);
+        assert_eq!(usage.stand_ups, u64::MAX);
+
+        let mut total = Usage {
+            motor_on: Duration::MAX,
+            ..usage
+        };
+        let same = total;
+        total.add(&same);
+        assert_eq!(total.motor_on, Duration::MAX);
+        assert_eq!(total.stand_ups, u64::MAX);
+    }
+
+    #[test]
+    fn test_parse_push() {
+        let body = Value::parse(
+            r#"{"desks":["a"],"limits":{"min_height_cm":70,"max_height_cm":100},"schedule":"0 12 * * * stand"}"#,
+        )
+        .unwrap();
+        let config = DeskConfig::from_json(&body).unwrap();
+        assert_eq!(
+            config.limits,
+            Some(DeskProfile {
+                min_height_cm: 70.0,
+                max_height_cm: 100.0
+            })
+        );
+        assert_eq!(config.schedule, Some(Some("0 12 * * * stand".to_string())));
+        assert_eq!(config.presets, None);
+        assert_eq!(
+            Selection::from_json(&body).unwrap().desks,
+            Some(vec!["a".to_string()])
+        );
+
+        let bad = Value::parse(r#"{"limits":{"min_height_cm":100,"max_height_cm":70}}"#).unwrap();
+        assert!(DeskConfig::from_json(&bad).is_err());
+        assert!(Selection::from_json(&bad).is_err());
+    }
+
+    #[test]
+    fn test_simulated_desks() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let fleet = Arc::new(Mutex::new(Fleet::new()));
+        let shared = fleet.clone();
+        thread::spawn(move || serve(listener, shared, SystemClock::new()));
+
+        let clock = ManualClock::new();
+        let mut desks: Vec<(Daemon<ManualClock>, FleetAgent)> = ["one", "two", "three"]
+            .iter()
+            .enumerate()
+            .map(|(i, id)| {
+                let sim = DeskSimulator::new(DeskProfile::default(), 90.0);
+                let link = Box::new(SimulatedDesk::new(sim, clock.clone()));
+                let daemon = Daemon::new(link, None, clock.clone(), DaemonOptions::default());
+                let registration = Registration {
+                    id: id.to_string(),
+                    location: format!("hq/{}", i % 2),
+                    ..Registration::default()
+                };
+                (daemon, FleetAgent::new(&server, registration))
+            })
+            .collect();
+        for (daemon, agent) in &mut desks {
+            daemon.step().unwrap();
+            assert!(!agent.report(&daemon.handle()).unwrap());
+        }
+        assert_eq!(fleet.lock().unwrap().len(), 3);
+
+        let push = r#"{"location":"hq/0","presets":[70,null,120],"limits":{"min_height_cm":68,"max_height_cm":115}}"#;
+        let response = http::request(server.as_str(), "POST", "/push", Some(push)).unwrap();
+        assert_eq!(response.body, r#"{"desks":["one","three"]}"#);
+
+        for (daemon, agent) in &mut desks {
+            let handle = daemon.handle();
+            let pushed = agent.report(&handle).unwrap();
+            daemon.step().unwrap();
+            let state = handle.state();
+            if pushed {
+                assert_eq!(state.profile.max_height_cm, 115.0);
+                assert_eq!(state.presets, [Some(70.0), None, Some(115.0)]);
+            } else {
+                assert_eq!(state.profile, DeskProfile::default());
+            }
+            // Nothing more to apply the next time round.
+            assert!(!agent.report(&handle).unwrap());
+        }
+
+        let summary = http::request(server.as_str(), "GET", "/summary", None).unwrap();
+        let summary = Value::parse(&summary.body).unwrap();
+        assert_eq!(summary.get("desks"), Some(&Value::from(3usize)));
+        assert_eq!(summary.get("pending"), Some(&Value::from(0usize)));
+    }
+}
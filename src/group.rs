@@ -0,0 +1,410 @@
+//! Moving several linked desks together, such as the halves of an L-shaped or back-to-back
+//! cluster, each on a link of its own with its panel unplugged.
+//!
+//! Every desk is driven towards the same height by holding Up or Down, as the daemon does for
+//! one. The desk with the furthest still to go sets the pace: any other that gets more than
+//! `max_spread_cm` ahead of it is paused until it has caught up again. If any desk stalls or
+//! goes quiet the whole group is stopped, so that the desks never pull against whatever joins
+//! them.
+
+use std::time::Duration;
+
+use crate::link::{Clock, Link};
+use crate::tracker::BusTracker;
+use crate::{DeskProfile, Direction, PanelToDeskMessage};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GroupOptions {
+    pub profile: DeskProfile,
+    /// How close to the target each desk has to get to count as there.
+    pub tolerance_cm: f32,
+    /// How far ahead of the slowest desk another may get before it is paused.
+    pub max_spread_cm: f32,
+    pub send_interval: Duration,
+    /// How long a desk being driven may go without its height changing.
+    pub stall_timeout: Duration,
+    /// How long a desk may go without reporting its height at all.
+    pub link_timeout: Duration,
+}
+
+impl Default for GroupOptions {
+    fn default() -> Self {
+        GroupOptions {
+            profile: DeskProfile::default(),
+            tolerance_cm: 0.5,
+            max_spread_cm: 1.0,
+            send_interval: Duration::from_millis(50),
+            stall_timeout: Duration::from_secs(2),
+            link_timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+/// How a move of the group ended. Desks are numbered by their position in the group.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GroupOutcome {
+    Reached,
+    Stopped,
+    Stalled(usize),
+    LinkLost { desk: usize, reason: String },
+}
+
+struct Member {
+    link: Box<dyn Link>,
+    tracker: BusTracker,
+    key: PanelToDeskMessage,
+    paused: bool,
+    done: bool,
+    /// When the height last changed while the desk was being driven, and what it was.
+    progress: (Duration, Option<f32>),
+    last_send: Option<Duration>,
+}
+
+pub struct DeskGroup<C: Clock> {
+    members: Vec<Member>,
+    clock: C,
+    options: GroupOptions,
+    target: Option<f32>,
+    started: Duration,
+}
+
+impl<C: Clock> DeskGroup<C> {
+    pub fn new(links: Vec<Box<dyn Link>>, clock: C, options: GroupOptions) -> DeskGroup<C> {
+        let started = clock.now();
+        let members = links
+            .into_iter()
+            .map(|link| Member {
+                link,
+                tracker: BusTracker::new(),
+                key: PanelToDeskMessage::NoKey,
+                paused: false,
+                done: false,
+                progress: (started, None),
+                last_send: None,
+            })
+            .collect();
+        DeskGroup {
+            members,
+            clock,
+            options,
+            target: None,
+            started,
+        }
+    }
+
+    /// The last height each desk reported.
+    pub fn heights(&self) -> Vec<Option<f32>> {
+        self.members.iter().map(|m| m.tracker.height_cm()).collect()
+    }
+
+    /// Which desks are waiting for a slower one to catch up.
+    pub fn paused(&self) -> Vec<bool> {
+        self.members.iter().map(|m| m.paused).collect()
+    }
+
+    pub fn target_cm(&self) -> Option<f32> {
+        self.target
+    }
+
+    /// Starts moving every desk, and returns the target after clamping it to the desks' range.
+    pub fn move_to(&mut self, height_cm: f32) -> f32 {
+        let target = self.options.profile.clamp(height_cm);
+        let now = self.clock.now();
+        for member in &mut self.members {
+            member.paused = false;
+            member.done = false;
+            member.progress = (now, member.tracker.height_cm());
+        }
+        self.target = Some(target);
+        target
+    }
+
+    pub fn stop(&mut self) -> GroupOutcome {
+        let now = self.clock.now();
+        self.halt(GroupOutcome::Stopped, now)
+    }
+
+    /// Steps until the move under way ends.
+    pub fn run(&mut self) -> GroupOutcome {
+        loop {
+            if let Some(outcome) = self.step() {
+                return outcome;
+            }
+            self.clock.sleep(Duration::from_millis(2));
+        }
+    }
+
+    /// Does whatever is due now, and returns how the move ended if it has.
+    pub fn step(&mut self) -> Option<GroupOutcome> {
+        let now = self.clock.now();
+        for (desk, member) in self.members.iter_mut().enumerate() {
+            loop {
+                match member.link.recv() {
+                    Ok(Some(data)) => member.tracker.observe(Direction::DeskToPanel, &data, now),
+                    Ok(None) => break,
+                    Err(e) => {
+                        let reason = e.to_string();
+                        return Some(self.halt(GroupOutcome::LinkLost { desk, reason }, now));
+                    }
+                }
+            }
+        }
+
+        if let Some(target) = self.target {
+            if let Some(outcome) = self.steer(target, now) {
+                return Some(self.halt(outcome, now));
+            }
+        }
+
+        for (desk, member) in self.members.iter_mut().enumerate() {
+            let due = member
+                .last_send
+                .is_none_or(|last| now >= last + self.options.send_interval);
+            if due {
+                member.last_send = Some(now);
+                if let Err(e) = member.link.send(&member.key.as_frame()) {
+                    let reason = e.to_string();
+                    return Some(self.halt(GroupOutcome::LinkLost { desk, reason }, now));
+                }
+            }
+        }
+
+        if self.target.is_some() && self.members.iter().all(|m| m.done) {
+            self.target = None;
+            return Some(GroupOutcome::Reached);
+        }
+        None
+    }
+
+    /// Works out which key each desk should have held.
+    fn steer(&mut self, target: f32, now: Duration) -> Option<GroupOutcome> {
+        let options = self.options;
+        let mut heights = Vec::with_capacity(self.members.len());
+        for (desk, member) in self.members.iter().enumerate() {
+            let last = member.tracker.last_report_at().unwrap_or(self.started);
+            if now.saturating_sub(last) > options.link_timeout {
+                let reason = "desk stopped reporting its height".to_string();
+                return Some(GroupOutcome::LinkLost { desk, reason });
+            }
+            heights.push(member.tracker.height_cm());
+        }
+        // Nothing moves until every desk has said where it is.
+        let heights = heights.into_iter().collect::<Option<Vec<f32>>>()?;
+
+        for (member, &height) in self.members.iter_mut().zip(&heights) {
+            let remaining = target - height;
+            let overshot = match member.key {
+                PanelToDeskMessage::Up => remaining < 0.0,
+                PanelToDeskMessage::Down => remaining > 0.0,
+                _ => false,
+            };
+            if overshot || remaining.abs() <= options.tolerance_cm {
+                member.done = true;
+            }
+        }
+        let slowest = self
+            .members
+            .iter()
+            .zip(&heights)
+            .filter(|(m, _)| !m.done)
+            .map(|(_, h)| (target - h).abs())
+            .fold(0.0, f32::max);
+
+        for (desk, (member, &height)) in self.members.iter_mut().zip(&heights).enumerate() {
+            let remaining = target - height;
+            let ahead = slowest - remaining.abs();
+            if member.paused && ahead <= options.max_spread_cm / 2.0 {
+                member.paused = false;
+            } else if !member.paused && ahead > options.max_spread_cm {
+                member.paused = true;
+            }
+
+            let key = if member.done || member.paused {
+                PanelToDeskMessage::NoKey
+            } else if remaining > 0.0 {
+                PanelToDeskMessage::Up
+            } else {
+                PanelToDeskMessage::Down
+            };
+            if key == PanelToDeskMessage::NoKey || member.key == PanelToDeskMessage::NoKey {
+                // Time spent waiting doesn't count towards a stall.
+                member.progress = (now, Some(height));
+            } else if Some(height) != member.progress.1 {
+                member.progress = (now, Some(height));
+            } else if now.saturating_sub(member.progress.0) >= options.stall_timeout {
+                return Some(GroupOutcome::Stalled(desk));
+            }
+            member.key = key;
+        }
+        None
+    }
+
+    /// Lets go of every desk.
+    fn halt(&mut self, outcome: GroupOutcome, now: Duration) -> GroupOutcome {
+        self.target = None;
+        for member in &mut self.members {
+            member.key = PanelToDeskMessage::NoKey;
+            member.paused = false;
+            member.last_send = Some(now);
+            // A desk whose link has failed can't be told anything more.
+            let _ = member.link.send(&PanelToDeskMessage::NoKey.as_frame());
+        }
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Decoded;
+    use crate::link::{ManualClock, SimulatedDesk};
+    use crate::sim::DeskSimulator;
+    use crate::DataFrame;
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+
+    /// A simulated desk that can be made to stop moving or stop reporting, and which keeps the
+    /// last frame it was sent.
+    struct Faulty {
+        desk: SimulatedDesk<ManualClock>,
+        jammed: Rc<RefCell<bool>>,
+        silent: Rc<RefCell<bool>>,
+        last: Rc<RefCell<Option<DataFrame>>>,
+    }
+
+    impl Link for Faulty {
+        fn send(&mut self, frame: &DataFrame) -> io::Result<()> {
+            *self.last.borrow_mut() = Some(*frame);
+            if !*self.jammed.borrow() {
+                self.desk.send(frame)?;
+            }
+            Ok(())
+        }
+
+        fn recv(&mut self) -> io::Result<Option<Decoded>> {
+            let data = self.desk.recv()?;
+            Ok(data.filter(|_| !*self.silent.borrow()))
+        }
+    }
+
+    struct Fixture {
+        group: DeskGroup<ManualClock>,
+        clock: ManualClock,
+        jammed: Rc<RefCell<bool>>,
+        silent: Rc<RefCell<bool>>,
+        last: Vec<Rc<RefCell<Option<DataFrame>>>>,
+    }
+
+    /// Three desks at the same height, the last of them slower than the others and the one
+    /// that can be made faulty.
+    fn fixture() -> Fixture {
+        let clock = ManualClock::new();
+        let jammed = Rc::new(RefCell::new(false));
+        let silent = Rc::new(RefCell::new(false));
+        let mut last = Vec::new();
+        let mut links: Vec<Box<dyn Link>> = Vec::new();
+        for speed in [4.0, 4.0, 3.0] {
+            let frame = Rc::new(RefCell::new(None));
+            last.push(frame.clone());
+            let sim = DeskSimulator::new(DeskProfile::default(), 80.0).with_speed(speed);
+            let faulty = speed < 4.0;
+            links.push(Box::new(Faulty {
+                desk: SimulatedDesk::new(sim, clock.clone()),
+                jammed: if faulty {
+                    jammed.clone()
+                } else {
+                    Rc::default()
+                },
+                silent: if faulty {
+                    silent.clone()
+                } else {
+                    Rc::default()
+                },
+                last: frame,
+            }));
+        }
+        let group = DeskGroup::new(links, clock.clone(), GroupOptions::default());
+        Fixture {
+            group,
+            clock,
+            jammed,
+            silent,
+            last,
+        }
+    }
+
+    /// Steps every 10ms until the move ends, returning how it ended and the widest spread of
+    /// heights seen on the way.
+    fn run(fixture: &mut Fixture, limit: Duration) -> (Option<GroupOutcome>, f32) {
+        let end = fixture.clock.now() + limit;
+        let mut spread: f32 = 0.0;
+        while fixture.clock.now() < end {
+            let outcome = fixture.group.step();
+            let heights: Vec<f32> = fixture.group.heights().into_iter().flatten().collect();
+            if let (Some(min), Some(max)) = (
+                heights.iter().copied().reduce(f32::min),
+                heights.iter().copied().reduce(f32::max),
+            ) {
+                spread = spread.max(max - min);
+            }
+            if outcome.is_some() {
+                return (outcome, spread);
+            }
+            fixture.clock.advance(Duration::from_millis(10));
+        }
+        (None, spread)
+    }
+
+    #[test]
+    fn test_moves_together() {
+        let mut fixture = fixture();
+        run(&mut fixture, Duration::from_millis(100));
+        assert_eq!(fixture.group.move_to(100.0), 100.0);
+
+        let (outcome, spread) = run(&mut fixture, Duration::from_secs(20));
+        assert_eq!(outcome, Some(GroupOutcome::Reached));
+        // The faster desks waited for the slow one rather than running 5cm ahead of it.
+        assert!(spread <= 1.5, "{}", spread);
+        for height in fixture.group.heights() {
+            assert!((height.unwrap() - 100.0).abs() <= 0.5);
+        }
+    }
+
+    #[test]
+    fn test_stall_stops_all() {
+        let mut fixture = fixture();
+        run(&mut fixture, Duration::from_millis(100));
+        fixture.group.move_to(100.0);
+        run(&mut fixture, Duration::from_secs(1));
+        *fixture.jammed.borrow_mut() = true;
+
+        let (outcome, _) = run(&mut fixture, Duration::from_secs(5));
+        assert_eq!(outcome, Some(GroupOutcome::Stalled(2)));
+        for last in &fixture.last {
+            assert_eq!(*last.borrow(), Some(PanelToDeskMessage::NoKey.as_frame()));
+        }
+    }
+
+    #[test]
+    fn test_lost_link_stops_all() {
+        let mut fixture = fixture();
+        run(&mut fixture, Duration::from_millis(100));
+        fixture.group.move_to(60.0);
+        run(&mut fixture, Duration::from_secs(1));
+        *fixture.silent.borrow_mut() = true;
+
+        let (outcome, _) = run(&mut fixture, Duration::from_secs(1));
+        assert_eq!(
+            outcome,
+            Some(GroupOutcome::LinkLost {
+                desk: 2,
+                reason: "desk stopped reporting its height".to_string()
+            })
+        );
+        for last in &fixture.last {
+            assert_eq!(*last.borrow(), Some(PanelToDeskMessage::NoKey.as_frame()));
+        }
+        assert_eq!(fixture.group.target_cm(), None);
+    }
+}
@@ -0,0 +1,166 @@
+//! Supervision of the bus: whether the panel and desk are still there, and whether the line is
+//! noisy.
+//!
+//! The panel sends a frame every few tens of milliseconds whether or not a key is held, and the
+//! desk reports its height as often, so silence from either for longer than that means it has
+//! been unplugged or has failed. A direction is noisy when it has seen a number of checksum
+//! failures and resyncs within a window.
+
+use core::time::Duration;
+
+use crate::tracker::BusTracker;
+use crate::Direction;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkHealth {
+    Ok,
+    /// Nothing, or for the desk no height, for longer than the timeout.
+    Silent,
+    Noisy,
+}
+
+impl LinkHealth {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LinkHealth::Ok => "ok",
+            LinkHealth::Silent => "silent",
+            LinkHealth::Noisy => "noisy",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HealthOptions {
+    /// How long the panel may go without sending a frame.
+    pub panel_timeout: Duration,
+    /// How long the desk may go without reporting its height.
+    pub desk_timeout: Duration,
+    pub noise_window: Duration,
+    /// Checksum failures and resyncs within `noise_window` that make a direction noisy.
+    pub noisy_errors: u64,
+}
+
+impl Default for HealthOptions {
+    fn default() -> Self {
+        HealthOptions {
+            panel_timeout: Duration::from_secs(1),
+            desk_timeout: Duration::from_millis(500),
+            noise_window: Duration::from_secs(10),
+            noisy_errors: 5,
+        }
+    }
+}
+
+/// Works out the health of each direction of the bus from what a [`BusTracker`] has seen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HealthMonitor {
+    options: HealthOptions,
+    started: Duration,
+    window_start: Duration,
+    // Errors per direction at the start of the current window, and whether the last whole
+    // window was noisy.
+    errors_at_start: [u64; 2],
+    was_noisy: [bool; 2],
+}
+
+impl HealthMonitor {
+    pub fn new(options: HealthOptions, now: Duration) -> HealthMonitor {
+        HealthMonitor {
+            options,
+            started: now,
+            window_start: now,
+            errors_at_start: [0; 2],
+            was_noisy: [false; 2],
+        }
+    }
+
+    /// The health of each direction at `now`, indexed by [`Direction`].
+    pub fn check(&mut self, tracker: &BusTracker, now: Duration) -> [LinkHealth; 2] {
+        let errors = [Direction::PanelToDesk, Direction::DeskToPanel].map(|d| {
+            let stats = tracker.stats(d);
+            stats.checksum_failures + stats.resyncs
+        });
+        let noisy_since =
+            |start: [u64; 2]| [0, 1].map(|i| errors[i] - start[i] >= self.options.noisy_errors);
+        if now.saturating_sub(self.window_start) >= self.options.noise_window {
+            self.was_noisy = noisy_since(self.errors_at_start);
+            self.errors_at_start = errors;
+            self.window_start = now;
+        }
+        let noisy = noisy_since(self.errors_at_start);
+
+        let silent = |last: Option<Duration>, timeout: Duration| {
+            now.saturating_sub(last.unwrap_or(self.started)) > timeout
+        };
+        let silent = [
+            silent(
+                tracker.stats(Direction::PanelToDesk).last_seen,
+                self.options.panel_timeout,
+            ),
+            silent(tracker.last_report_at(), self.options.desk_timeout),
+        ];
+        [0, 1].map(|i| {
+            if silent[i] {
+                LinkHealth::Silent
+            } else if noisy[i] || self.was_noisy[i] {
+                LinkHealth::Noisy
+            } else {
+                LinkHealth::Ok
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Decoded;
+    use crate::{DeskToPanelMessage, PanelToDeskMessage};
+
+    #[test]
+    fn test_health() {
+        let ms = Duration::from_millis;
+        let mut tracker = BusTracker::new();
+        let mut monitor = HealthMonitor::new(HealthOptions::default(), ms(0));
+        let key = Decoded::Frame(PanelToDeskMessage::NoKey.as_frame());
+        let height = Decoded::Frame(DeskToPanelMessage::Height(90.0).as_frame());
+        let (panel, desk) = (
+            Direction::PanelToDesk as usize,
+            Direction::DeskToPanel as usize,
+        );
+
+        assert_eq!(monitor.check(&tracker, ms(100)), [LinkHealth::Ok; 2]);
+        assert_eq!(monitor.check(&tracker, ms(600))[desk], LinkHealth::Silent);
+        for at in (600..2000).step_by(50) {
+            tracker.observe(Direction::PanelToDesk, &key, ms(at));
+            tracker.observe(Direction::DeskToPanel, &height, ms(at));
+        }
+        assert_eq!(monitor.check(&tracker, ms(2000)), [LinkHealth::Ok; 2]);
+
+        // The panel is unplugged.
+        for at in (2000..3500).step_by(50) {
+            tracker.observe(Direction::DeskToPanel, &height, ms(at));
+        }
+        let health = monitor.check(&tracker, ms(3500));
+        assert_eq!(health[panel], LinkHealth::Silent);
+        assert_eq!(health[desk], LinkHealth::Ok);
+
+        // Other frames from the desk don't count as height reports.
+        for at in (3500..4500).step_by(50) {
+            tracker.observe(Direction::DeskToPanel, &Decoded::Stray(0x42), ms(at));
+        }
+        assert_eq!(monitor.check(&tracker, ms(4500))[desk], LinkHealth::Silent);
+
+        // Noise is remembered for a whole window after it stops.
+        tracker.observe(Direction::DeskToPanel, &height, ms(4500));
+        assert_eq!(monitor.check(&tracker, ms(4500))[desk], LinkHealth::Noisy);
+        for at in (4500..25_000).step_by(50) {
+            tracker.observe(Direction::DeskToPanel, &height, ms(at));
+            let health = monitor.check(&tracker, ms(at));
+            if at == 15_000 {
+                assert_eq!(health[desk], LinkHealth::Noisy);
+            }
+        }
+        assert_eq!(monitor.check(&tracker, ms(25_000))[desk], LinkHealth::Ok);
+    }
+}
@@ -0,0 +1,554 @@
+//! A SQLite store of every movement of the desk, and sit/stand statistics worked out from it.
+//!
+//! The bus can't tell whether anyone is at the desk, so the time between two movements is
+//! credited to the posture the desk was left in for at most [`StatsOptions::max_idle`]; anything
+//! longer is taken as the desk having been left alone.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{Datelike, Days, IsoWeek, NaiveDate, TimeZone};
+use rusqlite::{params, Connection};
+
+use crate::daemon::Event;
+use crate::json::Value;
+use crate::metrics::Posture;
+use crate::tracker::Movement;
+
+const SCHEMA_VERSION: i32 = 1;
+
+/// One movement of the desk, with times in milliseconds since the Unix epoch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MovementRecord {
+    pub started_at: i64,
+    pub ended_at: i64,
+    pub from_cm: f32,
+    pub to_cm: f32,
+}
+
+impl MovementRecord {
+    pub fn to_json(&self) -> Value {
+        Value::object([
+            ("started_at", (self.started_at as f64).into()),
+            ("ended_at", (self.ended_at as f64).into()),
+            ("from_cm", self.from_cm.into()),
+            ("to_cm", self.to_cm.into()),
+        ])
+    }
+}
+
+pub fn unix_millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+/// Turns a daemon's events into movement records.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Recorder {
+    // The last height reported and when it was.
+    last: Option<(i64, f32)>,
+    // When the current movement started and where from.
+    started: Option<(i64, f32)>,
+}
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        Recorder::default()
+    }
+
+    /// Takes an event that happened at `at`, and returns the movement it finished, if any.
+    pub fn on_event(&mut self, event: &Event, at: i64) -> Option<MovementRecord> {
+        match *event {
+            Event::Height(h) => {
+                if let Some((_, last)) = self.last {
+                    if self.started.is_none() && h != last {
+                        self.started = Some((at, last));
+                    }
+                }
+                self.last = Some((at, h));
+                None
+            }
+            Event::Movement(Movement::Stopped) => {
+                let (started_at, from_cm) = self.started.take()?;
+                let (ended_at, to_cm) = self.last?;
+                Some(MovementRecord {
+                    started_at,
+                    ended_at,
+                    from_cm,
+                    to_cm,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+pub struct History {
+    conn: Connection,
+}
+
+impl History {
+    pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<History> {
+        History::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> rusqlite::Result<History> {
+        History::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> rusqlite::Result<History> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS movements (
+                 id INTEGER PRIMARY KEY,
+                 started_at INTEGER NOT NULL,
+                 ended_at INTEGER NOT NULL,
+                 from_cm REAL NOT NULL,
+                 to_cm REAL NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS movements_by_start ON movements (started_at);",
+        )?;
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        Ok(History { conn })
+    }
+
+    pub fn record(&self, movement: &MovementRecord) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO movements (started_at, ended_at, from_cm, to_cm) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                movement.started_at,
+                movement.ended_at,
+                movement.from_cm as f64,
+                movement.to_cm as f64
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Movements that started in `[from, to)`, oldest first.
+    pub fn movements(&self, from: i64, to: i64) -> rusqlite::Result<Vec<MovementRecord>> {
+        let mut statement = self.conn.prepare(
+            "SELECT started_at, ended_at, from_cm, to_cm FROM movements
+             WHERE started_at >= ?1 AND started_at < ?2 ORDER BY started_at",
+        )?;
+        let rows = statement.query_map(params![from, to], |row| {
+            Ok(MovementRecord {
+                started_at: row.get(0)?,
+                ended_at: row.get(1)?,
+                from_cm: row.get::<_, f64>(2)? as f32,
+                to_cm: row.get::<_, f64>(3)? as f32,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// The last movement that started before `before`, which says where the desk was then.
+    pub fn last_movement_before(&self, before: i64) -> rusqlite::Result<Option<MovementRecord>> {
+        let mut statement = self.conn.prepare(
+            "SELECT started_at, ended_at, from_cm, to_cm FROM movements
+             WHERE started_at < ?1 ORDER BY started_at DESC LIMIT 1",
+        )?;
+        let mut rows = statement.query_map(params![before], |row| {
+            Ok(MovementRecord {
+                started_at: row.get(0)?,
+                ended_at: row.get(1)?,
+                from_cm: row.get::<_, f64>(2)? as f32,
+                to_cm: row.get::<_, f64>(3)? as f32,
+            })
+        })?;
+        rows.next().transpose()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StatsOptions {
+    pub standing_threshold_cm: f32,
+    /// The longest a stay at one height is counted for.
+    pub max_idle: Duration,
+    /// How much standing makes a day count towards a streak.
+    pub standing_goal: Duration,
+}
+
+impl Default for StatsOptions {
+    fn default() -> Self {
+        StatsOptions {
+            standing_threshold_cm: 90.0,
+            max_idle: Duration::from_secs(2 * 60 * 60),
+            standing_goal: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DayStats {
+    pub date: NaiveDate,
+    pub sitting: Duration,
+    pub standing: Duration,
+    /// Movements that went from sitting to standing height or back.
+    pub transitions: u32,
+    pub longest_standing: Duration,
+}
+
+impl DayStats {
+    fn new(date: NaiveDate) -> DayStats {
+        DayStats {
+            date,
+            sitting: Duration::ZERO,
+            standing: Duration::ZERO,
+            transitions: 0,
+            longest_standing: Duration::ZERO,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        Value::object([
+            ("date", self.date.to_string().into()),
+            ("sitting_s", self.sitting.as_secs().into()),
+            ("standing_s", self.standing.as_secs().into()),
+            ("transitions", (self.transitions as u64).into()),
+            ("longest_standing_s", self.longest_standing.as_secs().into()),
+        ])
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WeekStats {
+    pub week: IsoWeek,
+    pub sitting: Duration,
+    pub standing: Duration,
+    pub transitions: u32,
+    /// Days that reached the standing goal.
+    pub goal_days: u32,
+}
+
+impl WeekStats {
+    pub fn label(&self) -> String {
+        format!("{}-W{:02}", self.week.year(), self.week.week())
+    }
+
+    pub fn to_json(&self) -> Value {
+        Value::object([
+            ("week", self.label().into()),
+            ("sitting_s", self.sitting.as_secs().into()),
+            ("standing_s", self.standing.as_secs().into()),
+            ("transitions", (self.transitions as u64).into()),
+            ("goal_days", (self.goal_days as u64).into()),
+        ])
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Streaks {
+    /// Days in a row, up to the last day, that reached the standing goal. A last day that hasn't
+    /// reached it yet doesn't break the streak.
+    pub current_days: u32,
+    pub longest_days: u32,
+}
+
+/// Works out sitting and standing time for every day from `since` up to `until` in `tz`.
+/// `before` is the last movement before `since`, if known, which says how the first day started.
+pub fn daily_stats<Tz: TimeZone>(
+    tz: &Tz,
+    before: Option<&MovementRecord>,
+    movements: &[MovementRecord],
+    since: i64,
+    until: i64,
+    options: &StatsOptions,
+) -> Vec<DayStats> {
+    let date_of = |ms: i64| tz.timestamp_millis_opt(ms).unwrap().date_naive();
+    let mut days: BTreeMap<NaiveDate, DayStats> = BTreeMap::new();
+    let (first, last) = (date_of(since), date_of(until.max(since)));
+    let mut date = first;
+    while date <= last {
+        days.insert(date, DayStats::new(date));
+        date = date + Days::new(1);
+    }
+
+    let threshold = options.standing_threshold_cm;
+    let mut stretch = Duration::ZERO;
+    let all: Vec<&MovementRecord> = before.into_iter().chain(movements).collect();
+    for (i, movement) in all.iter().enumerate() {
+        let posture = Posture::at(movement.to_cm, threshold);
+        if Posture::at(movement.from_cm, threshold) != posture {
+            stretch = Duration::ZERO;
+            if movement.started_at >= since {
+                if let Some(day) = days.get_mut(&date_of(movement.started_at)) {
+                    day.transitions += 1;
+                }
+            }
+        }
+
+        let next = all.get(i + 1).map_or(until, |m| m.started_at).min(until);
+        let idle_end = movement.ended_at + options.max_idle.as_millis() as i64;
+        let end = next.min(idle_end);
+        let mut at = movement.ended_at.max(since);
+        while at < end {
+            let day_end = next_midnight(tz, at).min(end);
+            let spent = Duration::from_millis((day_end - at) as u64);
+            if let Some(day) = days.get_mut(&date_of(at)) {
+                match posture {
+                    Posture::Sitting => day.sitting += spent,
+                    Posture::Standing => {
+                        day.standing += spent;
+                        // A stretch carries on over midnight but is credited to each day only
+                        // for the part within it.
+                        stretch = if date_of(at) == date_of(at - 1) {
+                            stretch + spent
+                        } else {
+                            spent
+                        };
+                        day.longest_standing = day.longest_standing.max(stretch);
+                    }
+                }
+            }
+            at = day_end;
+        }
+        if end < next {
+            stretch = Duration::ZERO;
+        }
+    }
+    days.into_values().collect()
+}
+
+fn next_midnight<Tz: TimeZone>(tz: &Tz, ms: i64) -> i64 {
+    let date = tz.timestamp_millis_opt(ms).unwrap().date_naive() + Days::new(1);
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+    match tz.from_local_datetime(&midnight).earliest() {
+        Some(time) => time.timestamp_millis(),
+        // Midnight skipped by a clock change: the day ends an hour later.
+        None => tz
+            .from_local_datetime(&(midnight + chrono::Duration::hours(1)))
+            .earliest()
+            .map_or(ms + 24 * 60 * 60 * 1000, |t| t.timestamp_millis()),
+    }
+}
+
+pub fn weekly_stats(days: &[DayStats], options: &StatsOptions) -> Vec<WeekStats> {
+    let mut weeks: Vec<WeekStats> = Vec::new();
+    for day in days {
+        let week = day.date.iso_week();
+        if weeks.last().is_none_or(|w| w.week != week) {
+            weeks.push(WeekStats {
+                week,
+                sitting: Duration::ZERO,
+                standing: Duration::ZERO,
+                transitions: 0,
+                goal_days: 0,
+            });
+        }
+        let stats = weeks.last_mut().unwrap();
+        stats.sitting += day.sitting;
+        stats.standing += day.standing;
+        stats.transitions += day.transitions;
+        if day.standing >= options.standing_goal {
+            stats.goal_days += 1;
+        }
+    }
+    weeks
+}
+
+pub fn streaks(days: &[DayStats], options: &StatsOptions) -> Streaks {
+    let met: Vec<bool> = days
+        .iter()
+        .map(|d| d.standing >= options.standing_goal)
+        .collect();
+
+    let mut longest = 0;
+    let mut run = 0;
+    for &m in &met {
+        run = if m { run + 1 } else { 0 };
+        longest = longest.max(run);
+    }
+
+    let mut counted = &met[..];
+    if let Some((false, rest)) = counted.split_last() {
+        counted = rest;
+    }
+    let current = counted.iter().rev().take_while(|&&m| m).count() as u32;
+    Streaks {
+        current_days: current,
+        longest_days: longest,
+    }
+}
+
+pub fn format_duration(d: Duration) -> String {
+    let minutes = d.as_secs() / 60;
+    format!("{}h{:02}m", minutes / 60, minutes % 60)
+}
+
+pub fn days_csv(days: &[DayStats]) -> String {
+    let mut csv = "date,sitting_s,standing_s,transitions,longest_standing_s\n".to_string();
+    for day in days {
+        csv.push_str(&format!(
+            "{},{},{},{},{}\n",
+            day.date,
+            day.sitting.as_secs(),
+            day.standing.as_secs(),
+            day.transitions,
+            day.longest_standing.as_secs()
+        ));
+    }
+    csv
+}
+
+pub fn weeks_csv(weeks: &[WeekStats]) -> String {
+    let mut csv = "week,sitting_s,standing_s,transitions,goal_days\n".to_string();
+    for week in weeks {
+        csv.push_str(&format!(
+            "{},{},{},{},{}\n",
+            week.label(),
+            week.sitting.as_secs(),
+            week.standing.as_secs(),
+            week.transitions,
+            week.goal_days
+        ));
+    }
+    csv
+}
+
+pub fn movements_csv(movements: &[MovementRecord]) -> String {
+    let mut csv = "started_at,ended_at,from_cm,to_cm\n".to_string();
+    for m in movements {
+        csv.push_str(&format!(
+            "{},{},{:.1},{:.1}\n",
+            m.started_at, m.ended_at, m.from_cm, m.to_cm
+        ));
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, NaiveTime};
+
+    const MINUTE: i64 = 60 * 1000;
+
+    fn tz() -> FixedOffset {
+        FixedOffset::east_opt(2 * 60 * 60).unwrap()
+    }
+
+    fn at(date: &str, time: &str) -> i64 {
+        let date: NaiveDate = date.parse().unwrap();
+        let time: NaiveTime = time.parse().unwrap();
+        tz().from_local_datetime(&date.and_time(time))
+            .unwrap()
+            .timestamp_millis()
+    }
+
+    fn movement(start: i64, from_cm: f32, to_cm: f32) -> MovementRecord {
+        MovementRecord {
+            started_at: start,
+            ended_at: start,
+            from_cm,
+            to_cm,
+        }
+    }
+
+    #[test]
+    fn test_recorder() {
+        let mut recorder = Recorder::new();
+        assert_eq!(recorder.on_event(&Event::Height(72.0), 0), None);
+        assert_eq!(recorder.on_event(&Event::Height(72.0), 5000), None);
+        assert_eq!(recorder.on_event(&Event::Height(73.0), 5050), None);
+        assert_eq!(recorder.on_event(&Event::Height(110.0), 15000), None);
+        assert_eq!(
+            recorder.on_event(&Event::Movement(Movement::Stopped), 15300),
+            Some(MovementRecord {
+                started_at: 5050,
+                ended_at: 15000,
+                from_cm: 72.0,
+                to_cm: 110.0,
+            })
+        );
+        assert_eq!(
+            recorder.on_event(&Event::Movement(Movement::Stopped), 16000),
+            None
+        );
+    }
+
+    #[test]
+    fn test_store() {
+        let history = History::open_in_memory().unwrap();
+        let a = movement(1000, 72.0, 110.0);
+        let b = movement(5000, 110.0, 72.0);
+        history.record(&b).unwrap();
+        history.record(&a).unwrap();
+        assert_eq!(history.movements(0, 10_000).unwrap(), [a, b]);
+        assert_eq!(history.movements(2000, 10_000).unwrap(), [b]);
+        assert_eq!(history.last_movement_before(5000).unwrap(), Some(a));
+        assert_eq!(history.last_movement_before(1000).unwrap(), None);
+    }
+
+    #[test]
+    fn test_daily_stats() {
+        let options = StatsOptions::default();
+        let before = movement(at("2024-03-03", "17:00"), 110.0, 72.0);
+        let movements = [
+            // Movements that take no time keep the sums round. Sit from 9:00 until standing at 10:00, then back down at 10:45, then leave the desk
+            // at sitting height for the night.
+            movement(at("2024-03-04", "09:00"), 72.0, 73.0),
+            movement(at("2024-03-04", "10:00"), 73.0, 110.0),
+            movement(at("2024-03-04", "10:30"), 110.0, 115.0),
+            movement(at("2024-03-04", "10:45"), 115.0, 72.0),
+            // Stand from 23:00 until 01:00 the next day.
+            movement(at("2024-03-04", "23:00"), 72.0, 110.0),
+            movement(at("2024-03-05", "01:00"), 110.0, 72.0),
+        ];
+        let days = daily_stats(
+            &tz(),
+            Some(&before),
+            &movements,
+            at("2024-03-04", "00:00"),
+            at("2024-03-05", "12:00"),
+            &options,
+        );
+
+        assert_eq!(days.len(), 2);
+        let minutes = |m: i64| Duration::from_millis((m * MINUTE) as u64);
+        // Last night's sitting doesn't count, being more than two hours before anything else.
+        assert_eq!(days[0].sitting, minutes(60 + 120));
+        assert_eq!(days[0].standing, minutes(45 + 60));
+        assert_eq!(days[0].transitions, 3);
+        assert_eq!(days[0].longest_standing, minutes(60));
+
+        assert_eq!(days[1].standing, minutes(60));
+        assert_eq!(days[1].sitting, minutes(120));
+        assert_eq!(days[1].transitions, 1);
+        assert_eq!(days[1].longest_standing, minutes(60));
+
+        let weeks = weekly_stats(&days, &options);
+        assert_eq!(weeks.len(), 1);
+        assert_eq!(weeks[0].label(), "2024-W10");
+        assert_eq!(weeks[0].standing, minutes(165));
+        assert_eq!(weeks[0].goal_days, 2);
+
+        assert!(days_csv(&days).starts_with(
+            "date,sitting_s,standing_s,transitions,longest_standing_s\n2024-03-04,10800,6300,3,3600\n"
+        ));
+    }
+
+    #[test]
+    fn test_streaks() {
+        let options = StatsOptions::default();
+        let date: NaiveDate = "2024-03-04".parse().unwrap();
+        let days: Vec<DayStats> = [2, 0, 1, 1, 2, 0]
+            .iter()
+            .enumerate()
+            .map(|(i, hours)| DayStats {
+                standing: Duration::from_secs(hours * 60 * 60),
+                ..DayStats::new(date + Days::new(i as u64))
+            })
+            .collect();
+
+        assert_eq!(
+            streaks(&days, &options),
+            Streaks {
+                current_days: 3,
+                longest_days: 3,
+            }
+        );
+        assert_eq!(streaks(&days[..2], &options).current_days, 1);
+        assert_eq!(streaks(&[], &options), Streaks::default());
+    }
+}
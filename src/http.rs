@@ -0,0 +1,395 @@
+//! A small HTTP/1.1 server and client, enough for a JSON API on a local network. Each connection
+//! carries one request and is closed after the response, apart from event streams which stay open
+//! until the client goes away.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::json::{ParseError, Value};
+
+const MAX_HEADER_LEN: usize = 8 * 1024;
+const MAX_BODY_LEN: usize = 64 * 1024;
+/// Responses are only read by our own client, from daemons and fleet servers.
+const MAX_RESPONSE_LEN: usize = 4 * 1024 * 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    /// The path without any query string.
+    pub path: String,
+    pub query: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Reads one request, or `None` if the connection closed before one started.
+    pub fn read_from<R: BufRead>(reader: &mut R) -> io::Result<Option<Request>> {
+        let mut line = String::new();
+        if read_line(reader, &mut line, MAX_HEADER_LEN)? == 0 {
+            return Ok(None);
+        }
+        let mut parts = line.split_whitespace();
+        let (method, target) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => {
+                (method.to_string(), target.to_string())
+            }
+            _ => return Err(invalid("malformed request line")),
+        };
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path.to_string(), Some(query.to_string())),
+            None => (target, None),
+        };
+
+        let mut headers = Vec::new();
+        let mut header_len = line.len();
+        loop {
+            line.clear();
+            header_len += read_line(reader, &mut line, MAX_HEADER_LEN - header_len)?;
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            let (name, value) = header
+                .split_once(':')
+                .ok_or_else(|| invalid("malformed header"))?;
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+
+        let mut request = Request {
+            method,
+            path,
+            query,
+            headers,
+            body: Vec::new(),
+        };
+        let len = match request.header("content-length") {
+            Some(len) => len
+                .parse::<usize>()
+                .map_err(|_| invalid("bad content-length"))?,
+            None => 0,
+        };
+        if len > MAX_BODY_LEN {
+            return Err(invalid("body too long"));
+        }
+        request.body.resize(len, 0);
+        reader.read_exact(&mut request.body)?;
+        Ok(Some(request))
+    }
+
+    /// Looks up a header by its lower-case name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Splits the path into its non-empty segments, percent-decoded, or `None` if one is
+    /// badly encoded.
+    pub fn segments(&self) -> Option<Vec<String>> {
+        self.path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(percent_decode)
+            .collect()
+    }
+
+    /// Parses the body as JSON, treating an empty body as an empty object.
+    pub fn json(&self) -> Result<Value, ParseError> {
+        let body = String::from_utf8_lossy(&self.body);
+        if body.trim().is_empty() {
+            return Ok(Value::Object(Vec::new()));
+        }
+        Value::parse(&body)
+    }
+}
+
+/// Decodes `%XX` escapes, or returns `None` for a bad escape or if the result isn't UTF-8.
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = tail.get(..2)?;
+            let hex = std::str::from_utf8(hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Reads a line of no more than `limit` bytes, so a client can't make us buffer without end.
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String, limit: usize) -> io::Result<usize> {
+    let len = reader.take(limit as u64 + 1).read_line(line)?;
+    if len > limit {
+        return Err(invalid("headers too long"));
+    }
+    Ok(len)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn json(status: u16, value: &Value) -> Response {
+        Response {
+            status,
+            content_type: "application/json",
+            body: value.to_string().into_bytes(),
+        }
+    }
+
+    pub fn text(status: u16, content_type: &'static str, body: String) -> Response {
+        Response {
+            status,
+            content_type,
+            body: body.into_bytes(),
+        }
+    }
+
+    /// A JSON `{"error": ...}` body.
+    pub fn error(status: u16, message: &str) -> Response {
+        Response::json(status, &Value::object([("error", message.into())]))
+    }
+
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(
+            out,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            reason(self.status),
+            self.content_type,
+            self.body.len()
+        )?;
+        out.write_all(&self.body)?;
+        out.flush()
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// Server-sent events: call `start` once, then `send` for each event.
+pub struct EventStream<W: Write> {
+    out: W,
+}
+
+impl<W: Write> EventStream<W> {
+    pub fn start(mut out: W) -> io::Result<EventStream<W>> {
+        write!(
+            out,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"
+        )?;
+        out.flush()?;
+        Ok(EventStream { out })
+    }
+
+    pub fn send(&mut self, event: &str, data: &str) -> io::Result<()> {
+        writeln!(self.out, "event: {}", event)?;
+        for line in data.lines() {
+            writeln!(self.out, "data: {}", line)?;
+        }
+        writeln!(self.out)?;
+        self.out.flush()
+    }
+
+    /// A comment line, which keeps proxies from timing out an idle stream and tells us whether
+    /// the client is still there.
+    pub fn keep_alive(&mut self) -> io::Result<()> {
+        self.out.write_all(b": keep-alive\n\n")?;
+        self.out.flush()
+    }
+}
+
+/// Serves connections on `listener` forever, one thread per connection. The handler is given the
+/// request and the connection to write its response to.
+pub fn serve<H>(listener: TcpListener, handler: H) -> io::Result<()>
+where
+    H: Fn(&Request, &mut TcpStream) -> io::Result<()> + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => continue,
+            Err(e) => return Err(e),
+        };
+        let handler = handler.clone();
+        thread::spawn(move || {
+            let _ = stream.set_read_timeout(Some(Duration::from_secs(10)));
+            let request = match stream.try_clone().map(BufReader::new) {
+                Ok(mut reader) => Request::read_from(&mut reader),
+                Err(e) => Err(e),
+            };
+            let result = match request {
+                Ok(Some(request)) => handler(&request, &mut stream),
+                Ok(None) => Ok(()),
+                Err(e) => Response::error(400, &e.to_string()).write_to(&mut stream),
+            };
+            // The client hanging up part way through is its own business.
+            drop(result);
+        });
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+/// Makes one request and reads the whole response, for talking to another daemon or for tests.
+pub fn request<A: ToSocketAddrs>(
+    addr: A,
+    method: &str,
+    path: &str,
+    body: Option<&str>,
+) -> io::Result<ClientResponse> {
+    let mut stream = TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let body = body.unwrap_or("");
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+
+    let mut response = String::new();
+    stream
+        .take(MAX_RESPONSE_LEN as u64 + 1)
+        .read_to_string(&mut response)?;
+    if response.len() > MAX_RESPONSE_LEN {
+        return Err(invalid("response too long"));
+    }
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| invalid("truncated response"))?;
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| invalid("malformed status line"))?;
+    Ok(ClientResponse {
+        status,
+        body: body.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_request() {
+        let raw = "POST /move?now=1 HTTP/1.1\r\nHost: x\r\nContent-Length: 18\r\n\r\n{\"height_cm\": 110}";
+        let request = Request::read_from(&mut raw.as_bytes()).unwrap().unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/move");
+        assert_eq!(request.query.as_deref(), Some("now=1"));
+        assert_eq!(request.header("host"), Some("x"));
+        assert_eq!(
+            request.json().unwrap().get("height_cm"),
+            Some(&Value::Number(110.0))
+        );
+
+        assert_eq!(Request::read_from(&mut "".as_bytes()).unwrap(), None);
+
+        let raw = "GET //profiles/%61lex%2d2/ HTTP/1.1\r\n\r\n";
+        let request = Request::read_from(&mut raw.as_bytes()).unwrap().unwrap();
+        assert_eq!(request.segments().unwrap(), ["profiles", "alex-2"]);
+        for bad in ["/a%", "/a%4", "/a%zz", "/a%ff"] {
+            let request = Request {
+                path: bad.to_string(),
+                ..request.clone()
+            };
+            assert_eq!(request.segments(), None, "{}", bad);
+        }
+        assert!(Request::read_from(&mut "nonsense\r\n\r\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_read_request_limits() {
+        let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_HEADER_LEN));
+        assert!(Request::read_from(&mut long_line.as_bytes()).is_err());
+        // Without a newline, a line is never finished.
+        let endless = "a".repeat(MAX_HEADER_LEN * 2);
+        assert!(Request::read_from(&mut endless.as_bytes()).is_err());
+
+        let long_header = format!(
+            "GET / HTTP/1.1\r\nX-Padding: {}\r\n\r\n",
+            "a".repeat(MAX_HEADER_LEN)
+        );
+        assert!(Request::read_from(&mut long_header.as_bytes()).is_err());
+
+        let long_body = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_LEN + 1
+        );
+        assert!(Request::read_from(&mut long_body.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            serve(listener, |request, stream| {
+                let body = Value::object([
+                    ("path", request.path.as_str().into()),
+                    (
+                        "body",
+                        String::from_utf8_lossy(&request.body).into_owned().into(),
+                    ),
+                ]);
+                Response::json(200, &body).write_to(stream)
+            })
+        });
+
+        let response = request(addr, "POST", "/echo", Some("hello")).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, r#"{"path":"/echo","body":"hello"}"#);
+    }
+
+    #[test]
+    fn test_event_stream() {
+        let mut out = Vec::new();
+        let mut events = EventStream::start(&mut out).unwrap();
+        events.send("height", "{\"height_cm\":100}").unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\nContent-Type: text/event-stream"));
+        assert!(out.ends_with("\r\n\r\nevent: height\ndata: {\"height_cm\":100}\n\n"));
+    }
+}
@@ -1,9 +1,56 @@
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use core::fmt;
+
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod api;
+#[cfg(feature = "calendar")]
+pub mod calendar;
+pub mod capture;
+#[cfg(feature = "std")]
+pub mod daemon;
+pub mod decoder;
+pub mod desk;
+pub mod display;
+#[cfg(feature = "std")]
+pub mod duty;
+pub mod ergonomics;
+#[cfg(feature = "std")]
+pub mod fleet;
+#[cfg(feature = "std")]
+pub mod group;
+pub mod health;
+#[cfg(feature = "history")]
+pub mod history;
+#[cfg(feature = "std")]
+pub mod http;
+#[cfg(feature = "std")]
+pub mod json;
+#[cfg(feature = "std")]
+pub mod link;
+pub mod metrics;
+#[cfg(feature = "std")]
+pub mod mqtt;
+pub mod panel;
+#[cfg(feature = "std")]
+pub mod pcapng;
+#[cfg(feature = "std")]
+pub mod profile;
+#[cfg(feature = "std")]
+pub mod replay;
+#[cfg(feature = "schedule")]
+pub mod schedule;
+pub mod sim;
+pub mod tracker;
+#[cfg(feature = "std")]
+pub mod uart;
 
 pub const DATA_FRAME_SIZE: usize = 7;
 
-const DATA_FRAME_START_BYTE: u8 = 104u8;
-const DATA_FRAME_END_BYTE: u8 = 22u8;
+pub const DATA_FRAME_START_BYTE: u8 = 104u8;
+pub const DATA_FRAME_END_BYTE: u8 = 22u8;
 
 const DESK_TO_PANEL_HEIGHT_BYTE: u8 = 0u8;
 
@@ -20,6 +67,84 @@ const PANEL_TO_DESK_RESET_THREE_BYTE: u8 = 12u8;
 
 pub type DataFrame = [u8; DATA_FRAME_SIZE];
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Direction {
+    PanelToDesk,
+    DeskToPanel,
+}
+
+/// The physical travel of a particular desk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DeskProfile {
+    pub min_height_cm: f32,
+    pub max_height_cm: f32,
+}
+
+impl DeskProfile {
+    /// A desk's travel, which has to be a finite range with the minimum below the maximum.
+    pub fn new(min_height_cm: f32, max_height_cm: f32) -> Result<DeskProfile, DeskProfileError> {
+        let valid =
+            min_height_cm.is_finite() && max_height_cm.is_finite() && min_height_cm < max_height_cm;
+        if !valid {
+            return Err(DeskProfileError {
+                min_height_cm,
+                max_height_cm,
+            });
+        }
+        Ok(DeskProfile {
+            min_height_cm,
+            max_height_cm,
+        })
+    }
+
+    /// Checks a profile built field by field, as [`DeskProfile::new`] would.
+    pub fn validated(self) -> Result<DeskProfile, DeskProfileError> {
+        DeskProfile::new(self.min_height_cm, self.max_height_cm)
+    }
+
+    /// Clamps to the desk's range. The profile has to be valid, see [`DeskProfile::new`].
+    pub fn clamp(&self, height_cm: f32) -> f32 {
+        height_cm.clamp(self.min_height_cm, self.max_height_cm)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DeskProfileError {
+    pub min_height_cm: f32,
+    pub max_height_cm: f32,
+}
+
+impl fmt::Display for DeskProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid height range {}-{}cm: the minimum has to be below the maximum",
+            self.min_height_cm, self.max_height_cm
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DeskProfileError {}
+
+impl Default for DeskProfile {
+    fn default() -> Self {
+        DeskProfile {
+            min_height_cm: 65.0,
+            max_height_cm: 129.5,
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Direction::PanelToDesk => write!(f, "panel->desk"),
+            Direction::DeskToPanel => write!(f, "desk->panel"),
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum PanelToDeskMessage {
     Up,
@@ -118,6 +243,41 @@ impl DeskToPanelMessage {
     }
 }
 
+impl fmt::Display for PanelToDeskMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            PanelToDeskMessage::Up => write!(f, "up key held"),
+            PanelToDeskMessage::Down => write!(f, "down key held"),
+            PanelToDeskMessage::NoKey => write!(f, "no key held"),
+            PanelToDeskMessage::DeskReset => write!(f, "reset desk"),
+            PanelToDeskMessage::One(h) => write!(f, "recall preset 1 ({:.1}cm)", h),
+            PanelToDeskMessage::Two(h) => write!(f, "recall preset 2 ({:.1}cm)", h),
+            PanelToDeskMessage::Three(h) => write!(f, "recall preset 3 ({:.1}cm)", h),
+            PanelToDeskMessage::ResetOne => write!(f, "reset preset 1"),
+            PanelToDeskMessage::ResetTwo => write!(f, "reset preset 2"),
+            PanelToDeskMessage::ResetThree => write!(f, "reset preset 3"),
+            PanelToDeskMessage::Unknown(a, b, c, d, e) => write_unknown(f, [a, b, c, d, e]),
+        }
+    }
+}
+
+impl fmt::Display for DeskToPanelMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            DeskToPanelMessage::Height(h) => write!(f, "height {:.1}cm", h),
+            DeskToPanelMessage::Unknown(a, b, c, d, e) => write_unknown(f, [a, b, c, d, e]),
+        }
+    }
+}
+
+fn write_unknown(f: &mut fmt::Formatter<'_>, b: [u8; 5]) -> fmt::Result {
+    write!(
+        f,
+        "unknown command {} ({:02x} {:02x} {:02x} {:02x} {:02x})",
+        b[1], b[0], b[1], b[2], b[3], b[4]
+    )
+}
+
 pub fn is_start_byte(b: u8) -> bool {
     b == DATA_FRAME_START_BYTE
 }
@@ -147,7 +307,11 @@ pub fn validate_frame(frame: &DataFrame) -> bool {
         return false;
     }
 
-    return true;
+    true
+}
+
+pub fn validate_checksum(frame: &DataFrame) -> bool {
+    checksum(&frame[1..DATA_FRAME_SIZE - 2]) == frame[DATA_FRAME_SIZE - 2]
 }
 
 fn bytes_to_height_cm(msb: u8, lsb: u8, offset_cm: f32) -> f32 {
@@ -155,10 +319,9 @@ fn bytes_to_height_cm(msb: u8, lsb: u8, offset_cm: f32) -> f32 {
 }
 
 fn height_to_bytes(height_cm: f32, offset_cm: f32) -> (u8, u8) {
-    let net_height_mm = (height_cm - offset_cm) * 10.0;
-    let msb = (net_height_mm / 256.0) as u8;
-
-    let lsb = (net_height_mm - (msb as f32 * 256.0)) as u8;
+    // Rounded, as truncating turns heights like 100.7 into 100.6 through float error.
+    let tenths = display::round_to_i32((height_cm - offset_cm) * 10.0).clamp(0, u16::MAX as i32);
+    let [msb, lsb] = (tenths as u16).to_be_bytes();
     (msb, lsb)
 }
 
@@ -171,6 +334,33 @@ fn checksum(b: &[u8]) -> u8 {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_height_round_trip() {
+        for tenths in 650..=1295 {
+            let height = tenths as f32 / 10.0;
+            let frame = DeskToPanelMessage::Height(height).as_frame();
+            assert_eq!(
+                DeskToPanelMessage::from_frame(&frame),
+                DeskToPanelMessage::Height(height)
+            );
+            let frame = PanelToDeskMessage::One(height).as_frame();
+            assert_eq!(
+                PanelToDeskMessage::from_frame(&frame),
+                PanelToDeskMessage::One(height)
+            );
+        }
+    }
+
+    #[test]
+    fn test_desk_profile_rejects_bad_ranges() {
+        assert!(DeskProfile::new(65.0, 129.5).is_ok());
+        assert!(DeskProfile::new(120.0, 80.0).is_err());
+        assert!(DeskProfile::new(80.0, 80.0).is_err());
+        assert!(DeskProfile::new(f32::NAN, 80.0).is_err());
+        assert!(DeskProfile::new(65.0, f32::INFINITY).is_err());
+        assert!(DeskProfile::default().validated().is_ok());
+    }
+
     #[test]
     fn test_panel_to_desk_message_from_frame() {
         assert_eq!(
@@ -947,4 +1137,94 @@ mod tests {
             DATA_FRAME_END_BYTE
         ]));
     }
+
+    #[test]
+    fn test_validate_checksum() {
+        assert!(validate_checksum(&PanelToDeskMessage::Up.as_frame()));
+        assert!(validate_checksum(
+            &DeskToPanelMessage::Height(129.5).as_frame()
+        ));
+        assert!(!validate_checksum(&[
+            DATA_FRAME_START_BYTE,
+            1u8,
+            PANEL_TO_DESK_UP_BYTE,
+            0u8,
+            0u8,
+            3u8,
+            DATA_FRAME_END_BYTE
+        ]));
+    }
+
+    // Every frame we can put a name to, alongside how it should read in logs. Frames the desk
+    // sends for error codes, "RST" and the lock indicator have not been identified yet, so they
+    // still decode as Unknown.
+    const DESK_TO_PANEL_CORPUS: &[(DataFrame, &str)] = &[
+        ([104, 1, 0, 0, 0, 1, 22], "height 65.0cm"),
+        ([104, 1, 0, 1, 94, 96, 22], "height 100.0cm"),
+        ([104, 1, 0, 2, 133, 136, 22], "height 129.5cm"),
+        (
+            [104, 99, 64, 254, 1, 98, 22],
+            "unknown command 64 (63 40 fe 01 62)",
+        ),
+    ];
+
+    const PANEL_TO_DESK_CORPUS: &[(DataFrame, &str)] = &[
+        ([104, 1, 1, 0, 0, 2, 22], "up key held"),
+        ([104, 1, 2, 0, 0, 3, 22], "down key held"),
+        ([104, 1, 3, 0, 0, 4, 22], "no key held"),
+        ([104, 1, 4, 0, 0, 5, 22], "reset desk"),
+        ([104, 1, 6, 232, 3, 242, 22], "recall preset 1 (100.0cm)"),
+        ([104, 1, 7, 138, 2, 148, 22], "recall preset 2 (65.0cm)"),
+        ([104, 1, 8, 15, 5, 29, 22], "recall preset 3 (129.5cm)"),
+        ([104, 1, 10, 0, 0, 11, 22], "reset preset 1"),
+        ([104, 1, 11, 0, 0, 12, 22], "reset preset 2"),
+        ([104, 1, 12, 0, 0, 13, 22], "reset preset 3"),
+        (
+            [104, 1, 9, 0, 0, 10, 22],
+            "unknown command 9 (01 09 00 00 0a)",
+        ),
+    ];
+
+    struct Buf {
+        bytes: [u8; 64],
+        len: usize,
+    }
+
+    impl fmt::Write for Buf {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            self.bytes[self.len..end].copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    fn describe<T: fmt::Display>(msg: T) -> Buf {
+        let mut buf = Buf {
+            bytes: [0u8; 64],
+            len: 0,
+        };
+        fmt::Write::write_fmt(&mut buf, format_args!("{}", msg)).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_desk_to_panel_message_corpus() {
+        for (frame, description) in DESK_TO_PANEL_CORPUS {
+            let msg = DeskToPanelMessage::from_frame(frame);
+            let buf = describe(msg);
+            assert_eq!(&buf.bytes[..buf.len], description.as_bytes());
+            assert_eq!(msg.as_frame(), *frame);
+        }
+    }
+
+    #[test]
+    fn test_panel_to_desk_message_corpus() {
+        for (frame, description) in PANEL_TO_DESK_CORPUS {
+            let msg = PanelToDeskMessage::from_frame(frame);
+            let buf = describe(msg);
+            assert_eq!(&buf.bytes[..buf.len], description.as_bytes());
+            assert_eq!(msg.as_frame(), *frame);
+        }
+    }
 }
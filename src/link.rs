@@ -0,0 +1,241 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::decoder::{Decoded, FrameDecoder};
+use crate::sim::DeskSimulator;
+use crate::DataFrame;
+
+/// One side of the bus: frames go out to whatever is on the other end and come back decoded.
+pub trait Link {
+    fn send(&mut self, frame: &DataFrame) -> io::Result<()>;
+
+    /// Returns the next thing received, or `None` if nothing arrived within the link's read
+    /// timeout.
+    fn recv(&mut self) -> io::Result<Option<Decoded>>;
+}
+
+/// A link over any byte stream, such as a serial port or a TCP socket. Reads that time out are
+/// treated as nothing having been received.
+pub struct StreamLink<S: Read + Write> {
+    stream: S,
+    decoder: FrameDecoder,
+    pending: VecDeque<Decoded>,
+}
+
+impl<S: Read + Write> StreamLink<S> {
+    pub fn new(stream: S) -> StreamLink<S> {
+        StreamLink {
+            stream,
+            decoder: FrameDecoder::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+}
+
+impl<S: Read + Write> Link for StreamLink<S> {
+    fn send(&mut self, frame: &DataFrame) -> io::Result<()> {
+        self.stream.write_all(frame)?;
+        self.stream.flush()
+    }
+
+    fn recv(&mut self) -> io::Result<Option<Decoded>> {
+        if let Some(decoded) = self.pending.pop_front() {
+            return Ok(Some(decoded));
+        }
+
+        let mut buf = [0u8; 64];
+        let n = match self.stream.read(&mut buf) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => return Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        for b in &buf[..n] {
+            if let Some(decoded) = self.decoder.push(*b) {
+                self.pending.push_back(decoded);
+            }
+        }
+        Ok(self.pending.pop_front())
+    }
+}
+
+/// Opens a serial port to one side of the bus.
+#[cfg(feature = "serial")]
+pub fn open_serial(
+    path: &str,
+    baud: u32,
+) -> io::Result<StreamLink<Box<dyn serialport::SerialPort>>> {
+    let port = serialport::new(path, baud)
+        .timeout(Duration::from_millis(10))
+        .open()?;
+    Ok(StreamLink::new(port))
+}
+
+/// Where the time comes from, so that anything driving a link can run against a simulator
+/// without waiting for real time to pass.
+pub trait Clock {
+    /// Monotonic time since some fixed point.
+    fn now(&self) -> Duration;
+
+    fn sleep(&self, duration: Duration);
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+/// A clock that only moves when something sleeps on it. Clones share the same time.
+#[derive(Clone, Debug, Default)]
+pub struct ManualClock {
+    now: Rc<Cell<Duration>>,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock::default()
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+/// A link to a simulated desk that runs on the given clock.
+pub struct SimulatedDesk<C: Clock> {
+    sim: DeskSimulator,
+    clock: C,
+}
+
+impl<C: Clock> SimulatedDesk<C> {
+    pub fn new(sim: DeskSimulator, clock: C) -> SimulatedDesk<C> {
+        SimulatedDesk { sim, clock }
+    }
+
+    pub fn simulator(&self) -> &DeskSimulator {
+        &self.sim
+    }
+}
+
+impl<C: Clock> Link for SimulatedDesk<C> {
+    fn send(&mut self, frame: &DataFrame) -> io::Result<()> {
+        self.sim.on_panel_frame(frame, self.clock.now());
+        Ok(())
+    }
+
+    fn recv(&mut self) -> io::Result<Option<Decoded>> {
+        Ok(self.sim.poll(self.clock.now()).map(Decoded::Frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DeskProfile, DeskToPanelMessage, PanelToDeskMessage};
+
+    struct Loopback {
+        read: io::Cursor<Vec<u8>>,
+        written: Vec<u8>,
+    }
+
+    impl Read for Loopback {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.read.read(buf)? {
+                0 => Err(io::ErrorKind::TimedOut.into()),
+                n => Ok(n),
+            }
+        }
+    }
+
+    impl Write for Loopback {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_stream_link() {
+        let height = DeskToPanelMessage::Height(100.0).as_frame();
+        let mut bytes = vec![0u8];
+        bytes.extend_from_slice(&height);
+        bytes.extend_from_slice(&height);
+
+        let mut link = StreamLink::new(Loopback {
+            read: io::Cursor::new(bytes),
+            written: Vec::new(),
+        });
+
+        link.send(&PanelToDeskMessage::Up.as_frame()).unwrap();
+        assert_eq!(link.get_ref().written, PanelToDeskMessage::Up.as_frame());
+
+        assert_eq!(link.recv().unwrap(), Some(Decoded::Stray(0u8)));
+        assert_eq!(link.recv().unwrap(), Some(Decoded::Frame(height)));
+        assert_eq!(link.recv().unwrap(), Some(Decoded::Frame(height)));
+        assert_eq!(link.recv().unwrap(), None);
+    }
+
+    #[test]
+    fn test_simulated_desk() {
+        let clock = ManualClock::new();
+        let mut link = SimulatedDesk::new(
+            DeskSimulator::new(DeskProfile::default(), 100.0).with_speed(10.0),
+            clock.clone(),
+        );
+
+        link.send(&PanelToDeskMessage::One(101.0).as_frame())
+            .unwrap();
+        clock.sleep(Duration::from_secs(1));
+        assert_eq!(
+            link.recv().unwrap(),
+            Some(Decoded::Frame(DeskToPanelMessage::Height(101.0).as_frame()))
+        );
+    }
+}
@@ -0,0 +1,363 @@
+use core::fmt;
+use core::time::Duration;
+
+use crate::decoder::Decoded;
+use crate::{validate_checksum, DeskToPanelMessage, Direction, PanelToDeskMessage};
+
+/// How far past the standing threshold the desk has to go before it counts as having changed
+/// posture, so that hovering around the threshold isn't counted as lots of transitions.
+const POSTURE_HYSTERESIS_CM: f32 = 1.0;
+
+/// Gaps between height reports longer than this are taken as the desk having gone quiet rather
+/// than as time spent moving.
+const MAX_REPORT_GAP: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Posture {
+    Sitting,
+    Standing,
+}
+
+impl Posture {
+    pub fn at(height_cm: f32, standing_threshold_cm: f32) -> Posture {
+        if height_cm >= standing_threshold_cm {
+            Posture::Standing
+        } else {
+            Posture::Sitting
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Posture::Sitting => "sitting",
+            Posture::Standing => "standing",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct DirectionCounters {
+    frames: u64,
+    checksum_failures: u64,
+    resyncs: u64,
+    unknown: [u64; 256],
+}
+
+/// Counters and gauges for a stream of frames, rendered in the Prometheus text format by
+/// `Display`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BusMetrics {
+    standing_threshold_cm: f32,
+    counters: [DirectionCounters; 2],
+    frames_sent: u64,
+    height: Option<(Duration, f32)>,
+    travel_cm: f64,
+    motor_on: Duration,
+    posture: Option<Posture>,
+    transitions: [u64; 2],
+}
+
+impl BusMetrics {
+    pub fn new(standing_threshold_cm: f32) -> BusMetrics {
+        BusMetrics {
+            standing_threshold_cm,
+            counters: [DirectionCounters {
+                frames: 0,
+                checksum_failures: 0,
+                resyncs: 0,
+                unknown: [0; 256],
+            }; 2],
+            frames_sent: 0,
+            height: None,
+            travel_cm: 0.0,
+            motor_on: Duration::ZERO,
+            posture: None,
+            transitions: [0; 2],
+        }
+    }
+
+    pub fn observe(&mut self, direction: Direction, data: &Decoded, now: Duration) {
+        let counters = &mut self.counters[direction as usize];
+        let frame = match data {
+            Decoded::Frame(frame) => frame,
+            Decoded::Invalid(_) | Decoded::Stray(_) => {
+                counters.resyncs += 1;
+                return;
+            }
+        };
+        counters.frames += 1;
+        if !validate_checksum(frame) {
+            counters.checksum_failures += 1;
+            return;
+        }
+
+        match direction {
+            Direction::PanelToDesk => {
+                if let PanelToDeskMessage::Unknown(..) = PanelToDeskMessage::from_frame(frame) {
+                    counters.unknown[frame[2] as usize] += 1;
+                }
+            }
+            Direction::DeskToPanel => match DeskToPanelMessage::from_frame(frame) {
+                DeskToPanelMessage::Height(h) => self.on_height(h, now),
+                DeskToPanelMessage::Unknown(..) => counters.unknown[frame[2] as usize] += 1,
+            },
+        }
+    }
+
+    /// Counts a frame the daemon itself sent towards the desk.
+    pub fn on_sent(&mut self) {
+        self.frames_sent += 1;
+    }
+
+    fn on_height(&mut self, height_cm: f32, now: Duration) {
+        if let Some((at, last)) = self.height {
+            let delta = (height_cm - last).abs();
+            if delta > 0.0 {
+                self.travel_cm += delta as f64;
+                let elapsed = now.saturating_sub(at);
+                if elapsed <= MAX_REPORT_GAP {
+                    self.motor_on += elapsed;
+                }
+            }
+        }
+        self.height = Some((now, height_cm));
+
+        let posture = match self.posture {
+            Some(Posture::Sitting)
+                if height_cm < self.standing_threshold_cm + POSTURE_HYSTERESIS_CM =>
+            {
+                Posture::Sitting
+            }
+            Some(Posture::Standing)
+                if height_cm > self.standing_threshold_cm - POSTURE_HYSTERESIS_CM =>
+            {
+                Posture::Standing
+            }
+            _ => Posture::at(height_cm, self.standing_threshold_cm),
+        };
+        if self.posture.is_some_and(|p| p != posture) {
+            self.transitions[posture as usize] += 1;
+        }
+        self.posture = Some(posture);
+    }
+
+    pub fn frames(&self, direction: Direction) -> u64 {
+        self.counters[direction as usize].frames
+    }
+
+    pub fn unknown(&self, direction: Direction, command: u8) -> u64 {
+        self.counters[direction as usize].unknown[command as usize]
+    }
+
+    pub fn travel_cm(&self) -> f64 {
+        self.travel_cm
+    }
+
+    pub fn motor_on(&self) -> Duration {
+        self.motor_on
+    }
+
+    /// How many times the desk has gone into `posture` from the other one.
+    pub fn transitions(&self, posture: Posture) -> u64 {
+        self.transitions[posture as usize]
+    }
+}
+
+type Counter = fn(&DirectionCounters) -> u64;
+
+fn label(direction: Direction) -> &'static str {
+    match direction {
+        Direction::PanelToDesk => "panel_to_desk",
+        Direction::DeskToPanel => "desk_to_panel",
+    }
+}
+
+fn header(f: &mut fmt::Formatter<'_>, name: &str, kind: &str, help: &str) -> fmt::Result {
+    writeln!(f, "# HELP {} {}", name, help)?;
+    writeln!(f, "# TYPE {} {}", name, kind)
+}
+
+impl fmt::Display for BusMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let directions = [Direction::PanelToDesk, Direction::DeskToPanel];
+        // Name, help text and value of each counter kept per direction.
+        let per_direction: [(&str, &str, Counter); 3] = [
+            ("varidesk_frames_total", "Frames received.", |c| c.frames),
+            (
+                "varidesk_checksum_failures_total",
+                "Frames received with a bad checksum.",
+                |c| c.checksum_failures,
+            ),
+            (
+                "varidesk_resyncs_total",
+                "Invalid frames and stray bytes the decoder skipped to find the next frame.",
+                |c| c.resyncs,
+            ),
+        ];
+        for (name, help, value) in per_direction {
+            header(f, name, "counter", help)?;
+            for direction in directions {
+                let counters = &self.counters[direction as usize];
+                writeln!(
+                    f,
+                    "{}{{direction=\"{}\"}} {}",
+                    name,
+                    label(direction),
+                    value(counters)
+                )?;
+            }
+        }
+
+        header(
+            f,
+            "varidesk_unknown_frames_total",
+            "counter",
+            "Frames with a command byte the decoder doesn't know.",
+        )?;
+        for direction in directions {
+            let counters = &self.counters[direction as usize];
+            for (command, count) in counters.unknown.iter().enumerate() {
+                if *count > 0 {
+                    writeln!(
+                        f,
+                        "varidesk_unknown_frames_total{{direction=\"{}\",command=\"0x{:02x}\"}} {}",
+                        label(direction),
+                        command,
+                        count
+                    )?;
+                }
+            }
+        }
+
+        header(
+            f,
+            "varidesk_frames_sent_total",
+            "counter",
+            "Frames sent to the desk by the daemon itself.",
+        )?;
+        writeln!(f, "varidesk_frames_sent_total {}", self.frames_sent)?;
+
+        if let Some((_, h)) = self.height {
+            header(f, "varidesk_height_cm", "gauge", "Last reported height.")?;
+            writeln!(f, "varidesk_height_cm {}", h)?;
+        }
+
+        header(
+            f,
+            "varidesk_travel_cm_total",
+            "counter",
+            "Distance the desk has moved, up and down.",
+        )?;
+        writeln!(f, "varidesk_travel_cm_total {:.1}", self.travel_cm)?;
+
+        header(
+            f,
+            "varidesk_motor_on_seconds_total",
+            "counter",
+            "Time the desk has spent moving.",
+        )?;
+        writeln!(
+            f,
+            "varidesk_motor_on_seconds_total {:.3}",
+            self.motor_on.as_secs_f64()
+        )?;
+
+        header(
+            f,
+            "varidesk_posture_transitions_total",
+            "counter",
+            "Changes between sitting and standing height, by the posture changed to.",
+        )?;
+        for posture in [Posture::Sitting, Posture::Standing] {
+            writeln!(
+                f,
+                "varidesk_posture_transitions_total{{to=\"{}\"}} {}",
+                posture.name(),
+                self.transitions[posture as usize]
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    struct Buf {
+        bytes: [u8; 4096],
+        len: usize,
+    }
+
+    impl Write for Buf {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            self.bytes
+                .get_mut(self.len..end)
+                .ok_or(fmt::Error)?
+                .copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    fn height(h: f32) -> Decoded {
+        Decoded::Frame(DeskToPanelMessage::Height(h).as_frame())
+    }
+
+    #[test]
+    fn test_movement() {
+        let mut metrics = BusMetrics::new(90.0);
+        let ms = Duration::from_millis;
+        let desk = Direction::DeskToPanel;
+
+        metrics.observe(desk, &height(80.0), ms(0));
+        metrics.observe(desk, &height(85.0), ms(100));
+        metrics.observe(desk, &height(90.5), ms(200));
+        assert_eq!(metrics.transitions(Posture::Standing), 0);
+        metrics.observe(desk, &height(91.0), ms(300));
+        assert_eq!(metrics.transitions(Posture::Standing), 1);
+
+        // Dipping just under the threshold isn't sitting down.
+        metrics.observe(desk, &height(89.5), ms(400));
+        metrics.observe(desk, &height(70.0), ms(5000));
+        assert_eq!(metrics.transitions(Posture::Sitting), 1);
+        assert_eq!(metrics.transitions(Posture::Standing), 1);
+
+        assert!((metrics.travel_cm() - 32.0).abs() < 0.01);
+        // The last move came after a long silence and isn't counted as motor time.
+        assert_eq!(metrics.motor_on(), ms(400));
+        assert_eq!(metrics.frames(desk), 6);
+    }
+
+    #[test]
+    fn test_render() {
+        let mut metrics = BusMetrics::new(90.0);
+        let panel = Direction::PanelToDesk;
+        let now = Duration::ZERO;
+        metrics.observe(panel, &Decoded::Frame([0x68, 1, 9, 0, 0, 10, 0x16]), now);
+        metrics.observe(panel, &Decoded::Stray(0), now);
+        metrics.observe(Direction::DeskToPanel, &height(100.5), now);
+        metrics.on_sent();
+        assert_eq!(metrics.unknown(panel, 9), 1);
+
+        let mut buf = Buf {
+            bytes: [0; 4096],
+            len: 0,
+        };
+        write!(buf, "{}", metrics).unwrap();
+        let text = core::str::from_utf8(&buf.bytes[..buf.len]).unwrap();
+        for line in [
+            "# TYPE varidesk_frames_total counter",
+            "varidesk_frames_total{direction=\"panel_to_desk\"} 1",
+            "varidesk_resyncs_total{direction=\"panel_to_desk\"} 1",
+            "varidesk_unknown_frames_total{direction=\"panel_to_desk\",command=\"0x09\"} 1",
+            "varidesk_frames_sent_total 1",
+            "varidesk_height_cm 100.5",
+            "varidesk_posture_transitions_total{to=\"standing\"} 0",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {}", line);
+        }
+    }
+}
@@ -0,0 +1,1017 @@
+//! Publishing a daemon's state to MQTT, with Home Assistant discovery.
+//!
+//! The bridge speaks just enough MQTT 3.1.1 (QoS 0, clean sessions) to publish retained state and
+//! take commands, and keeps retrying the broker in its own thread so that a broker going away
+//! never holds up the desk link. Under `<base>` (`varidesk/<device id>` by default):
+//!
+//! | topic                                 | direction | payload                              |
+//! |---------------------------------------|-----------|--------------------------------------|
+//! | `<base>/availability`                 | out       | `online` or `offline`                |
+//! | `<base>/height`                       | out       | height in cm                         |
+//! | `<base>/movement`                     | out       | `stopped`, `rising` or `falling`     |
+//! | `<base>/position`                     | out       | 0 (lowest) to 100 (highest)          |
+//! | `<base>/cover`                        | out       | `open`, `opening`, `closed`, ...     |
+//! | `<base>/preset/<n>`                   | out       | preset height in cm                  |
+//! | `<base>/event`                        | out       | each daemon event as JSON            |
+//! | `<base>/target/set`                   | in        | height to move to in cm              |
+//! | `<base>/position/set`                 | in        | 0 to 100                             |
+//! | `<base>/cover/set`                    | in        | `OPEN`, `CLOSE` or `STOP`            |
+//! | `<base>/stop`                         | in        | anything                             |
+//! | `<base>/preset/<n>/set`               | in        | height to program in cm              |
+//! | `<base>/preset/<n>/recall`            | in        | anything                             |
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::daemon::{movement_name, Event, Handle, State};
+use crate::json::Value;
+use crate::tracker::Movement;
+use crate::DeskProfile;
+
+const PROTOCOL_LEVEL: u8 = 4;
+
+/// The largest packet we accept. Ours are a few hundred bytes; anything much bigger would only
+/// be buffered while waiting for the rest of it.
+const MAX_PACKET_LEN: usize = 64 * 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Will {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub retain: bool,
+}
+
+/// The MQTT 3.1.1 control packets the bridge needs, in both directions so that a test broker can
+/// use them too.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Packet {
+    Connect {
+        client_id: String,
+        keep_alive: u16,
+        username: Option<String>,
+        password: Option<String>,
+        will: Option<Will>,
+    },
+    ConnAck {
+        session_present: bool,
+        code: u8,
+    },
+    Publish {
+        topic: String,
+        payload: Vec<u8>,
+        retain: bool,
+        /// Only set for QoS 1, which is all we ever acknowledge.
+        packet_id: Option<u16>,
+    },
+    PubAck(u16),
+    Subscribe {
+        packet_id: u16,
+        topics: Vec<String>,
+    },
+    SubAck(u16),
+    PingReq,
+    PingResp,
+    Disconnect,
+}
+
+impl Packet {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        let header = match self {
+            Packet::Connect {
+                client_id,
+                keep_alive,
+                username,
+                password,
+                will,
+            } => {
+                put_str(&mut body, "MQTT");
+                body.push(PROTOCOL_LEVEL);
+                let mut flags = 0x02;
+                if let Some(will) = will {
+                    flags |= 0x04;
+                    if will.retain {
+                        flags |= 0x20;
+                    }
+                }
+                if password.is_some() {
+                    flags |= 0x40;
+                }
+                if username.is_some() {
+                    flags |= 0x80;
+                }
+                body.push(flags);
+                body.extend_from_slice(&keep_alive.to_be_bytes());
+                put_str(&mut body, client_id);
+                if let Some(will) = will {
+                    put_str(&mut body, &will.topic);
+                    put_bytes(&mut body, &will.payload);
+                }
+                if let Some(username) = username {
+                    put_str(&mut body, username);
+                }
+                if let Some(password) = password {
+                    put_str(&mut body, password);
+                }
+                0x10
+            }
+            Packet::ConnAck {
+                session_present,
+                code,
+            } => {
+                body.extend_from_slice(&[*session_present as u8, *code]);
+                0x20
+            }
+            Packet::Publish {
+                topic,
+                payload,
+                retain,
+                packet_id,
+            } => {
+                put_str(&mut body, topic);
+                if let Some(id) = packet_id {
+                    body.extend_from_slice(&id.to_be_bytes());
+                }
+                body.extend_from_slice(payload);
+                0x30 | (*retain as u8) | if packet_id.is_some() { 0x02 } else { 0 }
+            }
+            Packet::PubAck(id) => {
+                body.extend_from_slice(&id.to_be_bytes());
+                0x40
+            }
+            Packet::Subscribe { packet_id, topics } => {
+                body.extend_from_slice(&packet_id.to_be_bytes());
+                for topic in topics {
+                    put_str(&mut body, topic);
+                    body.push(0);
+                }
+                0x82
+            }
+            Packet::SubAck(id) => {
+                body.extend_from_slice(&id.to_be_bytes());
+                body.push(0);
+                0x90
+            }
+            Packet::PingReq => 0xc0,
+            Packet::PingResp => 0xd0,
+            Packet::Disconnect => 0xe0,
+        };
+
+        let mut out = vec![header];
+        let mut len = body.len();
+        loop {
+            let mut b = (len % 128) as u8;
+            len /= 128;
+            if len > 0 {
+                b |= 0x80;
+            }
+            out.push(b);
+            if len == 0 {
+                break;
+            }
+        }
+        out.extend_from_slice(&body);
+        out
+    }
+
+    /// Decodes the packet at the start of `buf`, returning it and its length, or `None` if `buf`
+    /// doesn't hold a whole packet yet.
+    pub fn decode(buf: &[u8]) -> io::Result<Option<(Packet, usize)>> {
+        let Some(&header) = buf.first() else {
+            return Ok(None);
+        };
+        let mut len = 0usize;
+        let mut at = 1;
+        loop {
+            let Some(&b) = buf.get(at) else {
+                return Ok(None);
+            };
+            len |= ((b & 0x7f) as usize) << (7 * (at - 1));
+            at += 1;
+            if b & 0x80 == 0 {
+                break;
+            }
+            if at > 4 {
+                return Err(malformed());
+            }
+        }
+        if len > MAX_PACKET_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "MQTT packet too large",
+            ));
+        }
+        let Some(body) = buf.get(at..at + len) else {
+            return Ok(None);
+        };
+        let mut body = Reader { buf: body, at: 0 };
+
+        let packet = match header >> 4 {
+            1 => {
+                if body.string()? != "MQTT" || body.u8()? != PROTOCOL_LEVEL {
+                    return Err(malformed());
+                }
+                let flags = body.u8()?;
+                let keep_alive = body.u16()?;
+                let client_id = body.string()?;
+                let will = match flags & 0x04 {
+                    0 => None,
+                    _ => Some(Will {
+                        topic: body.string()?,
+                        payload: body.bytes()?.to_vec(),
+                        retain: flags & 0x20 != 0,
+                    }),
+                };
+                let username = match flags & 0x80 {
+                    0 => None,
+                    _ => Some(body.string()?),
+                };
+                let password = match flags & 0x40 {
+                    0 => None,
+                    _ => Some(body.string()?),
+                };
+                Packet::Connect {
+                    client_id,
+                    keep_alive,
+                    username,
+                    password,
+                    will,
+                }
+            }
+            2 => Packet::ConnAck {
+                session_present: body.u8()? & 1 != 0,
+                code: body.u8()?,
+            },
+            3 => {
+                let topic = body.string()?;
+                let packet_id = match (header >> 1) & 0x03 {
+                    0 => None,
+                    _ => Some(body.u16()?),
+                };
+                Packet::Publish {
+                    topic,
+                    payload: body.rest().to_vec(),
+                    retain: header & 0x01 != 0,
+                    packet_id,
+                }
+            }
+            4 => Packet::PubAck(body.u16()?),
+            8 => {
+                let packet_id = body.u16()?;
+                let mut topics = Vec::new();
+                while !body.is_empty() {
+                    topics.push(body.string()?);
+                    body.u8()?;
+                }
+                Packet::Subscribe { packet_id, topics }
+            }
+            9 => Packet::SubAck(body.u16()?),
+            12 => Packet::PingReq,
+            13 => Packet::PingResp,
+            14 => Packet::Disconnect,
+            _ => return Err(malformed()),
+        };
+        Ok(Some((packet, at + len)))
+    }
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    put_bytes(out, s.as_bytes());
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed MQTT packet")
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        let bytes = self.buf.get(self.at..self.at + n).ok_or_else(malformed)?;
+        self.at += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| malformed())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.at >= self.buf.len()
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.buf[self.at..];
+        self.at = self.buf.len();
+        rest
+    }
+}
+
+/// A connection to a broker.
+pub struct Client {
+    stream: TcpStream,
+    buf: Vec<u8>,
+    next_packet_id: u16,
+}
+
+impl Client {
+    /// Connects and waits for the broker to accept us.
+    pub fn connect(addr: &str, connect: &Packet, timeout: Duration) -> io::Result<Client> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        let mut client = Client {
+            stream,
+            buf: Vec::new(),
+            next_packet_id: 1,
+        };
+        client.send(connect)?;
+        match client.recv(timeout)? {
+            Some(Packet::ConnAck { code: 0, .. }) => Ok(client),
+            Some(Packet::ConnAck { code, .. }) => Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("broker refused the connection with code {}", code),
+            )),
+            _ => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "broker did not acknowledge the connection",
+            )),
+        }
+    }
+
+    pub fn send(&mut self, packet: &Packet) -> io::Result<()> {
+        self.stream.write_all(&packet.encode())
+    }
+
+    pub fn publish(&mut self, topic: &str, payload: &str, retain: bool) -> io::Result<()> {
+        self.send(&Packet::Publish {
+            topic: topic.to_string(),
+            payload: payload.as_bytes().to_vec(),
+            retain,
+            packet_id: None,
+        })
+    }
+
+    pub fn subscribe(&mut self, topics: Vec<String>) -> io::Result<()> {
+        let packet_id = self.next_packet_id;
+        self.next_packet_id = self.next_packet_id.wrapping_add(1).max(1);
+        self.send(&Packet::Subscribe { packet_id, topics })
+    }
+
+    /// Waits up to `timeout` for the next packet, acknowledging QoS 1 publishes as they arrive.
+    pub fn recv(&mut self, timeout: Duration) -> io::Result<Option<Packet>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some((packet, len)) = Packet::decode(&self.buf)? {
+                self.buf.drain(..len);
+                if let Packet::Publish {
+                    packet_id: Some(id),
+                    ..
+                } = packet
+                {
+                    self.send(&Packet::PubAck(id))?;
+                }
+                return Ok(Some(packet));
+            }
+
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(None);
+            }
+            self.stream.set_read_timeout(Some(left))?;
+            let mut chunk = [0u8; 1024];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MqttOptions {
+    /// The broker as `host:port`.
+    pub broker: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Used for the client id, the topics and the Home Assistant unique ids.
+    pub device_id: String,
+    pub device_name: String,
+    pub base_topic: String,
+    /// Where Home Assistant looks for discovery payloads, or `None` to not publish any.
+    pub discovery_prefix: Option<String>,
+    pub keep_alive: Duration,
+    /// The first wait before reconnecting, doubled on each failure up to a minute.
+    pub retry_interval: Duration,
+}
+
+impl MqttOptions {
+    pub fn new(broker: &str, device_id: &str) -> MqttOptions {
+        MqttOptions {
+            broker: broker.to_string(),
+            username: None,
+            password: None,
+            device_id: device_id.to_string(),
+            device_name: "Varidesk".to_string(),
+            base_topic: format!("varidesk/{}", device_id),
+            discovery_prefix: Some("homeassistant".to_string()),
+            keep_alive: Duration::from_secs(30),
+            retry_interval: Duration::from_secs(1),
+        }
+    }
+
+    fn topic(&self, suffix: &str) -> String {
+        format!("{}/{}", self.base_topic, suffix)
+    }
+}
+
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Bridges a daemon to a broker forever, reconnecting whenever the connection drops.
+pub fn run_bridge(handle: Handle, options: MqttOptions) {
+    let events = handle.subscribe();
+    let mut retry = options.retry_interval;
+    loop {
+        match bridge(&handle, &options, &events, &mut retry) {
+            // The daemon has gone away.
+            Ok(()) => return,
+            Err(e) => handle.notify("mqtt", format!("{}: {}", options.broker, e)),
+        }
+        thread::sleep(retry);
+        retry = (retry * 2).min(MAX_RETRY_INTERVAL);
+        // Whatever happened while we were away is covered by the full state sent on reconnect.
+        while events.try_recv().is_ok() {}
+    }
+}
+
+/// One connection's worth of bridging. Returns when the connection is lost.
+fn bridge(
+    handle: &Handle,
+    options: &MqttOptions,
+    events: &Receiver<Event>,
+    retry: &mut Duration,
+) -> io::Result<()> {
+    let availability = options.topic("availability");
+    let connect = Packet::Connect {
+        client_id: format!("varidesk-{}", options.device_id),
+        keep_alive: options.keep_alive.as_secs().min(u16::MAX as u64) as u16,
+        username: options.username.clone(),
+        password: options.password.clone(),
+        will: Some(Will {
+            topic: availability.clone(),
+            payload: b"offline".to_vec(),
+            retain: true,
+        }),
+    };
+    let mut client = Client::connect(&options.broker, &connect, Duration::from_secs(10))?;
+    *retry = options.retry_interval;
+
+    client.subscribe(
+        COMMAND_TOPICS
+            .iter()
+            .map(|suffix| options.topic(suffix))
+            .collect(),
+    )?;
+    if let Some(prefix) = &options.discovery_prefix {
+        for (topic, config) in discovery(options, prefix, &handle.state().profile) {
+            client.publish(&topic, &config.to_string(), true)?;
+        }
+    }
+    client.publish(&availability, "online", true)?;
+    let state = handle.state();
+    publish_state(&mut client, options, &state)?;
+
+    let mut last_sent = Instant::now();
+    let mut last_heard = Instant::now();
+    loop {
+        loop {
+            match events.try_recv() {
+                Ok(event) => {
+                    publish_event(&mut client, options, &handle.state(), &event)?;
+                    last_sent = Instant::now();
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+
+        if let Some(packet) = client.recv(POLL_INTERVAL)? {
+            last_heard = Instant::now();
+            if let Packet::Publish { topic, payload, .. } = packet {
+                if let Some(suffix) = topic.strip_prefix(&format!("{}/", options.base_topic)) {
+                    command(handle, suffix, &String::from_utf8_lossy(&payload));
+                }
+            }
+        }
+
+        if last_sent.elapsed() >= options.keep_alive / 2 {
+            client.send(&Packet::PingReq)?;
+            last_sent = Instant::now();
+        }
+        if last_heard.elapsed() >= options.keep_alive * 3 / 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "broker stopped answering",
+            ));
+        }
+    }
+}
+
+const COMMAND_TOPICS: &[&str] = &[
+    "target/set",
+    "position/set",
+    "cover/set",
+    "stop",
+    "preset/+/set",
+    "preset/+/recall",
+];
+
+/// Acts on a message to one of the command topics, given relative to the base topic. Anything
+/// that doesn't make sense is ignored, as there is nobody to report it to.
+pub fn command(handle: &Handle, topic: &str, payload: &str) {
+    let payload = payload.trim();
+    let profile = handle.state().profile;
+    let parts: Vec<&str> = topic.split('/').collect();
+
+    match parts.as_slice() {
+        ["target", "set"] => {
+            if let Some(height) = number(payload) {
+                let _ = handle.move_to(height);
+            }
+        }
+        ["position", "set"] => {
+            if let Some(position) = number(payload) {
+                let _ = handle.move_to(position_to_height(&profile, position));
+            }
+        }
+        ["cover", "set"] => match payload {
+            "OPEN" => drop(handle.move_to(profile.max_height_cm)),
+            "CLOSE" => drop(handle.move_to(profile.min_height_cm)),
+            "STOP" => handle.stop(),
+            _ => {}
+        },
+        ["stop"] => handle.stop(),
+        ["preset", n, "set"] => {
+            if let (Ok(n), Some(height)) = (n.parse(), number(payload)) {
+                let _ = handle.program_preset(n, height);
+            }
+        }
+        ["preset", n, "recall"] => {
+            if let Ok(n) = n.parse() {
+                let _ = handle.recall_preset(n);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Parses a number, leaving out the `nan` and `inf` that `f32` also parses.
+fn number(payload: &str) -> Option<f32> {
+    payload.parse::<f32>().ok().filter(|n| n.is_finite())
+}
+
+/// Where a height sits between the lowest and highest the desk goes, from 0 to 100.
+pub fn height_to_position(profile: &DeskProfile, height_cm: f32) -> f32 {
+    let range = profile.max_height_cm - profile.min_height_cm;
+    ((height_cm - profile.min_height_cm) / range * 100.0).clamp(0.0, 100.0)
+}
+
+pub fn position_to_height(profile: &DeskProfile, position: f32) -> f32 {
+    let range = profile.max_height_cm - profile.min_height_cm;
+    profile.min_height_cm + position.clamp(0.0, 100.0) / 100.0 * range
+}
+
+fn cover_state(state: &State) -> &'static str {
+    match state.movement {
+        Movement::Rising => "opening",
+        Movement::Falling => "closing",
+        Movement::Stopped => match state.height_cm {
+            Some(h) if h <= state.profile.min_height_cm + 0.5 => "closed",
+            _ => "open",
+        },
+    }
+}
+
+fn publish_state(client: &mut Client, options: &MqttOptions, state: &State) -> io::Result<()> {
+    if let Some(h) = state.height_cm {
+        client.publish(&options.topic("height"), &format!("{:.1}", h), true)?;
+        let position = height_to_position(&state.profile, h);
+        client.publish(
+            &options.topic("position"),
+            &format!("{:.0}", position),
+            true,
+        )?;
+    }
+    client.publish(
+        &options.topic("movement"),
+        movement_name(state.movement),
+        true,
+    )?;
+    client.publish(&options.topic("cover"), cover_state(state), true)?;
+    for (i, preset) in state.presets.iter().enumerate() {
+        if let Some(h) = preset {
+            let topic = options.topic(&format!("preset/{}", i + 1));
+            client.publish(&topic, &format!("{:.1}", h), true)?;
+        }
+    }
+    Ok(())
+}
+
+fn publish_event(
+    client: &mut Client,
+    options: &MqttOptions,
+    state: &State,
+    event: &Event,
+) -> io::Result<()> {
+    let mut json = event.to_json();
+    if let Value::Object(fields) = &mut json {
+        fields.insert(0, ("event".to_string(), event.name().into()));
+    }
+    client.publish(&options.topic("event"), &json.to_string(), false)?;
+
+    match event {
+        Event::Height(_) | Event::Movement(_) | Event::PresetProgrammed { .. } => {
+            publish_state(client, options, state)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The retained discovery payloads that make the desk show up in Home Assistant as a cover for
+/// up and down, a number for the target height, and a number and a button per preset.
+pub fn discovery(
+    options: &MqttOptions,
+    prefix: &str,
+    profile: &DeskProfile,
+) -> Vec<(String, Value)> {
+    let id = &options.device_id;
+    let device = || {
+        Value::object([
+            ("identifiers", vec![format!("varidesk_{}", id)].into()),
+            ("name", options.device_name.as_str().into()),
+            ("manufacturer", "Varidesk".into()),
+            ("model", "Varidesk 2020".into()),
+        ])
+    };
+    let entity = |component: &str, object: &str, name: &str, fields: Vec<(&'static str, Value)>| {
+        let mut config = vec![
+            ("name", name.into()),
+            ("unique_id", format!("varidesk_{}_{}", id, object).into()),
+            ("availability_topic", options.topic("availability").into()),
+            ("device", device()),
+        ];
+        config.extend(fields);
+        (
+            format!("{}/{}/varidesk_{}/{}/config", prefix, component, id, object),
+            Value::object(config),
+        )
+    };
+    let height_number = |fields: Vec<(&'static str, Value)>| {
+        let mut all = vec![
+            ("min", profile.min_height_cm.into()),
+            ("max", profile.max_height_cm.into()),
+            ("step", 0.1f32.into()),
+            ("unit_of_measurement", "cm".into()),
+            ("mode", "box".into()),
+        ];
+        all.extend(fields);
+        all
+    };
+
+    let mut configs = vec![
+        entity(
+            "cover",
+            "desk",
+            "Desk",
+            vec![
+                ("command_topic", options.topic("cover/set").into()),
+                ("state_topic", options.topic("cover").into()),
+                ("position_topic", options.topic("position").into()),
+                ("set_position_topic", options.topic("position/set").into()),
+            ],
+        ),
+        entity(
+            "sensor",
+            "height",
+            "Height",
+            vec![
+                ("state_topic", options.topic("height").into()),
+                ("unit_of_measurement", "cm".into()),
+                ("device_class", "distance".into()),
+                ("state_class", "measurement".into()),
+            ],
+        ),
+        entity(
+            "number",
+            "target",
+            "Target height",
+            height_number(vec![
+                ("command_topic", options.topic("target/set").into()),
+                ("state_topic", options.topic("height").into()),
+            ]),
+        ),
+        entity(
+            "sensor",
+            "movement",
+            "Movement",
+            vec![("state_topic", options.topic("movement").into())],
+        ),
+        entity(
+            "button",
+            "stop",
+            "Stop",
+            vec![("command_topic", options.topic("stop").into())],
+        ),
+    ];
+    for n in 1..=3 {
+        configs.push(entity(
+            "number",
+            &format!("preset_{}", n),
+            &format!("Preset {}", n),
+            height_number(vec![
+                (
+                    "command_topic",
+                    options.topic(&format!("preset/{}/set", n)).into(),
+                ),
+                (
+                    "state_topic",
+                    options.topic(&format!("preset/{}", n)).into(),
+                ),
+            ]),
+        ));
+        configs.push(entity(
+            "button",
+            &format!("recall_preset_{}", n),
+            &format!("Recall preset {}", n),
+            vec![(
+                "command_topic",
+                options.topic(&format!("preset/{}/recall", n)).into(),
+            )],
+        ));
+    }
+    configs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::{Daemon, DaemonOptions};
+    use crate::link::{ManualClock, SimulatedDesk};
+    use crate::sim::DeskSimulator;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_packets() {
+        let packets = [
+            Packet::Connect {
+                client_id: "varidesk-a".into(),
+                keep_alive: 30,
+                username: Some("user".into()),
+                password: Some("secret".into()),
+                will: Some(Will {
+                    topic: "varidesk/a/availability".into(),
+                    payload: b"offline".to_vec(),
+                    retain: true,
+                }),
+            },
+            Packet::ConnAck {
+                session_present: false,
+                code: 0,
+            },
+            Packet::Publish {
+                topic: "t".into(),
+                payload: vec![b'x'; 200],
+                retain: true,
+                packet_id: None,
+            },
+            Packet::Publish {
+                topic: "t".into(),
+                payload: b"1".to_vec(),
+                retain: false,
+                packet_id: Some(7),
+            },
+            Packet::PubAck(7),
+            Packet::Subscribe {
+                packet_id: 1,
+                topics: vec!["a/+".into(), "b".into()],
+            },
+            Packet::SubAck(1),
+            Packet::PingReq,
+            Packet::PingResp,
+            Packet::Disconnect,
+        ];
+        // The long publish needs two bytes of remaining length.
+        for packet in packets {
+            let bytes = packet.encode();
+            assert_eq!(
+                Packet::decode(&bytes).unwrap(),
+                Some((packet.clone(), bytes.len()))
+            );
+            assert_eq!(Packet::decode(&bytes[..bytes.len() - 1]).unwrap(), None);
+        }
+
+        assert_eq!(Packet::PingReq.encode(), [0xc0, 0x00]);
+        assert!(Packet::decode(&[0x30, 0x80, 0x80, 0x80, 0x80, 0x01]).is_err());
+        // Too large is refused from the length alone, without waiting for the body.
+        assert!(Packet::decode(&[0x30, 0xff, 0xff, 0x7f]).is_err());
+    }
+
+    #[test]
+    fn test_positions() {
+        let profile = DeskProfile {
+            min_height_cm: 60.0,
+            max_height_cm: 120.0,
+        };
+        assert_eq!(height_to_position(&profile, 90.0), 50.0);
+        assert_eq!(height_to_position(&profile, 200.0), 100.0);
+        assert_eq!(position_to_height(&profile, 25.0), 75.0);
+    }
+
+    #[test]
+    fn test_command_rejects_non_finite_heights() {
+        let clock = ManualClock::new();
+        let desk = SimulatedDesk::new(
+            DeskSimulator::new(DeskProfile::default(), 90.0),
+            clock.clone(),
+        );
+        let mut daemon = Daemon::new(Box::new(desk), None, clock, DaemonOptions::default());
+        let handle = daemon.handle();
+        for payload in ["nan", "inf", "-inf"] {
+            command(&handle, "target/set", payload);
+            command(&handle, "position/set", payload);
+            command(&handle, "preset/1/set", payload);
+        }
+        daemon.step().unwrap();
+        let state = handle.state();
+        assert_eq!(state.target_cm, None);
+        assert_eq!(state.presets, [None; 3]);
+    }
+
+    #[test]
+    fn test_discovery() {
+        let options = MqttOptions::new("localhost:1883", "office");
+        let configs = discovery(&options, "homeassistant", &DeskProfile::default());
+        assert_eq!(configs.len(), 11);
+
+        let (topic, cover) = &configs[0];
+        assert_eq!(topic, "homeassistant/cover/varidesk_office/desk/config");
+        assert_eq!(
+            cover.get("set_position_topic").and_then(Value::as_str),
+            Some("varidesk/office/position/set")
+        );
+        assert_eq!(
+            cover
+                .get("device")
+                .and_then(|d| d.get("identifiers"))
+                .map(Value::to_string),
+            Some(r#"["varidesk_office"]"#.to_string())
+        );
+    }
+
+    /// Accepts connections as a broker would, hands every packet it receives to the test, and
+    /// publishes whatever the test sends it to the connected client. Dropping the sender for a
+    /// connection closes it.
+    fn broker(
+        listener: TcpListener,
+        received: mpsc::Sender<Packet>,
+        to_client: mpsc::Receiver<Option<Packet>>,
+    ) {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_millis(20)))
+                .unwrap();
+            let mut buf = Vec::new();
+            loop {
+                let mut chunk = [0u8; 1024];
+                match stream.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    Err(_) => {}
+                }
+                while let Some((packet, len)) = Packet::decode(&buf).unwrap() {
+                    buf.drain(..len);
+                    if let Packet::Connect { .. } = packet {
+                        let ack = Packet::ConnAck {
+                            session_present: false,
+                            code: 0,
+                        };
+                        stream.write_all(&ack.encode()).unwrap();
+                    }
+                    received.send(packet).unwrap();
+                }
+                match to_client.try_recv() {
+                    Ok(Some(packet)) => stream.write_all(&packet.encode()).unwrap(),
+                    Ok(None) => break,
+                    Err(_) => {}
+                }
+            }
+        }
+    }
+
+    fn wait_for<F: Fn(&Packet) -> bool>(received: &mpsc::Receiver<Packet>, f: F) -> Packet {
+        loop {
+            let packet = received
+                .recv_timeout(Duration::from_secs(5))
+                .expect("broker saw nothing");
+            if f(&packet) {
+                return packet;
+            }
+        }
+    }
+
+    #[test]
+    fn test_bridge() {
+        let clock = ManualClock::new();
+        let desk = SimulatedDesk::new(
+            DeskSimulator::new(DeskProfile::default(), 90.0),
+            clock.clone(),
+        );
+        let mut daemon = Daemon::new(
+            Box::new(desk),
+            None,
+            clock.clone(),
+            DaemonOptions::default(),
+        );
+        daemon.step().unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut options = MqttOptions::new(&listener.local_addr().unwrap().to_string(), "test");
+        options.retry_interval = Duration::from_millis(10);
+        let (received_tx, received) = mpsc::channel();
+        let (to_client, to_client_rx) = mpsc::channel();
+        thread::spawn(move || broker(listener, received_tx, to_client_rx));
+        let handle = daemon.handle();
+        thread::spawn(move || run_bridge(handle, options));
+
+        let is_publish_to = |want: &'static str| move |p: &Packet| matches!(p, Packet::Publish { topic, .. } if topic == want);
+        let connect = wait_for(&received, |p| matches!(p, Packet::Connect { .. }));
+        assert!(matches!(connect, Packet::Connect { will: Some(_), .. }));
+        wait_for(
+            &received,
+            is_publish_to("homeassistant/cover/varidesk_test/desk/config"),
+        );
+        let height = wait_for(&received, is_publish_to("varidesk/test/height"));
+        assert!(
+            matches!(height, Packet::Publish { payload, retain: true, .. } if payload == b"90.0")
+        );
+
+        // A command from the broker reaches the daemon.
+        to_client
+            .send(Some(Packet::Publish {
+                topic: "varidesk/test/target/set".into(),
+                payload: b"100".to_vec(),
+                retain: false,
+                packet_id: None,
+            }))
+            .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while daemon.handle().state().target_cm.is_none() {
+            assert!(Instant::now() < deadline, "command never arrived");
+            thread::sleep(Duration::from_millis(10));
+            daemon.step().unwrap();
+        }
+        assert_eq!(daemon.handle().state().target_cm, Some(100.0));
+
+        // The broker going away only costs a reconnect, and the desk keeps moving meanwhile.
+        to_client.send(None).unwrap();
+        wait_for(&received, |p| matches!(p, Packet::Connect { .. }));
+        wait_for(&received, is_publish_to("varidesk/test/availability"));
+        clock.advance(Duration::from_secs(1));
+        daemon.step().unwrap();
+        assert!(daemon.handle().state().height_cm.unwrap() > 90.0);
+    }
+}
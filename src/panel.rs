@@ -0,0 +1,328 @@
+//! An emulation of the panel, for driving a desk with the panel unplugged.
+//!
+//! The panel sends a frame every 50ms whether or not anything is happening: `NoKey` while no key
+//! is down, and the held key's frame for as long as a key is held, preset recalls included. The
+//! desk stops a manual move soon after the key frames stop, and stops listening altogether if the
+//! keep-alives do. [`PanelEmulator`] keeps to that pattern, and is driven with the same calls a
+//! person pressing keys would make, or told to move to a height and left to it.
+//!
+//! It owns no link: call [`PanelEmulator::poll`] often and send whatever frame it returns, and
+//! pass it what the desk sends back.
+//!
+//! For control over a serial console or similar, [`PanelCommand`] parses one-line commands:
+//!
+//! | command          |                                            |
+//! |------------------|--------------------------------------------|
+//! | `up`, `down`     | hold the key until `stop` or another command |
+//! | `stop`           | let go of any key                          |
+//! | `move <cm>`      | hold Up or Down until the desk is at a height |
+//! | `preset <n> <cm>`| recall a preset, carrying its height       |
+
+use core::time::Duration;
+
+use crate::{DataFrame, DeskToPanelMessage, PanelToDeskMessage};
+
+const SEND_INTERVAL: Duration = Duration::from_millis(50);
+/// How long a single press keeps the key's frame going out, as a quick tap on the panel does.
+const PRESS_LENGTH: Duration = Duration::from_millis(300);
+/// How close to the target a [`PanelEmulator::move_to`] has to get to count as done.
+const TOLERANCE_CM: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PanelCommand {
+    Hold(PanelToDeskMessage),
+    Stop,
+    MoveTo(f32),
+    Recall(usize, f32),
+}
+
+impl PanelCommand {
+    pub fn parse(line: &str) -> Result<PanelCommand, &'static str> {
+        let mut words = line.split_whitespace();
+        let height = |word: Option<&str>| {
+            word.map(|w| w.trim_end_matches("cm"))
+                .and_then(|w| w.parse::<f32>().ok())
+                .filter(|h| h.is_finite())
+                .ok_or("expected a height in cm")
+        };
+        let command = match words.next() {
+            Some("up") => PanelCommand::Hold(PanelToDeskMessage::Up),
+            Some("down") => PanelCommand::Hold(PanelToDeskMessage::Down),
+            Some("stop") => PanelCommand::Stop,
+            Some("move") => PanelCommand::MoveTo(height(words.next())?),
+            Some("preset") => {
+                let preset = words
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|n| (1..=3).contains(n))
+                    .ok_or("expected a preset from 1 to 3")?;
+                PanelCommand::Recall(preset, height(words.next())?)
+            }
+            _ => return Err("expected up, down, stop, move <cm> or preset <n> <cm>"),
+        };
+        match words.next() {
+            Some(_) => Err("too many words"),
+            None => Ok(command),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Key {
+    None,
+    /// Held until a given time, or until released.
+    Held(PanelToDeskMessage, Option<Duration>),
+    /// Up or Down held until the desk reaches a height, and which of them it was last.
+    Target(f32, Option<PanelToDeskMessage>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PanelEmulator {
+    send_interval: Duration,
+    key: Key,
+    height_cm: Option<f32>,
+    last_send: Option<Duration>,
+}
+
+impl PanelEmulator {
+    pub fn new() -> PanelEmulator {
+        PanelEmulator {
+            send_interval: SEND_INTERVAL,
+            key: Key::None,
+            height_cm: None,
+            last_send: None,
+        }
+    }
+
+    pub fn with_send_interval(mut self, send_interval: Duration) -> PanelEmulator {
+        self.send_interval = send_interval;
+        self
+    }
+
+    /// Holds `message` down until [`release`](Self::release) or another key.
+    pub fn hold(&mut self, message: PanelToDeskMessage) {
+        self.key = Key::Held(message, None);
+    }
+
+    /// Presses `message` briefly, as a tap on the panel would.
+    pub fn press(&mut self, message: PanelToDeskMessage, now: Duration) {
+        self.key = Key::Held(message, Some(now + PRESS_LENGTH));
+    }
+
+    /// Recalls a preset, numbered from 1, carrying the height the desk is to go to.
+    pub fn recall(&mut self, preset: usize, height_cm: f32, now: Duration) -> Result<(), usize> {
+        let message = match preset {
+            1 => PanelToDeskMessage::One(height_cm),
+            2 => PanelToDeskMessage::Two(height_cm),
+            3 => PanelToDeskMessage::Three(height_cm),
+            _ => return Err(preset),
+        };
+        self.press(message, now);
+        Ok(())
+    }
+
+    pub fn apply(&mut self, command: PanelCommand, now: Duration) {
+        match command {
+            PanelCommand::Hold(message) => self.hold(message),
+            PanelCommand::Stop => self.release(),
+            PanelCommand::MoveTo(target_cm) => self.move_to(target_cm),
+            PanelCommand::Recall(preset, height_cm) => {
+                // Parsed commands only carry presets 1 to 3.
+                let _ = self.recall(preset, height_cm, now);
+            }
+        }
+    }
+
+    /// Holds Up or Down until the desk reports `target_cm`.
+    pub fn move_to(&mut self, target_cm: f32) {
+        self.key = Key::Target(target_cm, None);
+    }
+
+    /// Lets go of any key, and stops a move.
+    pub fn release(&mut self) {
+        self.key = Key::None;
+    }
+
+    /// Whether a key is down, or a move under way.
+    pub fn is_busy(&self) -> bool {
+        self.key != Key::None
+    }
+
+    /// The last height the desk reported.
+    pub fn height_cm(&self) -> Option<f32> {
+        self.height_cm
+    }
+
+    /// Takes a frame from the desk.
+    pub fn on_desk_frame(&mut self, frame: &DataFrame) {
+        if let DeskToPanelMessage::Height(h) = DeskToPanelMessage::from_frame(frame) {
+            self.height_cm = Some(h);
+        }
+    }
+
+    /// The frame to send at `now`, if one is due.
+    pub fn poll(&mut self, now: Duration) -> Option<DataFrame> {
+        if self
+            .last_send
+            .is_some_and(|last| now < last + self.send_interval)
+        {
+            return None;
+        }
+        self.last_send = Some(now);
+        Some(self.key_at(now).as_frame())
+    }
+
+    /// When the next frame is due.
+    pub fn next_due(&self) -> Duration {
+        self.last_send
+            .map_or(Duration::ZERO, |last| last + self.send_interval)
+    }
+
+    fn key_at(&mut self, now: Duration) -> PanelToDeskMessage {
+        match self.key {
+            Key::Held(message, until) if until.is_none_or(|until| now < until) => message,
+            Key::Target(target, last) => {
+                // Without a height there is nothing to steer by.
+                let Some(height) = self.height_cm else {
+                    return PanelToDeskMessage::NoKey;
+                };
+                let remaining = target - height;
+                let key = if remaining > 0.0 {
+                    PanelToDeskMessage::Up
+                } else {
+                    PanelToDeskMessage::Down
+                };
+                // Turning round means the desk went past the target.
+                let overshot = last.is_some_and(|last| last != key);
+                if overshot || remaining.abs() <= TOLERANCE_CM {
+                    self.key = Key::None;
+                    return PanelToDeskMessage::NoKey;
+                }
+                self.key = Key::Target(target, Some(key));
+                key
+            }
+            _ => {
+                self.key = Key::None;
+                PanelToDeskMessage::NoKey
+            }
+        }
+    }
+}
+
+impl Default for PanelEmulator {
+    fn default() -> Self {
+        PanelEmulator::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::DeskSimulator;
+    use crate::DeskProfile;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    /// Runs the panel against a simulated desk every 10ms up to `until`, and returns what the
+    /// panel sent.
+    fn run(
+        panel: &mut PanelEmulator,
+        desk: &mut DeskSimulator,
+        from: u64,
+        until: u64,
+    ) -> [usize; 2] {
+        let mut sent = [0; 2];
+        for at in (from..until).step_by(10) {
+            if let Some(frame) = panel.poll(ms(at)) {
+                let key = PanelToDeskMessage::from_frame(&frame);
+                sent[(key != PanelToDeskMessage::NoKey) as usize] += 1;
+                desk.on_panel_frame(&frame, ms(at));
+            }
+            if let Some(frame) = desk.poll(ms(at)) {
+                panel.on_desk_frame(&frame);
+            }
+        }
+        sent
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let mut panel = PanelEmulator::new();
+        assert_eq!(
+            panel.poll(ms(0)),
+            Some(PanelToDeskMessage::NoKey.as_frame())
+        );
+        assert_eq!(panel.poll(ms(30)), None);
+        assert_eq!(panel.next_due(), ms(50));
+        assert_eq!(
+            panel.poll(ms(50)),
+            Some(PanelToDeskMessage::NoKey.as_frame())
+        );
+
+        panel.press(PanelToDeskMessage::Two(110.0), ms(60));
+        assert_eq!(
+            panel.poll(ms(100)),
+            Some(PanelToDeskMessage::Two(110.0).as_frame())
+        );
+        assert_eq!(
+            panel.poll(ms(350)),
+            Some(PanelToDeskMessage::Two(110.0).as_frame())
+        );
+        assert_eq!(
+            panel.poll(ms(400)),
+            Some(PanelToDeskMessage::NoKey.as_frame())
+        );
+        assert!(!panel.is_busy());
+        assert_eq!(panel.recall(4, 100.0, ms(400)), Err(4));
+    }
+
+    #[test]
+    fn test_commands() {
+        assert_eq!(
+            PanelCommand::parse("up"),
+            Ok(PanelCommand::Hold(PanelToDeskMessage::Up))
+        );
+        assert_eq!(PanelCommand::parse(" stop "), Ok(PanelCommand::Stop));
+        assert_eq!(
+            PanelCommand::parse("move 100.5cm"),
+            Ok(PanelCommand::MoveTo(100.5))
+        );
+        assert_eq!(
+            PanelCommand::parse("preset 2 110"),
+            Ok(PanelCommand::Recall(2, 110.0))
+        );
+        assert!(PanelCommand::parse("preset 4 110").is_err());
+        assert!(PanelCommand::parse("move").is_err());
+        assert!(PanelCommand::parse("up now").is_err());
+        assert!(PanelCommand::parse("").is_err());
+    }
+
+    #[test]
+    fn test_drives_desk() {
+        let mut panel = PanelEmulator::new();
+        let mut desk = DeskSimulator::new(DeskProfile::default(), 90.0);
+
+        // A held key keeps the desk going, at one frame per interval.
+        panel.hold(PanelToDeskMessage::Up);
+        assert_eq!(run(&mut panel, &mut desk, 0, 1000), [0, 20]);
+        assert!(desk.height_cm() > 93.0);
+        panel.release();
+        run(&mut panel, &mut desk, 1000, 1500);
+        assert!(!desk.is_moving());
+
+        panel.move_to(80.0);
+        run(&mut panel, &mut desk, 1500, 10_000);
+        assert!(
+            (desk.height_cm() - 80.0).abs() <= 0.5,
+            "{}",
+            desk.height_cm()
+        );
+        assert!(!panel.is_busy());
+
+        panel.recall(1, 100.0, ms(10_000)).unwrap();
+        run(&mut panel, &mut desk, 10_000, 16_000);
+        assert_eq!(desk.height_cm(), 100.0);
+    }
+}
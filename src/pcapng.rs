@@ -0,0 +1,459 @@
+//! Reading and writing captures as pcapng, for Wireshark.
+//!
+//! Files have one interface per direction: interface 0 carries panel-to-desk traffic and
+//! interface 1 desk-to-panel traffic. Both use `LINKTYPE_USER0` with nanosecond timestamps.
+//! Each frame goes in its own enhanced packet block, with the decoded message as a comment.
+//! Stray bytes become one-byte packets commented as such.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use crate::capture::Record;
+use crate::decoder::Decoded;
+use crate::{
+    validate_frame, DataFrame, DeskToPanelMessage, Direction, PanelToDeskMessage, DATA_FRAME_SIZE,
+};
+
+pub const LINKTYPE_USER0: u16 = 147;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0a0d_0d0a;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+/// The longest block we read, well beyond any a seven-byte frame needs, so that a corrupt length
+/// can't make us allocate gigabytes. Wireshark has the same limit.
+const MAX_BLOCK_LEN: u32 = 16 * 1024 * 1024;
+
+const OPT_END_OF_OPT: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const OPT_IF_NAME: u16 = 2;
+const OPT_IF_TSRESOL: u16 = 9;
+
+const TSRESOL_NANOSECONDS: u8 = 9;
+// Set in if_tsresol for a power of two rather than of ten.
+const TSRESOL_BASE_2: u8 = 0x80;
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+const PANEL_TO_DESK_INTERFACE: u32 = 0;
+const DESK_TO_PANEL_INTERFACE: u32 = 1;
+
+pub struct PcapngWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<PcapngWriter<W>> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&(-1i64).to_le_bytes());
+        end_options(&mut body);
+        write_block(&mut writer, SECTION_HEADER_BLOCK, &body)?;
+
+        for name in ["panel->desk", "desk->panel"] {
+            let mut body = Vec::new();
+            body.extend_from_slice(&LINKTYPE_USER0.to_le_bytes());
+            body.extend_from_slice(&0u16.to_le_bytes());
+            body.extend_from_slice(&0u32.to_le_bytes());
+            push_option(&mut body, OPT_IF_NAME, name.as_bytes());
+            push_option(&mut body, OPT_IF_TSRESOL, &[TSRESOL_NANOSECONDS]);
+            end_options(&mut body);
+            write_block(&mut writer, INTERFACE_DESCRIPTION_BLOCK, &body)?;
+        }
+
+        Ok(PcapngWriter { writer })
+    }
+
+    pub fn write(&mut self, record: &Record) -> io::Result<()> {
+        let interface = match record.direction {
+            Direction::PanelToDesk => PANEL_TO_DESK_INTERFACE,
+            Direction::DeskToPanel => DESK_TO_PANEL_INTERFACE,
+        };
+        let (bytes, comment) = match &record.data {
+            Decoded::Frame(frame) => (&frame[..], describe(record.direction, frame)),
+            Decoded::Invalid(frame) => (&frame[..], "invalid frame".to_string()),
+            Decoded::Stray(b) => (core::slice::from_ref(b), "stray byte".to_string()),
+        };
+        let nanos = record.timestamp.as_nanos() as u64;
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&interface.to_le_bytes());
+        body.extend_from_slice(&((nanos >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(nanos as u32).to_le_bytes());
+        body.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        body.extend_from_slice(bytes);
+        pad(&mut body);
+        push_option(&mut body, OPT_COMMENT, comment.as_bytes());
+        end_options(&mut body);
+
+        write_block(&mut self.writer, ENHANCED_PACKET_BLOCK, &body)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[derive(Debug)]
+pub enum PcapngError {
+    Io(io::Error),
+    NotPcapng,
+    BigEndian,
+    BadBlock(u32),
+    UnknownInterface(u32),
+    BadPacketLength(u32),
+    BadResolution(u8),
+}
+
+impl fmt::Display for PcapngError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PcapngError::Io(e) => write!(f, "failed to read pcapng: {}", e),
+            PcapngError::NotPcapng => write!(f, "not a pcapng file"),
+            PcapngError::BigEndian => write!(f, "big-endian pcapng files are not supported"),
+            PcapngError::BadBlock(t) => write!(f, "malformed block of type {:#x}", t),
+            PcapngError::UnknownInterface(i) => write!(f, "packet on unknown interface {}", i),
+            PcapngError::BadPacketLength(n) => {
+                write!(
+                    f,
+                    "packet of {} bytes is neither a frame nor a stray byte",
+                    n
+                )
+            }
+            PcapngError::BadResolution(r) => {
+                write!(f, "unsupported timestamp resolution {:#04x}", r)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PcapngError {}
+
+impl From<io::Error> for PcapngError {
+    fn from(e: io::Error) -> Self {
+        PcapngError::Io(e)
+    }
+}
+
+/// Reads frames back out of a pcapng file written by [`PcapngWriter`], or any file following
+/// the same one-interface-per-direction layout. Blocks other than packets are skipped.
+pub struct PcapngReader<R: Read> {
+    reader: R,
+    // Timestamp units per second for each interface, indexed by interface id.
+    interfaces: Vec<u128>,
+}
+
+impl<R: Read> PcapngReader<R> {
+    pub fn new(mut reader: R) -> Result<PcapngReader<R>, PcapngError> {
+        match read_block(&mut reader) {
+            Ok(Some((SECTION_HEADER_BLOCK, _))) => {}
+            Err(e @ PcapngError::Io(_)) | Err(e @ PcapngError::BigEndian) => return Err(e),
+            _ => return Err(PcapngError::NotPcapng),
+        }
+
+        Ok(PcapngReader {
+            reader,
+            interfaces: Vec::new(),
+        })
+    }
+
+    fn read_record(&mut self) -> Result<Option<Record>, PcapngError> {
+        loop {
+            let (block_type, body) = match read_block(&mut self.reader)? {
+                Some(block) => block,
+                None => return Ok(None),
+            };
+
+            match block_type {
+                SECTION_HEADER_BLOCK => {
+                    check_section_header(&body)?;
+                    self.interfaces.clear();
+                }
+                INTERFACE_DESCRIPTION_BLOCK => {
+                    let options = body.get(8..).ok_or(PcapngError::BadBlock(block_type))?;
+                    self.interfaces.push(units_per_second(options)?);
+                }
+                ENHANCED_PACKET_BLOCK => return self.parse_packet(&body).map(Some),
+                _ => {}
+            }
+        }
+    }
+
+    fn parse_packet(&self, body: &[u8]) -> Result<Record, PcapngError> {
+        if body.len() < 20 {
+            return Err(PcapngError::BadBlock(ENHANCED_PACKET_BLOCK));
+        }
+
+        let interface = u32_at(body, 0);
+        let direction = match interface {
+            PANEL_TO_DESK_INTERFACE => Direction::PanelToDesk,
+            DESK_TO_PANEL_INTERFACE => Direction::DeskToPanel,
+            _ => return Err(PcapngError::UnknownInterface(interface)),
+        };
+        let units_per_second = *self
+            .interfaces
+            .get(interface as usize)
+            .ok_or(PcapngError::UnknownInterface(interface))?;
+
+        let units = (((u32_at(body, 4) as u64) << 32) | u32_at(body, 8) as u64) as u128;
+        // Both fit: the whole seconds are no more than `units`, and units_per_second was
+        // checked to leave room for the multiplication.
+        let timestamp = Duration::new(
+            (units / units_per_second) as u64,
+            ((units % units_per_second) * NANOS_PER_SECOND / units_per_second) as u32,
+        );
+
+        let captured = u32_at(body, 12);
+        let bytes = 20usize
+            .checked_add(captured as usize)
+            .and_then(|end| body.get(20..end))
+            .ok_or(PcapngError::BadBlock(ENHANCED_PACKET_BLOCK))?;
+
+        let data = match bytes.len() {
+            DATA_FRAME_SIZE => {
+                let mut frame = [0u8; DATA_FRAME_SIZE];
+                frame.copy_from_slice(bytes);
+                if validate_frame(&frame) {
+                    Decoded::Frame(frame)
+                } else {
+                    Decoded::Invalid(frame)
+                }
+            }
+            1 => Decoded::Stray(bytes[0]),
+            n => return Err(PcapngError::BadPacketLength(n as u32)),
+        };
+
+        Ok(Record {
+            direction,
+            timestamp,
+            data,
+        })
+    }
+}
+
+impl<R: Read> Iterator for PcapngReader<R> {
+    type Item = Result<Record, PcapngError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+fn describe(direction: Direction, frame: &DataFrame) -> String {
+    match direction {
+        Direction::PanelToDesk => PanelToDeskMessage::from_frame(frame).to_string(),
+        Direction::DeskToPanel => DeskToPanelMessage::from_frame(frame).to_string(),
+    }
+}
+
+fn write_block<W: Write>(writer: &mut W, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let total = (body.len() + 12) as u32;
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&total.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&total.to_le_bytes())
+}
+
+fn read_block<R: Read>(reader: &mut R) -> Result<Option<(u32, Vec<u8>)>, PcapngError> {
+    let mut head = [0u8; 8];
+    match reader.read(&mut head[..1])? {
+        0 => return Ok(None),
+        _ => reader.read_exact(&mut head[1..])?,
+    }
+
+    let block_type = u32_at(&head, 0);
+    let total = u32_at(&head, 4);
+
+    // The byte order magic has to be checked before the length can be trusted.
+    let mut body = Vec::new();
+    if block_type == SECTION_HEADER_BLOCK {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        check_section_header(&magic)?;
+        body.extend_from_slice(&magic);
+    }
+    if total < 12 + body.len() as u32 || total > MAX_BLOCK_LEN || !total.is_multiple_of(4) {
+        return Err(PcapngError::BadBlock(block_type));
+    }
+
+    let read = body.len();
+    body.resize(total as usize - 8, 0u8);
+    reader.read_exact(&mut body[read..])?;
+    if u32_at(&body, body.len() - 4) != total {
+        return Err(PcapngError::BadBlock(block_type));
+    }
+    body.truncate(body.len() - 4);
+
+    Ok(Some((block_type, body)))
+}
+
+fn check_section_header(body: &[u8]) -> Result<(), PcapngError> {
+    if body.len() < 4 {
+        return Err(PcapngError::NotPcapng);
+    }
+    match u32_at(body, 0) {
+        BYTE_ORDER_MAGIC => Ok(()),
+        magic if magic.swap_bytes() == BYTE_ORDER_MAGIC => Err(PcapngError::BigEndian),
+        _ => Err(PcapngError::NotPcapng),
+    }
+}
+
+/// Reads if_tsresol from an interface's options, refusing a resolution too fine for nanosecond
+/// arithmetic in 128 bits.
+fn units_per_second(mut options: &[u8]) -> Result<u128, PcapngError> {
+    // Without if_tsresol, timestamps are in microseconds.
+    let mut units = 1_000_000;
+
+    while options.len() >= 4 {
+        let code = u16::from_le_bytes([options[0], options[1]]);
+        let len = u16::from_le_bytes([options[2], options[3]]) as usize;
+        let value = match options.get(4..4 + len) {
+            Some(value) => value,
+            None => break,
+        };
+
+        match code {
+            OPT_END_OF_OPT => break,
+            OPT_IF_TSRESOL if len == 1 => {
+                let resolution = value[0];
+                let exponent = (resolution & !TSRESOL_BASE_2) as u32;
+                let base: u128 = if resolution & TSRESOL_BASE_2 == 0 {
+                    10
+                } else {
+                    2
+                };
+                units = base
+                    .checked_pow(exponent)
+                    .filter(|u| u.checked_mul(NANOS_PER_SECOND).is_some())
+                    .ok_or(PcapngError::BadResolution(resolution))?;
+            }
+            _ => {}
+        }
+
+        options = options.get(4 + len.div_ceil(4) * 4..).unwrap_or(&[]);
+    }
+
+    Ok(units)
+}
+
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    body.extend_from_slice(value);
+    pad(body);
+}
+
+fn end_options(body: &mut Vec<u8>) {
+    body.extend_from_slice(&OPT_END_OF_OPT.to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes());
+}
+
+fn pad(body: &mut Vec<u8>) {
+    while !body.len().is_multiple_of(4) {
+        body.push(0u8);
+    }
+}
+
+fn u32_at(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<Record> {
+        vec![
+            Record {
+                direction: Direction::PanelToDesk,
+                timestamp: Duration::from_nanos(1_234_567_891),
+                data: Decoded::Frame(PanelToDeskMessage::One(100.0).as_frame()),
+            },
+            Record {
+                direction: Direction::DeskToPanel,
+                timestamp: Duration::from_secs(5_000_000_000),
+                data: Decoded::Frame(DeskToPanelMessage::Height(100.0).as_frame()),
+            },
+            Record {
+                direction: Direction::DeskToPanel,
+                timestamp: Duration::from_secs(6_000_000_000),
+                data: Decoded::Invalid([104u8, 1, 0, 0, 0, 1, 0]),
+            },
+            Record {
+                direction: Direction::PanelToDesk,
+                timestamp: Duration::from_secs(6_000_000_001),
+                data: Decoded::Stray(7u8),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_pcapng_round_trip() {
+        let mut writer = PcapngWriter::new(Vec::new()).unwrap();
+        for record in records() {
+            writer.write(&record).unwrap();
+        }
+        let bytes = writer.into_inner();
+
+        assert_eq!(bytes.len() % 4, 0);
+        assert!(bytes.windows(25).any(|w| w == b"recall preset 1 (100.0cm)"));
+
+        let read: Vec<Record> = PcapngReader::new(&bytes[..])
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(read, records());
+    }
+
+    #[test]
+    fn test_pcapng_reader_errors() {
+        assert!(matches!(
+            PcapngReader::new(&b"VDCP\x01\x00\x00\x00"[..]),
+            Err(PcapngError::NotPcapng)
+        ));
+
+        let mut writer = PcapngWriter::new(Vec::new()).unwrap();
+        writer.write(&records()[0]).unwrap();
+        let mut bytes = writer.into_inner();
+
+        // Point the packet at an interface that was never described.
+        let packet = bytes.len() - u32_at(&bytes, bytes.len() - 4) as usize;
+        bytes[packet + 8] = 5u8;
+        let mut reader = PcapngReader::new(&bytes[..]).unwrap();
+        assert!(matches!(
+            reader.next(),
+            Some(Err(PcapngError::UnknownInterface(5)))
+        ));
+
+        // A huge length is refused before anything is allocated for it.
+        bytes[packet + 4..packet + 8].copy_from_slice(&0xffff_fff0u32.to_le_bytes());
+        let mut reader = PcapngReader::new(&bytes[..]).unwrap();
+        assert!(matches!(
+            reader.next(),
+            Some(Err(PcapngError::BadBlock(ENHANCED_PACKET_BLOCK)))
+        ));
+    }
+
+    #[test]
+    fn test_units_per_second() {
+        let tsresol = |r: u8| units_per_second(&[9, 0, 1, 0, r, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(units_per_second(&[]).unwrap(), 1_000_000);
+        assert_eq!(tsresol(6).unwrap(), 1_000_000);
+        assert_eq!(tsresol(9).unwrap(), 1_000_000_000);
+        assert_eq!(tsresol(12).unwrap(), 1_000_000_000_000);
+        assert_eq!(tsresol(0x80 | 10).unwrap(), 1024);
+        assert!(matches!(tsresol(30), Err(PcapngError::BadResolution(30))));
+        assert!(matches!(
+            tsresol(0xff),
+            Err(PcapngError::BadResolution(0xff))
+        ));
+        assert_eq!(
+            units_per_second(&[2, 0, 2, 0, b'a', b'b', 0, 0, 9, 0, 1, 0, 3, 0, 0, 0]).unwrap(),
+            1_000
+        );
+    }
+}
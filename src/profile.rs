@@ -0,0 +1,394 @@
+//! User profiles for desks shared between people.
+//!
+//! A profile holds someone's sitting and standing heights, their three presets and, optionally,
+//! their own sit/stand schedule. Activating a profile makes its presets the daemon's: recalls
+//! through the API go to them, and in pass-through mode so do the panel's own preset keys.
+//!
+//! Profiles are kept in a single JSON file, and each can be exported and imported on its own in
+//! the same form it has in the store:
+//!
+//! ```json
+//! {"name":"alex","sitting_cm":72,"standing_cm":110,"presets":[72,110,null],"schedule":null}
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::json::Value;
+use crate::{DeskProfile, PanelToDeskMessage};
+
+/// How quickly the keys of the panel gesture have to follow each other.
+const GESTURE_WINDOW: Duration = Duration::from_secs(3);
+
+/// Up, Down, Up, Down in quick succession switches to the next profile.
+const GESTURE: [PanelToDeskMessage; 4] = [
+    PanelToDeskMessage::Up,
+    PanelToDeskMessage::Down,
+    PanelToDeskMessage::Up,
+    PanelToDeskMessage::Down,
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub sitting_cm: f32,
+    pub standing_cm: f32,
+    pub presets: [Option<f32>; 3],
+    /// Schedule rules in the form read by the scheduler, used in place of the daemon's own.
+    pub schedule: Option<String>,
+}
+
+impl Profile {
+    pub fn new(name: &str, sitting_cm: f32, standing_cm: f32) -> Profile {
+        Profile {
+            name: name.to_string(),
+            sitting_cm,
+            standing_cm,
+            presets: [None; 3],
+            schedule: None,
+        }
+    }
+
+    /// Sets the sitting and standing heights, and presets 1 and 2 to go to them.
+    pub fn set_heights(&mut self, sitting_cm: f32, standing_cm: f32) {
+        self.sitting_cm = sitting_cm;
+        self.standing_cm = standing_cm;
+        self.presets[0] = Some(sitting_cm);
+        self.presets[1] = Some(standing_cm);
+    }
+
+    /// Keeps every height within the desk's range.
+    pub fn clamped(mut self, desk: &DeskProfile) -> Profile {
+        self.sitting_cm = desk.clamp(self.sitting_cm);
+        self.standing_cm = desk.clamp(self.standing_cm);
+        self.presets = self.presets.map(|p| p.map(|h| desk.clamp(h)));
+        self
+    }
+
+    pub fn to_json(&self) -> Value {
+        Value::object([
+            ("name", self.name.as_str().into()),
+            ("sitting_cm", self.sitting_cm.into()),
+            ("standing_cm", self.standing_cm.into()),
+            ("presets", self.presets.to_vec().into()),
+            ("schedule", self.schedule.clone().into()),
+        ])
+    }
+
+    pub fn from_json(value: &Value) -> Result<Profile, String> {
+        let name = value
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or("a profile needs a name")?;
+        check_name(name)?;
+        let height = |key: &str| {
+            value
+                .get(key)
+                .and_then(Value::as_f32)
+                .filter(|h| h.is_finite())
+                .ok_or_else(|| format!("expected a number for {}", key))
+        };
+
+        let mut presets = [None; 3];
+        if let Some(values) = value.get("presets").filter(|v| !v.is_null()) {
+            let values = values
+                .as_array()
+                .filter(|v| v.len() <= 3)
+                .ok_or("expected up to three presets")?;
+            for (preset, value) in presets.iter_mut().zip(values) {
+                *preset = match value {
+                    Value::Null => None,
+                    _ => Some(
+                        value
+                            .as_f32()
+                            .filter(|h| h.is_finite())
+                            .ok_or("expected a number or null for each preset")?,
+                    ),
+                };
+            }
+        }
+
+        let schedule = match value.get("schedule") {
+            None | Some(Value::Null) => None,
+            Some(Value::String(s)) => Some(s.clone()),
+            Some(_) => return Err("expected a string or null for schedule".to_string()),
+        };
+        Ok(Profile {
+            name: name.to_string(),
+            sitting_cm: height("sitting_cm")?,
+            standing_cm: height("standing_cm")?,
+            presets,
+            schedule,
+        })
+    }
+}
+
+/// Names end up in URLs and file names, so they are kept to letters, digits, `-`, `_` and `.`.
+pub fn check_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid profile name {:?}: use letters, digits, -, _ and .",
+            name
+        ))
+    }
+}
+
+/// The profiles, and which one is active, saved to a file after every change if there is one.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProfileStore {
+    path: Option<PathBuf>,
+    profiles: Vec<Profile>,
+    active: Option<String>,
+}
+
+impl ProfileStore {
+    /// A store that isn't saved anywhere.
+    pub fn new() -> ProfileStore {
+        ProfileStore::default()
+    }
+
+    /// Opens the store at `path`, which is created on the first change if it doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<ProfileStore> {
+        let path = path.as_ref().to_path_buf();
+        let mut store = match fs::read_to_string(&path) {
+            Ok(text) => ProfileStore::from_json(&text)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => ProfileStore::new(),
+            Err(e) => return Err(e),
+        };
+        store.path = Some(path);
+        Ok(store)
+    }
+
+    fn from_json(text: &str) -> Result<ProfileStore, String> {
+        let value = Value::parse(text).map_err(|e| e.to_string())?;
+        let profiles = value
+            .get("profiles")
+            .and_then(Value::as_array)
+            .ok_or("expected {\"profiles\": [...]}")?
+            .iter()
+            .map(Profile::from_json)
+            .collect::<Result<Vec<Profile>, String>>()?;
+        let active = value
+            .get("active")
+            .and_then(Value::as_str)
+            .filter(|name| profiles.iter().any(|p| p.name == *name))
+            .map(str::to_string);
+        Ok(ProfileStore {
+            path: None,
+            profiles,
+            active,
+        })
+    }
+
+    pub fn to_json(&self) -> Value {
+        Value::object([
+            (
+                "profiles",
+                Value::Array(self.profiles.iter().map(Profile::to_json).collect()),
+            ),
+            ("active", self.active.as_deref().into()),
+        ])
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        // Written alongside and renamed into place, so a crash never leaves half a file.
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, format!("{}\n", self.to_json()))?;
+        fs::rename(&temporary, path)
+    }
+
+    pub fn profiles(&self) -> &[Profile] {
+        &self.profiles
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    pub fn active(&self) -> Option<&Profile> {
+        self.get(self.active.as_deref()?)
+    }
+
+    /// Adds a profile, or replaces the one with the same name.
+    pub fn insert(&mut self, profile: Profile) -> io::Result<()> {
+        match self.profiles.iter_mut().find(|p| p.name == profile.name) {
+            Some(existing) => *existing = profile,
+            None => self.profiles.push(profile),
+        }
+        self.save()
+    }
+
+    /// Removes a profile, and returns whether there was one.
+    pub fn remove(&mut self, name: &str) -> io::Result<bool> {
+        let before = self.profiles.len();
+        self.profiles.retain(|p| p.name != name);
+        if self.active.as_deref() == Some(name) {
+            self.active = None;
+        }
+        if self.profiles.len() == before {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn set_active(&mut self, name: Option<&str>) -> io::Result<()> {
+        self.active = name.map(str::to_string);
+        self.save()
+    }
+
+    /// The profile after the active one, wrapping round, or the first if none is active.
+    pub fn next(&self) -> Option<&Profile> {
+        let i = match self.active.as_deref() {
+            Some(name) => self
+                .profiles
+                .iter()
+                .position(|p| p.name == name)
+                .map_or(0, |i| i + 1),
+            None => 0,
+        };
+        self.profiles.get(i % self.profiles.len().max(1))
+    }
+}
+
+/// Spots the panel gesture for switching profiles among the keys pressed on the panel.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Gesture {
+    last_key: Option<PanelToDeskMessage>,
+    // How much of the gesture has been seen, and when the last part of it was.
+    matched: usize,
+    last_press: Duration,
+}
+
+impl Gesture {
+    pub fn new() -> Gesture {
+        Gesture::default()
+    }
+
+    /// Takes a frame from the panel, and returns whether it completed the gesture.
+    pub fn on_key(&mut self, key: PanelToDeskMessage, now: Duration) -> bool {
+        let pressed = key != PanelToDeskMessage::NoKey && Some(key) != self.last_key;
+        self.last_key = Some(key);
+        if !pressed {
+            return false;
+        }
+
+        if self.matched > 0 && now.saturating_sub(self.last_press) > GESTURE_WINDOW {
+            self.matched = 0;
+        }
+        self.matched = if key == GESTURE[self.matched] {
+            self.matched + 1
+        } else if key == GESTURE[0] {
+            1
+        } else {
+            0
+        };
+        self.last_press = now;
+        if self.matched == GESTURE.len() {
+            self.matched = 0;
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let mut profile = Profile::new("alex", 72.0, 110.5);
+        profile.presets[1] = Some(100.0);
+        let text = profile.to_json().to_string();
+        assert_eq!(
+            text,
+            r#"{"name":"alex","sitting_cm":72,"standing_cm":110.5,"presets":[null,100,null],"schedule":null}"#
+        );
+        assert_eq!(
+            Profile::from_json(&Value::parse(&text).unwrap()),
+            Ok(profile)
+        );
+
+        let parse = |s| Profile::from_json(&Value::parse(s).unwrap());
+        assert!(parse(r#"{"name":"sam","sitting_cm":70,"standing_cm":105}"#).is_ok());
+        assert!(parse(r#"{"name":"../sam","sitting_cm":70,"standing_cm":105}"#).is_err());
+        assert!(parse(r#"{"name":"sam","sitting_cm":70}"#).is_err());
+        assert!(
+            parse(r#"{"name":"sam","sitting_cm":70,"standing_cm":105,"presets":[1,2,3,4]}"#)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_store() {
+        let path =
+            std::env::temp_dir().join(format!("varidesk-profiles-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut store = ProfileStore::open(&path).unwrap();
+        assert_eq!(store.next(), None);
+        store.insert(Profile::new("alex", 72.0, 110.0)).unwrap();
+        store.insert(Profile::new("sam", 68.0, 104.0)).unwrap();
+        store.insert(Profile::new("alex", 74.0, 112.0)).unwrap();
+        store.set_active(Some("sam")).unwrap();
+        assert_eq!(store.next().map(|p| p.name.as_str()), Some("alex"));
+
+        let reopened = ProfileStore::open(&path).unwrap();
+        assert_eq!(reopened, store);
+        assert_eq!(reopened.active().map(|p| p.sitting_cm), Some(68.0));
+        assert_eq!(reopened.get("alex").map(|p| p.sitting_cm), Some(74.0));
+
+        assert!(store.remove("sam").unwrap());
+        assert!(!store.remove("sam").unwrap());
+        assert_eq!(store.active(), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_gesture() {
+        let mut gesture = Gesture::new();
+        let ms = Duration::from_millis;
+        let mut press = |key, at| {
+            let done = gesture.on_key(key, ms(at));
+            gesture.on_key(PanelToDeskMessage::NoKey, ms(at + 100));
+            done
+        };
+        use PanelToDeskMessage::{Down, Up};
+
+        assert!(!press(Up, 0));
+        assert!(!press(Down, 500));
+        assert!(!press(Up, 1000));
+        assert!(press(Down, 1500));
+
+        // Too slow.
+        assert!(!press(Up, 10_000));
+        assert!(!press(Down, 10_500));
+        assert!(!press(Up, 14_000));
+        assert!(!press(Down, 14_500));
+        assert!(!press(Up, 15_000));
+        assert!(press(Down, 15_500));
+
+        // Holding a key down is one press, not many.
+        assert!(!gesture.on_key(Up, ms(20_000)));
+        assert!(!gesture.on_key(Up, ms(20_050)));
+        assert!(!gesture.on_key(Down, ms(20_100)));
+        assert!(!gesture.on_key(Up, ms(20_150)));
+        assert!(!gesture.on_key(Up, ms(20_200)));
+        assert!(gesture.on_key(Down, ms(20_250)));
+    }
+}
@@ -0,0 +1,368 @@
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use crate::capture::Record;
+use crate::decoder::Decoded;
+use crate::link::{Clock, Link};
+use crate::{DeskToPanelMessage, Direction};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReplayOptions {
+    /// Playback speed relative to the recording; 2.0 plays twice as fast. It has to be finite and
+    /// above zero, see [`ReplayOptions::with_speed`].
+    pub speed: f64,
+    /// How long to keep listening for the desk after the last frame has been sent.
+    pub settle: Duration,
+    /// How far apart a replayed and a recorded height can be and still count as the same.
+    pub height_tolerance_cm: f32,
+    pub poll_interval: Duration,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        ReplayOptions {
+            speed: 1.0,
+            settle: Duration::from_secs(1),
+            height_tolerance_cm: 0.0,
+            poll_interval: Duration::from_millis(5),
+        }
+    }
+}
+
+impl ReplayOptions {
+    pub fn with_speed(self, speed: f64) -> Result<ReplayOptions, InvalidSpeed> {
+        if !(speed.is_finite() && speed > 0.0) {
+            return Err(InvalidSpeed(speed));
+        }
+        Ok(ReplayOptions { speed, ..self })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InvalidSpeed(pub f64);
+
+impl fmt::Display for InvalidSpeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid replay speed {}: it has to be a number above zero",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidSpeed {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Response {
+    /// Time since the replay started.
+    pub timestamp: Duration,
+    pub message: DeskToPanelMessage,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Difference {
+    pub index: usize,
+    pub expected: Option<DeskToPanelMessage>,
+    pub received: Option<DeskToPanelMessage>,
+}
+
+/// What the desk said during a replay compared with what it said in the recording. Runs of
+/// identical messages are collapsed on both sides before comparing, because the number of
+/// repeated height reports depends on timing rather than behaviour.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReplayReport {
+    pub frames_sent: usize,
+    pub expected: Vec<DeskToPanelMessage>,
+    pub received: Vec<Response>,
+    pub differences: Vec<Difference>,
+}
+
+impl ReplayReport {
+    pub fn is_match(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "sent {} frames, expected {} desk messages, received {}",
+            self.frames_sent,
+            self.expected.len(),
+            self.received.len()
+        )?;
+
+        if self.is_match() {
+            return writeln!(f, "desk responses match the recording");
+        }
+
+        writeln!(f, "{} differences:", self.differences.len())?;
+        for difference in &self.differences {
+            write!(f, "  #{}: expected ", difference.index)?;
+            match difference.expected {
+                Some(msg) => write!(f, "{}", msg)?,
+                None => write!(f, "nothing")?,
+            }
+            write!(f, ", received ")?;
+            match difference.received {
+                Some(msg) => writeln!(f, "{}", msg)?,
+                None => writeln!(f, "nothing")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Plays the panel side of a capture into `link` with the recorded inter-frame timing, and
+/// compares what comes back against the recorded desk side.
+///
+/// Stray bytes are not replayed; invalid frames are, exactly as they were captured.
+pub fn replay<L: Link, C: Clock>(
+    records: &[Record],
+    link: &mut L,
+    clock: &C,
+    options: &ReplayOptions,
+) -> io::Result<ReplayReport> {
+    let mut report = ReplayReport {
+        expected: collapse(
+            records
+                .iter()
+                .filter(|r| r.direction == Direction::DeskToPanel)
+                .filter_map(|r| match r.data {
+                    Decoded::Frame(frame) => Some(DeskToPanelMessage::from_frame(&frame)),
+                    _ => None,
+                }),
+            options.height_tolerance_cm,
+        ),
+        ..ReplayReport::default()
+    };
+
+    // The options' fields are public, so they may not have been through `with_speed`.
+    let options = &options
+        .with_speed(options.speed)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let start = clock.now();
+    let first = match records.first() {
+        Some(record) => record.timestamp,
+        None => return Ok(report),
+    };
+
+    for record in records
+        .iter()
+        .filter(|r| r.direction == Direction::PanelToDesk)
+    {
+        let frame = match record.data {
+            Decoded::Frame(frame) | Decoded::Invalid(frame) => frame,
+            Decoded::Stray(_) => continue,
+        };
+
+        let offset = record.timestamp.saturating_sub(first).as_secs_f64() / options.speed;
+        let due = Duration::try_from_secs_f64(offset)
+            .ok()
+            .and_then(|offset| start.checked_add(offset))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "replay speed is too slow")
+            })?;
+
+        listen_until(due, link, clock, options, start, &mut report)?;
+        link.send(&frame)?;
+        report.frames_sent += 1;
+    }
+
+    let done = clock
+        .now()
+        .checked_add(options.settle)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "settle time is too long"))?;
+    listen_until(done, link, clock, options, start, &mut report)?;
+
+    let received = collapse(
+        report.received.iter().map(|r| r.message),
+        options.height_tolerance_cm,
+    );
+    for index in 0..report.expected.len().max(received.len()) {
+        let expected = report.expected.get(index).copied();
+        let actual = received.get(index).copied();
+        let same = match (expected, actual) {
+            (Some(a), Some(b)) => same_message(a, b, options.height_tolerance_cm),
+            _ => false,
+        };
+        if !same {
+            report.differences.push(Difference {
+                index,
+                expected,
+                received: actual,
+            });
+        }
+    }
+
+    Ok(report)
+}
+
+fn listen_until<L: Link, C: Clock>(
+    until: Duration,
+    link: &mut L,
+    clock: &C,
+    options: &ReplayOptions,
+    start: Duration,
+    report: &mut ReplayReport,
+) -> io::Result<()> {
+    loop {
+        while let Some(decoded) = link.recv()? {
+            if let Decoded::Frame(frame) = decoded {
+                report.received.push(Response {
+                    timestamp: clock.now() - start,
+                    message: DeskToPanelMessage::from_frame(&frame),
+                });
+            }
+        }
+
+        let now = clock.now();
+        if now >= until {
+            return Ok(());
+        }
+        clock.sleep(options.poll_interval.min(until - now));
+    }
+}
+
+fn collapse<I: Iterator<Item = DeskToPanelMessage>>(
+    messages: I,
+    tolerance_cm: f32,
+) -> Vec<DeskToPanelMessage> {
+    let mut collapsed: Vec<DeskToPanelMessage> = Vec::new();
+    for msg in messages {
+        match collapsed.last() {
+            Some(last) if same_message(*last, msg, tolerance_cm) => {}
+            _ => collapsed.push(msg),
+        }
+    }
+    collapsed
+}
+
+fn same_message(a: DeskToPanelMessage, b: DeskToPanelMessage, tolerance_cm: f32) -> bool {
+    match (a, b) {
+        (DeskToPanelMessage::Height(a), DeskToPanelMessage::Height(b)) => {
+            (a - b).abs() <= tolerance_cm + f32::EPSILON * 256.0
+        }
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::{ManualClock, SimulatedDesk};
+    use crate::sim::DeskSimulator;
+    use crate::{DeskProfile, PanelToDeskMessage};
+
+    // Records a session against a simulated desk, polling it every 10ms and pressing Up for a
+    // second before letting go.
+    fn recording(sim: DeskSimulator) -> Vec<Record> {
+        let mut sim = sim;
+        let mut records = Vec::new();
+
+        for ms in (0..3000).step_by(10) {
+            let now = Duration::from_millis(1000 + ms);
+            if let Some(frame) = sim.poll(now) {
+                records.push(Record {
+                    direction: Direction::DeskToPanel,
+                    timestamp: now,
+                    data: Decoded::Frame(frame),
+                });
+            }
+
+            let key = match ms {
+                0..=900 if ms % 100 == 0 => PanelToDeskMessage::Up,
+                _ if ms % 500 == 0 => PanelToDeskMessage::NoKey,
+                _ => continue,
+            };
+            sim.on_panel_frame(&key.as_frame(), now);
+            records.push(Record {
+                direction: Direction::PanelToDesk,
+                timestamp: now,
+                data: Decoded::Frame(key.as_frame()),
+            });
+        }
+
+        records
+    }
+
+    fn desk() -> DeskSimulator {
+        DeskSimulator::new(DeskProfile::default(), 100.0).with_speed(10.0)
+    }
+
+    #[test]
+    fn test_replay_matches_recording() {
+        let records = recording(desk());
+        assert_eq!(
+            records.last().map(|r| r.data),
+            Some(Decoded::Frame(DeskToPanelMessage::Height(110.0).as_frame()))
+        );
+
+        let clock = ManualClock::new();
+        let mut link = SimulatedDesk::new(desk(), clock.clone());
+        let report = replay(&records, &mut link, &clock, &ReplayOptions::default()).unwrap();
+
+        assert_eq!(report.frames_sent, 14);
+        assert_eq!(
+            report.expected.first(),
+            Some(&DeskToPanelMessage::Height(100.0))
+        );
+        assert_eq!(
+            report.expected.last(),
+            Some(&DeskToPanelMessage::Height(110.0))
+        );
+        assert!(report.is_match(), "{}", report);
+        assert_eq!(clock.now(), Duration::from_millis(3500));
+    }
+
+    #[test]
+    fn test_replay_reports_differences() {
+        let records = recording(desk());
+
+        let clock = ManualClock::new();
+        let mut link = SimulatedDesk::new(desk().with_speed(5.0), clock.clone());
+        let options = ReplayOptions {
+            height_tolerance_cm: 0.5,
+            ..ReplayOptions::default()
+        }
+        .with_speed(2.0)
+        .unwrap();
+        let report = replay(&records, &mut link, &clock, &options).unwrap();
+
+        assert!(!report.is_match());
+        assert_eq!(
+            report.received.last().map(|r| r.message),
+            Some(DeskToPanelMessage::Height(102.5))
+        );
+        assert_eq!(clock.now(), Duration::from_millis(2250));
+    }
+
+    #[test]
+    fn test_replay_speed() {
+        for speed in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(ReplayOptions::default().with_speed(speed).is_err());
+        }
+
+        let records = recording(desk());
+        let clock = ManualClock::new();
+        let mut link = SimulatedDesk::new(desk(), clock.clone());
+        for speed in [0.0, 1e-300] {
+            let options = ReplayOptions {
+                speed,
+                ..ReplayOptions::default()
+            };
+            assert!(replay(&records, &mut link, &clock, &options).is_err());
+        }
+
+        let options = ReplayOptions {
+            settle: Duration::MAX,
+            ..ReplayOptions::default()
+        };
+        let e = replay(&records, &mut link, &clock, &options).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+}
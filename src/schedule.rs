@@ -0,0 +1,652 @@
+//! Sit/stand routines that move the desk on a schedule.
+//!
+//! A schedule has one rule per line, of two kinds:
+//!
+//! | Rule                                          | Meaning                                              |
+//! |-----------------------------------------------|------------------------------------------------------|
+//! | `every 45m sit 15m stand 09:00-17:00 mon-fri` | alternate between two postures, starting with the first at the start of the hours |
+//! | `0 12 * * mon-fri stand`                      | cron-style minute, hour, day of month, month and day of week, then the posture |
+//!
+//! The hours and days of an `every` rule are optional and default to all day, every day. Blank
+//! lines and `#` comments are ignored.
+//!
+//! Before each move the panel counts down the seconds to it, and any Up, Down or preset key
+//! pressed on the panel puts the schedule on hold for a while, so it never fights whoever is at
+//! the desk.
+
+use std::fmt;
+use std::sync::mpsc::TryRecvError;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{Datelike, Days, Local, NaiveDateTime, NaiveTime, TimeDelta, Timelike};
+
+use crate::daemon::{Event, Handle};
+use crate::display::DISPLAY_RANGE;
+use crate::metrics::Posture;
+use crate::profile::Profile;
+use crate::PanelToDeskMessage;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How far ahead to look for the next change, which covers any weekly schedule.
+const LOOK_AHEAD_DAYS: u64 = 8;
+
+/// Changes more than this late, say after the machine was suspended, are dropped rather than
+/// acted on.
+const MAX_LATENESS: Duration = Duration::from_secs(60);
+
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScheduleError {
+    /// Numbered from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ScheduleError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Rule {
+    Cron {
+        // Bit n is set for each value n the field matches.
+        minutes: u64,
+        hours: u64,
+        days: u64,
+        months: u64,
+        weekdays: u64,
+        // Cron matches either day field when both are restricted.
+        either_day: bool,
+        posture: Posture,
+    },
+    Every {
+        phases: [(Posture, Duration); 2],
+        // Minutes since midnight; `end` may be 1440.
+        start: u32,
+        end: u32,
+        weekdays: u64,
+    },
+}
+
+impl Rule {
+    fn next_change(&self, after: NaiveDateTime) -> Option<(NaiveDateTime, Posture)> {
+        match *self {
+            Rule::Cron {
+                minutes,
+                hours,
+                days,
+                months,
+                weekdays,
+                either_day,
+                posture,
+            } => {
+                let bit = |mask: u64, n: u32| mask & (1 << n) != 0;
+                let mut t = after.with_second(0)?.with_nanosecond(0)? + TimeDelta::minutes(1);
+                for _ in 0..LOOK_AHEAD_DAYS * 24 * 60 {
+                    let day = bit(days, t.day());
+                    let weekday = bit(weekdays, t.weekday().num_days_from_sunday());
+                    let day_matches = if either_day {
+                        day || weekday
+                    } else {
+                        day && weekday
+                    };
+                    if day_matches
+                        && bit(months, t.month())
+                        && bit(hours, t.hour())
+                        && bit(minutes, t.minute())
+                    {
+                        return Some((t, posture));
+                    }
+                    t += TimeDelta::minutes(1);
+                }
+                None
+            }
+            Rule::Every {
+                phases,
+                start,
+                end,
+                weekdays,
+            } => {
+                for offset in 0..LOOK_AHEAD_DAYS {
+                    let date = after.date() + Days::new(offset);
+                    if weekdays & (1 << date.weekday().num_days_from_sunday()) == 0 {
+                        continue;
+                    }
+                    let midnight = date.and_time(NaiveTime::MIN);
+                    let end = midnight + TimeDelta::minutes(end as i64);
+                    let mut t = midnight + TimeDelta::minutes(start as i64);
+                    for (posture, duration) in phases.iter().cycle() {
+                        if t >= end {
+                            break;
+                        }
+                        if t > after {
+                            return Some((t, *posture));
+                        }
+                        t += TimeDelta::from_std(*duration).ok()?;
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schedule {
+    rules: Vec<Rule>,
+}
+
+impl Schedule {
+    pub fn parse(text: &str) -> Result<Schedule, ScheduleError> {
+        let mut rules = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let rule = parse_rule(line).map_err(|message| ScheduleError {
+                line: i + 1,
+                message,
+            })?;
+            rules.push(rule);
+        }
+        Ok(Schedule { rules })
+    }
+
+    /// The first change of posture strictly after `after`, in local time.
+    pub fn next_change(&self, after: NaiveDateTime) -> Option<(NaiveDateTime, Posture)> {
+        self.rules
+            .iter()
+            .filter_map(|rule| rule.next_change(after))
+            .min_by_key(|(at, _)| *at)
+    }
+}
+
+fn parse_rule(line: &str) -> Result<Rule, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    if words[0] == "every" {
+        let [_, first, first_posture, second, second_posture, rest @ ..] = &words[..] else {
+            return Err("expected `every <duration> <posture> <duration> <posture>`".to_string());
+        };
+        let phases = [
+            (parse_posture(first_posture)?, parse_duration(first)?),
+            (parse_posture(second_posture)?, parse_duration(second)?),
+        ];
+        if phases[0].0 == phases[1].0 {
+            return Err("expected one sitting and one standing phase".to_string());
+        }
+        let (mut start, mut end, mut weekdays) = (0, 24 * 60, 0x7f);
+        for word in rest {
+            if word.contains(':') {
+                (start, end) = parse_hours(word)?;
+            } else {
+                weekdays = parse_weekdays(word)?;
+            }
+        }
+        return Ok(Rule::Every {
+            phases,
+            start,
+            end,
+            weekdays,
+        });
+    }
+
+    let [minutes, hours, days, months, weekdays, posture] = &words[..] else {
+        return Err("expected five cron fields and a posture, or an `every` rule".to_string());
+    };
+    Ok(Rule::Cron {
+        minutes: parse_field(minutes, 0, 59, &[])?,
+        hours: parse_field(hours, 0, 23, &[])?,
+        days: parse_field(days, 1, 31, &[])?,
+        months: parse_field(months, 1, 12, &MONTHS)?,
+        weekdays: parse_weekdays(weekdays)?,
+        either_day: *days != "*" && *weekdays != "*",
+        posture: parse_posture(posture)?,
+    })
+}
+
+fn parse_posture(word: &str) -> Result<Posture, String> {
+    match word {
+        "sit" | "sitting" => Ok(Posture::Sitting),
+        "stand" | "standing" => Ok(Posture::Standing),
+        _ => Err(format!("expected sit or stand, not {:?}", word)),
+    }
+}
+
+/// Parses durations such as `45m`, `1h30m` and `90s`.
+pub fn parse_duration(word: &str) -> Result<Duration, String> {
+    crate::duty::parse_duration(word)
+        .filter(|d| !d.is_zero())
+        .ok_or_else(|| format!("invalid duration {:?}", word))
+}
+
+fn parse_hours(word: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("invalid hours {:?}, expected e.g. 09:00-17:00", word);
+    let minutes = |time: &str| -> Option<u32> {
+        let (h, m) = time.split_once(':')?;
+        let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+        let minutes = h.checked_mul(60)?.checked_add(m)?;
+        (m < 60 && minutes <= 24 * 60).then_some(minutes)
+    };
+    let (start, end) = word.split_once('-').ok_or_else(invalid)?;
+    match (minutes(start), minutes(end)) {
+        (Some(start), Some(end)) if start < end => Ok((start, end)),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_weekdays(word: &str) -> Result<u64, String> {
+    let mask = parse_field(word, 0, 7, &WEEKDAYS)?;
+    // Both 0 and 7 are Sunday.
+    Ok((mask | mask >> 7) & 0x7f)
+}
+
+/// Parses a cron field: `*`, a value, a range `a-b`, any of those with a step `/n`, or a list of
+/// them. `names`, if given, stand for the values from `min` up.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+    let invalid = || format!("invalid field {:?}", field);
+    let value = |s: &str| -> Option<u32> {
+        let n = match names.iter().position(|name| name.eq_ignore_ascii_case(s)) {
+            Some(i) => min + i as u32,
+            None => s.parse().ok()?,
+        };
+        (min..=max).contains(&n).then_some(n)
+    };
+
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().ok().filter(|&s| s > 0)),
+            None => (part, Some(1)),
+        };
+        let step = step.ok_or_else(invalid)?;
+        let (from, to) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((a, b)) => (value(a).ok_or_else(invalid)?, value(b).ok_or_else(invalid)?),
+                None => {
+                    let n = value(range).ok_or_else(invalid)?;
+                    (n, n)
+                }
+            },
+        };
+        if from > to {
+            return Err(invalid());
+        }
+        for n in (from..=to).step_by(step) {
+            mask |= 1 << n;
+        }
+    }
+    Ok(mask)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SchedulerOptions {
+    pub sitting_cm: f32,
+    pub standing_cm: f32,
+    pub standing_threshold_cm: f32,
+    /// How long before a move the panel starts counting down.
+    pub warning: Duration,
+    /// How long a key pressed on the panel puts the schedule on hold.
+    pub back_off: Duration,
+}
+
+impl Default for SchedulerOptions {
+    fn default() -> Self {
+        SchedulerOptions {
+            sitting_cm: 72.0,
+            standing_cm: 110.0,
+            standing_threshold_cm: 90.0,
+            warning: Duration::from_secs(30),
+            back_off: Duration::from_secs(30 * 60),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// Show the seconds left before moving on the panel.
+    Warn {
+        seconds_left: u32,
+    },
+    MoveTo(f32),
+    /// A change that came due while the schedule was on hold.
+    Skipped(Posture),
+}
+
+/// Works out what to do and when from a schedule, in local time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scheduler {
+    schedule: Schedule,
+    options: SchedulerOptions,
+    next: Option<(NaiveDateTime, Posture)>,
+    on_hold_until: Option<NaiveDateTime>,
+}
+
+impl Scheduler {
+    pub fn new(schedule: Schedule, options: SchedulerOptions) -> Scheduler {
+        Scheduler {
+            schedule,
+            options,
+            next: None,
+            on_hold_until: None,
+        }
+    }
+
+    /// The scheduler for someone's profile: their heights, and their schedule if they have one
+    /// in place of this one.
+    pub fn for_profile(&self, profile: &Profile) -> Result<Scheduler, ScheduleError> {
+        let schedule = match &profile.schedule {
+            Some(text) => Schedule::parse(text)?,
+            None => self.schedule.clone(),
+        };
+        let options = SchedulerOptions {
+            sitting_cm: profile.sitting_cm,
+            standing_cm: profile.standing_cm,
+            ..self.options
+        };
+        Ok(Scheduler::new(schedule, options))
+    }
+
+    /// The next change that will be acted on.
+    pub fn next_change(&self) -> Option<(NaiveDateTime, Posture)> {
+        self.next
+    }
+
+    /// Takes note of someone using the panel, which puts the schedule on hold.
+    pub fn on_manual(&mut self, now: NaiveDateTime) {
+        self.on_hold_until = TimeDelta::from_std(self.options.back_off)
+            .ok()
+            .map(|back_off| now + back_off);
+    }
+
+    pub fn is_on_hold(&self, now: NaiveDateTime) -> bool {
+        self.on_hold_until.is_some_and(|until| now < until)
+    }
+
+    /// Returns what to do now, given the desk's height. Changes are only acted on from the first
+    /// poll after they are due, so starting up halfway through a phase doesn't move the desk.
+    pub fn poll(&mut self, now: NaiveDateTime, height_cm: Option<f32>) -> Option<Action> {
+        let (at, posture) = match self.next {
+            Some(next) => next,
+            None => {
+                self.next = self.schedule.next_change(now);
+                self.next?
+            }
+        };
+        let already_there = height_cm
+            .is_some_and(|h| Posture::at(h, self.options.standing_threshold_cm) == posture);
+
+        if now >= at {
+            let late = (now - at).to_std().unwrap_or_default();
+            if late > MAX_LATENESS {
+                self.next = self.schedule.next_change(now);
+                return None;
+            }
+            self.next = self.schedule.next_change(at);
+            if already_there {
+                return None;
+            }
+            if self.is_on_hold(now) {
+                return Some(Action::Skipped(posture));
+            }
+            return Some(Action::MoveTo(match posture {
+                Posture::Sitting => self.options.sitting_cm,
+                Posture::Standing => self.options.standing_cm,
+            }));
+        }
+
+        let left = (at - now).to_std().unwrap_or_default();
+        if already_there || self.is_on_hold(now) || left > self.options.warning {
+            return None;
+        }
+        Some(Action::Warn {
+            seconds_left: left.as_secs_f32().ceil() as u32,
+        })
+    }
+}
+
+fn is_manual(key: PanelToDeskMessage) -> bool {
+    matches!(
+        key,
+        PanelToDeskMessage::Up
+            | PanelToDeskMessage::Down
+            | PanelToDeskMessage::One(_)
+            | PanelToDeskMessage::Two(_)
+            | PanelToDeskMessage::Three(_)
+    )
+}
+
+/// Moves the desk on `original`'s schedule, or one pushed to the daemon in its place, or that of
+/// the active user profile, until the daemon goes away.
+pub fn run_scheduler(handle: Handle, original: Scheduler) {
+    let events = handle.subscribe();
+    let mut base = original.clone();
+    let for_profile = |base: &Scheduler, profile: Option<Profile>| match profile {
+        Some(profile) => base.for_profile(&profile).unwrap_or_else(|e| {
+            handle.notify("schedule", format!("profile {}: {}", profile.name, e));
+            base.clone()
+        }),
+        None => base.clone(),
+    };
+    let mut scheduler = for_profile(&base, handle.active_profile());
+    loop {
+        loop {
+            match events.try_recv() {
+                Ok(Event::PanelKey(key)) if is_manual(key) => {
+                    scheduler.on_manual(Local::now().naive_local())
+                }
+                Ok(Event::ProfileActivated { .. }) => {
+                    scheduler = for_profile(&base, handle.active_profile())
+                }
+                Ok(Event::ScheduleChanged { schedule }) => {
+                    let schedule = match schedule.as_deref().map(Schedule::parse) {
+                        Some(Ok(schedule)) => schedule,
+                        Some(Err(e)) => {
+                            handle.notify("schedule", format!("pushed schedule: {}", e));
+                            continue;
+                        }
+                        None => original.schedule.clone(),
+                    };
+                    base = Scheduler::new(schedule, original.options);
+                    scheduler = for_profile(&base, handle.active_profile());
+                }
+                Ok(_) => {}
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+
+        match scheduler.poll(Local::now().naive_local(), handle.state().height_cm) {
+            Some(Action::Warn { seconds_left }) => {
+                // A long warning counts down from the most the panel can show.
+                let shown = (seconds_left as f32).min(*DISPLAY_RANGE.end());
+                if let Err(e) = handle.show_on_panel(shown, POLL_INTERVAL * 4) {
+                    handle.notify("schedule", e.to_string());
+                }
+            }
+            Some(Action::MoveTo(height_cm)) => {
+                if let Err(e) = handle.move_to(height_cm) {
+                    handle.notify("schedule", e);
+                }
+            }
+            Some(Action::Skipped(posture)) => handle.notify(
+                "schedule",
+                format!(
+                    "not moving to {} height, the panel is in use",
+                    posture.name()
+                ),
+            ),
+            None => {}
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> NaiveDateTime {
+        // 2024-03-04 is a Monday.
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let schedule = Schedule::parse(
+            "# Work hours\n\nevery 45m sit 15m stand 09:00-17:00 mon-fri\n0 12 * * 1-5 stand\n",
+        )
+        .unwrap();
+        assert_eq!(schedule.rules.len(), 2);
+        assert_eq!(
+            schedule.rules[0],
+            Rule::Every {
+                phases: [
+                    (Posture::Sitting, Duration::from_secs(45 * 60)),
+                    (Posture::Standing, Duration::from_secs(15 * 60)),
+                ],
+                start: 9 * 60,
+                end: 17 * 60,
+                weekdays: 0b011_1110,
+            }
+        );
+
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(90 * 60)));
+        assert!(parse_duration("30").is_err());
+        assert!(parse_duration("0m").is_err());
+        assert!(parse_duration("5é").is_err());
+        assert!(parse_duration("18446744073709551h").is_err());
+        assert!(parse_duration("99999999999999999999999s").is_err());
+        assert!(Schedule::parse("every 5é sit 15m stand 09:00-17:00").is_err());
+        assert!(Schedule::parse("every 45m sit 15m stand 99999999:00-17:00").is_err());
+        assert_eq!(
+            parse_field("*/15", 0, 59, &[]),
+            Ok(1 | 1 << 15 | 1 << 30 | 1 << 45)
+        );
+        assert_eq!(parse_field("jan,mar-apr", 1, 12, &MONTHS), Ok(0b1_1010));
+        assert_eq!(parse_weekdays("sat-7"), Ok(0b100_0001));
+
+        let error = Schedule::parse("every 45m sit\n").unwrap_err();
+        assert_eq!(error.line, 1);
+        assert_eq!(Schedule::parse("\n0 12 * * * lie\n").unwrap_err().line, 2);
+        assert!(Schedule::parse("every 1h sit 1h sit").is_err());
+        assert!(Schedule::parse("60 * * * * sit").is_err());
+        assert!(Schedule::parse("every 1h sit 1h stand 17:00-09:00").is_err());
+    }
+
+    #[test]
+    fn test_next_change() {
+        let schedule = Schedule::parse("every 45m sit 15m stand 09:00-17:00 mon-fri").unwrap();
+        let next = |s| schedule.next_change(at(s));
+        assert_eq!(
+            next("2024-03-04 08:00:00"),
+            Some((at("2024-03-04 09:00:00"), Posture::Sitting))
+        );
+        assert_eq!(
+            next("2024-03-04 09:00:00"),
+            Some((at("2024-03-04 09:45:00"), Posture::Standing))
+        );
+        assert_eq!(
+            next("2024-03-04 16:46:00"),
+            Some((at("2024-03-05 09:00:00"), Posture::Sitting))
+        );
+        // Friday evening to Monday morning.
+        assert_eq!(
+            next("2024-03-08 17:00:00"),
+            Some((at("2024-03-11 09:00:00"), Posture::Sitting))
+        );
+
+        let schedule = Schedule::parse("30 10 * * mon stand\n0 11 * * * sit").unwrap();
+        let next = |s| schedule.next_change(at(s));
+        assert_eq!(
+            next("2024-03-04 10:29:59"),
+            Some((at("2024-03-04 10:30:00"), Posture::Standing))
+        );
+        assert_eq!(
+            next("2024-03-04 10:30:00"),
+            Some((at("2024-03-04 11:00:00"), Posture::Sitting))
+        );
+
+        let schedule = Schedule::parse("0 9 1 * mon stand").unwrap();
+        // Either the 1st of the month or a Monday.
+        assert_eq!(
+            schedule.next_change(at("2024-03-04 10:00:00")),
+            Some((at("2024-03-11 09:00:00"), Posture::Standing))
+        );
+        assert_eq!(
+            Schedule::parse("")
+                .unwrap()
+                .next_change(at("2024-03-04 10:00:00")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_scheduler() {
+        let schedule = Schedule::parse("every 45m sit 15m stand 09:00-17:00").unwrap();
+        let mut scheduler = Scheduler::new(schedule, SchedulerOptions::default());
+        let sitting = Some(72.0);
+
+        // Started halfway through sitting: wait for the next change.
+        assert_eq!(scheduler.poll(at("2024-03-04 09:10:00"), sitting), None);
+        assert_eq!(scheduler.poll(at("2024-03-04 09:44:00"), sitting), None);
+        assert_eq!(
+            scheduler.poll(at("2024-03-04 09:44:35"), sitting),
+            Some(Action::Warn { seconds_left: 25 })
+        );
+        assert_eq!(
+            scheduler.poll(at("2024-03-04 09:45:00"), sitting),
+            Some(Action::MoveTo(110.0))
+        );
+        assert_eq!(
+            scheduler.next_change(),
+            Some((at("2024-03-04 10:00:00"), Posture::Sitting))
+        );
+
+        // Someone is using the panel, so the schedule holds off.
+        scheduler.on_manual(at("2024-03-04 09:50:00"));
+        assert_eq!(scheduler.poll(at("2024-03-04 09:59:50"), Some(110.0)), None);
+        assert_eq!(
+            scheduler.poll(at("2024-03-04 10:00:00"), Some(110.0)),
+            Some(Action::Skipped(Posture::Sitting))
+        );
+
+        // After the back-off the schedule picks up again, but doesn't move a desk that is
+        // already where it should be.
+        assert_eq!(scheduler.poll(at("2024-03-04 10:45:00"), Some(110.0)), None);
+        assert_eq!(
+            scheduler.poll(at("2024-03-04 11:00:01"), Some(110.0)),
+            Some(Action::MoveTo(72.0))
+        );
+
+        // Changes missed while asleep aren't caught up on.
+        assert_eq!(scheduler.poll(at("2024-03-04 14:00:00"), sitting), None);
+
+        let mut profile = Profile::new("sam", 68.0, 104.0);
+        let mut theirs = scheduler.for_profile(&profile).unwrap();
+        assert_eq!(theirs.poll(at("2024-03-04 14:10:00"), sitting), None);
+        assert_eq!(
+            theirs.poll(at("2024-03-04 14:45:00"), sitting),
+            Some(Action::MoveTo(104.0))
+        );
+        profile.schedule = Some("0 16 * * * stand".to_string());
+        let theirs = scheduler.for_profile(&profile).unwrap();
+        assert_eq!(
+            theirs.schedule.next_change(at("2024-03-04 14:45:00")),
+            Some((at("2024-03-04 16:00:00"), Posture::Standing))
+        );
+        profile.schedule = Some("every 1h".to_string());
+        assert!(scheduler.for_profile(&profile).is_err());
+    }
+}
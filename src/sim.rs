@@ -0,0 +1,208 @@
+use core::time::Duration;
+
+use crate::display::round_to_i32;
+use crate::{DataFrame, DeskProfile, DeskToPanelMessage, PanelToDeskMessage};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Motion {
+    Idle,
+    Manual { up: bool, last_key: Duration },
+    Target(f32),
+}
+
+/// A stand-in for the desk controller that reacts to panel frames and reports its height the way
+/// the real desk does.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DeskSimulator {
+    profile: DeskProfile,
+    height_cm: f32,
+    speed_cm_per_s: f32,
+    report_interval: Duration,
+    key_timeout: Duration,
+    motion: Motion,
+    last_step: Option<Duration>,
+    last_report: Option<Duration>,
+}
+
+impl DeskSimulator {
+    pub fn new(profile: DeskProfile, height_cm: f32) -> DeskSimulator {
+        DeskSimulator {
+            profile,
+            height_cm: profile.clamp(height_cm),
+            speed_cm_per_s: 3.8,
+            report_interval: Duration::from_millis(50),
+            key_timeout: Duration::from_millis(200),
+            motion: Motion::Idle,
+            last_step: None,
+            last_report: None,
+        }
+    }
+
+    pub fn with_speed(mut self, speed_cm_per_s: f32) -> DeskSimulator {
+        self.speed_cm_per_s = speed_cm_per_s;
+        self
+    }
+
+    pub fn with_report_interval(mut self, report_interval: Duration) -> DeskSimulator {
+        self.report_interval = report_interval;
+        self
+    }
+
+    /// How long a held key keeps the desk moving after its last frame.
+    pub fn with_key_timeout(mut self, key_timeout: Duration) -> DeskSimulator {
+        self.key_timeout = key_timeout;
+        self
+    }
+
+    pub fn height_cm(&self) -> f32 {
+        self.height_cm
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.motion != Motion::Idle
+    }
+
+    pub fn on_panel_frame(&mut self, frame: &DataFrame, now: Duration) {
+        self.advance(now);
+
+        match PanelToDeskMessage::from_frame(frame) {
+            PanelToDeskMessage::Up => {
+                self.motion = Motion::Manual {
+                    up: true,
+                    last_key: now,
+                }
+            }
+            PanelToDeskMessage::Down => {
+                self.motion = Motion::Manual {
+                    up: false,
+                    last_key: now,
+                }
+            }
+            PanelToDeskMessage::NoKey => {
+                if let Motion::Manual { .. } = self.motion {
+                    self.motion = Motion::Idle;
+                }
+            }
+            PanelToDeskMessage::One(h)
+            | PanelToDeskMessage::Two(h)
+            | PanelToDeskMessage::Three(h) => {
+                self.motion = Motion::Target(self.profile.clamp(h));
+            }
+            PanelToDeskMessage::DeskReset => {
+                self.motion = Motion::Target(self.profile.min_height_cm);
+            }
+            PanelToDeskMessage::ResetOne
+            | PanelToDeskMessage::ResetTwo
+            | PanelToDeskMessage::ResetThree
+            | PanelToDeskMessage::Unknown(..) => {}
+        }
+    }
+
+    /// Moves the desk on to `now` and returns a height frame if one is due.
+    pub fn poll(&mut self, now: Duration) -> Option<DataFrame> {
+        self.advance(now);
+
+        match self.last_report {
+            Some(last) if now < last + self.report_interval => None,
+            _ => {
+                self.last_report = Some(now);
+                let tenths = round_to_i32(self.height_cm * 10.0);
+                Some(DeskToPanelMessage::Height(tenths as f32 / 10.0).as_frame())
+            }
+        }
+    }
+
+    fn advance(&mut self, now: Duration) {
+        let last = self.last_step.replace(now).unwrap_or(now);
+        let mut elapsed = now.saturating_sub(last);
+
+        let target = match self.motion {
+            Motion::Idle => return,
+            Motion::Manual { up, last_key } => {
+                let released_at = last_key + self.key_timeout;
+                if now >= released_at {
+                    elapsed = released_at.saturating_sub(last);
+                    self.motion = Motion::Idle;
+                }
+                if up {
+                    self.profile.max_height_cm
+                } else {
+                    self.profile.min_height_cm
+                }
+            }
+            Motion::Target(target) => target,
+        };
+
+        let step = self.speed_cm_per_s * elapsed.as_secs_f32();
+        if (target - self.height_cm).abs() <= step {
+            self.height_cm = target;
+            if let Motion::Target(_) = self.motion {
+                self.motion = Motion::Idle;
+            }
+        } else if target > self.height_cm {
+            self.height_cm += step;
+        } else {
+            self.height_cm -= step;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_desk_simulator_reports_height() {
+        let mut sim = DeskSimulator::new(DeskProfile::default(), 100.0);
+
+        assert_eq!(
+            sim.poll(ms(0)),
+            Some(DeskToPanelMessage::Height(100.0).as_frame())
+        );
+        assert_eq!(sim.poll(ms(10)), None);
+        assert_eq!(
+            sim.poll(ms(50)),
+            Some(DeskToPanelMessage::Height(100.0).as_frame())
+        );
+    }
+
+    #[test]
+    fn test_desk_simulator_manual_movement() {
+        let mut sim = DeskSimulator::new(DeskProfile::default(), 100.0).with_speed(10.0);
+
+        sim.on_panel_frame(&PanelToDeskMessage::Up.as_frame(), ms(0));
+        sim.on_panel_frame(&PanelToDeskMessage::Up.as_frame(), ms(100));
+        assert!(sim.is_moving());
+        assert_eq!(sim.height_cm(), 101.0);
+
+        sim.on_panel_frame(&PanelToDeskMessage::NoKey.as_frame(), ms(200));
+        assert!(!sim.is_moving());
+        assert_eq!(sim.height_cm(), 102.0);
+
+        // Without repeated key frames, the desk stops once the key times out.
+        sim.on_panel_frame(&PanelToDeskMessage::Down.as_frame(), ms(1000));
+        sim.poll(ms(5000));
+        assert!(!sim.is_moving());
+        assert_eq!(sim.height_cm(), 100.0);
+    }
+
+    #[test]
+    fn test_desk_simulator_preset_movement() {
+        let mut sim = DeskSimulator::new(DeskProfile::default(), 100.0).with_speed(10.0);
+
+        sim.on_panel_frame(&PanelToDeskMessage::Two(110.0).as_frame(), ms(0));
+        sim.poll(ms(500));
+        assert_eq!(sim.height_cm(), 105.0);
+        sim.poll(ms(5000));
+        assert_eq!(sim.height_cm(), 110.0);
+        assert!(!sim.is_moving());
+
+        sim.on_panel_frame(&PanelToDeskMessage::One(200.0).as_frame(), ms(5000));
+        sim.poll(ms(60000));
+        assert_eq!(sim.height_cm(), 129.5);
+    }
+}
@@ -0,0 +1,219 @@
+use core::time::Duration;
+
+use crate::decoder::Decoded;
+use crate::{validate_checksum, DeskToPanelMessage, Direction, PanelToDeskMessage};
+
+/// How long the reported height has to stay put before the desk counts as stopped. The desk
+/// reports several times a second while moving, so this only has to cover a couple of reports.
+const STOPPED_AFTER: Duration = Duration::from_millis(300);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Movement {
+    Stopped,
+    Rising,
+    Falling,
+}
+
+/// Counters for one direction of the bus.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    pub frames: u64,
+    pub checksum_failures: u64,
+    /// Invalid frames and stray bytes, each of which means the decoder had to resynchronise.
+    pub resyncs: u64,
+    pub last_seen: Option<Duration>,
+}
+
+/// What can be worked out about the desk from watching the bus: where it is, where it is going,
+/// which presets the panel has recalled and how healthy each direction of the link looks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BusTracker {
+    height_cm: Option<f32>,
+    movement: Movement,
+    speed_cm_per_s: f32,
+    last_report: Option<(Duration, f32)>,
+    last_change: Option<Duration>,
+    presets: [Option<f32>; 3],
+    last_key: Option<PanelToDeskMessage>,
+    stats: [LinkStats; 2],
+}
+
+impl BusTracker {
+    pub fn new() -> BusTracker {
+        BusTracker {
+            height_cm: None,
+            movement: Movement::Stopped,
+            speed_cm_per_s: 0.0,
+            last_report: None,
+            last_change: None,
+            presets: [None; 3],
+            last_key: None,
+            stats: [LinkStats::default(); 2],
+        }
+    }
+
+    /// The last height the desk reported.
+    pub fn height_cm(&self) -> Option<f32> {
+        self.height_cm
+    }
+
+    /// When the desk last reported its height.
+    pub fn last_report_at(&self) -> Option<Duration> {
+        self.last_report.map(|(at, _)| at)
+    }
+
+    pub fn movement(&self, now: Duration) -> Movement {
+        match self.last_change {
+            Some(at) if now.saturating_sub(at) < STOPPED_AFTER => self.movement,
+            _ => Movement::Stopped,
+        }
+    }
+
+    /// Speed over the last height change, or zero once the desk has stopped.
+    pub fn speed_cm_per_s(&self, now: Duration) -> f32 {
+        match self.movement(now) {
+            Movement::Stopped => 0.0,
+            _ => self.speed_cm_per_s,
+        }
+    }
+
+    /// Preset heights, as last carried by a recall from the panel.
+    pub fn presets(&self) -> [Option<f32>; 3] {
+        self.presets
+    }
+
+    /// The last message the panel sent, which is `NoKey` unless a key is held.
+    pub fn last_key(&self) -> Option<PanelToDeskMessage> {
+        self.last_key
+    }
+
+    pub fn stats(&self, direction: Direction) -> &LinkStats {
+        &self.stats[direction as usize]
+    }
+
+    pub fn observe(&mut self, direction: Direction, data: &Decoded, now: Duration) {
+        let stats = &mut self.stats[direction as usize];
+        stats.last_seen = Some(now);
+
+        let frame = match data {
+            Decoded::Frame(frame) => frame,
+            Decoded::Invalid(_) | Decoded::Stray(_) => {
+                stats.resyncs += 1;
+                return;
+            }
+        };
+        stats.frames += 1;
+        if !validate_checksum(frame) {
+            stats.checksum_failures += 1;
+            return;
+        }
+
+        match direction {
+            Direction::PanelToDesk => {
+                let message = PanelToDeskMessage::from_frame(frame);
+                match message {
+                    PanelToDeskMessage::One(h) => self.presets[0] = Some(h),
+                    PanelToDeskMessage::Two(h) => self.presets[1] = Some(h),
+                    PanelToDeskMessage::Three(h) => self.presets[2] = Some(h),
+                    _ => {}
+                }
+                self.last_key = Some(message);
+            }
+            Direction::DeskToPanel => {
+                if let DeskToPanelMessage::Height(h) = DeskToPanelMessage::from_frame(frame) {
+                    self.on_height(h, now);
+                }
+            }
+        }
+    }
+
+    fn on_height(&mut self, height_cm: f32, now: Duration) {
+        if let Some((at, last)) = self.last_report {
+            let delta = height_cm - last;
+            if delta != 0.0 {
+                let elapsed = now.saturating_sub(at).as_secs_f32();
+                if elapsed > 0.0 {
+                    self.speed_cm_per_s = delta.abs() / elapsed;
+                }
+                self.movement = if delta > 0.0 {
+                    Movement::Rising
+                } else {
+                    Movement::Falling
+                };
+                self.last_change = Some(now);
+            }
+        }
+        self.last_report = Some((now, height_cm));
+        self.height_cm = Some(height_cm);
+    }
+}
+
+impl Default for BusTracker {
+    fn default() -> Self {
+        BusTracker::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn height(h: f32) -> Decoded {
+        Decoded::Frame(DeskToPanelMessage::Height(h).as_frame())
+    }
+
+    #[test]
+    fn test_movement() {
+        let mut tracker = BusTracker::new();
+        let ms = Duration::from_millis;
+
+        tracker.observe(Direction::DeskToPanel, &height(100.0), ms(0));
+        assert_eq!(tracker.height_cm(), Some(100.0));
+        assert_eq!(tracker.movement(ms(0)), Movement::Stopped);
+
+        tracker.observe(Direction::DeskToPanel, &height(100.5), ms(50));
+        tracker.observe(Direction::DeskToPanel, &height(101.0), ms(100));
+        assert_eq!(tracker.movement(ms(100)), Movement::Rising);
+        assert!((tracker.speed_cm_per_s(ms(100)) - 10.0).abs() < 0.01);
+
+        tracker.observe(Direction::DeskToPanel, &height(100.5), ms(150));
+        assert_eq!(tracker.movement(ms(150)), Movement::Falling);
+
+        tracker.observe(Direction::DeskToPanel, &height(100.5), ms(200));
+        assert_eq!(tracker.movement(ms(400)), Movement::Falling);
+        assert_eq!(tracker.movement(ms(450)), Movement::Stopped);
+        assert_eq!(tracker.speed_cm_per_s(ms(450)), 0.0);
+    }
+
+    #[test]
+    fn test_presets_and_stats() {
+        let mut tracker = BusTracker::new();
+        let now = Duration::from_secs(1);
+        let panel = Direction::PanelToDesk;
+
+        tracker.observe(
+            panel,
+            &Decoded::Frame(PanelToDeskMessage::Two(110.5).as_frame()),
+            now,
+        );
+        assert_eq!(tracker.presets(), [None, Some(110.5), None]);
+        assert_eq!(tracker.last_key(), Some(PanelToDeskMessage::Two(110.5)));
+
+        let mut bad = PanelToDeskMessage::Three(120.0).as_frame();
+        bad[5] ^= 1;
+        tracker.observe(panel, &Decoded::Frame(bad), now);
+        tracker.observe(panel, &Decoded::Stray(0), now);
+        assert_eq!(tracker.presets(), [None, Some(110.5), None]);
+
+        assert_eq!(
+            *tracker.stats(panel),
+            LinkStats {
+                frames: 2,
+                checksum_failures: 1,
+                resyncs: 1,
+                last_seen: Some(now),
+            }
+        );
+        assert_eq!(*tracker.stats(Direction::DeskToPanel), LinkStats::default());
+    }
+}
@@ -0,0 +1,537 @@
+//! Decoding logic-analyzer exports of the raw RX/TX lines.
+//!
+//! Saleae and sigrok both export sampled channels as CSV. [`LogicCapture::parse_csv`] reads
+//! either, [`decode_uart`] turns one channel into bytes, and [`transcript`] merges the panel and
+//! desk channels into time-ordered capture records.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::capture::Record;
+use crate::decoder::FrameDecoder;
+use crate::Direction;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UartConfig {
+    pub baud: u32,
+    /// One of [`DATA_BITS`].
+    pub data_bits: u8,
+    pub parity: Parity,
+    pub stop_bits: u8,
+    /// Whether the line idles low instead of high.
+    pub inverted: bool,
+}
+
+/// The character sizes UARTs use, which also fit in the decoded `u8`.
+pub const DATA_BITS: core::ops::RangeInclusive<u8> = 5..=8;
+
+impl Default for UartConfig {
+    fn default() -> Self {
+        UartConfig {
+            baud: 9600,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: 1,
+            inverted: false,
+        }
+    }
+}
+
+impl UartConfig {
+    /// Checks the config describes a UART that [`decode_uart`] can decode.
+    pub fn check(&self) -> Result<(), UartConfigError> {
+        if self.baud == 0 {
+            return Err(UartConfigError::ZeroBaud);
+        }
+        if !DATA_BITS.contains(&self.data_bits) {
+            return Err(UartConfigError::DataBits(self.data_bits));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UartConfigError {
+    ZeroBaud,
+    DataBits(u8),
+}
+
+impl fmt::Display for UartConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UartConfigError::ZeroBaud => write!(f, "the baud rate can't be zero"),
+            UartConfigError::DataBits(bits) => {
+                write!(f, "UARTs send 5 to 8 data bits, not {}", bits)
+            }
+        }
+    }
+}
+
+impl std::error::Error for UartConfigError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UartError {
+    Parity,
+    /// A stop bit was not at the idle level.
+    Framing,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UartByte {
+    /// When the start bit began.
+    pub timestamp: Duration,
+    pub value: u8,
+    pub error: Option<UartError>,
+}
+
+/// Decodes one channel, given as its level at each sample time (or just at each transition).
+pub fn decode_uart(
+    samples: &[(Duration, bool)],
+    config: &UartConfig,
+) -> Result<Vec<UartByte>, UartConfigError> {
+    config.check()?;
+    let mut bytes = Vec::new();
+    if samples.is_empty() {
+        return Ok(bytes);
+    }
+
+    let bit = 1.0 / config.baud as f64;
+    let end = samples[samples.len() - 1].0.as_secs_f64();
+    let idle = !config.inverted;
+    let level_at = |t: f64| -> bool {
+        let i = samples.partition_point(|(ts, _)| ts.as_secs_f64() <= t);
+        let level = samples[i.saturating_sub(1)].1;
+        level != config.inverted
+    };
+
+    let parity_bits = if config.parity == Parity::None { 0 } else { 1 };
+    let frame_bits = 1 + config.data_bits as u32 + parity_bits + config.stop_bits as u32;
+
+    let mut i = 1;
+    while i < samples.len() {
+        // Look for the edge from idle into a start bit.
+        let (ts, level) = samples[i];
+        if level == idle || samples[i - 1].1 != idle {
+            i += 1;
+            continue;
+        }
+
+        let start = ts.as_secs_f64();
+        if start + (frame_bits as f64 - 0.5) * bit > end {
+            break;
+        }
+
+        let sample = |n: u32| level_at(start + (n as f64 + 0.5) * bit);
+
+        let mut value = 0u8;
+        let mut ones = 0u32;
+        for n in 0..config.data_bits as u32 {
+            if sample(1 + n) {
+                value |= 1 << n;
+                ones += 1;
+            }
+        }
+
+        let mut error = None;
+        let mut n = 1 + config.data_bits as u32;
+        if config.parity != Parity::None {
+            if sample(n) {
+                ones += 1;
+            }
+            let even = ones.is_multiple_of(2);
+            if even != (config.parity == Parity::Even) {
+                error = Some(UartError::Parity);
+            }
+            n += 1;
+        }
+        for _ in 0..config.stop_bits {
+            if !sample(n) {
+                error = Some(UartError::Framing);
+            }
+            n += 1;
+        }
+
+        bytes.push(UartByte {
+            timestamp: ts,
+            value,
+            error,
+        });
+
+        // Resume searching half way through the last stop bit.
+        let resume = start + (frame_bits as f64 - 0.5) * bit;
+        while i < samples.len() && samples[i].0.as_secs_f64() <= resume {
+            i += 1;
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Splits both channels into frames and merges them into a single time-ordered transcript.
+/// Each record is stamped with the start of its first byte. Bytes with parity or framing
+/// errors are dropped.
+pub fn transcript(panel_to_desk: &[UartByte], desk_to_panel: &[UartByte]) -> Vec<Record> {
+    let mut records = Vec::new();
+
+    for (direction, bytes) in [
+        (Direction::PanelToDesk, panel_to_desk),
+        (Direction::DeskToPanel, desk_to_panel),
+    ] {
+        let mut decoder = FrameDecoder::new();
+        let mut frame_start = Duration::ZERO;
+
+        for byte in bytes.iter().filter(|b| b.error.is_none()) {
+            if !decoder.is_mid_frame() {
+                frame_start = byte.timestamp;
+            }
+            if let Some(data) = decoder.push(byte.value) {
+                records.push(Record {
+                    direction,
+                    timestamp: frame_start,
+                    data,
+                });
+            }
+        }
+    }
+
+    records.sort_by_key(|r| r.timestamp);
+    records
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CsvError {
+    Empty,
+    /// There is no time column and no samplerate to derive times from.
+    NoTimebase,
+    BadValue {
+        line: usize,
+        value: String,
+    },
+    UnknownChannel(String),
+    /// A samplerate comment that isn't a positive frequency.
+    BadSamplerate(String),
+    /// A sample time that doesn't fit in a `Duration`.
+    BadTime {
+        line: usize,
+    },
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CsvError::Empty => write!(f, "no samples in CSV"),
+            CsvError::NoTimebase => {
+                write!(f, "CSV has neither a time column nor a samplerate comment")
+            }
+            CsvError::BadValue { line, value } => {
+                write!(f, "line {}: cannot parse {:?}", line, value)
+            }
+            CsvError::UnknownChannel(name) => write!(f, "no channel named {:?}", name),
+            CsvError::BadSamplerate(rate) => write!(f, "bad samplerate {:?}", rate),
+            CsvError::BadTime { line } => write!(f, "line {}: sample time out of range", line),
+        }
+    }
+}
+
+impl std::error::Error for CsvError {}
+
+/// Sampled digital channels from a logic analyzer.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LogicCapture {
+    pub channels: Vec<String>,
+    pub samples: Vec<(Duration, Vec<bool>)>,
+}
+
+impl LogicCapture {
+    /// Parses a Saleae or sigrok CSV export.
+    ///
+    /// Lines starting with `;` are sigrok comments; a `Samplerate: 1 MHz` comment supplies the
+    /// timebase when there is no time column. A first column headed `Time`, optionally with a
+    /// unit such as `Time [s]` or `Time [us]`, is read as the sample time.
+    pub fn parse_csv(text: &str) -> Result<LogicCapture, CsvError> {
+        let mut samplerate = None;
+        let mut have_header = false;
+        let mut time_scale = None;
+        let mut capture = LogicCapture::default();
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(comment) = line.strip_prefix(';') {
+                if let Some(rate) = comment.trim().strip_prefix("Samplerate:") {
+                    let rate = rate.trim();
+                    samplerate = Some(
+                        parse_samplerate(rate)
+                            .ok_or_else(|| CsvError::BadSamplerate(rate.to_string()))?,
+                    );
+                }
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+
+            if !have_header {
+                have_header = true;
+                if fields.iter().any(|f| f.parse::<f64>().is_err()) {
+                    time_scale = time_column_scale(fields[0]);
+                    capture.channels = fields
+                        .iter()
+                        .skip(time_scale.is_some() as usize)
+                        .map(|f| f.to_string())
+                        .collect();
+                    continue;
+                }
+                // sigrok can leave the header row out entirely.
+                capture.channels = (0..fields.len()).map(|i| format!("D{}", i)).collect();
+            }
+
+            let (seconds, levels) = match time_scale {
+                Some(scale) => {
+                    let t: f64 = parse_field(n, fields[0])?;
+                    ((t * scale).max(0.0), &fields[1..])
+                }
+                None => {
+                    let rate = samplerate.ok_or(CsvError::NoTimebase)?;
+                    (capture.samples.len() as f64 / rate, &fields[..])
+                }
+            };
+            let timestamp = Duration::try_from_secs_f64(seconds)
+                .map_err(|_| CsvError::BadTime { line: n + 1 })?;
+
+            let levels = levels
+                .iter()
+                .map(|f| parse_field::<f64>(n, f).map(|v| v >= 0.5))
+                .collect::<Result<Vec<bool>, CsvError>>()?;
+            capture.samples.push((timestamp, levels));
+        }
+
+        if capture.samples.is_empty() {
+            return Err(CsvError::Empty);
+        }
+
+        Ok(capture)
+    }
+
+    pub fn channel_index(&self, name: &str) -> Result<usize, CsvError> {
+        self.channels
+            .iter()
+            .position(|c| c == name)
+            .or_else(|| name.parse().ok().filter(|i| *i < self.channels.len()))
+            .ok_or_else(|| CsvError::UnknownChannel(name.to_string()))
+    }
+
+    /// The samples of one channel, in the form [`decode_uart`] takes.
+    pub fn channel(&self, index: usize) -> Vec<(Duration, bool)> {
+        self.samples
+            .iter()
+            .filter_map(|(t, levels)| levels.get(index).map(|l| (*t, *l)))
+            .collect()
+    }
+}
+
+fn parse_field<T: std::str::FromStr>(line: usize, field: &str) -> Result<T, CsvError> {
+    field.parse().map_err(|_| CsvError::BadValue {
+        line: line + 1,
+        value: field.to_string(),
+    })
+}
+
+fn time_column_scale(header: &str) -> Option<f64> {
+    let lower = header.to_ascii_lowercase();
+    if !lower.starts_with("time") {
+        return None;
+    }
+
+    let unit = lower
+        .split(['[', '('])
+        .nth(1)
+        .map(|u| u.trim_end_matches([']', ')']).trim());
+    Some(match unit {
+        Some("ms") => 1e-3,
+        Some("us") | Some("µs") => 1e-6,
+        Some("ns") => 1e-9,
+        _ => 1.0,
+    })
+}
+
+fn parse_samplerate(rate: &str) -> Option<f64> {
+    let mut parts = rate.split_whitespace();
+    let value: f64 = parts.next()?.parse().ok()?;
+    let multiplier = match parts.next().unwrap_or("Hz") {
+        "Hz" => 1.0,
+        "kHz" => 1e3,
+        "MHz" => 1e6,
+        "GHz" => 1e9,
+        _ => return None,
+    };
+    Some(value * multiplier).filter(|rate| rate.is_finite() && *rate > 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Decoded;
+    use crate::{DeskToPanelMessage, PanelToDeskMessage};
+
+    // Renders bytes as level transitions, the way Saleae exports them.
+    fn waveform(start: f64, bytes: &[u8], config: &UartConfig) -> Vec<(Duration, bool)> {
+        let bit = 1.0 / config.baud as f64;
+        let idle = !config.inverted;
+        let mut samples = vec![(Duration::ZERO, idle)];
+        let mut t = start;
+
+        for b in bytes {
+            let mut bits = vec![false];
+            let mut ones = 0;
+            for n in 0..config.data_bits {
+                let one = b & (1 << n) != 0;
+                ones += one as u32;
+                bits.push(one);
+            }
+            match config.parity {
+                Parity::None => {}
+                Parity::Even => bits.push(ones % 2 == 1),
+                Parity::Odd => bits.push(ones % 2 == 0),
+            }
+            bits.extend(std::iter::repeat_n(true, config.stop_bits as usize));
+
+            for one in bits {
+                samples.push((Duration::from_secs_f64(t), one != config.inverted));
+                t += bit;
+            }
+            t += bit;
+        }
+
+        samples.push((Duration::from_secs_f64(t), idle));
+        samples
+    }
+
+    #[test]
+    fn test_decode_uart() {
+        let config = UartConfig::default();
+        let bytes = decode_uart(
+            &waveform(0.001, &[0x68, 0x00, 0xff, 0x16], &config),
+            &config,
+        )
+        .unwrap();
+        assert_eq!(
+            bytes.iter().map(|b| b.value).collect::<Vec<u8>>(),
+            [0x68, 0x00, 0xff, 0x16]
+        );
+        assert!(bytes.iter().all(|b| b.error.is_none()));
+        assert!((bytes[0].timestamp.as_secs_f64() - 0.001).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_decode_uart_parity_and_polarity() {
+        let config = UartConfig {
+            baud: 115200,
+            parity: Parity::Odd,
+            stop_bits: 2,
+            inverted: true,
+            ..UartConfig::default()
+        };
+        let bytes = decode_uart(&waveform(0.0, &[0x01, 0x03, 0xa5], &config), &config).unwrap();
+        assert_eq!(
+            bytes.iter().map(|b| b.value).collect::<Vec<u8>>(),
+            [0x01, 0x03, 0xa5]
+        );
+        assert!(bytes.iter().all(|b| b.error.is_none()));
+
+        let wrong = UartConfig {
+            parity: Parity::Even,
+            ..config
+        };
+        let bytes = decode_uart(&waveform(0.0, &[0x01], &config), &wrong).unwrap();
+        assert_eq!(bytes[0].error, Some(UartError::Parity));
+
+        let wide = UartConfig {
+            data_bits: 9,
+            ..config
+        };
+        assert_eq!(
+            decode_uart(&waveform(0.0, &[0x01], &config), &wide),
+            Err(UartConfigError::DataBits(9))
+        );
+    }
+
+    #[test]
+    fn test_parse_saleae_csv() {
+        let csv = "Time [s],Channel 0,Channel 1\n0.000000000,1,1\n0.001000000,0,1\n0.002,1,0\n";
+        let capture = LogicCapture::parse_csv(csv).unwrap();
+        assert_eq!(capture.channels, ["Channel 0", "Channel 1"]);
+        assert_eq!(capture.channel_index("Channel 1"), Ok(1));
+        assert_eq!(capture.channel_index("0"), Ok(0));
+        assert_eq!(
+            capture.channel(0),
+            [
+                (Duration::ZERO, true),
+                (Duration::from_millis(1), false),
+                (Duration::from_millis(2), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_sigrok_csv() {
+        let csv = "; CSV, generated by libsigrok4DSL\n; Samplerate: 1 kHz\nD0,D1\n1,0\n0,0\n1,1\n";
+        let capture = LogicCapture::parse_csv(csv).unwrap();
+        assert_eq!(capture.channels, ["D0", "D1"]);
+        assert_eq!(
+            capture.samples[2],
+            (Duration::from_millis(2), vec![true, true])
+        );
+
+        assert_eq!(
+            LogicCapture::parse_csv("D0\n1\n"),
+            Err(CsvError::NoTimebase)
+        );
+        assert_eq!(
+            LogicCapture::parse_csv("Time [us],D0\n1,x\n"),
+            Err(CsvError::BadValue {
+                line: 2,
+                value: "x".to_string()
+            })
+        );
+
+        for time in ["inf", "1e300"] {
+            assert_eq!(
+                LogicCapture::parse_csv(&format!("Time [s],D0\n{},1\n", time)),
+                Err(CsvError::BadTime { line: 2 })
+            );
+        }
+        for rate in ["0 Hz", "-1 MHz", "inf Hz", "NaN"] {
+            assert_eq!(
+                LogicCapture::parse_csv(&format!("; Samplerate: {}\nD0\n1\n", rate)),
+                Err(CsvError::BadSamplerate(rate.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_transcript() {
+        let config = UartConfig::default();
+        let up = PanelToDeskMessage::Up.as_frame();
+        let height = DeskToPanelMessage::Height(100.0).as_frame();
+
+        let panel = decode_uart(&waveform(0.010, &up, &config), &config).unwrap();
+        let desk = decode_uart(&waveform(0.005, &height, &config), &config).unwrap();
+
+        let records = transcript(&panel, &desk);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].direction, Direction::DeskToPanel);
+        assert_eq!(records[0].data, Decoded::Frame(height));
+        assert_eq!(records[1].direction, Direction::PanelToDesk);
+        assert_eq!(records[1].data, Decoded::Frame(up));
+        assert!((records[1].timestamp.as_secs_f64() - 0.010).abs() < 1e-9);
+    }
+}